@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use novai_codec::{decode_block_header_v1, encode_block_header_v1};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(header) = decode_block_header_v1(data) {
+        assert_eq!(encode_block_header_v1(&header).unwrap(), data);
+    }
+});
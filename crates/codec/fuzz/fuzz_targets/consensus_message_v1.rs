@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use novai_codec::{decode_consensus_message_v1, encode_consensus_message_v1};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(msg) = decode_consensus_message_v1(data) {
+        assert_eq!(encode_consensus_message_v1(&msg).unwrap(), data);
+    }
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use novai_codec::{decode_handshake_v1, encode_handshake_v1};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(handshake) = decode_handshake_v1(data) {
+        assert_eq!(encode_handshake_v1(&handshake).unwrap(), data);
+    }
+});
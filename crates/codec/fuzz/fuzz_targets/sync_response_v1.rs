@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use novai_codec::{decode_sync_response_v1, encode_sync_response_v1};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(response) = decode_sync_response_v1(data) {
+        assert_eq!(encode_sync_response_v1(&response).unwrap(), data);
+    }
+});
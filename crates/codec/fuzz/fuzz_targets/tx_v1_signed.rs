@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use novai_codec::{decode_tx_v1_signed, encode_tx_v1_signed, TxV1Ref};
+
+fuzz_target!(|data: &[u8]| {
+    let owned = decode_tx_v1_signed(data);
+    // The borrowed decoder accepts and rejects exactly the same bytes.
+    assert_eq!(TxV1Ref::decode(data).map(|tx| tx.to_owned_tx()), owned);
+    if let Ok(tx) = owned {
+        assert_eq!(encode_tx_v1_signed(&tx).unwrap(), data);
+    }
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use novai_codec::{decode_tx_v1_unsigned, encode_tx_v1_unsigned};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(tx) = decode_tx_v1_unsigned(data) {
+        assert_eq!(encode_tx_v1_unsigned(&tx).unwrap(), data);
+    }
+});
@@ -0,0 +1,383 @@
+//! Canonical V1 block and block sync encodings.
+//!
+//! ```text
+//! block    = header:BlockHeaderV1 (169 bytes) || tx_count:u32 || (len:u32 || signed_tx)* || qc
+//! qc       = height:u64 || round:u64 || block_hash:[u8;32] || vote_count:u32 || (voter:[u8;32] || sig:[u8;64])*
+//!
+//! request  = version:u8 || kind:u8 || body
+//!   kind 1 blocks by range: start:u64 || count:u32
+//!   kind 2 block by hash:   hash:[u8;32]
+//!   kind 3 headers:         start:u64 || count:u32
+//!   kind 4 snapshot chunk:  height:u64 || start:[u8;32] || max_leaves:u32
+//! response = version:u8 || kind:u8 || body
+//!   kind 1 blocks:          n:u32 || block*
+//!   kind 2 headers:         n:u32 || header*
+//!   kind 3 snapshot chunks: n:u32 || chunk*
+//!
+//! chunk    = start:[u8;32] || end:[u8;32] || n:u32 || (key:[u8;32] || len:u32 || value)*
+//!            || m:u32 || proof_node*
+//! proof_node = tag:u8 || body
+//!   tag 0 subtree:      hash:[u8;32]
+//!   tag 1 split
+//!   tag 2 collapsed
+//!   tag 3 outside leaf: key:[u8;32] || value_hash:[u8;32]
+//! ```
+//!
+//! Field order is CONSENSUS-RELEVANT for blocks and QCs. Counts come from
+//! the wire, so decoders never preallocate from them, and every count and
+//! payload length is held to [`DecodeLimits`] as soon as it is read.
+
+use novai_encoding::{FieldContext, Reader};
+use novai_types::{
+    BlockHeaderV1, BlockV1, QuorumCertV1, RangeProofNodeV1, SnapshotChunkV1, SyncRequestV1,
+    SyncResponseV1,
+};
+
+use crate::{
+    encode_block_header_v1, encode_tx_v1_signed, read_32, read_64, read_tx_v1_signed, read_u32_le,
+    read_u64_le, read_u8, write_32, write_64, write_bytes, write_u32_le, write_u64_le, write_u8,
+    CodecError, CodecErrorKind, Decode, DecodeLimits,
+};
+
+/// Current sync request/response version byte.
+pub const SYNC_MESSAGE_V1: u8 = 1;
+
+const KIND_BLOCKS_BY_RANGE: u8 = 1;
+const KIND_BLOCK_BY_HASH: u8 = 2;
+const KIND_HEADERS: u8 = 3;
+const KIND_SNAPSHOT_CHUNK: u8 = 4;
+
+const KIND_BLOCKS: u8 = 1;
+const KIND_HEADER_LIST: u8 = 2;
+const KIND_SNAPSHOT_CHUNKS: u8 = 3;
+
+const PROOF_SUBTREE: u8 = 0;
+const PROOF_SPLIT: u8 = 1;
+const PROOF_COLLAPSED: u8 = 2;
+const PROOF_OUTSIDE_LEAF: u8 = 3;
+
+fn write_count(out: &mut Vec<u8>, n: usize) -> Result<(), CodecError> {
+    let n: u32 = n
+        .try_into()
+        .map_err(|_| CodecError::from(CodecErrorKind::LengthOverflow))?;
+    write_u32_le(out, n);
+    Ok(())
+}
+
+fn write_block(out: &mut Vec<u8>, block: &BlockV1) -> Result<(), CodecError> {
+    out.extend_from_slice(&encode_block_header_v1(&block.header)?);
+    write_count(out, block.txs.len())?;
+    for tx in &block.txs {
+        write_bytes(out, &encode_tx_v1_signed(tx)?)?;
+    }
+
+    let qc = &block.qc;
+    write_u64_le(out, qc.height);
+    write_u64_le(out, qc.round);
+    write_32(out, &qc.block_hash);
+    write_count(out, qc.votes.len())?;
+    for (voter, sig) in &qc.votes {
+        write_32(out, voter);
+        write_64(out, sig);
+    }
+    Ok(())
+}
+
+fn read_block(input: &mut Reader<'_>, limits: &DecodeLimits) -> Result<BlockV1, CodecError> {
+    let header = BlockHeaderV1::decode_from(input).in_field("BlockV1", "header")?;
+    let count_at = input.offset();
+    let tx_count = read_u32_le(input).in_field("BlockV1", "txs")?;
+    limits
+        .check_txs(tx_count, count_at)
+        .in_field("BlockV1", "txs")?;
+    let mut txs = Vec::new();
+    for i in 0..tx_count {
+        let tx = read_u32_le(input)
+            .and_then(|len| input.sub(len as usize))
+            .and_then(|mut tx_input| {
+                let tx = read_tx_v1_signed(&mut tx_input, limits)?;
+                tx_input.finish()?;
+                Ok(tx)
+            })
+            .map_err(|e| e.in_field("BlockV1", &format!("txs[{i}]")))?;
+        txs.push(tx);
+    }
+    let qc = read_qc(input, limits).in_field("BlockV1", "qc")?;
+    Ok(BlockV1 { header, txs, qc })
+}
+
+fn read_qc(input: &mut Reader<'_>, limits: &DecodeLimits) -> Result<QuorumCertV1, CodecError> {
+    let height = read_u64_le(input).in_field("QuorumCertV1", "height")?;
+    let round = read_u64_le(input).in_field("QuorumCertV1", "round")?;
+    let block_hash = read_32(input).in_field("QuorumCertV1", "block_hash")?;
+    let count_at = input.offset();
+    let vote_count = read_u32_le(input).in_field("QuorumCertV1", "votes")?;
+    limits
+        .check_votes(vote_count, count_at)
+        .in_field("QuorumCertV1", "votes")?;
+    let mut votes = Vec::new();
+    for i in 0..vote_count {
+        let vote = read_32(input)
+            .and_then(|voter| Ok((voter, read_64(input)?)))
+            .map_err(|e| e.in_field("QuorumCertV1", &format!("votes[{i}]")))?;
+        votes.push(vote);
+    }
+    Ok(QuorumCertV1 {
+        height,
+        round,
+        block_hash,
+        votes,
+    })
+}
+
+fn write_chunk(out: &mut Vec<u8>, chunk: &SnapshotChunkV1) -> Result<(), CodecError> {
+    write_32(out, &chunk.start);
+    write_32(out, &chunk.end);
+    write_count(out, chunk.leaves.len())?;
+    for (key, value) in &chunk.leaves {
+        write_32(out, key);
+        write_bytes(out, value)?;
+    }
+    write_count(out, chunk.proof.len())?;
+    for node in &chunk.proof {
+        match node {
+            RangeProofNodeV1::Subtree(hash) => {
+                write_u8(out, PROOF_SUBTREE);
+                write_32(out, hash);
+            }
+            RangeProofNodeV1::Split => write_u8(out, PROOF_SPLIT),
+            RangeProofNodeV1::Collapsed => write_u8(out, PROOF_COLLAPSED),
+            RangeProofNodeV1::OutsideLeaf { key, value_hash } => {
+                write_u8(out, PROOF_OUTSIDE_LEAF);
+                write_32(out, key);
+                write_32(out, value_hash);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_chunk(
+    input: &mut Reader<'_>,
+    limits: &DecodeLimits,
+) -> Result<SnapshotChunkV1, CodecError> {
+    let start = read_32(input).in_field("SnapshotChunkV1", "start")?;
+    let end = read_32(input).in_field("SnapshotChunkV1", "end")?;
+    let count_at = input.offset();
+    let n = read_u32_le(input).in_field("SnapshotChunkV1", "leaves")?;
+    limits
+        .check_leaves(n, count_at)
+        .in_field("SnapshotChunkV1", "leaves")?;
+    let mut leaves = Vec::new();
+    for i in 0..n {
+        let leaf = read_32(input)
+            .and_then(|key| Ok((key, Vec::<u8>::decode_from(input)?)))
+            .map_err(|e| e.in_field("SnapshotChunkV1", &format!("leaves[{i}]")))?;
+        leaves.push(leaf);
+    }
+    let count_at = input.offset();
+    let m = read_u32_le(input).in_field("SnapshotChunkV1", "proof")?;
+    limits
+        .check_proof_nodes(m, count_at)
+        .in_field("SnapshotChunkV1", "proof")?;
+    let mut proof = Vec::new();
+    for i in 0..m {
+        let node = read_proof_node(input)
+            .map_err(|e| e.in_field("SnapshotChunkV1", &format!("proof[{i}]")))?;
+        proof.push(node);
+    }
+    Ok(SnapshotChunkV1 {
+        start,
+        end,
+        leaves,
+        proof,
+    })
+}
+
+fn read_proof_node(input: &mut Reader<'_>) -> Result<RangeProofNodeV1, CodecError> {
+    let at = input.offset();
+    Ok(match read_u8(input)? {
+        PROOF_SUBTREE => RangeProofNodeV1::Subtree(read_32(input)?),
+        PROOF_SPLIT => RangeProofNodeV1::Split,
+        PROOF_COLLAPSED => RangeProofNodeV1::Collapsed,
+        PROOF_OUTSIDE_LEAF => RangeProofNodeV1::OutsideLeaf {
+            key: read_32(input)?,
+            value_hash: read_32(input)?,
+        },
+        tag => return Err(CodecError::new(CodecErrorKind::UnknownKind(tag), at)),
+    })
+}
+
+/// Canonical encoding of BlockV1.
+pub fn encode_block_v1(block: &BlockV1) -> Result<Vec<u8>, CodecError> {
+    let mut out = Vec::new();
+    write_block(&mut out, block)?;
+    Ok(out)
+}
+
+pub fn decode_block_v1(bytes: &[u8]) -> Result<BlockV1, CodecError> {
+    decode_block_v1_with(bytes, &DecodeLimits::DEFAULT)
+}
+
+pub fn decode_block_v1_with(bytes: &[u8], limits: &DecodeLimits) -> Result<BlockV1, CodecError> {
+    limits.check_total(bytes)?;
+    let mut input = Reader::new(bytes);
+    let block = read_block(&mut input, limits)?;
+    input.finish()?;
+    Ok(block)
+}
+
+pub fn encode_sync_request_v1(req: &SyncRequestV1) -> Result<Vec<u8>, CodecError> {
+    let mut out = Vec::new();
+    write_u8(&mut out, SYNC_MESSAGE_V1);
+    match req {
+        SyncRequestV1::BlocksByRange { start, count } => {
+            write_u8(&mut out, KIND_BLOCKS_BY_RANGE);
+            write_u64_le(&mut out, *start);
+            write_u32_le(&mut out, *count);
+        }
+        SyncRequestV1::BlockByHash(hash) => {
+            write_u8(&mut out, KIND_BLOCK_BY_HASH);
+            write_32(&mut out, hash);
+        }
+        SyncRequestV1::Headers { start, count } => {
+            write_u8(&mut out, KIND_HEADERS);
+            write_u64_le(&mut out, *start);
+            write_u32_le(&mut out, *count);
+        }
+        SyncRequestV1::SnapshotChunk {
+            height,
+            start,
+            max_leaves,
+        } => {
+            write_u8(&mut out, KIND_SNAPSHOT_CHUNK);
+            write_u64_le(&mut out, *height);
+            write_32(&mut out, start);
+            write_u32_le(&mut out, *max_leaves);
+        }
+    }
+    Ok(out)
+}
+
+/// The version and kind bytes of a sync message.
+fn read_envelope(input: &mut Reader<'_>, ty: &str) -> Result<u8, CodecError> {
+    let at = input.offset();
+    let version = read_u8(input).in_field(ty, "version")?;
+    if version != SYNC_MESSAGE_V1 {
+        return Err(
+            CodecError::new(CodecErrorKind::InvalidVersion(version), at).in_field(ty, "version")
+        );
+    }
+    read_u8(input).in_field(ty, "kind")
+}
+
+fn unknown_kind(kind: u8, input: &Reader<'_>, ty: &str) -> CodecError {
+    CodecError::new(CodecErrorKind::UnknownKind(kind), input.offset() - 1).in_field(ty, "kind")
+}
+
+pub fn decode_sync_request_v1(bytes: &[u8]) -> Result<SyncRequestV1, CodecError> {
+    const TY: &str = "SyncRequestV1";
+    let mut input = Reader::new(bytes);
+    let req = match read_envelope(&mut input, TY)? {
+        KIND_BLOCKS_BY_RANGE => SyncRequestV1::BlocksByRange {
+            start: read_u64_le(&mut input).in_field(TY, "start")?,
+            count: read_u32_le(&mut input).in_field(TY, "count")?,
+        },
+        KIND_BLOCK_BY_HASH => SyncRequestV1::BlockByHash(read_32(&mut input).in_field(TY, "hash")?),
+        KIND_HEADERS => SyncRequestV1::Headers {
+            start: read_u64_le(&mut input).in_field(TY, "start")?,
+            count: read_u32_le(&mut input).in_field(TY, "count")?,
+        },
+        KIND_SNAPSHOT_CHUNK => SyncRequestV1::SnapshotChunk {
+            height: read_u64_le(&mut input).in_field(TY, "height")?,
+            start: read_32(&mut input).in_field(TY, "start")?,
+            max_leaves: read_u32_le(&mut input).in_field(TY, "max_leaves")?,
+        },
+        kind => return Err(unknown_kind(kind, &input, TY)),
+    };
+    input.finish()?;
+    Ok(req)
+}
+
+pub fn encode_sync_response_v1(res: &SyncResponseV1) -> Result<Vec<u8>, CodecError> {
+    let mut out = Vec::new();
+    write_u8(&mut out, SYNC_MESSAGE_V1);
+    match res {
+        SyncResponseV1::Blocks(blocks) => {
+            write_u8(&mut out, KIND_BLOCKS);
+            write_count(&mut out, blocks.len())?;
+            for block in blocks {
+                write_block(&mut out, block)?;
+            }
+        }
+        SyncResponseV1::Headers(headers) => {
+            write_u8(&mut out, KIND_HEADER_LIST);
+            write_count(&mut out, headers.len())?;
+            for header in headers {
+                out.extend_from_slice(&encode_block_header_v1(header)?);
+            }
+        }
+        SyncResponseV1::SnapshotChunks(chunks) => {
+            write_u8(&mut out, KIND_SNAPSHOT_CHUNKS);
+            write_count(&mut out, chunks.len())?;
+            for chunk in chunks {
+                write_chunk(&mut out, chunk)?;
+            }
+        }
+    }
+    Ok(out)
+}
+
+pub fn decode_sync_response_v1(bytes: &[u8]) -> Result<SyncResponseV1, CodecError> {
+    decode_sync_response_v1_with(bytes, &DecodeLimits::DEFAULT)
+}
+
+pub fn decode_sync_response_v1_with(
+    bytes: &[u8],
+    limits: &DecodeLimits,
+) -> Result<SyncResponseV1, CodecError> {
+    const TY: &str = "SyncResponseV1";
+    limits.check_total(bytes)?;
+    let mut input = Reader::new(bytes);
+    let res = match read_envelope(&mut input, TY)? {
+        KIND_BLOCKS => {
+            let count_at = input.offset();
+            let n = read_u32_le(&mut input).in_field(TY, "blocks")?;
+            limits.check_items(n, count_at).in_field(TY, "blocks")?;
+            let mut blocks = Vec::new();
+            for i in 0..n {
+                let block = read_block(&mut input, limits)
+                    .map_err(|e| e.in_field(TY, &format!("blocks[{i}]")))?;
+                blocks.push(block);
+            }
+            SyncResponseV1::Blocks(blocks)
+        }
+        KIND_HEADER_LIST => {
+            let count_at = input.offset();
+            let n = read_u32_le(&mut input).in_field(TY, "headers")?;
+            limits.check_items(n, count_at).in_field(TY, "headers")?;
+            let mut headers = Vec::new();
+            for i in 0..n {
+                let header = BlockHeaderV1::decode_from(&mut input)
+                    .map_err(|e| e.in_field(TY, &format!("headers[{i}]")))?;
+                headers.push(header);
+            }
+            SyncResponseV1::Headers(headers)
+        }
+        KIND_SNAPSHOT_CHUNKS => {
+            let count_at = input.offset();
+            let n = read_u32_le(&mut input).in_field(TY, "chunks")?;
+            limits.check_chunks(n, count_at).in_field(TY, "chunks")?;
+            let mut chunks = Vec::new();
+            for i in 0..n {
+                let chunk = read_chunk(&mut input, limits)
+                    .map_err(|e| e.in_field(TY, &format!("chunks[{i}]")))?;
+                chunks.push(chunk);
+            }
+            SyncResponseV1::SnapshotChunks(chunks)
+        }
+        kind => return Err(unknown_kind(kind, &input, TY)),
+    };
+    input.finish()?;
+    Ok(res)
+}
@@ -0,0 +1,139 @@
+//! Consensus wire envelope (V1).
+//!
+//! ```text
+//! envelope = envelope_version:u8 || kind:u8 || body
+//! kind 1 proposal: round:u64 || header:BlockHeaderV1 (169 bytes) || sig:[u8;64]
+//! kind 2 vote:     height:u64 || round:u64 || block_hash:[u8;32] || voter:[u8;32] || sig:[u8;64]
+//! kind 3 timeout:  height:u64 || round:u64 || voter:[u8;32] || sig:[u8;64]
+//! ```
+//!
+//! Signatures cover `domain || body-without-sig`. Each kind has its own
+//! domain tag, so a signature over one message kind never verifies as
+//! another, and none of the tags can be confused with a tx signing payload
+//! (which starts with the tx version byte).
+//!
+//! Field order is CONSENSUS-RELEVANT. Changing it is a hard fork unless
+//! [`CONSENSUS_ENVELOPE_V1`] is bumped.
+
+use novai_encoding::{FieldContext, Reader};
+use novai_types::{BlockHeaderV1, ConsensusMessage, ProposalV1, TimeoutV1, VoteV1};
+
+use crate::{
+    encode_block_header_v1, read_32, read_64, read_u64_le, read_u8, write_32, write_64,
+    write_u64_le, write_u8, CodecError, CodecErrorKind, Decode,
+};
+
+/// Current envelope version byte.
+pub const CONSENSUS_ENVELOPE_V1: u8 = 1;
+
+pub const PROPOSAL_DOMAIN_V1: &[u8] = b"NOVAI/CONSENSUS/PROPOSAL/V1";
+pub const VOTE_DOMAIN_V1: &[u8] = b"NOVAI/CONSENSUS/VOTE/V1";
+pub const TIMEOUT_DOMAIN_V1: &[u8] = b"NOVAI/CONSENSUS/TIMEOUT/V1";
+
+const KIND_PROPOSAL: u8 = 1;
+const KIND_VOTE: u8 = 2;
+const KIND_TIMEOUT: u8 = 3;
+
+fn write_body_unsigned(out: &mut Vec<u8>, msg: &ConsensusMessage) -> Result<(), CodecError> {
+    match msg {
+        ConsensusMessage::Proposal(p) => {
+            write_u64_le(out, p.round);
+            out.extend_from_slice(&encode_block_header_v1(&p.header)?);
+        }
+        ConsensusMessage::Vote(v) => {
+            write_u64_le(out, v.height);
+            write_u64_le(out, v.round);
+            write_32(out, &v.block_hash);
+            write_32(out, &v.voter);
+        }
+        ConsensusMessage::Timeout(t) => {
+            write_u64_le(out, t.height);
+            write_u64_le(out, t.round);
+            write_32(out, &t.voter);
+        }
+    }
+    Ok(())
+}
+
+fn kind(msg: &ConsensusMessage) -> u8 {
+    match msg {
+        ConsensusMessage::Proposal(_) => KIND_PROPOSAL,
+        ConsensusMessage::Vote(_) => KIND_VOTE,
+        ConsensusMessage::Timeout(_) => KIND_TIMEOUT,
+    }
+}
+
+/// Bytes a consensus signature is computed over: `domain || body-without-sig`.
+pub fn consensus_signing_bytes_v1(msg: &ConsensusMessage) -> Result<Vec<u8>, CodecError> {
+    let domain = match msg {
+        ConsensusMessage::Proposal(_) => PROPOSAL_DOMAIN_V1,
+        ConsensusMessage::Vote(_) => VOTE_DOMAIN_V1,
+        ConsensusMessage::Timeout(_) => TIMEOUT_DOMAIN_V1,
+    };
+    let mut out = domain.to_vec();
+    write_body_unsigned(&mut out, msg)?;
+    Ok(out)
+}
+
+/// Canonical envelope encoding, signature included.
+pub fn encode_consensus_message_v1(msg: &ConsensusMessage) -> Result<Vec<u8>, CodecError> {
+    let mut out = Vec::new();
+    write_u8(&mut out, CONSENSUS_ENVELOPE_V1);
+    write_u8(&mut out, kind(msg));
+    write_body_unsigned(&mut out, msg)?;
+    write_64(&mut out, msg.sig());
+    Ok(out)
+}
+
+pub fn decode_consensus_message_v1(bytes: &[u8]) -> Result<ConsensusMessage, CodecError> {
+    let mut input = Reader::new(bytes);
+    let version = read_u8(&mut input).in_field("ConsensusMessage", "version")?;
+    if version != CONSENSUS_ENVELOPE_V1 {
+        return Err(CodecError::new(CodecErrorKind::InvalidVersion(version), 0)
+            .in_field("ConsensusMessage", "version"));
+    }
+    let msg = match read_u8(&mut input).in_field("ConsensusMessage", "kind")? {
+        KIND_PROPOSAL => {
+            const TY: &str = "ProposalV1";
+            let round = read_u64_le(&mut input).in_field(TY, "round")?;
+            let header = BlockHeaderV1::decode_from(&mut input).in_field(TY, "header")?;
+            let sig = read_64(&mut input).in_field(TY, "sig")?;
+            ConsensusMessage::Proposal(ProposalV1 { round, header, sig })
+        }
+        KIND_VOTE => {
+            const TY: &str = "VoteV1";
+            let height = read_u64_le(&mut input).in_field(TY, "height")?;
+            let round = read_u64_le(&mut input).in_field(TY, "round")?;
+            let block_hash = read_32(&mut input).in_field(TY, "block_hash")?;
+            let voter = read_32(&mut input).in_field(TY, "voter")?;
+            let sig = read_64(&mut input).in_field(TY, "sig")?;
+            ConsensusMessage::Vote(VoteV1 {
+                height,
+                round,
+                block_hash,
+                voter,
+                sig,
+            })
+        }
+        KIND_TIMEOUT => {
+            const TY: &str = "TimeoutV1";
+            let height = read_u64_le(&mut input).in_field(TY, "height")?;
+            let round = read_u64_le(&mut input).in_field(TY, "round")?;
+            let voter = read_32(&mut input).in_field(TY, "voter")?;
+            let sig = read_64(&mut input).in_field(TY, "sig")?;
+            ConsensusMessage::Timeout(TimeoutV1 {
+                height,
+                round,
+                voter,
+                sig,
+            })
+        }
+        kind => {
+            return Err(CodecError::new(CodecErrorKind::UnknownKind(kind), 1)
+                .in_field("ConsensusMessage", "kind"))
+        }
+    };
+
+    input.finish()?;
+    Ok(msg)
+}
@@ -0,0 +1,68 @@
+//! Peer handshake encoding (V1).
+//!
+//! ```text
+//! handshake = version:u8 || chain_id:u64 || genesis_hash:[u8;32]
+//!             || n:u8 || tx_version:u8 * n || m:u8 || header_version:u8 * m
+//!             || best_finalized_height:u64
+//! ```
+//!
+//! Version lists are raw bytes rather than [`novai_types::TxVersion`]s so
+//! that a peer announcing versions we don't know still decodes, and the
+//! mismatch is reported as such.
+
+use novai_types::HandshakeV1;
+
+use novai_encoding::{FieldContext, Reader};
+
+use crate::{
+    read_32, read_u64_le, read_u8, write_32, write_u64_le, write_u8, CodecError, CodecErrorKind,
+};
+
+/// Current handshake version byte.
+pub const HANDSHAKE_V1: u8 = 1;
+
+fn write_versions(out: &mut Vec<u8>, versions: &[u8]) -> Result<(), CodecError> {
+    let n: u8 = versions
+        .len()
+        .try_into()
+        .map_err(|_| CodecError::from(CodecErrorKind::LengthOverflow))?;
+    write_u8(out, n);
+    out.extend_from_slice(versions);
+    Ok(())
+}
+
+fn read_versions(input: &mut Reader<'_>) -> Result<Vec<u8>, CodecError> {
+    let n = read_u8(input)? as usize;
+    Ok(input.take(n)?.to_vec())
+}
+
+pub fn encode_handshake_v1(handshake: &HandshakeV1) -> Result<Vec<u8>, CodecError> {
+    let mut out = Vec::new();
+    write_u8(&mut out, HANDSHAKE_V1);
+    write_u64_le(&mut out, handshake.chain_id);
+    write_32(&mut out, &handshake.genesis_hash);
+    write_versions(&mut out, &handshake.tx_versions)?;
+    write_versions(&mut out, &handshake.header_versions)?;
+    write_u64_le(&mut out, handshake.best_finalized_height);
+    Ok(out)
+}
+
+pub fn decode_handshake_v1(bytes: &[u8]) -> Result<HandshakeV1, CodecError> {
+    const TY: &str = "HandshakeV1";
+    let mut input = Reader::new(bytes);
+    let version = read_u8(&mut input).in_field(TY, "version")?;
+    if version != HANDSHAKE_V1 {
+        return Err(
+            CodecError::new(CodecErrorKind::InvalidVersion(version), 0).in_field(TY, "version")
+        );
+    }
+    let handshake = HandshakeV1 {
+        chain_id: read_u64_le(&mut input).in_field(TY, "chain_id")?,
+        genesis_hash: read_32(&mut input).in_field(TY, "genesis_hash")?,
+        tx_versions: read_versions(&mut input).in_field(TY, "tx_versions")?,
+        header_versions: read_versions(&mut input).in_field(TY, "header_versions")?,
+        best_finalized_height: read_u64_le(&mut input).in_field(TY, "best_finalized_height")?,
+    };
+    input.finish()?;
+    Ok(handshake)
+}
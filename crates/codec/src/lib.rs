@@ -1,52 +1,50 @@
-use novai_types::{
-    Address, BlockHeaderV1, BlockHeaderVersion, Hash32, SignatureBytes, TxId, TxV1, TxVersion,
+use novai_encoding::{FieldContext, Reader};
+use novai_types::{BlockHeaderV1, Hash32, TxId, TxV1, TxVersion};
+
+pub mod block;
+pub mod consensus;
+pub mod handshake;
+pub mod limits;
+pub mod tx_ref;
+
+pub use block::{
+    decode_block_v1, decode_block_v1_with, decode_sync_request_v1, decode_sync_response_v1,
+    decode_sync_response_v1_with, encode_block_v1, encode_sync_request_v1, encode_sync_response_v1,
+    SYNC_MESSAGE_V1,
 };
+pub use consensus::{
+    consensus_signing_bytes_v1, decode_consensus_message_v1, encode_consensus_message_v1,
+    CONSENSUS_ENVELOPE_V1, PROPOSAL_DOMAIN_V1, TIMEOUT_DOMAIN_V1, VOTE_DOMAIN_V1,
+};
+pub use handshake::{decode_handshake_v1, encode_handshake_v1, HANDSHAKE_V1};
+pub use limits::DecodeLimits;
+pub use tx_ref::TxV1Ref;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum CodecError {
-    UnexpectedEof,
-    TrailingBytes,
-    InvalidVersion,
-    LengthOverflow,
-}
+pub use novai_encoding::{
+    CodecError, CodecErrorKind, Decode, DecodeUnsigned, Encode, EncodeUnsigned,
+};
 
-fn take<'a>(input: &mut &'a [u8], n: usize) -> Result<&'a [u8], CodecError> {
-    if input.len() < n {
-        return Err(CodecError::UnexpectedEof);
-    }
-    let (a, b) = input.split_at(n);
-    *input = b;
-    Ok(a)
-}
+// Field helpers for the formats not yet derived; each is the canonical
+// encoding from `novai-encoding`.
 
-fn read_u8(input: &mut &[u8]) -> Result<u8, CodecError> {
-    Ok(take(input, 1)?[0])
+fn read_u8(input: &mut Reader<'_>) -> Result<u8, CodecError> {
+    u8::decode_from(input)
 }
 
-fn read_u32_le(input: &mut &[u8]) -> Result<u32, CodecError> {
-    let b = take(input, 4)?;
-    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+fn read_u32_le(input: &mut Reader<'_>) -> Result<u32, CodecError> {
+    u32::decode_from(input)
 }
 
-fn read_u64_le(input: &mut &[u8]) -> Result<u64, CodecError> {
-    let b = take(input, 8)?;
-    Ok(u64::from_le_bytes([
-        b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7],
-    ]))
+fn read_u64_le(input: &mut Reader<'_>) -> Result<u64, CodecError> {
+    u64::decode_from(input)
 }
 
-fn read_32(input: &mut &[u8]) -> Result<[u8; 32], CodecError> {
-    let b = take(input, 32)?;
-    let mut out = [0u8; 32];
-    out.copy_from_slice(b);
-    Ok(out)
+fn read_32(input: &mut Reader<'_>) -> Result<[u8; 32], CodecError> {
+    <[u8; 32]>::decode_from(input)
 }
 
-fn read_64(input: &mut &[u8]) -> Result<[u8; 64], CodecError> {
-    let b = take(input, 64)?;
-    let mut out = [0u8; 64];
-    out.copy_from_slice(b);
-    Ok(out)
+fn read_64(input: &mut Reader<'_>) -> Result<[u8; 64], CodecError> {
+    <[u8; 64]>::decode_from(input)
 }
 
 fn write_u8(out: &mut Vec<u8>, v: u8) {
@@ -70,122 +68,86 @@ fn write_64(out: &mut Vec<u8>, v: &[u8; 64]) {
 }
 
 fn write_bytes(out: &mut Vec<u8>, b: &[u8]) -> Result<(), CodecError> {
-    let len_u32: u32 = b.len().try_into().map_err(|_| CodecError::LengthOverflow)?;
+    let len_u32: u32 = b
+        .len()
+        .try_into()
+        .map_err(|_| CodecError::from(CodecErrorKind::LengthOverflow))?;
     write_u32_le(out, len_u32);
     out.extend_from_slice(b);
     Ok(())
 }
 
-/// Canonical encoding of TxV1 without signature.
+/// Canonical encoding of TxV1 without signature: the derived
+/// [`EncodeUnsigned`], i.e. version || from || nonce || fee || payload.
 /// Field order is CONSENSUS-RELEVANT. Changing it is a hard fork.
 pub fn encode_tx_v1_unsigned(tx: &TxV1) -> Result<Vec<u8>, CodecError> {
-    let mut out = Vec::new();
-    write_u8(&mut out, tx.version as u8);
-    write_32(&mut out, &tx.from);
-    write_u64_le(&mut out, tx.nonce);
-    write_u64_le(&mut out, tx.fee);
-    write_bytes(&mut out, &tx.payload)?;
-    Ok(out)
+    tx.encode_unsigned()
 }
 
-/// Canonical encoding of TxV1 including signature.
+/// Canonical encoding of TxV1 including signature (the derived [`Encode`]).
 pub fn encode_tx_v1_signed(tx: &TxV1) -> Result<Vec<u8>, CodecError> {
-    let mut out = encode_tx_v1_unsigned(tx)?;
-    write_64(&mut out, &tx.sig);
-    Ok(out)
+    tx.encode()
 }
 
+/// Unsigned decode sets `sig` to zeros.
 pub fn decode_tx_v1_unsigned(bytes: &[u8]) -> Result<TxV1, CodecError> {
-    let mut input = bytes;
-    let v = read_u8(&mut input)?;
-    let version = TxVersion::from_u8(v).ok_or(CodecError::InvalidVersion)?;
-    let from: Address = read_32(&mut input)?;
-    let nonce = read_u64_le(&mut input)?;
-    let fee = read_u64_le(&mut input)?;
-    let payload_len = read_u32_le(&mut input)? as usize;
-    let payload = take(&mut input, payload_len)?.to_vec();
-
-    // unsigned decode sets sig to zeros
-    let sig: SignatureBytes = [0u8; 64];
-
-    if !input.is_empty() {
-        return Err(CodecError::TrailingBytes);
-    }
+    decode_tx_v1_unsigned_with(bytes, &DecodeLimits::DEFAULT)
+}
 
-    Ok(TxV1 {
-        version,
-        from,
-        nonce,
-        fee,
-        payload,
-        sig,
-    })
+pub fn decode_tx_v1_unsigned_with(bytes: &[u8], limits: &DecodeLimits) -> Result<TxV1, CodecError> {
+    limits.check_total(bytes)?;
+    let mut input = Reader::new(bytes);
+    check_payload_len(&input, limits)?;
+    let tx = TxV1::decode_unsigned_from(&mut input)?;
+    input.finish()?;
+    Ok(tx)
 }
 
 pub fn decode_tx_v1_signed(bytes: &[u8]) -> Result<TxV1, CodecError> {
-    let mut input = bytes;
-    let v = read_u8(&mut input)?;
-    let version = TxVersion::from_u8(v).ok_or(CodecError::InvalidVersion)?;
-    let from: Address = read_32(&mut input)?;
-    let nonce = read_u64_le(&mut input)?;
-    let fee = read_u64_le(&mut input)?;
-
-    let payload_len = read_u32_le(&mut input)? as usize;
-    let payload = take(&mut input, payload_len)?.to_vec();
-
-    let sig: SignatureBytes = read_64(&mut input)?;
-
-    if !input.is_empty() {
-        return Err(CodecError::TrailingBytes);
+    decode_tx_v1_signed_with(bytes, &DecodeLimits::DEFAULT)
+}
+
+pub fn decode_tx_v1_signed_with(bytes: &[u8], limits: &DecodeLimits) -> Result<TxV1, CodecError> {
+    limits.check_total(bytes)?;
+    let mut input = Reader::new(bytes);
+    let tx = read_tx_v1_signed(&mut input, limits)?;
+    input.finish()?;
+    Ok(tx)
+}
+
+/// One signed TxV1 from `input`, payload length checked against `limits`.
+pub(crate) fn read_tx_v1_signed(
+    input: &mut Reader<'_>,
+    limits: &DecodeLimits,
+) -> Result<TxV1, CodecError> {
+    check_payload_len(input, limits)?;
+    TxV1::decode_from(input)
+}
+
+/// Offset of the payload length in both TxV1 encodings.
+const TX_V1_PAYLOAD_LEN_AT: usize = 1 + 32 + 8 + 8;
+
+/// Refuse an over-limit payload length before the derived decode copies
+/// the payload. Anything malformed before it is left for that decode to
+/// report.
+fn check_payload_len(input: &Reader<'_>, limits: &DecodeLimits) -> Result<(), CodecError> {
+    let mut peek = input.clone();
+    if TxVersion::decode_from(&mut peek).is_ok() && peek.take(TX_V1_PAYLOAD_LEN_AT - 1).is_ok() {
+        let at = peek.offset();
+        if let Ok(len) = read_u32_le(&mut peek) {
+            limits.check_payload(len, at).in_field("TxV1", "payload")?;
+        }
     }
-
-    Ok(TxV1 {
-        version,
-        from,
-        nonce,
-        fee,
-        payload,
-        sig,
-    })
+    Ok(())
 }
 
-/// Canonical encoding of BlockHeaderV1.
+/// Canonical encoding of BlockHeaderV1 (the derived [`Encode`]).
 pub fn encode_block_header_v1(h: &BlockHeaderV1) -> Result<Vec<u8>, CodecError> {
-    let mut out = Vec::new();
-    write_u8(&mut out, h.version as u8);
-    write_u64_le(&mut out, h.height);
-    write_32(&mut out, &h.prev_hash);
-    write_32(&mut out, &h.state_root);
-    write_32(&mut out, &h.tx_root);
-    write_32(&mut out, &h.proposer);
-    write_32(&mut out, &h.qc_hash);
-    Ok(out)
+    h.encode()
 }
 
 pub fn decode_block_header_v1(bytes: &[u8]) -> Result<BlockHeaderV1, CodecError> {
-    let mut input = bytes;
-    let v = read_u8(&mut input)?;
-    let version = BlockHeaderVersion::from_u8(v).ok_or(CodecError::InvalidVersion)?;
-    let height = read_u64_le(&mut input)?;
-    let prev_hash: Hash32 = read_32(&mut input)?;
-    let state_root: Hash32 = read_32(&mut input)?;
-    let tx_root: Hash32 = read_32(&mut input)?;
-    let proposer: Address = read_32(&mut input)?;
-    let qc_hash: Hash32 = read_32(&mut input)?;
-
-    if !input.is_empty() {
-        return Err(CodecError::TrailingBytes);
-    }
-
-    Ok(BlockHeaderV1 {
-        version,
-        height,
-        prev_hash,
-        state_root,
-        tx_root,
-        proposer,
-        qc_hash,
-    })
+    BlockHeaderV1::decode(bytes)
 }
 
 /// Helper: compute TxId as blake3(encode_tx_v1_unsigned(tx))
@@ -196,3 +158,49 @@ pub fn txid_v1(tx: &TxV1) -> Result<TxId, CodecError> {
     out.copy_from_slice(hash.as_bytes());
     Ok(out)
 }
+
+/// Helper: compute the block hash as blake3(encode_block_header_v1(h))
+pub fn block_hash_v1(h: &BlockHeaderV1) -> Result<Hash32, CodecError> {
+    let bytes = encode_block_header_v1(h)?;
+    Ok(*blake3::hash(&bytes).as_bytes())
+}
+
+/// Merkle root over a block's txs, for `BlockHeaderV1::tx_root`.
+///
+/// CONSENSUS-RELEVANT:
+/// - leaf = blake3(0x00 || encode_tx_v1_signed(tx)), in block order
+/// - node = blake3(0x01 || left || right)
+/// - an odd node at the end of a level is promoted unchanged
+/// - an empty block has root [0u8; 32]
+///
+/// Leaves commit to the signed bytes, so the root also pins signatures.
+pub fn tx_root_v1(txs: &[TxV1]) -> Result<Hash32, CodecError> {
+    let mut level = Vec::with_capacity(txs.len());
+    for tx in txs {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&[0x00]);
+        hasher.update(&encode_tx_v1_signed(tx)?);
+        level.push(*hasher.finalize().as_bytes());
+    }
+    if level.is_empty() {
+        return Ok([0u8; 32]);
+    }
+
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => {
+                    let mut hasher = blake3::Hasher::new();
+                    hasher.update(&[0x01]);
+                    hasher.update(left);
+                    hasher.update(right);
+                    *hasher.finalize().as_bytes()
+                }
+                [single] => *single,
+                _ => unreachable!("chunks(2) yields one or two items"),
+            })
+            .collect();
+    }
+    Ok(level[0])
+}
@@ -0,0 +1,122 @@
+//! Resource limits for decoding untrusted bytes.
+//!
+//! Every variable-size decoder has a `*_with` form taking [`DecodeLimits`];
+//! the plain form uses [`DecodeLimits::DEFAULT`]. Every length and count
+//! read from the wire is held to a limit: payloads, txs per block, votes per
+//! QC, items per sync response, and leaves and proof nodes per snapshot
+//! chunk. Counts matter even with the total size capped, since a proof node
+//! is one byte on the wire but far larger in memory. Going over a limit is
+//! [`CodecErrorKind::LimitExceeded`], checked as soon as the offending length
+//! or count is read and before anything is copied for it. The error's offset
+//! is that of the length or count, or `max_total_bytes` for an input that is
+//! too long overall.
+//!
+//! Headers, consensus envelopes, sync requests and handshakes have a fixed
+//! (or, for handshakes, u8-bounded) layout, so they have no limits to apply.
+//!
+//! The defaults are CONSENSUS-RELEVANT in practice: a block that the default
+//! limits reject cannot be synced. Block producers must stay under them.
+
+use crate::{CodecError, CodecErrorKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Largest `TxV1::payload`.
+    pub max_payload_bytes: u32,
+    /// Largest tx count in one block body.
+    pub max_txs_per_block: u32,
+    /// Largest vote count in one QC.
+    pub max_votes_per_qc: u32,
+    /// Most blocks or headers in one sync response.
+    pub max_items_per_response: u32,
+    /// Most snapshot chunks in one sync response.
+    pub max_chunks_per_response: u32,
+    /// Most leaves in one snapshot chunk.
+    pub max_leaves_per_chunk: u32,
+    /// Most nodes in one snapshot chunk's range proof.
+    pub max_proof_nodes_per_chunk: u32,
+    /// Largest input to a single decode call.
+    pub max_total_bytes: usize,
+}
+
+impl DecodeLimits {
+    /// Payloads fit a gossip message, and a whole input fits the largest
+    /// sync response. Sync counts leave room above what nodes serve. A range
+    /// proof has at most two straddling subtrees per level of the 256-level
+    /// tree, each with at most one sibling outside the range.
+    pub const DEFAULT: DecodeLimits = DecodeLimits {
+        max_payload_bytes: 64 * 1024,
+        max_txs_per_block: 65_536,
+        max_votes_per_qc: 1024,
+        max_items_per_response: 1024,
+        max_chunks_per_response: 16,
+        max_leaves_per_chunk: 4096,
+        max_proof_nodes_per_chunk: 4 * 256,
+        max_total_bytes: 16 * 1024 * 1024,
+    };
+
+    /// Most bytes of signed tx encodings one block can carry and still decode
+    /// under these limits, alone in a sync response and with the largest QC
+    /// they allow.
+    pub fn max_block_tx_bytes(&self) -> usize {
+        // envelope and block count, header, tx count, QC without votes
+        let fixed = 2 + 4 + 169 + 4 + 52;
+        let votes = self.max_votes_per_qc as usize * 96;
+        let tx_lengths = self.max_txs_per_block as usize * 4;
+        self.max_total_bytes
+            .saturating_sub(fixed + votes + tx_lengths)
+    }
+
+    pub(crate) fn check_total(&self, bytes: &[u8]) -> Result<(), CodecError> {
+        check(bytes.len() <= self.max_total_bytes, self.max_total_bytes)
+    }
+
+    /// `len` is a payload length read at `offset`.
+    pub(crate) fn check_payload(&self, len: u32, offset: usize) -> Result<(), CodecError> {
+        check(len <= self.max_payload_bytes, offset)
+    }
+
+    /// `count` is a block's tx count read at `offset`.
+    pub(crate) fn check_txs(&self, count: u32, offset: usize) -> Result<(), CodecError> {
+        check(count <= self.max_txs_per_block, offset)
+    }
+
+    /// `count` is a QC's vote count read at `offset`.
+    pub(crate) fn check_votes(&self, count: u32, offset: usize) -> Result<(), CodecError> {
+        check(count <= self.max_votes_per_qc, offset)
+    }
+
+    /// `count` is a sync response's block or header count read at `offset`.
+    pub(crate) fn check_items(&self, count: u32, offset: usize) -> Result<(), CodecError> {
+        check(count <= self.max_items_per_response, offset)
+    }
+
+    /// `count` is a sync response's chunk count read at `offset`.
+    pub(crate) fn check_chunks(&self, count: u32, offset: usize) -> Result<(), CodecError> {
+        check(count <= self.max_chunks_per_response, offset)
+    }
+
+    /// `count` is a chunk's leaf count read at `offset`.
+    pub(crate) fn check_leaves(&self, count: u32, offset: usize) -> Result<(), CodecError> {
+        check(count <= self.max_leaves_per_chunk, offset)
+    }
+
+    /// `count` is a chunk's proof node count read at `offset`.
+    pub(crate) fn check_proof_nodes(&self, count: u32, offset: usize) -> Result<(), CodecError> {
+        check(count <= self.max_proof_nodes_per_chunk, offset)
+    }
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+fn check(ok: bool, offset: usize) -> Result<(), CodecError> {
+    if ok {
+        Ok(())
+    } else {
+        Err(CodecError::new(CodecErrorKind::LimitExceeded, offset))
+    }
+}
@@ -0,0 +1,92 @@
+//! Borrowed view of a canonical signed TxV1.
+//!
+//! [`TxV1Ref::decode`] accepts exactly the bytes [`crate::decode_tx_v1_signed`]
+//! accepts and fails with the same errors, offsets and field paths included,
+//! but points into the input instead of copying it. The unsigned prefix is
+//! kept as a slice, so the txid and the signature message need no
+//! re-encoding. Convert with [`TxV1Ref::to_owned_tx`] once the tx is worth
+//! keeping.
+
+use novai_encoding::{Decode, FieldContext, Reader};
+use novai_types::{Address, Fee, Nonce, SignatureBytes, TxId, TxV1, TxVersion};
+
+use crate::{read_u32_le, read_u64_le, CodecError, DecodeLimits};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxV1Ref<'a> {
+    pub version: TxVersion,
+    pub from: &'a Address,
+    pub nonce: Nonce,
+    pub fee: Fee,
+    pub payload: &'a [u8],
+    pub sig: &'a SignatureBytes,
+    /// `encode_tx_v1_unsigned` of this tx: the input up to the signature.
+    unsigned: &'a [u8],
+}
+
+impl<'a> TxV1Ref<'a> {
+    /// Parse `encode_tx_v1_signed` bytes in place.
+    pub fn decode(bytes: &'a [u8]) -> Result<Self, CodecError> {
+        Self::decode_with(bytes, &DecodeLimits::DEFAULT)
+    }
+
+    pub fn decode_with(bytes: &'a [u8], limits: &DecodeLimits) -> Result<Self, CodecError> {
+        limits.check_total(bytes)?;
+        let mut input = Reader::new(bytes);
+        let version = TxVersion::decode_from(&mut input).in_field("TxV1", "version")?;
+        let from = input
+            .take(32)
+            .in_field("TxV1", "from")?
+            .try_into()
+            .expect("sized take");
+        let nonce = read_u64_le(&mut input).in_field("TxV1", "nonce")?;
+        let fee = read_u64_le(&mut input).in_field("TxV1", "fee")?;
+        let len_at = input.offset();
+        let payload_len = read_u32_le(&mut input).in_field("TxV1", "payload")?;
+        limits
+            .check_payload(payload_len, len_at)
+            .in_field("TxV1", "payload")?;
+        let payload = input
+            .take(payload_len as usize)
+            .in_field("TxV1", "payload")?;
+        let unsigned = &bytes[..input.offset()];
+        let sig = input
+            .take(64)
+            .in_field("TxV1", "sig")?
+            .try_into()
+            .expect("sized take");
+        input.finish()?;
+
+        Ok(TxV1Ref {
+            version,
+            from,
+            nonce,
+            fee,
+            payload,
+            sig,
+            unsigned,
+        })
+    }
+
+    /// The canonical unsigned encoding, i.e. the signed message.
+    pub fn unsigned_bytes(&self) -> &'a [u8] {
+        self.unsigned
+    }
+
+    /// Same as [`crate::txid_v1`] on the owned tx.
+    pub fn txid(&self) -> TxId {
+        *blake3::hash(self.unsigned).as_bytes()
+    }
+
+    /// Copy into an owned [`TxV1`].
+    pub fn to_owned_tx(&self) -> TxV1 {
+        TxV1 {
+            version: self.version,
+            from: *self.from,
+            nonce: self.nonce,
+            fee: self.fee,
+            payload: self.payload.to_vec(),
+            sig: *self.sig,
+        }
+    }
+}
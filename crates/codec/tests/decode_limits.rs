@@ -0,0 +1,305 @@
+use novai_codec::{
+    decode_block_v1, decode_block_v1_with, decode_sync_response_v1, decode_sync_response_v1_with,
+    decode_tx_v1_signed, decode_tx_v1_signed_with, decode_tx_v1_unsigned_with, encode_block_v1,
+    encode_sync_response_v1, encode_tx_v1_signed, encode_tx_v1_unsigned, CodecErrorKind,
+    DecodeLimits, TxV1Ref,
+};
+use novai_types::{
+    BlockHeaderV1, BlockHeaderVersion, BlockV1, QuorumCertV1, RangeProofNodeV1, SnapshotChunkV1,
+    SyncResponseV1, TxV1, TxVersion,
+};
+
+fn tx(payload_len: usize) -> TxV1 {
+    TxV1 {
+        version: TxVersion::V1,
+        from: [0x11u8; 32],
+        nonce: 1,
+        fee: 2,
+        payload: vec![0xAB; payload_len],
+        sig: [0x22u8; 64],
+    }
+}
+
+fn block(txs: Vec<TxV1>) -> BlockV1 {
+    BlockV1 {
+        header: BlockHeaderV1 {
+            version: BlockHeaderVersion::V1,
+            height: 1,
+            prev_hash: [1u8; 32],
+            state_root: [2u8; 32],
+            tx_root: [3u8; 32],
+            proposer: [4u8; 32],
+            qc_hash: [5u8; 32],
+        },
+        txs,
+        qc: QuorumCertV1 {
+            height: 1,
+            round: 0,
+            block_hash: [6u8; 32],
+            votes: vec![([7u8; 32], [8u8; 64])],
+        },
+    }
+}
+
+const TIGHT: DecodeLimits = DecodeLimits {
+    max_payload_bytes: 16,
+    max_txs_per_block: 2,
+    max_votes_per_qc: 2,
+    max_items_per_response: 2,
+    max_chunks_per_response: 1,
+    max_leaves_per_chunk: 2,
+    max_proof_nodes_per_chunk: 4,
+    max_total_bytes: 1024,
+};
+
+#[test]
+fn payload_limit_applies_to_every_tx_decoder() {
+    let at = encode_tx_v1_signed(&tx(16)).unwrap();
+    let over = encode_tx_v1_signed(&tx(17)).unwrap();
+    assert_eq!(decode_tx_v1_signed_with(&at, &TIGHT).unwrap(), tx(16));
+    assert!(TxV1Ref::decode_with(&at, &TIGHT).is_ok());
+    assert_eq!(
+        decode_tx_v1_signed_with(&over, &TIGHT).unwrap_err().kind,
+        CodecErrorKind::LimitExceeded
+    );
+    assert_eq!(
+        TxV1Ref::decode_with(&over, &TIGHT).unwrap_err().kind,
+        CodecErrorKind::LimitExceeded
+    );
+    let unsigned = encode_tx_v1_unsigned(&tx(17)).unwrap();
+    assert_eq!(
+        decode_tx_v1_unsigned_with(&unsigned, &TIGHT)
+            .unwrap_err()
+            .kind,
+        CodecErrorKind::LimitExceeded
+    );
+
+    // The defaults refuse a lying length before reading further, but only
+    // after the version byte.
+    let mut lying = encode_tx_v1_signed(&tx(0)).unwrap();
+    lying[49..53].copy_from_slice(&u32::MAX.to_le_bytes());
+    let err = decode_tx_v1_signed(&lying).unwrap_err();
+    assert_eq!(err.kind, CodecErrorKind::LimitExceeded);
+    assert_eq!((err.offset, err.field.as_str()), (49, "TxV1.payload"));
+    assert_eq!(TxV1Ref::decode(&lying).unwrap_err(), err);
+    lying[0] = 9;
+    assert_eq!(
+        decode_tx_v1_signed(&lying).unwrap_err().kind,
+        CodecErrorKind::InvalidVersion(9)
+    );
+}
+
+#[test]
+fn block_limits_cover_counts_nested_payloads_and_total_size() {
+    let ok = encode_block_v1(&block(vec![tx(1), tx(2)])).unwrap();
+    assert!(decode_block_v1_with(&ok, &TIGHT).is_ok());
+
+    let three = encode_block_v1(&block(vec![tx(1), tx(2), tx(3)])).unwrap();
+    assert_eq!(
+        decode_block_v1_with(&three, &TIGHT).unwrap_err().kind,
+        CodecErrorKind::LimitExceeded
+    );
+    assert!(decode_block_v1(&three).is_ok());
+
+    let fat = encode_block_v1(&block(vec![tx(17)])).unwrap();
+    assert_eq!(
+        decode_block_v1_with(&fat, &TIGHT).unwrap_err().kind,
+        CodecErrorKind::LimitExceeded
+    );
+
+    let big = encode_block_v1(&block(vec![tx(16); 2])).unwrap();
+    let small_total = DecodeLimits {
+        max_total_bytes: big.len() - 1,
+        ..TIGHT
+    };
+    assert_eq!(
+        decode_block_v1_with(&big, &small_total).unwrap_err().kind,
+        CodecErrorKind::LimitExceeded
+    );
+
+    // Limits reach blocks nested in a sync response.
+    let response = encode_sync_response_v1(&SyncResponseV1::Blocks(vec![
+        block(vec![tx(1)]),
+        block(vec![tx(1), tx(2), tx(3)]),
+    ]))
+    .unwrap();
+    assert_eq!(
+        decode_sync_response_v1_with(&response, &TIGHT)
+            .unwrap_err()
+            .kind,
+        CodecErrorKind::LimitExceeded
+    );
+    let generous = DecodeLimits {
+        max_total_bytes: usize::MAX,
+        ..DecodeLimits::default()
+    };
+    assert!(decode_sync_response_v1_with(&response, &generous).is_ok());
+}
+
+#[test]
+fn adversarial_counts_never_allocate_or_panic() {
+    // A block body claiming the maximum tx count with nothing behind it, and
+    // a tx within it claiming a huge payload.
+    let mut header_only = encode_block_v1(&block(Vec::new())).unwrap();
+    header_only.truncate(169);
+    for count in [u32::MAX, DecodeLimits::DEFAULT.max_txs_per_block] {
+        let mut bytes = header_only.clone();
+        bytes.extend_from_slice(&count.to_le_bytes());
+        let expected = if count > DecodeLimits::DEFAULT.max_txs_per_block {
+            CodecErrorKind::LimitExceeded
+        } else {
+            CodecErrorKind::UnexpectedEof
+        };
+        assert_eq!(decode_block_v1(&bytes).unwrap_err().kind, expected);
+    }
+
+    let mut nested = header_only;
+    nested.extend_from_slice(&1u32.to_le_bytes());
+    let mut inner = encode_tx_v1_signed(&tx(0)).unwrap();
+    inner[49..53].copy_from_slice(&u32::MAX.to_le_bytes());
+    nested.extend_from_slice(&(inner.len() as u32).to_le_bytes());
+    nested.extend_from_slice(&inner);
+    // Reported at the inner length field, with the path down to it.
+    let err = decode_block_v1(&nested).unwrap_err();
+    assert_eq!(err.kind, CodecErrorKind::LimitExceeded);
+    assert_eq!(
+        (err.offset, err.field.as_str()),
+        (169 + 4 + 4 + 49, "BlockV1.txs[0].payload")
+    );
+
+    // Every truncation of a valid block is an error, never a panic.
+    let full = encode_block_v1(&block(vec![tx(3), tx(0)])).unwrap();
+    for len in 0..full.len() {
+        assert!(decode_block_v1_with(&full[..len], &TIGHT).is_err());
+    }
+}
+
+fn chunk(leaves: usize, proof: usize) -> SnapshotChunkV1 {
+    SnapshotChunkV1 {
+        start: [0u8; 32],
+        end: [0xFFu8; 32],
+        leaves: (0..leaves).map(|i| ([i as u8; 32], vec![1])).collect(),
+        proof: vec![RangeProofNodeV1::Split; proof],
+    }
+}
+
+#[test]
+fn qc_and_sync_response_counts_are_limited() {
+    let limited = |bytes: &[u8]| {
+        decode_sync_response_v1_with(bytes, &TIGHT)
+            .unwrap_err()
+            .kind
+            == CodecErrorKind::LimitExceeded
+    };
+
+    let mut voted = block(Vec::new());
+    voted.qc.votes = vec![([7u8; 32], [8u8; 64]); 3];
+    let bytes = encode_block_v1(&voted).unwrap();
+    assert_eq!(
+        decode_block_v1_with(&bytes, &TIGHT).unwrap_err().kind,
+        CodecErrorKind::LimitExceeded
+    );
+    voted.qc.votes.pop();
+    assert!(decode_block_v1_with(&encode_block_v1(&voted).unwrap(), &TIGHT).is_ok());
+
+    let blocks = SyncResponseV1::Blocks(vec![block(Vec::new()); 3]);
+    assert!(limited(&encode_sync_response_v1(&blocks).unwrap()));
+    let headers = SyncResponseV1::Headers(vec![block(Vec::new()).header; 3]);
+    assert!(limited(&encode_sync_response_v1(&headers).unwrap()));
+    let chunks = SyncResponseV1::SnapshotChunks(vec![chunk(0, 0); 2]);
+    assert!(limited(&encode_sync_response_v1(&chunks).unwrap()));
+    let leaves = SyncResponseV1::SnapshotChunks(vec![chunk(3, 0)]);
+    assert!(limited(&encode_sync_response_v1(&leaves).unwrap()));
+    let proof = SyncResponseV1::SnapshotChunks(vec![chunk(0, 5)]);
+    assert!(limited(&encode_sync_response_v1(&proof).unwrap()));
+
+    let at = SyncResponseV1::SnapshotChunks(vec![chunk(2, 4)]);
+    let bytes = encode_sync_response_v1(&at).unwrap();
+    assert_eq!(decode_sync_response_v1_with(&bytes, &TIGHT).unwrap(), at);
+}
+
+#[test]
+fn adversarial_sync_counts_are_refused_before_reading_on() {
+    let max = DecodeLimits::DEFAULT;
+
+    // A QC claiming every vote, with nothing behind it.
+    let mut qc = encode_block_v1(&block(Vec::new())).unwrap();
+    let votes_at = qc.len() - 4 - 96;
+    qc.truncate(votes_at);
+    qc.extend_from_slice(&u32::MAX.to_le_bytes());
+    let err = decode_block_v1(&qc).unwrap_err();
+    assert_eq!(err.kind, CodecErrorKind::LimitExceeded);
+    assert_eq!(
+        (err.offset, err.field.as_str()),
+        (votes_at, "BlockV1.qc.votes")
+    );
+
+    // Response item counts, read right after the version and kind bytes.
+    for (kind, field, count) in [
+        (1u8, "SyncResponseV1.blocks", max.max_items_per_response),
+        (2, "SyncResponseV1.headers", max.max_items_per_response),
+        (3, "SyncResponseV1.chunks", max.max_chunks_per_response),
+    ] {
+        let mut bytes = vec![1, kind];
+        bytes.extend_from_slice(&(count + 1).to_le_bytes());
+        let err = decode_sync_response_v1(&bytes).unwrap_err();
+        assert_eq!(err.kind, CodecErrorKind::LimitExceeded);
+        assert_eq!((err.offset, err.field.as_str()), (2, field));
+        bytes[2..6].copy_from_slice(&count.to_le_bytes());
+        assert_eq!(
+            decode_sync_response_v1(&bytes).unwrap_err().kind,
+            CodecErrorKind::UnexpectedEof
+        );
+    }
+
+    // Split nodes are one byte each on the wire: a response packed with
+    // them is refused at the proof count, not expanded into memory.
+    let nodes = max.max_proof_nodes_per_chunk as usize;
+    let packed = SyncResponseV1::SnapshotChunks(vec![chunk(0, nodes + 1)]);
+    let bytes = encode_sync_response_v1(&packed).unwrap();
+    let err = decode_sync_response_v1(&bytes).unwrap_err();
+    assert_eq!(err.kind, CodecErrorKind::LimitExceeded);
+    assert_eq!(
+        (err.offset, err.field.as_str()),
+        (2 + 4 + 64 + 4, "SyncResponseV1.chunks[0].proof")
+    );
+    let full = SyncResponseV1::SnapshotChunks(vec![chunk(0, nodes)]);
+    let bytes = encode_sync_response_v1(&full).unwrap();
+    assert_eq!(decode_sync_response_v1(&bytes).unwrap(), full);
+
+    let mut leaves =
+        encode_sync_response_v1(&SyncResponseV1::SnapshotChunks(vec![chunk(0, 0)])).unwrap();
+    leaves[2 + 4 + 64..2 + 4 + 64 + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    let err = decode_sync_response_v1(&leaves).unwrap_err();
+    assert_eq!(err.kind, CodecErrorKind::LimitExceeded);
+    assert_eq!(
+        (err.offset, err.field.as_str()),
+        (2 + 4 + 64, "SyncResponseV1.chunks[0].leaves")
+    );
+}
+
+#[test]
+fn max_block_tx_bytes_fill_exactly_one_sync_response() {
+    let limits = DecodeLimits {
+        max_txs_per_block: 1,
+        max_votes_per_qc: 3,
+        max_total_bytes: 4096,
+        ..DecodeLimits::DEFAULT
+    };
+    // Signed tx overhead is 117 bytes on top of the payload.
+    let payload = limits.max_block_tx_bytes() - 117;
+    let mut full = block(vec![tx(payload)]);
+    full.qc.votes = vec![([7u8; 32], [8u8; 64]); 3];
+
+    let response = SyncResponseV1::Blocks(vec![full.clone()]);
+    let bytes = encode_sync_response_v1(&response).unwrap();
+    assert_eq!(bytes.len(), limits.max_total_bytes);
+    assert_eq!(
+        decode_sync_response_v1_with(&bytes, &limits).unwrap(),
+        response
+    );
+
+    full.txs = vec![tx(payload + 1)];
+    let bytes = encode_sync_response_v1(&SyncResponseV1::Blocks(vec![full])).unwrap();
+    assert!(decode_sync_response_v1_with(&bytes, &limits).is_err());
+}
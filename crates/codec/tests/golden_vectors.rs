@@ -2,11 +2,17 @@ use std::fs;
 use std::path::Path;
 
 use novai_codec::{
-    decode_block_header_v1, decode_tx_v1_signed, decode_tx_v1_unsigned, encode_block_header_v1,
-    encode_tx_v1_signed, encode_tx_v1_unsigned,
+    consensus_signing_bytes_v1, decode_block_header_v1, decode_block_v1,
+    decode_consensus_message_v1, decode_handshake_v1, decode_sync_request_v1,
+    decode_sync_response_v1, decode_tx_v1_signed, decode_tx_v1_unsigned, encode_block_header_v1,
+    encode_block_v1, encode_consensus_message_v1, encode_handshake_v1, encode_sync_request_v1,
+    encode_sync_response_v1, encode_tx_v1_signed, encode_tx_v1_unsigned, tx_root_v1, txid_v1,
+    CodecErrorKind, Decode, DecodeUnsigned, Encode, EncodeUnsigned, TxV1Ref, VOTE_DOMAIN_V1,
 };
 use novai_types::{
-    Address, BlockHeaderV1, BlockHeaderVersion, Hash32, SignatureBytes, TxV1, TxVersion,
+    Address, BlockHeaderV1, BlockHeaderVersion, BlockV1, ConsensusMessage, HandshakeV1, Hash32,
+    ProposalV1, QuorumCertV1, RangeProofNodeV1, SignatureBytes, SnapshotChunkV1, SyncRequestV1,
+    SyncResponseV1, TimeoutV1, TxV1, TxVersion, VoteV1,
 };
 
 fn write_or_compare(path: &Path, actual: &[u8]) {
@@ -100,3 +106,380 @@ fn golden_vectors_tx_and_header_v1() {
     write_or_compare(tx_signed_path, &signed);
     write_or_compare(header_path, &header_bytes);
 }
+
+#[test]
+fn derived_encodings_match_golden_vectors() {
+    let tx = sample_tx();
+    let header = sample_header();
+    let read = |file: &str| fs::read(Path::new("tests/vectors").join(file)).expect("vector file");
+
+    let unsigned = read("txv1_unsigned.bin");
+    let signed = read("txv1_signed.bin");
+    let header_bytes = read("blockheader_v1.bin");
+    assert_eq!(tx.encode_unsigned().unwrap(), unsigned);
+    assert_eq!(tx.encode().unwrap(), signed);
+    assert_eq!(header.encode().unwrap(), header_bytes);
+    assert_eq!(TxV1::decode(&signed).unwrap(), tx);
+    assert_eq!(
+        TxV1::decode_unsigned(&unsigned).unwrap(),
+        TxV1 {
+            sig: [0u8; 64],
+            ..tx
+        }
+    );
+    assert_eq!(BlockHeaderV1::decode(&header_bytes).unwrap(), header);
+
+    // The version tag leads and is checked before anything else is read.
+    assert_eq!(
+        TxV1::decode(&[2]).unwrap_err().kind,
+        CodecErrorKind::InvalidVersion(2)
+    );
+    assert_eq!(
+        BlockHeaderV1::decode(&[0]).unwrap_err().kind,
+        CodecErrorKind::InvalidVersion(0)
+    );
+    let mut trailing = signed;
+    trailing.push(0);
+    assert_eq!(
+        TxV1::decode(&trailing).unwrap_err().kind,
+        CodecErrorKind::TrailingBytes
+    );
+    assert_eq!(
+        BlockHeaderV1::decode(&header_bytes[..header_bytes.len() - 1])
+            .unwrap_err()
+            .kind,
+        CodecErrorKind::UnexpectedEof
+    );
+
+    // Errors name the field being read and where it starts.
+    let err = BlockHeaderV1::decode(&header_bytes[..80]).unwrap_err();
+    assert_eq!(
+        (err.offset, err.field.as_str()),
+        (1 + 8 + 32 + 32, "BlockHeaderV1.tx_root")
+    );
+    assert_eq!(
+        err.to_string(),
+        "unexpected end of input at byte 73 (BlockHeaderV1.tx_root)"
+    );
+}
+
+#[test]
+fn borrowed_tx_matches_owned_decode() {
+    let tx = sample_tx();
+    let signed = fs::read("tests/vectors/txv1_signed.bin").expect("vector file");
+    let unsigned = fs::read("tests/vectors/txv1_unsigned.bin").expect("vector file");
+
+    let borrowed = TxV1Ref::decode(&signed).expect("decode borrowed");
+    assert_eq!(borrowed.unsigned_bytes(), &unsigned[..]);
+    assert_eq!(borrowed.txid(), txid_v1(&tx).unwrap());
+    assert_eq!(borrowed.to_owned_tx(), tx);
+    // Fields point into the input rather than at copies.
+    assert!(signed.as_ptr_range().contains(&borrowed.payload.as_ptr()));
+
+    // Every prefix, extension and version fails exactly like the owned decoder.
+    for len in 0..signed.len() {
+        assert_eq!(
+            TxV1Ref::decode(&signed[..len]).err(),
+            decode_tx_v1_signed(&signed[..len]).err()
+        );
+    }
+    let mut trailing = signed.clone();
+    trailing.push(0);
+    assert_eq!(
+        TxV1Ref::decode(&trailing).unwrap_err().kind,
+        CodecErrorKind::TrailingBytes
+    );
+    let mut bad = signed;
+    bad[0] = 2;
+    assert_eq!(
+        TxV1Ref::decode(&bad).unwrap_err().kind,
+        CodecErrorKind::InvalidVersion(2)
+    );
+}
+
+#[test]
+fn golden_vector_tx_root_v1() {
+    let txs: Vec<TxV1> = (0..5u64)
+        .map(|i| TxV1 {
+            nonce: i,
+            ..sample_tx()
+        })
+        .collect();
+
+    assert_eq!(tx_root_v1(&[]).expect("empty root"), [0u8; 32]);
+
+    let mut leaf = vec![0x00];
+    leaf.extend_from_slice(&encode_tx_v1_signed(&txs[0]).expect("encode signed"));
+    assert_eq!(
+        tx_root_v1(&txs[..1]).expect("single root"),
+        *blake3::hash(&leaf).as_bytes()
+    );
+
+    // Order and signatures are committed to.
+    let root = tx_root_v1(&txs).expect("root");
+    let mut swapped = txs.clone();
+    swapped.swap(1, 2);
+    assert_ne!(tx_root_v1(&swapped).expect("root"), root);
+    let mut resigned = txs.clone();
+    resigned[4].sig[0] ^= 0x01;
+    assert_ne!(tx_root_v1(&resigned).expect("root"), root);
+
+    write_or_compare(Path::new("tests/vectors/tx_root_v1.bin"), &root);
+}
+
+#[test]
+fn golden_vectors_consensus_envelope_v1() {
+    let messages = [
+        (
+            "consensus_proposal_v1.bin",
+            ConsensusMessage::Proposal(ProposalV1 {
+                round: 3,
+                header: sample_header(),
+                sig: [0x31u8; 64],
+            }),
+        ),
+        (
+            "consensus_vote_v1.bin",
+            ConsensusMessage::Vote(VoteV1 {
+                height: 123,
+                round: 3,
+                block_hash: [0x32u8; 32],
+                voter: [0x33u8; 32],
+                sig: [0x34u8; 64],
+            }),
+        ),
+        (
+            "consensus_timeout_v1.bin",
+            ConsensusMessage::Timeout(TimeoutV1 {
+                height: 123,
+                round: 3,
+                voter: [0x33u8; 32],
+                sig: [0x35u8; 64],
+            }),
+        ),
+    ];
+
+    for (file, msg) in &messages {
+        let bytes = encode_consensus_message_v1(msg).expect("encode envelope");
+        assert_eq!(&decode_consensus_message_v1(&bytes).expect("decode"), msg);
+        write_or_compare(&Path::new("tests/vectors").join(file), &bytes);
+
+        // Signing bytes exclude the sig and the envelope header.
+        let signing = consensus_signing_bytes_v1(msg).expect("signing bytes");
+        let mut resigned = msg.clone();
+        resigned.sig_mut()[0] ^= 0x01;
+        assert_eq!(consensus_signing_bytes_v1(&resigned).unwrap(), signing);
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            decode_consensus_message_v1(&trailing).unwrap_err().kind,
+            CodecErrorKind::TrailingBytes
+        );
+        assert_eq!(
+            decode_consensus_message_v1(&bytes[..bytes.len() - 1])
+                .unwrap_err()
+                .kind,
+            CodecErrorKind::UnexpectedEof
+        );
+    }
+
+    // A vote and a timeout with the same fields still sign different bytes.
+    let vote = consensus_signing_bytes_v1(&messages[1].1).unwrap();
+    let timeout = consensus_signing_bytes_v1(&messages[2].1).unwrap();
+    assert!(vote.starts_with(VOTE_DOMAIN_V1));
+    assert_ne!(vote[..], timeout[..]);
+
+    let mut bytes = encode_consensus_message_v1(&messages[1].1).unwrap();
+    bytes[1] = 9;
+    let err = decode_consensus_message_v1(&bytes).unwrap_err();
+    assert_eq!(err.kind, CodecErrorKind::UnknownKind(9));
+    assert_eq!(
+        (err.offset, err.field.as_str()),
+        (1, "ConsensusMessage.kind")
+    );
+    bytes[0] = 2;
+    assert_eq!(
+        decode_consensus_message_v1(&bytes).unwrap_err().kind,
+        CodecErrorKind::InvalidVersion(2)
+    );
+}
+
+fn sample_block() -> BlockV1 {
+    BlockV1 {
+        header: sample_header(),
+        txs: vec![
+            sample_tx(),
+            TxV1 {
+                nonce: 43,
+                payload: Vec::new(),
+                ..sample_tx()
+            },
+        ],
+        qc: QuorumCertV1 {
+            height: 123,
+            round: 3,
+            block_hash: [0x41u8; 32],
+            votes: vec![([0x42u8; 32], [0x43u8; 64]), ([0x44u8; 32], [0x45u8; 64])],
+        },
+    }
+}
+
+#[test]
+fn golden_vectors_block_and_sync_v1() {
+    let block = sample_block();
+    let bytes = encode_block_v1(&block).expect("encode block");
+    assert_eq!(decode_block_v1(&bytes).expect("decode block"), block);
+    write_or_compare(Path::new("tests/vectors/block_v1.bin"), &bytes);
+
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert_eq!(
+        decode_block_v1(&trailing).unwrap_err().kind,
+        CodecErrorKind::TrailingBytes
+    );
+    assert_eq!(
+        decode_block_v1(&bytes[..bytes.len() - 1]).unwrap_err().kind,
+        CodecErrorKind::UnexpectedEof
+    );
+
+    // A huge claimed tx count is refused instead of allocated for; one
+    // within the limits fails on EOF.
+    let mut lying = encode_block_header_v1(&block.header).unwrap();
+    lying.extend_from_slice(&u32::MAX.to_le_bytes());
+    assert_eq!(
+        decode_block_v1(&lying).unwrap_err().kind,
+        CodecErrorKind::LimitExceeded
+    );
+    lying.truncate(lying.len() - 4);
+    lying.extend_from_slice(&1000u32.to_le_bytes());
+    assert_eq!(
+        decode_block_v1(&lying).unwrap_err().kind,
+        CodecErrorKind::UnexpectedEof
+    );
+
+    let requests = [
+        SyncRequestV1::BlocksByRange {
+            start: 10,
+            count: 32,
+        },
+        SyncRequestV1::BlockByHash([0x46u8; 32]),
+        SyncRequestV1::Headers {
+            start: 10,
+            count: 512,
+        },
+    ];
+    let mut all = Vec::new();
+    for req in &requests {
+        let bytes = encode_sync_request_v1(req).expect("encode request");
+        assert_eq!(
+            &decode_sync_request_v1(&bytes).expect("decode request"),
+            req
+        );
+        all.extend_from_slice(&bytes);
+    }
+    write_or_compare(Path::new("tests/vectors/sync_requests_v1.bin"), &all);
+
+    let responses = [
+        SyncResponseV1::Blocks(vec![block.clone(), block]),
+        SyncResponseV1::Headers(vec![sample_header()]),
+        SyncResponseV1::Blocks(Vec::new()),
+    ];
+    let mut all = Vec::new();
+    for res in &responses {
+        let bytes = encode_sync_response_v1(res).expect("encode response");
+        assert_eq!(
+            &decode_sync_response_v1(&bytes).expect("decode response"),
+            res
+        );
+        all.extend_from_slice(&bytes);
+    }
+    write_or_compare(Path::new("tests/vectors/sync_responses_v1.bin"), &all);
+
+    let mut bad = encode_sync_request_v1(&requests[0]).unwrap();
+    bad[1] = 7;
+    assert_eq!(
+        decode_sync_request_v1(&bad).unwrap_err().kind,
+        CodecErrorKind::UnknownKind(7)
+    );
+}
+
+#[test]
+fn golden_vector_snapshot_sync_v1() {
+    let request = SyncRequestV1::SnapshotChunk {
+        height: 123,
+        start: [0x50u8; 32],
+        max_leaves: 256,
+    };
+    let chunk = SnapshotChunkV1 {
+        start: [0x50u8; 32],
+        end: [0x5fu8; 32],
+        leaves: vec![([0x51u8; 32], vec![1, 2, 3]), ([0x52u8; 32], Vec::new())],
+        proof: vec![
+            RangeProofNodeV1::Subtree([0x53u8; 32]),
+            RangeProofNodeV1::Split,
+            RangeProofNodeV1::Collapsed,
+            RangeProofNodeV1::OutsideLeaf {
+                key: [0x60u8; 32],
+                value_hash: [0x61u8; 32],
+            },
+        ],
+    };
+    let response = SyncResponseV1::SnapshotChunks(vec![chunk]);
+
+    let mut all = encode_sync_request_v1(&request).expect("encode request");
+    assert_eq!(
+        decode_sync_request_v1(&all).expect("decode request"),
+        request
+    );
+    let bytes = encode_sync_response_v1(&response).expect("encode response");
+    assert_eq!(
+        decode_sync_response_v1(&bytes).expect("decode response"),
+        response
+    );
+    all.extend_from_slice(&bytes);
+    write_or_compare(Path::new("tests/vectors/snapshot_sync_v1.bin"), &all);
+
+    // Unknown proof node tag (the first proof node follows the two leaves).
+    let mut bad = bytes;
+    let tag_at = 2 + 4 + 64 + 4 + (32 + 4 + 3) + (32 + 4) + 4;
+    assert_eq!(bad[tag_at], 0);
+    bad[tag_at] = 9;
+    let err = decode_sync_response_v1(&bad).unwrap_err();
+    assert_eq!(err.kind, CodecErrorKind::UnknownKind(9));
+    assert_eq!(
+        (err.offset, err.field.as_str()),
+        (tag_at, "SyncResponseV1.chunks[0].proof[0]")
+    );
+}
+
+#[test]
+fn golden_vector_handshake_v1() {
+    let handshake = HandshakeV1 {
+        chain_id: 0x0102_0304_0506_0708,
+        genesis_hash: [0x70u8; 32],
+        tx_versions: vec![1],
+        header_versions: vec![1, 2],
+        best_finalized_height: 1234,
+    };
+    let bytes = encode_handshake_v1(&handshake).expect("encode handshake");
+    assert_eq!(bytes.len(), 1 + 8 + 32 + 2 + 3 + 8);
+    assert_eq!(
+        decode_handshake_v1(&bytes).expect("decode handshake"),
+        handshake
+    );
+    write_or_compare(Path::new("tests/vectors/handshake_v1.bin"), &bytes);
+
+    let mut bad = bytes.clone();
+    bad[0] = 2;
+    assert_eq!(
+        decode_handshake_v1(&bad).unwrap_err().kind,
+        CodecErrorKind::InvalidVersion(2)
+    );
+    assert_eq!(
+        decode_handshake_v1(&bytes[..bytes.len() - 1])
+            .unwrap_err()
+            .kind,
+        CodecErrorKind::UnexpectedEof
+    );
+}
@@ -0,0 +1,242 @@
+//! Property tests: every value encodes, decodes back to itself, and
+//! re-encodes to the same bytes; the same bytes one short fail to decode.
+//!
+//! A new wire type gets a strategy here and a line in `proptest!` below.
+
+use std::fmt::Debug;
+
+use novai_codec::{
+    decode_block_header_v1, decode_block_v1, decode_consensus_message_v1, decode_handshake_v1,
+    decode_sync_request_v1, decode_sync_response_v1, decode_tx_v1_signed, decode_tx_v1_unsigned,
+    encode_block_header_v1, encode_block_v1, encode_consensus_message_v1, encode_handshake_v1,
+    encode_sync_request_v1, encode_sync_response_v1, encode_tx_v1_signed, encode_tx_v1_unsigned,
+    CodecError, TxV1Ref,
+};
+use novai_types::{
+    BlockHeaderV1, BlockHeaderVersion, BlockV1, ConsensusMessage, HandshakeV1, ProposalV1,
+    QuorumCertV1, RangeProofNodeV1, SnapshotChunkV1, SyncRequestV1, SyncResponseV1, TimeoutV1,
+    TxV1, TxVersion, VoteV1,
+};
+use proptest::array::uniform;
+use proptest::collection::vec;
+use proptest::prelude::*;
+use proptest::test_runner::TestCaseError;
+
+type Encoder<T> = fn(&T) -> Result<Vec<u8>, CodecError>;
+type Decoder<T> = fn(&[u8]) -> Result<T, CodecError>;
+
+fn check_roundtrip<T: PartialEq + Debug>(
+    value: &T,
+    encode: Encoder<T>,
+    decode: Decoder<T>,
+) -> Result<Vec<u8>, TestCaseError> {
+    let bytes = encode(value).map_err(|e| TestCaseError::fail(e.to_string()))?;
+    let decoded = decode(&bytes).map_err(|e| TestCaseError::fail(e.to_string()))?;
+    prop_assert_eq!(&decoded, value);
+    prop_assert_eq!(encode(&decoded).unwrap(), bytes.clone());
+    prop_assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+    Ok(bytes)
+}
+
+fn tx() -> impl Strategy<Value = TxV1> {
+    (
+        uniform(any::<u8>()),
+        any::<u64>(),
+        any::<u64>(),
+        vec(any::<u8>(), 0..256),
+        uniform(any::<u8>()),
+    )
+        .prop_map(|(from, nonce, fee, payload, sig)| TxV1 {
+            version: TxVersion::V1,
+            from,
+            nonce,
+            fee,
+            payload,
+            sig,
+        })
+}
+
+fn header() -> impl Strategy<Value = BlockHeaderV1> {
+    (any::<u64>(), uniform(uniform(any::<u8>()))).prop_map(
+        |(height, [prev_hash, state_root, tx_root, proposer, qc_hash])| BlockHeaderV1 {
+            version: BlockHeaderVersion::V1,
+            height,
+            prev_hash,
+            state_root,
+            tx_root,
+            proposer,
+            qc_hash,
+        },
+    )
+}
+
+fn qc() -> impl Strategy<Value = QuorumCertV1> {
+    (
+        any::<u64>(),
+        any::<u64>(),
+        uniform(any::<u8>()),
+        vec((uniform(any::<u8>()), uniform(any::<u8>())), 0..8),
+    )
+        .prop_map(|(height, round, block_hash, votes)| QuorumCertV1 {
+            height,
+            round,
+            block_hash,
+            votes,
+        })
+}
+
+fn block() -> impl Strategy<Value = BlockV1> {
+    (header(), vec(tx(), 0..4), qc()).prop_map(|(header, txs, qc)| BlockV1 { header, txs, qc })
+}
+
+fn consensus_message() -> impl Strategy<Value = ConsensusMessage> {
+    prop_oneof![
+        (any::<u64>(), header(), uniform(any::<u8>())).prop_map(|(round, header, sig)| {
+            ConsensusMessage::Proposal(ProposalV1 { round, header, sig })
+        }),
+        (
+            any::<u64>(),
+            any::<u64>(),
+            uniform(any::<u8>()),
+            uniform(any::<u8>()),
+            uniform(any::<u8>()),
+        )
+            .prop_map(|(height, round, block_hash, voter, sig)| {
+                ConsensusMessage::Vote(VoteV1 {
+                    height,
+                    round,
+                    block_hash,
+                    voter,
+                    sig,
+                })
+            }),
+        (
+            any::<u64>(),
+            any::<u64>(),
+            uniform(any::<u8>()),
+            uniform(any::<u8>()),
+        )
+            .prop_map(|(height, round, voter, sig)| {
+                ConsensusMessage::Timeout(TimeoutV1 {
+                    height,
+                    round,
+                    voter,
+                    sig,
+                })
+            }),
+    ]
+}
+
+fn sync_request() -> impl Strategy<Value = SyncRequestV1> {
+    prop_oneof![
+        (any::<u64>(), any::<u32>())
+            .prop_map(|(start, count)| SyncRequestV1::BlocksByRange { start, count }),
+        uniform(any::<u8>()).prop_map(SyncRequestV1::BlockByHash),
+        (any::<u64>(), any::<u32>())
+            .prop_map(|(start, count)| SyncRequestV1::Headers { start, count }),
+        (any::<u64>(), uniform(any::<u8>()), any::<u32>()).prop_map(
+            |(height, start, max_leaves)| SyncRequestV1::SnapshotChunk {
+                height,
+                start,
+                max_leaves,
+            }
+        ),
+    ]
+}
+
+fn proof_node() -> impl Strategy<Value = RangeProofNodeV1> {
+    prop_oneof![
+        uniform(any::<u8>()).prop_map(RangeProofNodeV1::Subtree),
+        Just(RangeProofNodeV1::Split),
+        Just(RangeProofNodeV1::Collapsed),
+        (uniform(any::<u8>()), uniform(any::<u8>()))
+            .prop_map(|(key, value_hash)| RangeProofNodeV1::OutsideLeaf { key, value_hash }),
+    ]
+}
+
+fn chunk() -> impl Strategy<Value = SnapshotChunkV1> {
+    (
+        uniform(any::<u8>()),
+        uniform(any::<u8>()),
+        vec((uniform(any::<u8>()), vec(any::<u8>(), 0..64)), 0..8),
+        vec(proof_node(), 0..16),
+    )
+        .prop_map(|(start, end, leaves, proof)| SnapshotChunkV1 {
+            start,
+            end,
+            leaves,
+            proof,
+        })
+}
+
+fn sync_response() -> impl Strategy<Value = SyncResponseV1> {
+    prop_oneof![
+        vec(block(), 0..3).prop_map(SyncResponseV1::Blocks),
+        vec(header(), 0..8).prop_map(SyncResponseV1::Headers),
+        vec(chunk(), 0..2).prop_map(SyncResponseV1::SnapshotChunks),
+    ]
+}
+
+fn handshake() -> impl Strategy<Value = HandshakeV1> {
+    (
+        any::<u64>(),
+        uniform(any::<u8>()),
+        vec(any::<u8>(), 0..8),
+        vec(any::<u8>(), 0..8),
+        any::<u64>(),
+    )
+        .prop_map(
+            |(chain_id, genesis_hash, tx_versions, header_versions, best_finalized_height)| {
+                HandshakeV1 {
+                    chain_id,
+                    genesis_hash,
+                    tx_versions,
+                    header_versions,
+                    best_finalized_height,
+                }
+            },
+        )
+}
+
+proptest! {
+    #[test]
+    fn tx_v1_roundtrips(tx in tx()) {
+        let signed = check_roundtrip(&tx, encode_tx_v1_signed, decode_tx_v1_signed)?;
+        let borrowed = TxV1Ref::decode(&signed).unwrap();
+        prop_assert_eq!(borrowed.to_owned_tx(), tx.clone());
+
+        let unsigned = TxV1 { sig: [0u8; 64], ..tx };
+        let bytes = check_roundtrip(&unsigned, encode_tx_v1_unsigned, decode_tx_v1_unsigned)?;
+        prop_assert_eq!(borrowed.unsigned_bytes(), &bytes[..]);
+    }
+
+    #[test]
+    fn block_header_v1_roundtrips(header in header()) {
+        check_roundtrip(&header, encode_block_header_v1, decode_block_header_v1)?;
+    }
+
+    #[test]
+    fn block_v1_roundtrips(block in block()) {
+        check_roundtrip(&block, encode_block_v1, decode_block_v1)?;
+    }
+
+    #[test]
+    fn consensus_message_v1_roundtrips(msg in consensus_message()) {
+        check_roundtrip(&msg, encode_consensus_message_v1, decode_consensus_message_v1)?;
+    }
+
+    #[test]
+    fn sync_request_v1_roundtrips(request in sync_request()) {
+        check_roundtrip(&request, encode_sync_request_v1, decode_sync_request_v1)?;
+    }
+
+    #[test]
+    fn sync_response_v1_roundtrips(response in sync_response()) {
+        check_roundtrip(&response, encode_sync_response_v1, decode_sync_response_v1)?;
+    }
+
+    #[test]
+    fn handshake_v1_roundtrips(handshake in handshake()) {
+        check_roundtrip(&handshake, encode_handshake_v1, decode_handshake_v1)?;
+    }
+}
@@ -1,10 +1,19 @@
+use std::fmt;
+
 use blake3::Hasher;
+use curve25519_dalek::edwards::CompressedEdwardsY;
 use ed25519_dalek::Signer;
 use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
 use rand_core::OsRng;
 
-use novai_codec::{encode_tx_v1_unsigned, CodecError};
-use novai_types::{Address, SignatureBytes, TxV1};
+use novai_codec::{
+    consensus_signing_bytes_v1, encode_tx_v1_unsigned, txid_v1, CodecError, TxV1Ref,
+};
+use novai_types::{Address, ConsensusMessage, SignatureBytes, TxV1};
+
+pub mod sig_cache;
+
+pub use sig_cache::{SigCache, SigCacheMetrics};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CryptoError {
@@ -12,6 +21,24 @@ pub enum CryptoError {
     Codec(CodecError),
 }
 
+impl fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CryptoError::InvalidPublicKey => write!(f, "invalid public key"),
+            CryptoError::Codec(e) => write!(f, "codec: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for CryptoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CryptoError::InvalidPublicKey => None,
+            CryptoError::Codec(e) => Some(e),
+        }
+    }
+}
+
 pub fn generate_keypair() -> (SigningKey, VerifyingKey) {
     let sk = SigningKey::generate(&mut OsRng);
     let pk = sk.verifying_key();
@@ -38,6 +65,82 @@ pub fn verify_bytes(pk: &VerifyingKey, msg: &[u8], sig: &SignatureBytes) -> bool
     pk.verify_strict(msg, &sig).is_ok()
 }
 
+/// Verify many `(pubkey, message, signature)` items at once.
+///
+/// Result `i` always equals `verify_bytes(items[i])`:
+/// - Items that `verify_strict` would reject outright (weak key, small-order or
+///   non-canonical `R`) are marked invalid without entering the batch.
+/// - Items whose `R` or key has a torsion component are verified
+///   individually: the batch equation can hold for them where the strict one
+///   does not.
+/// - The rest go through ed25519 batch verification; if the batch fails, each
+///   item is re-verified individually to pinpoint the bad ones.
+pub fn verify_bytes_batch(items: &[(VerifyingKey, &[u8], SignatureBytes)]) -> Vec<bool> {
+    let mut out = vec![false; items.len()];
+    let mut candidates = Vec::new();
+    for (i, (pk, msg, sig)) in items.iter().enumerate() {
+        match route(pk, sig) {
+            Route::Reject => {}
+            Route::Single => out[i] = verify_bytes(pk, msg, sig),
+            Route::Batch => candidates.push(i),
+        }
+    }
+
+    if candidates.len() > 1 {
+        let msgs: Vec<&[u8]> = candidates.iter().map(|&i| items[i].1).collect();
+        let sigs: Vec<Signature> = candidates
+            .iter()
+            .map(|&i| Signature::from_bytes(&items[i].2))
+            .collect();
+        let keys: Vec<VerifyingKey> = candidates.iter().map(|&i| items[i].0).collect();
+
+        if ed25519_dalek::verify_batch(&msgs, &sigs, &keys).is_ok() {
+            for i in candidates {
+                out[i] = true;
+            }
+            return out;
+        }
+    }
+
+    for i in candidates {
+        let (pk, msg, sig) = &items[i];
+        out[i] = verify_bytes(pk, msg, sig);
+    }
+    out
+}
+
+/// How [`verify_bytes_batch`] handles one item.
+enum Route {
+    /// `verify_strict` rejects it before the curve equation.
+    Reject,
+    /// Only `verify_strict` itself gives the right answer.
+    Single,
+    /// The batch equation agrees with `verify_strict`.
+    Batch,
+}
+
+fn route(pk: &VerifyingKey, sig: &SignatureBytes) -> Route {
+    if pk.is_weak() {
+        return Route::Reject;
+    }
+    let mut r_bytes = [0u8; 32];
+    r_bytes.copy_from_slice(&sig[..32]);
+    let Some(r) = CompressedEdwardsY(r_bytes).decompress() else {
+        return Route::Reject;
+    };
+    if r.is_small_order() || r.compress().to_bytes() != r_bytes {
+        return Route::Reject;
+    }
+    let a = CompressedEdwardsY(pk.to_bytes())
+        .decompress()
+        .expect("verifying key is a valid point");
+    if r.is_torsion_free() && a.is_torsion_free() {
+        Route::Batch
+    } else {
+        Route::Single
+    }
+}
+
 /// Parse a VerifyingKey from raw 32-byte public key bytes.
 pub fn pubkey_from_bytes(bytes: &[u8; 32]) -> Result<VerifyingKey, CryptoError> {
     VerifyingKey::from_bytes(bytes).map_err(|_| CryptoError::InvalidPublicKey)
@@ -56,12 +159,53 @@ pub fn verify_tx_v1(pk: &VerifyingKey, tx: &TxV1) -> Result<bool, CryptoError> {
     Ok(verify_bytes(pk, &unsigned, &tx.sig))
 }
 
+/// [`verify_tx_v1`] that consults `cache` first and records successes.
+pub fn verify_tx_v1_cached(
+    cache: &SigCache,
+    pk: &VerifyingKey,
+    tx: &TxV1,
+) -> Result<bool, CryptoError> {
+    let unsigned = encode_tx_v1_unsigned(tx).map_err(CryptoError::Codec)?;
+    let txid = txid_v1(tx).map_err(CryptoError::Codec)?;
+    Ok(cache.verify(&txid, pk, &unsigned, &tx.sig))
+}
+
+/// [`verify_tx_v1`] on a borrowed tx: verifies against the input bytes
+/// directly, without encoding or allocating.
+pub fn verify_tx_v1_ref(pk: &VerifyingKey, tx: &TxV1Ref<'_>) -> bool {
+    verify_bytes(pk, tx.unsigned_bytes(), tx.sig)
+}
+
+/// [`verify_tx_v1_ref`] that consults `cache` first and records successes.
+pub fn verify_tx_v1_ref_cached(cache: &SigCache, pk: &VerifyingKey, tx: &TxV1Ref<'_>) -> bool {
+    cache.verify(&tx.txid(), pk, tx.unsigned_bytes(), tx.sig)
+}
+
+/// Sign a proposal, vote or timeout over its domain-separated signing bytes.
+///
+/// The key is not checked against the message's signer field; verification
+/// is what binds the two.
+pub fn sign_consensus_v1(sk: &SigningKey, msg: &mut ConsensusMessage) -> Result<(), CryptoError> {
+    let bytes = consensus_signing_bytes_v1(msg).map_err(CryptoError::Codec)?;
+    *msg.sig_mut() = sign_bytes(sk, &bytes);
+    Ok(())
+}
+
+/// Verify a consensus message's signature under its own signer field
+/// (`header.proposer` for proposals, `voter` otherwise).
+pub fn verify_consensus_v1(msg: &ConsensusMessage) -> Result<bool, CryptoError> {
+    let pk = pubkey_from_bytes(msg.signer())?;
+    let bytes = consensus_signing_bytes_v1(msg).map_err(CryptoError::Codec)?;
+    Ok(verify_bytes(&pk, &bytes, msg.sig()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     use ed25519_dalek::{Signer, SigningKey, Verifier, VerifyingKey};
-    use novai_types::TxVersion;
+    use novai_codec::encode_tx_v1_signed;
+    use novai_types::{TimeoutV1, TxVersion, VoteV1};
 
     #[test]
     fn sign_and_verify_roundtrip() {
@@ -128,8 +272,138 @@ mod tests {
         sign_tx_v1(&sk, &mut tx).unwrap();
         assert!(verify_tx_v1(&pk, &tx).unwrap());
 
+        let bytes = encode_tx_v1_signed(&tx).unwrap();
+        let borrowed = TxV1Ref::decode(&bytes).unwrap();
+        assert!(verify_tx_v1_ref(&pk, &borrowed));
+        let cache = SigCache::new(4);
+        assert!(verify_tx_v1_ref_cached(&cache, &pk, &borrowed));
+        assert!(cache.contains(&txid_v1(&tx).unwrap(), &tx.sig));
+
         // Mutating any unsigned field should break signature
         tx.fee += 1;
         assert!(!verify_tx_v1(&pk, &tx).unwrap());
     }
+
+    #[test]
+    fn batch_verify_matches_single_verify() {
+        let msgs: Vec<Vec<u8>> = (0..8u8).map(|i| vec![i; 3]).collect();
+        let mut items: Vec<(VerifyingKey, &[u8], SignatureBytes)> = msgs
+            .iter()
+            .enumerate()
+            .map(|(i, m)| {
+                let sk = SigningKey::from_bytes(&[i as u8 + 40; 32]);
+                (sk.verifying_key(), m.as_slice(), sign_bytes(&sk, m))
+            })
+            .collect();
+
+        assert_eq!(verify_bytes_batch(&items), vec![true; 8]);
+
+        // Corrupt two entries: a flipped sig bit and a sig from the wrong key.
+        items[2].2[40] ^= 0x01;
+        items[5].0 = items[6].0;
+
+        let batch = verify_bytes_batch(&items);
+        let single: Vec<bool> = items
+            .iter()
+            .map(|(pk, m, sig)| verify_bytes(pk, m, sig))
+            .collect();
+        assert_eq!(batch, single);
+        assert_eq!(batch.iter().filter(|ok| !**ok).count(), 2);
+        assert!(!batch[2] && !batch[5]);
+    }
+
+    #[test]
+    fn batch_verify_rejects_small_order_r_like_strict() {
+        let sk = SigningKey::from_bytes(&[50u8; 32]);
+        let pk = sk.verifying_key();
+        let msg = b"m";
+
+        // R = identity (small order), s = 0.
+        let mut sig = [0u8; 64];
+        sig[0] = 1;
+
+        let good = sign_bytes(&sk, msg);
+        let items = vec![(pk, &msg[..], sig), (pk, &msg[..], good)];
+        assert_eq!(verify_bytes_batch(&items), vec![false, true]);
+        assert!(!verify_bytes(&pk, msg, &sig));
+    }
+
+    #[test]
+    fn batch_verify_matches_strict_on_mixed_order_r() {
+        use curve25519_dalek::constants::{ED25519_BASEPOINT_POINT, EIGHT_TORSION};
+        use curve25519_dalek::Scalar;
+        use sha2::{Digest, Sha512};
+
+        // Signatures whose R carries an order-8 component T, with s computed
+        // for R - T: strict verification rejects them, but the batch
+        // equation holds whenever its (input-derived) weight for the item
+        // is a multiple of 8.
+        let sk = SigningKey::from_bytes(&[60u8; 32]);
+        let pk = sk.verifying_key();
+        let msg = b"mixed";
+        let good = sign_bytes(&sk, msg);
+        let mixed = |nonce: u64| {
+            let nonce = Scalar::from(nonce);
+            let r = (ED25519_BASEPOINT_POINT * nonce + EIGHT_TORSION[1]).compress();
+            let k = Scalar::from_bytes_mod_order_wide(
+                &Sha512::new()
+                    .chain_update(r.as_bytes())
+                    .chain_update(pk.as_bytes())
+                    .chain_update(msg)
+                    .finalize()
+                    .into(),
+            );
+            let mut sig = [0u8; 64];
+            sig[..32].copy_from_slice(r.as_bytes());
+            sig[32..].copy_from_slice((nonce + k * sk.to_scalar()).as_bytes());
+            sig
+        };
+
+        let sig = (1..256u64)
+            .map(mixed)
+            .find(|sig| {
+                let sigs = [Signature::from_bytes(sig), Signature::from_bytes(&good)];
+                ed25519_dalek::verify_batch(&[msg, msg], &sigs, &[pk, pk]).is_ok()
+            })
+            .expect("a mixed-order R the raw batch accepts");
+        assert!(!verify_bytes(&pk, msg, &sig));
+
+        let items = vec![(pk, &msg[..], sig), (pk, &msg[..], good)];
+        assert_eq!(verify_bytes_batch(&items), vec![false, true]);
+    }
+
+    #[test]
+    fn consensus_signatures_are_domain_separated() {
+        let sk = SigningKey::from_bytes(&[11u8; 32]);
+        let voter = sk.verifying_key().to_bytes();
+
+        let mut vote = ConsensusMessage::Vote(VoteV1 {
+            height: 4,
+            round: 1,
+            block_hash: [0u8; 32],
+            voter,
+            sig: [0u8; 64],
+        });
+        sign_consensus_v1(&sk, &mut vote).unwrap();
+        assert!(verify_consensus_v1(&vote).unwrap());
+
+        // Same signer and fields, other kind: the vote signature is useless.
+        let timeout = ConsensusMessage::Timeout(TimeoutV1 {
+            height: 4,
+            round: 1,
+            voter,
+            sig: *vote.sig(),
+        });
+        assert!(!verify_consensus_v1(&timeout).unwrap());
+
+        // Nor does it verify as a plain signature over the body.
+        let bytes = consensus_signing_bytes_v1(&vote).unwrap();
+        let body = &bytes[novai_codec::VOTE_DOMAIN_V1.len()..];
+        assert!(!verify_bytes(&sk.verifying_key(), body, vote.sig()));
+
+        if let ConsensusMessage::Vote(v) = &mut vote {
+            v.round += 1;
+        }
+        assert!(!verify_consensus_v1(&vote).unwrap());
+    }
 }
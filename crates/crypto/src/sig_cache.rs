@@ -0,0 +1,278 @@
+//! Bounded LRU cache of tx signatures that already passed strict verification.
+//!
+//! Shared (via `Arc`) between mempool admission and block validation so a tx
+//! is verified once on its way from gossip into a block.
+//!
+//! Safety rules:
+//! - Only signatures that passed `verify_bytes` (strict) are ever inserted,
+//!   checked either by it directly or by `verify_bytes_batch`, whose
+//!   per-item answers are the same. Callers outside this module go through
+//!   [`SigCache::verify`] or [`SigCache::verify_misses`].
+//! - A hit requires the exact signature bytes stored for that txid. The txid
+//!   is blake3 of the canonical unsigned bytes, which include `from`, so
+//!   (txid, sig) pins down the full (pubkey, message, signature) triple.
+//! - A different signature for a cached txid is a miss and must be verified.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use ed25519_dalek::VerifyingKey;
+use novai_types::{SignatureBytes, TxId};
+
+use crate::{verify_bytes, verify_bytes_batch};
+
+/// Hit/miss counters for a [`SigCache`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SigCacheMetrics {
+    pub hits: u64,
+    pub misses: u64,
+    pub insertions: u64,
+    pub evictions: u64,
+}
+
+pub struct SigCache {
+    capacity: usize,
+    inner: Mutex<Lru>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    insertions: AtomicU64,
+    evictions: AtomicU64,
+}
+
+/// Recency is a monotonically increasing tick; the smallest tick is evicted.
+#[derive(Default)]
+struct Lru {
+    tick: u64,
+    entries: HashMap<TxId, (SignatureBytes, u64)>,
+    by_tick: BTreeMap<u64, TxId>,
+}
+
+impl Lru {
+    fn touch(&mut self, txid: &TxId) {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some((_, t)) = self.entries.get_mut(txid) {
+            self.by_tick.remove(t);
+            *t = tick;
+            self.by_tick.insert(tick, *txid);
+        }
+    }
+
+    fn remove(&mut self, txid: &TxId) -> bool {
+        match self.entries.remove(txid) {
+            Some((_, t)) => {
+                self.by_tick.remove(&t);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl SigCache {
+    /// Create a cache holding at most `capacity` entries (minimum 1).
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            inner: Mutex::new(Lru::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            insertions: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    /// True if exactly this (txid, sig) pair was previously verified.
+    pub fn contains(&self, txid: &TxId, sig: &SignatureBytes) -> bool {
+        let mut lru = self.inner.lock().expect("sig cache lock");
+        let hit = matches!(lru.entries.get(txid), Some((cached, _)) if cached == sig);
+        if hit {
+            lru.touch(txid);
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+        hit
+    }
+
+    /// Record a (txid, sig) pair that passed strict verification.
+    ///
+    /// Only called once `verify_bytes` (or the batch check, whose per-item
+    /// answers match it) succeeded; outside callers go through
+    /// [`SigCache::verify`] or [`SigCache::verify_misses`].
+    pub(crate) fn insert(&self, txid: TxId, sig: SignatureBytes) {
+        let mut lru = self.inner.lock().expect("sig cache lock");
+        lru.remove(&txid);
+
+        while lru.entries.len() >= self.capacity {
+            let Some((_, oldest)) = lru.by_tick.pop_first() else {
+                break;
+            };
+            lru.entries.remove(&oldest);
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+
+        lru.tick += 1;
+        let tick = lru.tick;
+        lru.entries.insert(txid, (sig, tick));
+        lru.by_tick.insert(tick, txid);
+        self.insertions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Verify `sig` over `msg`, answering from the cache when possible and
+    /// caching successful verifications.
+    ///
+    /// `txid` must be the id of the tx whose canonical unsigned bytes are `msg`.
+    pub fn verify(&self, txid: &TxId, pk: &VerifyingKey, msg: &[u8], sig: &SignatureBytes) -> bool {
+        if self.contains(txid, sig) {
+            return true;
+        }
+        let ok = verify_bytes(pk, msg, sig);
+        if ok {
+            self.insert(*txid, *sig);
+        }
+        ok
+    }
+
+    /// [`SigCache::verify`] for many `(txid, pk, msg, sig)` items that already
+    /// missed the cache, sharing the work as one batch. Results are in input
+    /// order and equal `verify_bytes` on each item; accepted ones are cached.
+    pub fn verify_misses(
+        &self,
+        items: &[(TxId, VerifyingKey, &[u8], SignatureBytes)],
+    ) -> Vec<bool> {
+        let batch: Vec<(VerifyingKey, &[u8], SignatureBytes)> = items
+            .iter()
+            .map(|(_, pk, msg, sig)| (*pk, *msg, *sig))
+            .collect();
+        let results = verify_bytes_batch(&batch);
+        for ((txid, _, _, sig), ok) in items.iter().zip(&results) {
+            if *ok {
+                self.insert(*txid, *sig);
+            }
+        }
+        results
+    }
+
+    /// Drop the cached entry for `txid`, if any.
+    pub fn invalidate(&self, txid: &TxId) -> bool {
+        self.inner.lock().expect("sig cache lock").remove(txid)
+    }
+
+    /// Drop every entry (e.g. when verification rules change).
+    pub fn clear(&self) {
+        let mut lru = self.inner.lock().expect("sig cache lock");
+        lru.entries.clear();
+        lru.by_tick.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().expect("sig cache lock").entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn metrics(&self) -> SigCacheMetrics {
+        SigCacheMetrics {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            insertions: self.insertions.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ed25519_dalek::SigningKey;
+
+    use crate::sign_bytes;
+
+    #[test]
+    fn lru_evicts_least_recently_used() {
+        let cache = SigCache::new(2);
+        cache.insert([1u8; 32], [1u8; 64]);
+        cache.insert([2u8; 32], [2u8; 64]);
+
+        // Touch 1 so 2 becomes the eviction candidate.
+        assert!(cache.contains(&[1u8; 32], &[1u8; 64]));
+        cache.insert([3u8; 32], [3u8; 64]);
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.contains(&[1u8; 32], &[1u8; 64]));
+        assert!(!cache.contains(&[2u8; 32], &[2u8; 64]));
+        assert!(cache.contains(&[3u8; 32], &[3u8; 64]));
+
+        let m = cache.metrics();
+        assert_eq!((m.hits, m.misses), (3, 1));
+        assert_eq!((m.insertions, m.evictions), (3, 1));
+    }
+
+    #[test]
+    fn hit_requires_exact_signature() {
+        let sk = SigningKey::from_bytes(&[21u8; 32]);
+        let pk = sk.verifying_key();
+        let msg = b"unsigned tx bytes";
+        let txid = *blake3::hash(msg).as_bytes();
+        let sig = sign_bytes(&sk, msg);
+
+        let cache = SigCache::new(8);
+        assert!(cache.verify(&txid, &pk, msg, &sig));
+        assert!(cache.contains(&txid, &sig));
+
+        // Same txid, tampered signature: must miss and fail real verification.
+        let mut bad = sig;
+        bad[0] ^= 0x01;
+        assert!(!cache.contains(&txid, &bad));
+        assert!(!cache.verify(&txid, &pk, msg, &bad));
+
+        // The failed verification never replaced the good entry.
+        assert!(cache.contains(&txid, &sig));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn batch_misses_cache_only_what_strict_accepts() {
+        let msgs: Vec<Vec<u8>> = (0..3u8).map(|i| vec![i; 4]).collect();
+        let mut items: Vec<(TxId, VerifyingKey, &[u8], SignatureBytes)> = msgs
+            .iter()
+            .enumerate()
+            .map(|(i, m)| {
+                let sk = SigningKey::from_bytes(&[i as u8 + 30; 32]);
+                let sig = sign_bytes(&sk, m);
+                (
+                    *blake3::hash(m).as_bytes(),
+                    sk.verifying_key(),
+                    m.as_slice(),
+                    sig,
+                )
+            })
+            .collect();
+        items[1].3[10] ^= 0x01;
+
+        let cache = SigCache::new(8);
+        assert_eq!(cache.verify_misses(&items), vec![true, false, true]);
+        assert_eq!(cache.len(), 2);
+        assert!(cache.contains(&items[0].0, &items[0].3));
+        assert!(!cache.contains(&items[1].0, &items[1].3));
+    }
+
+    #[test]
+    fn invalidate_and_clear() {
+        let cache = SigCache::new(4);
+        cache.insert([1u8; 32], [1u8; 64]);
+        cache.insert([2u8; 32], [2u8; 64]);
+
+        assert!(cache.invalidate(&[1u8; 32]));
+        assert!(!cache.invalidate(&[1u8; 32]));
+        assert!(!cache.contains(&[1u8; 32], &[1u8; 64]));
+
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,279 @@
+//! novai-encoding-derive
+//!
+//! Purpose: `#[derive(Encode, Decode)]` for `novai-encoding`. Use it through
+//! the re-exports in `novai-encoding` (or `novai-codec`).
+//! Invariants: the generated encoding is the fields in declared order, each
+//! with its own canonical encoding, and nothing else. Supported shapes:
+//!
+//! - structs with named fields. A field marked `#[codec(signature)]` (a byte
+//!   array) is left out of the unsigned form: the derives then also
+//!   implement `EncodeUnsigned` / `DecodeUnsigned`, and unsigned decoding
+//!   zeroes it.
+//! - fieldless `#[repr(u8)]` enums, encoded as their discriminant byte. An
+//!   unknown byte is `CodecErrorKind::UnknownKind`, or
+//!   `CodecErrorKind::InvalidVersion` if the enum is marked
+//!   `#[codec(version)]`, carrying the byte.
+//!
+//! Errors from a struct field are tagged with its path (`Type.field`).
+//!
+//! Failure modes: anything else is a compile error at the derive site.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, Fields, Ident};
+
+#[proc_macro_derive(Encode, attributes(codec))]
+pub fn derive_encode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input, encode).unwrap_or_else(|e| e.to_compile_error().into())
+}
+
+#[proc_macro_derive(Decode, attributes(codec))]
+pub fn derive_decode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input, decode).unwrap_or_else(|e| e.to_compile_error().into())
+}
+
+/// Whether `attrs` contain `#[codec(<flag>)]`; any other `codec` flag is
+/// an error.
+fn has_flag(attrs: &[Attribute], flag: &str) -> syn::Result<bool> {
+    let mut found = false;
+    for attr in attrs.iter().filter(|a| a.path().is_ident("codec")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident(flag) {
+                found = true;
+                Ok(())
+            } else {
+                Err(meta.error("unsupported codec attribute"))
+            }
+        })?;
+    }
+    Ok(found)
+}
+
+/// A struct's field names, types and which one (if any) is the signature.
+struct Shape<'a> {
+    names: Vec<&'a Ident>,
+    types: Vec<&'a syn::Type>,
+    signature: Option<usize>,
+}
+
+fn struct_shape(input: &DeriveInput) -> syn::Result<Option<Shape<'_>>> {
+    let Data::Struct(data) = &input.data else {
+        return Ok(None);
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "only structs with named fields can derive Encode/Decode",
+        ));
+    };
+    let mut shape = Shape {
+        names: Vec::new(),
+        types: Vec::new(),
+        signature: None,
+    };
+    for (i, field) in fields.named.iter().enumerate() {
+        if has_flag(&field.attrs, "signature")? && shape.signature.replace(i).is_some() {
+            return Err(syn::Error::new_spanned(
+                field,
+                "at most one #[codec(signature)] field",
+            ));
+        }
+        shape.names.push(field.ident.as_ref().expect("named field"));
+        shape.types.push(&field.ty);
+    }
+    Ok(Some(shape))
+}
+
+/// Variant names of a fieldless enum.
+fn enum_variants(input: &DeriveInput) -> syn::Result<Vec<&Ident>> {
+    let Data::Enum(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "Encode/Decode can be derived for structs and fieldless enums only",
+        ));
+    };
+    let repr_u8 = input.attrs.iter().any(|a| {
+        a.path().is_ident("repr") && a.parse_args::<Ident>().is_ok_and(|repr| repr == "u8")
+    });
+    if !repr_u8 {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "enums must be #[repr(u8)] to derive Encode/Decode",
+        ));
+    }
+    data.variants
+        .iter()
+        .map(|v| match v.fields {
+            Fields::Unit => Ok(&v.ident),
+            _ => Err(syn::Error::new_spanned(
+                v,
+                "enum variants must be fieldless",
+            )),
+        })
+        .collect()
+}
+
+/// The type name and field names as string literals, for error paths.
+fn labels(ident: &Ident, names: &[&Ident]) -> (String, Vec<String>) {
+    (
+        ident.to_string(),
+        names.iter().map(|name| name.to_string()).collect(),
+    )
+}
+
+fn expand(
+    input: &DeriveInput,
+    f: fn(&DeriveInput) -> syn::Result<TokenStream2>,
+) -> syn::Result<TokenStream> {
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "generic types cannot derive Encode/Decode",
+        ));
+    }
+    f(input).map(Into::into)
+}
+
+fn encode(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let ident = &input.ident;
+    let Some(shape) = struct_shape(input)? else {
+        enum_variants(input)?;
+        has_flag(&input.attrs, "version")?;
+        return Ok(quote! {
+            impl ::novai_encoding::Encode for #ident {
+                fn encode_to(
+                    &self,
+                    out: &mut ::std::vec::Vec<u8>,
+                ) -> ::core::result::Result<(), ::novai_encoding::CodecError> {
+                    ::novai_encoding::Encode::encode_to(&(*self as u8), out)
+                }
+            }
+        });
+    };
+    let names = &shape.names;
+    let (ty, labels) = labels(ident, names);
+    let mut out = quote! {
+        impl ::novai_encoding::Encode for #ident {
+            fn encode_to(
+                &self,
+                out: &mut ::std::vec::Vec<u8>,
+            ) -> ::core::result::Result<(), ::novai_encoding::CodecError> {
+                #(
+                    ::novai_encoding::FieldContext::in_field(
+                        ::novai_encoding::Encode::encode_to(&self.#names, out),
+                        #ty,
+                        #labels,
+                    )?;
+                )*
+                ::core::result::Result::Ok(())
+            }
+        }
+    };
+    if let Some(sig) = shape.signature {
+        let (unsigned, unsigned_labels): (Vec<&Ident>, Vec<&String>) = names
+            .iter()
+            .zip(&labels)
+            .enumerate()
+            .filter(|(i, _)| *i != sig)
+            .map(|(_, pair)| pair)
+            .unzip();
+        out.extend(quote! {
+            impl ::novai_encoding::EncodeUnsigned for #ident {
+                fn encode_unsigned_to(
+                    &self,
+                    out: &mut ::std::vec::Vec<u8>,
+                ) -> ::core::result::Result<(), ::novai_encoding::CodecError> {
+                    #(
+                        ::novai_encoding::FieldContext::in_field(
+                            ::novai_encoding::Encode::encode_to(&self.#unsigned, out),
+                            #ty,
+                            #unsigned_labels,
+                        )?;
+                    )*
+                    ::core::result::Result::Ok(())
+                }
+            }
+        });
+    }
+    Ok(out)
+}
+
+fn decode(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let ident = &input.ident;
+    let Some(shape) = struct_shape(input)? else {
+        let variants = enum_variants(input)?;
+        let unknown = if has_flag(&input.attrs, "version")? {
+            quote!(InvalidVersion)
+        } else {
+            quote!(UnknownKind)
+        };
+        return Ok(quote! {
+            impl ::novai_encoding::Decode for #ident {
+                fn decode_from(
+                    input: &mut ::novai_encoding::Reader<'_>,
+                ) -> ::core::result::Result<Self, ::novai_encoding::CodecError> {
+                    let at = input.offset();
+                    let tag = <u8 as ::novai_encoding::Decode>::decode_from(input)?;
+                    #(
+                        if tag == #ident::#variants as u8 {
+                            return ::core::result::Result::Ok(#ident::#variants);
+                        }
+                    )*
+                    ::core::result::Result::Err(::novai_encoding::CodecError::new(
+                        ::novai_encoding::CodecErrorKind::#unknown(tag),
+                        at,
+                    ))
+                }
+            }
+        });
+    };
+    let (names, types) = (&shape.names, &shape.types);
+    let (ty, labels) = labels(ident, names);
+    let mut out = quote! {
+        impl ::novai_encoding::Decode for #ident {
+            fn decode_from(
+                input: &mut ::novai_encoding::Reader<'_>,
+            ) -> ::core::result::Result<Self, ::novai_encoding::CodecError> {
+                #(
+                    let #names = ::novai_encoding::FieldContext::in_field(
+                        <#types as ::novai_encoding::Decode>::decode_from(input),
+                        #ty,
+                        #labels,
+                    )?;
+                )*
+                ::core::result::Result::Ok(#ident { #( #names ),* })
+            }
+        }
+    };
+    if let Some(sig) = shape.signature {
+        let fields = names.iter().zip(types).zip(&labels).enumerate().map(
+            |(i, ((name, field_ty), label))| {
+                if i == sig {
+                    quote! { let #name: #field_ty = ::core::array::from_fn(|_| 0u8); }
+                } else {
+                    quote! {
+                        let #name = ::novai_encoding::FieldContext::in_field(
+                            <#field_ty as ::novai_encoding::Decode>::decode_from(input),
+                            #ty,
+                            #label,
+                        )?;
+                    }
+                }
+            },
+        );
+        out.extend(quote! {
+            impl ::novai_encoding::DecodeUnsigned for #ident {
+                fn decode_unsigned_from(
+                    input: &mut ::novai_encoding::Reader<'_>,
+                ) -> ::core::result::Result<Self, ::novai_encoding::CodecError> {
+                    #( #fields )*
+                    ::core::result::Result::Ok(#ident { #( #names ),* })
+                }
+            }
+        });
+    }
+    Ok(out)
+}
@@ -0,0 +1,375 @@
+//! novai-encoding
+//!
+//! Purpose: the canonical encoding traits, [`Encode`] and [`Decode`], and
+//! their derive. `novai-types` derives them on protocol types;
+//! `novai-codec` re-exports them next to the formats still written by hand.
+//! Invariants: CONSENSUS-RELEVANT, changing any rule below is a hard fork.
+//!
+//! ```text
+//! u8, u32, u64      little endian, fixed width
+//! [u8; N]           the N bytes
+//! Vec<u8>           u32 LE length || bytes
+//! derived struct    each field in declared order, nothing in between
+//! derived enum      the #[repr(u8)] discriminant byte
+//! ```
+//!
+//! Failure modes: decoding untrusted bytes returns [`CodecError`], never
+//! panics. Errors say where decoding stopped: the byte offset into the
+//! input passed to `decode` and, inside a derived or annotated decoder, the
+//! path of the field being read.
+
+use std::fmt;
+
+// Lets the derives' `::novai_encoding` paths resolve inside this crate.
+extern crate self as novai_encoding;
+
+pub use novai_encoding_derive::{Decode, Encode};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodecErrorKind {
+    UnexpectedEof,
+    TrailingBytes,
+    /// Version byte that this build does not know.
+    InvalidVersion(u8),
+    LengthOverflow,
+    /// Envelope kind or tag byte that this version does not know.
+    UnknownKind(u8),
+    /// A length, count or total size is over the decoder's configured limit.
+    LimitExceeded,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodecError {
+    pub kind: CodecErrorKind,
+    /// Where the failing field (or the trailing bytes, or the over-limit
+    /// length) starts in the decoded input. 0 for encoding errors.
+    pub offset: usize,
+    /// Path of the field being encoded or decoded, e.g.
+    /// `BlockHeaderV1.tx_root`. Empty outside any named field.
+    pub field: String,
+}
+
+impl CodecError {
+    pub fn new(kind: CodecErrorKind, offset: usize) -> Self {
+        Self {
+            kind,
+            offset,
+            field: String::new(),
+        }
+    }
+
+    /// Record that this error happened inside `ty.field`. A path already set
+    /// by the field's own type is kept under it, without repeating the inner
+    /// type name: `ProposalV1.header` over `BlockHeaderV1.tx_root` gives
+    /// `ProposalV1.header.tx_root`.
+    pub fn in_field(mut self, ty: &str, field: &str) -> Self {
+        let inner = match self.field.split_once('.') {
+            Some((_, rest)) => format!(".{rest}"),
+            None => String::new(),
+        };
+        self.field = format!("{ty}.{field}{inner}");
+        self
+    }
+}
+
+impl From<CodecErrorKind> for CodecError {
+    fn from(kind: CodecErrorKind) -> Self {
+        Self::new(kind, 0)
+    }
+}
+
+impl fmt::Display for CodecErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecErrorKind::UnexpectedEof => write!(f, "unexpected end of input"),
+            CodecErrorKind::TrailingBytes => write!(f, "trailing bytes"),
+            CodecErrorKind::InvalidVersion(v) => write!(f, "invalid version {v}"),
+            CodecErrorKind::LengthOverflow => write!(f, "length does not fit in u32"),
+            CodecErrorKind::UnknownKind(k) => write!(f, "unknown kind {k}"),
+            CodecErrorKind::LimitExceeded => write!(f, "decode limit exceeded"),
+        }
+    }
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at byte {}", self.kind, self.offset)?;
+        if !self.field.is_empty() {
+            write!(f, " ({})", self.field)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+/// [`CodecError::in_field`] on a result.
+pub trait FieldContext<T> {
+    fn in_field(self, ty: &str, field: &str) -> Result<T, CodecError>;
+}
+
+impl<T> FieldContext<T> for Result<T, CodecError> {
+    fn in_field(self, ty: &str, field: &str) -> Result<T, CodecError> {
+        self.map_err(|e| e.in_field(ty, field))
+    }
+}
+
+/// Cursor over the input being decoded. Offsets are absolute: a reader
+/// split off with [`Reader::sub`] reports positions in the outer input.
+#[derive(Debug, Clone)]
+pub struct Reader<'a> {
+    input: &'a [u8],
+    pos: usize,
+    base: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(input: &'a [u8]) -> Self {
+        Self {
+            input,
+            pos: 0,
+            base: 0,
+        }
+    }
+
+    /// Offset of the next unread byte.
+    pub fn offset(&self) -> usize {
+        self.base + self.pos
+    }
+
+    /// The unread bytes.
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.input[self.pos..]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.input.len()
+    }
+
+    /// An error of `kind` at the current offset.
+    pub fn error(&self, kind: CodecErrorKind) -> CodecError {
+        CodecError::new(kind, self.offset())
+    }
+
+    /// The next `n` bytes.
+    pub fn take(&mut self, n: usize) -> Result<&'a [u8], CodecError> {
+        if self.input.len() - self.pos < n {
+            return Err(self.error(CodecErrorKind::UnexpectedEof));
+        }
+        let out = &self.input[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(out)
+    }
+
+    /// A reader over the next `n` bytes, which it must consume exactly.
+    pub fn sub(&mut self, n: usize) -> Result<Reader<'a>, CodecError> {
+        let base = self.offset();
+        Ok(Reader {
+            input: self.take(n)?,
+            pos: 0,
+            base,
+        })
+    }
+
+    /// Fail with `TrailingBytes` unless everything has been read.
+    pub fn finish(&self) -> Result<(), CodecError> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self.error(CodecErrorKind::TrailingBytes))
+        }
+    }
+}
+
+/// Canonical encoding.
+pub trait Encode {
+    /// Append the encoding to `out`.
+    fn encode_to(&self, out: &mut Vec<u8>) -> Result<(), CodecError>;
+
+    fn encode(&self) -> Result<Vec<u8>, CodecError> {
+        let mut out = Vec::new();
+        self.encode_to(&mut out)?;
+        Ok(out)
+    }
+}
+
+/// Inverse of [`Encode`].
+pub trait Decode: Sized {
+    /// Read one value from `input`, advancing it.
+    fn decode_from(input: &mut Reader<'_>) -> Result<Self, CodecError>;
+
+    /// Decode exactly one value; leftover bytes are an error.
+    fn decode(bytes: &[u8]) -> Result<Self, CodecError> {
+        let mut input = Reader::new(bytes);
+        let value = Self::decode_from(&mut input)?;
+        input.finish()?;
+        Ok(value)
+    }
+}
+
+/// The encoding a signature is computed over: every field but the one
+/// marked `#[codec(signature)]`.
+pub trait EncodeUnsigned {
+    fn encode_unsigned_to(&self, out: &mut Vec<u8>) -> Result<(), CodecError>;
+
+    fn encode_unsigned(&self) -> Result<Vec<u8>, CodecError> {
+        let mut out = Vec::new();
+        self.encode_unsigned_to(&mut out)?;
+        Ok(out)
+    }
+}
+
+/// Inverse of [`EncodeUnsigned`]; the signature field decodes as zeros.
+pub trait DecodeUnsigned: Sized {
+    fn decode_unsigned_from(input: &mut Reader<'_>) -> Result<Self, CodecError>;
+
+    fn decode_unsigned(bytes: &[u8]) -> Result<Self, CodecError> {
+        let mut input = Reader::new(bytes);
+        let value = Self::decode_unsigned_from(&mut input)?;
+        input.finish()?;
+        Ok(value)
+    }
+}
+
+macro_rules! int {
+    ($($t:ty),*) => {$(
+        impl Encode for $t {
+            fn encode_to(&self, out: &mut Vec<u8>) -> Result<(), CodecError> {
+                out.extend_from_slice(&self.to_le_bytes());
+                Ok(())
+            }
+        }
+
+        impl Decode for $t {
+            fn decode_from(input: &mut Reader<'_>) -> Result<Self, CodecError> {
+                let bytes = input.take(std::mem::size_of::<$t>())?;
+                Ok(<$t>::from_le_bytes(bytes.try_into().expect("sized take")))
+            }
+        }
+    )*};
+}
+
+int!(u8, u32, u64);
+
+impl<const N: usize> Encode for [u8; N] {
+    fn encode_to(&self, out: &mut Vec<u8>) -> Result<(), CodecError> {
+        out.extend_from_slice(self);
+        Ok(())
+    }
+}
+
+impl<const N: usize> Decode for [u8; N] {
+    fn decode_from(input: &mut Reader<'_>) -> Result<Self, CodecError> {
+        Ok(input.take(N)?.try_into().expect("sized take"))
+    }
+}
+
+impl Encode for Vec<u8> {
+    fn encode_to(&self, out: &mut Vec<u8>) -> Result<(), CodecError> {
+        let len: u32 = self
+            .len()
+            .try_into()
+            .map_err(|_| CodecError::from(CodecErrorKind::LengthOverflow))?;
+        len.encode_to(out)?;
+        out.extend_from_slice(self);
+        Ok(())
+    }
+}
+
+impl Decode for Vec<u8> {
+    fn decode_from(input: &mut Reader<'_>) -> Result<Self, CodecError> {
+        let len = u32::decode_from(input)? as usize;
+        Ok(input.take(len)?.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(u8)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+    enum Kind {
+        A = 1,
+        B = 7,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+    struct Signed {
+        kind: Kind,
+        n: u32,
+        body: Vec<u8>,
+        #[codec(signature)]
+        sig: [u8; 4],
+        tail: u64,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+    struct Outer {
+        tag: u8,
+        inner: Signed,
+    }
+
+    #[test]
+    fn derived_fields_encode_in_declared_order() {
+        let value = Signed {
+            kind: Kind::B,
+            n: 0x0102_0304,
+            body: vec![9, 9],
+            sig: [0xAA; 4],
+            tail: 5,
+        };
+        let mut expected = vec![7, 4, 3, 2, 1, 2, 0, 0, 0, 9, 9];
+        let unsigned = [expected.clone(), 5u64.to_le_bytes().to_vec()].concat();
+        expected.extend_from_slice(&[0xAA; 4]);
+        expected.extend_from_slice(&5u64.to_le_bytes());
+
+        assert_eq!(value.encode().unwrap(), expected);
+        assert_eq!(Signed::decode(&expected).unwrap(), value);
+        assert_eq!(value.encode_unsigned().unwrap(), unsigned);
+        assert_eq!(
+            Signed::decode_unsigned(&unsigned).unwrap(),
+            Signed {
+                sig: [0; 4],
+                ..value
+            }
+        );
+
+        assert_eq!(Kind::decode(&[1]), Ok(Kind::A));
+        assert_eq!(
+            Kind::decode(&[2]).unwrap_err().kind,
+            CodecErrorKind::UnknownKind(2)
+        );
+        assert_eq!(
+            Kind::decode(&[1, 0]).unwrap_err(),
+            CodecError::new(CodecErrorKind::TrailingBytes, 1)
+        );
+    }
+
+    #[test]
+    fn errors_carry_offset_and_field_path() {
+        // Cut inside `body`: the length says 2 bytes, one is there.
+        let err = Signed::decode(&[7, 0, 0, 0, 0, 2, 0, 0, 0, 9]).unwrap_err();
+        assert_eq!(err.kind, CodecErrorKind::UnexpectedEof);
+        assert_eq!(err.offset, 9);
+        assert_eq!(err.field, "Signed.body");
+
+        let err = Outer::decode(&[0, 3]).unwrap_err();
+        assert_eq!(err.kind, CodecErrorKind::UnknownKind(3));
+        assert_eq!(err.offset, 1);
+        assert_eq!(err.field, "Outer.inner.kind");
+        assert_eq!(
+            err.to_string(),
+            "unknown kind 3 at byte 1 (Outer.inner.kind)"
+        );
+
+        // Sub-readers report offsets in the outer input.
+        let mut outer = Reader::new(&[0, 0, 1, 2]);
+        outer.take(1).unwrap();
+        let mut sub = outer.sub(2).unwrap();
+        assert_eq!(sub.offset(), 1);
+        sub.take(1).unwrap();
+        assert_eq!(sub.finish().unwrap_err().offset, 2);
+        assert_eq!(sub.take(2).unwrap_err().offset, 2);
+    }
+}
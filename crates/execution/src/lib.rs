@@ -1,5 +1,152 @@
 //! novai-execution
 //!
-//! Purpose: placeholder crate for clean-room workspace bootstrap.
-//! Invariants: no external dependencies (Week 1).
-//! Failure modes: none (placeholder).
+//! Purpose: block-level transaction checks ahead of execution.
+//! Invariants: validity never depends on cache state; the shared `SigCache`
+//! only skips work for signatures that already passed strict verification.
+//! Failure modes: the first invalid tx in block order is reported by index.
+
+use ed25519_dalek::VerifyingKey;
+use novai_codec::{encode_tx_v1_unsigned, txid_v1, CodecError};
+use novai_crypto::{pubkey_from_bytes, SigCache};
+use novai_types::{TxId, TxV1};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecutionError {
+    InvalidPublicKey { index: usize },
+    InvalidSignature { index: usize },
+    Codec { index: usize, err: CodecError },
+}
+
+/// Verify the signature of every tx in a block body.
+///
+/// Signatures already in `cache` (e.g. verified on mempool admission) are not
+/// re-verified; the rest are batch-verified and cached on success. The
+/// batch answers exactly as strict per-tx verification would, so the outcome
+/// does not depend on what was cached or which txs share the batch.
+///
+/// Txs after the first one that cannot even be checked (bad encoding or
+/// public key) are skipped, but the signatures before it are still verified
+/// so whichever failure comes first in the block is the one reported.
+pub fn verify_block_signatures(txs: &[TxV1], cache: &SigCache) -> Result<(), ExecutionError> {
+    let mut misses = Vec::new();
+    let mut unverifiable = None;
+    for (index, tx) in txs.iter().enumerate() {
+        match prepare(index, tx, cache) {
+            Ok(Some(miss)) => misses.push(miss),
+            Ok(None) => {}
+            Err(e) => {
+                unverifiable = Some(e);
+                break;
+            }
+        }
+    }
+
+    let items: Vec<_> = misses
+        .iter()
+        .map(|(index, txid, pk, unsigned)| (*txid, *pk, unsigned.as_slice(), txs[*index].sig))
+        .collect();
+    let results = cache.verify_misses(&items);
+
+    match misses.iter().zip(results).find(|(_, ok)| !ok) {
+        Some(((index, _, _, _), _)) => Err(ExecutionError::InvalidSignature { index: *index }),
+        None => unverifiable.map_or(Ok(()), Err),
+    }
+}
+
+type Miss = (usize, TxId, VerifyingKey, Vec<u8>);
+
+/// What it takes to verify `tx`, or `None` if `cache` already vouches for it.
+fn prepare(index: usize, tx: &TxV1, cache: &SigCache) -> Result<Option<Miss>, ExecutionError> {
+    let txid = txid_v1(tx).map_err(|err| ExecutionError::Codec { index, err })?;
+    if cache.contains(&txid, &tx.sig) {
+        return Ok(None);
+    }
+    let unsigned = encode_tx_v1_unsigned(tx).map_err(|err| ExecutionError::Codec { index, err })?;
+    let pk = pubkey_from_bytes(&tx.from).map_err(|_| ExecutionError::InvalidPublicKey { index })?;
+    Ok(Some((index, txid, pk, unsigned)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ed25519_dalek::SigningKey;
+    use novai_crypto::{sign_tx_v1, verify_tx_v1_cached};
+    use novai_types::TxVersion;
+
+    fn key(seed: u8) -> VerifyingKey {
+        SigningKey::from_bytes(&[seed; 32]).verifying_key()
+    }
+
+    fn signed_tx(seed: u8, nonce: u64) -> TxV1 {
+        let sk = SigningKey::from_bytes(&[seed; 32]);
+        let mut tx = TxV1 {
+            version: TxVersion::V1,
+            from: sk.verifying_key().to_bytes(),
+            nonce,
+            fee: 1,
+            payload: vec![seed],
+            sig: [0u8; 64],
+        };
+        sign_tx_v1(&sk, &mut tx).unwrap();
+        tx
+    }
+
+    #[test]
+    fn block_validation_reuses_and_fills_cache() {
+        let cache = SigCache::new(16);
+        let txs = vec![signed_tx(1, 0), signed_tx(2, 0), signed_tx(3, 0)];
+
+        // The mempool already verified the first tx.
+        assert!(verify_tx_v1_cached(&cache, &key(1), &txs[0]).unwrap());
+
+        verify_block_signatures(&txs, &cache).unwrap();
+        let m = cache.metrics();
+        assert_eq!(m.hits, 1);
+        // One miss is the mempool's own first look.
+        assert_eq!(m.misses, 3);
+        assert_eq!(cache.len(), 3);
+
+        // Re-validating the same block is answered entirely from the cache.
+        verify_block_signatures(&txs, &cache).unwrap();
+        assert_eq!(cache.metrics().hits, 4);
+    }
+
+    #[test]
+    fn bad_signature_reported_by_index_even_if_txid_cached() {
+        let cache = SigCache::new(16);
+        let good = signed_tx(4, 0);
+        assert!(verify_tx_v1_cached(&cache, &key(4), &good).unwrap());
+
+        let mut forged = good.clone();
+        forged.sig[63] ^= 0x01;
+        let txs = vec![signed_tx(5, 0), forged];
+
+        assert_eq!(
+            verify_block_signatures(&txs, &cache),
+            Err(ExecutionError::InvalidSignature { index: 1 })
+        );
+    }
+
+    #[test]
+    fn earliest_failure_wins_whatever_its_kind() {
+        let cache = SigCache::new(16);
+        let mut forged = signed_tx(6, 0);
+        forged.sig[0] ^= 0x01;
+        let mut keyless = signed_tx(7, 0);
+        // y = 2 has no matching x on the curve.
+        keyless.from = [0; 32];
+        keyless.from[0] = 2;
+
+        let txs = vec![signed_tx(8, 0), forged.clone(), keyless.clone()];
+        assert_eq!(
+            verify_block_signatures(&txs, &cache),
+            Err(ExecutionError::InvalidSignature { index: 1 })
+        );
+        let txs = vec![keyless, forged];
+        assert_eq!(
+            verify_block_signatures(&txs, &cache),
+            Err(ExecutionError::InvalidPublicKey { index: 0 })
+        );
+    }
+}
@@ -0,0 +1,90 @@
+//! Admission throughput: per-tx `insert` vs `insert_batch`.
+//!
+//! Run: `cargo bench -p mempool --bench insert_batch`
+
+use std::collections::HashMap;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use ed25519_dalek::SigningKey;
+use mempool::{AccountProvider, NonceProvider, TxMempool, TxSource};
+use novai_crypto::sign_tx_v1;
+use novai_types::{Address, TxV1, TxVersion};
+
+#[derive(Default)]
+struct FundedAccounts {
+    nonces: HashMap<Address, u64>,
+}
+
+impl NonceProvider for FundedAccounts {
+    fn expected_nonce(&self, from: &Address) -> u64 {
+        *self.nonces.get(from).unwrap_or(&0)
+    }
+}
+
+impl AccountProvider for FundedAccounts {
+    fn balance(&self, _from: &Address) -> u64 {
+        u64::MAX
+    }
+}
+
+/// One tx per sender, like a gossip burst from many wallets.
+fn signed_txs(n: usize) -> Vec<TxV1> {
+    (0..n)
+        .map(|i| {
+            let mut seed = [0u8; 32];
+            seed[..8].copy_from_slice(&(i as u64).to_le_bytes());
+            let sk = SigningKey::from_bytes(&seed);
+            let mut tx = TxV1 {
+                version: TxVersion::V1,
+                from: sk.verifying_key().to_bytes(),
+                nonce: 0,
+                fee: 1 + i as u64,
+                payload: vec![0xAB; 64],
+                sig: [0u8; 64],
+            };
+            sign_tx_v1(&sk, &mut tx).expect("sign");
+            tx
+        })
+        .collect()
+}
+
+fn bench_admission(c: &mut Criterion) {
+    let accounts = FundedAccounts::default();
+    let mut group = c.benchmark_group("mempool_admission");
+
+    for n in [64usize, 512, 2048] {
+        let txs = signed_txs(n);
+
+        group.bench_with_input(BenchmarkId::new("insert", n), &txs, |b, txs| {
+            b.iter_batched(
+                || txs.clone(),
+                |txs| {
+                    let mut mp = TxMempool::new(1, 16);
+                    for tx in txs {
+                        mp.insert(tx, &accounts).expect("insert");
+                    }
+                    mp
+                },
+                BatchSize::LargeInput,
+            )
+        });
+
+        group.bench_with_input(BenchmarkId::new("insert_batch", n), &txs, |b, txs| {
+            b.iter_batched(
+                || txs.clone(),
+                |txs| {
+                    let mut mp = TxMempool::new(1, 16);
+                    let results = mp.insert_batch(txs, &TxSource::Local, &accounts);
+                    assert!(results.iter().all(Result::is_ok));
+                    mp
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_admission);
+criterion_main!(benches);
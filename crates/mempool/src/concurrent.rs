@@ -0,0 +1,474 @@
+//! Thread-safe mempool handles.
+//!
+//! - [`ShardedMempool`]: the generic FIFO [`Mempool`](crate::Mempool) split
+//!   across independently locked shards. Inserts on different shards never
+//!   contend; a global sequence number keeps drain order FIFO.
+//! - [`SharedTxMempool`]: a cloneable handle to one [`TxMempool`]. Admission
+//!   runs policy checks under a read lock, verifies the signature with no lock
+//!   held, and takes the write lock only to re-check and store the tx.
+//!
+//! Both drain under their locks with the single-threaded rule unchanged, so
+//! for the same sequence of admissions the drained txs are identical to the
+//! plain types.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use novai_codec::TxV1Ref;
+use novai_types::{TxId, TxV1};
+
+use crate::{
+    verify_signature, verify_signature_ref, AccountProvider, MempoolError, MempoolEvent,
+    NonceProvider, TxMempool, TxMempoolError, TxSource,
+};
+
+struct Shard<Id, Tx> {
+    by_id: HashMap<Id, Tx>,
+    /// (global seq, id) in insertion order; may hold ids removed since.
+    order: VecDeque<(u64, Id)>,
+}
+
+/// Sharded, thread-safe counterpart of [`Mempool`](crate::Mempool).
+///
+/// Same semantics: duplicates rejected by id, `remove` supported, drain is
+/// FIFO by insertion and skips removed ids. Sequence numbers are taken while
+/// the target shard is locked, so each shard's queue stays sorted and drain is
+/// a k-way merge on the queue fronts.
+pub struct ShardedMempool<Id, Tx>
+where
+    Id: Eq + Hash + Copy,
+{
+    id_of: Arc<dyn Fn(&Tx) -> Id + Send + Sync>,
+    shards: Vec<Mutex<Shard<Id, Tx>>>,
+    next_seq: AtomicU64,
+}
+
+impl<Id, Tx> ShardedMempool<Id, Tx>
+where
+    Id: Eq + Hash + Copy,
+{
+    /// Create a mempool with `shards` independently locked shards (minimum 1).
+    pub fn new(shards: usize, id_of: impl Fn(&Tx) -> Id + Send + Sync + 'static) -> Self {
+        Self {
+            id_of: Arc::new(id_of),
+            shards: (0..shards.max(1))
+                .map(|_| {
+                    Mutex::new(Shard {
+                        by_id: HashMap::new(),
+                        order: VecDeque::new(),
+                    })
+                })
+                .collect(),
+            next_seq: AtomicU64::new(0),
+        }
+    }
+
+    fn shard(&self, id: &Id) -> MutexGuard<'_, Shard<Id, Tx>> {
+        // DefaultHasher::new() uses fixed keys, so placement is reproducible.
+        let mut h = DefaultHasher::new();
+        id.hash(&mut h);
+        let i = (h.finish() % self.shards.len() as u64) as usize;
+        self.shards[i].lock().expect("mempool shard lock")
+    }
+
+    /// Insert a transaction. Rejects duplicates by tx id.
+    pub fn insert(&self, tx: Tx) -> Result<(), MempoolError> {
+        let id = (self.id_of)(&tx);
+        let mut shard = self.shard(&id);
+        if shard.by_id.contains_key(&id) {
+            return Err(MempoolError::Duplicate);
+        }
+
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        shard.by_id.insert(id, tx);
+        shard.order.push_back((seq, id));
+        Ok(())
+    }
+
+    /// Remove a transaction by id.
+    pub fn remove(&self, id: Id) -> Option<Tx> {
+        self.shard(&id).by_id.remove(&id)
+    }
+
+    /// Returns true if the mempool currently contains this id.
+    pub fn contains(&self, id: Id) -> bool {
+        self.shard(&id).by_id.contains_key(&id)
+    }
+
+    /// Clone of the tx stored under `id`.
+    pub fn get(&self, id: Id) -> Option<Tx>
+    where
+        Tx: Clone,
+    {
+        self.shard(&id).by_id.get(&id).cloned()
+    }
+
+    /// Number of stored transactions. Shards are counted one at a time, so
+    /// concurrent inserts/removes may or may not be reflected.
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|s| s.lock().expect("mempool shard lock").by_id.len())
+            .sum()
+    }
+
+    /// True if empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drain up to `max` transactions in FIFO order.
+    ///
+    /// Locks every shard (in index order) for the duration of the drain.
+    pub fn drain_ready(&self, max: usize) -> Vec<Tx> {
+        let mut shards: Vec<_> = self
+            .shards
+            .iter()
+            .map(|s| s.lock().expect("mempool shard lock"))
+            .collect();
+
+        let mut out = Vec::new();
+        while out.len() < max {
+            let next = shards
+                .iter()
+                .enumerate()
+                .filter_map(|(i, s)| s.order.front().map(|(seq, _)| (*seq, i)))
+                .min();
+            let Some((_, i)) = next else {
+                break;
+            };
+
+            let shard = &mut shards[i];
+            let (_, id) = shard.order.pop_front().expect("front checked above");
+            if let Some(tx) = shard.by_id.remove(&id) {
+                out.push(tx);
+            }
+        }
+
+        out
+    }
+}
+
+/// Cloneable, thread-safe handle to a [`TxMempool`].
+///
+/// [`SharedTxMempool::insert`] never holds the lock while verifying a
+/// signature, so callers (gossip, RPC, block producer) admit in parallel.
+/// Everything else goes through the wrapped pool under the lock; use
+/// [`SharedTxMempool::read`] / [`SharedTxMempool::write`] for the full API.
+#[derive(Clone)]
+pub struct SharedTxMempool {
+    inner: Arc<RwLock<TxMempool>>,
+}
+
+impl SharedTxMempool {
+    pub fn new(mempool: TxMempool) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(mempool)),
+        }
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, TxMempool> {
+        self.inner.read().expect("mempool lock")
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, TxMempool> {
+        self.inner.write().expect("mempool lock")
+    }
+
+    /// Admit `tx` with the same rules and error precedence as
+    /// [`TxMempool::insert`].
+    pub fn insert(
+        &self,
+        tx: TxV1,
+        accounts: &impl AccountProvider,
+    ) -> Result<TxId, TxMempoolError> {
+        self.insert_from(tx, &TxSource::Local, accounts)
+    }
+
+    /// Concurrent [`TxMempool::insert_from`].
+    ///
+    /// The source budget is charged first, reserving a rejection slot until
+    /// the outcome is recorded so concurrent inserts can't overshoot it. Policy is then checked against a
+    /// read snapshot, the signature is verified unlocked, and policy is
+    /// re-checked under the write lock because the pool may have changed in
+    /// between.
+    pub fn insert_from(
+        &self,
+        tx: TxV1,
+        source: &TxSource,
+        accounts: &impl AccountProvider,
+    ) -> Result<TxId, TxMempoolError> {
+        let now = {
+            let mut mp = self.write();
+            let now = mp.now();
+            mp.check_source(source, now)?;
+            now
+        };
+        let result = self.admit_unlocked(tx, accounts);
+        self.write().record_source(source, now, &result);
+        result
+    }
+
+    /// Concurrent [`TxMempool::insert_ref_from`]: like
+    /// [`SharedTxMempool::insert_from`], but the unlocked checks run on the
+    /// borrowed view and the tx is copied out only once they pass.
+    pub fn insert_ref_from(
+        &self,
+        tx: &TxV1Ref<'_>,
+        source: &TxSource,
+        accounts: &impl AccountProvider,
+    ) -> Result<TxId, TxMempoolError> {
+        let now = {
+            let mut mp = self.write();
+            let now = mp.now();
+            mp.check_source(source, now)?;
+            now
+        };
+        let result = self.admit_ref_unlocked(tx, accounts);
+        self.write().record_source(source, now, &result);
+        result
+    }
+
+    fn admit_ref_unlocked(
+        &self,
+        tx: &TxV1Ref<'_>,
+        accounts: &impl AccountProvider,
+    ) -> Result<TxId, TxMempoolError> {
+        let cache = {
+            let mp = self.read();
+            mp.check_policy_ref(tx, accounts)?;
+            mp.sig_cache()
+        };
+
+        verify_signature_ref(cache.as_deref(), tx)?;
+
+        let tx = tx.to_owned_tx();
+        let mut mp = self.write();
+        let (id, _) = mp.check_policy(&tx, accounts)?;
+        let inserted_at = mp.now();
+        mp.admit(id, tx, inserted_at)
+    }
+
+    fn admit_unlocked(
+        &self,
+        tx: TxV1,
+        accounts: &impl AccountProvider,
+    ) -> Result<TxId, TxMempoolError> {
+        let (id, unsigned, cache) = {
+            let mp = self.read();
+            let (id, unsigned) = mp.check_policy(&tx, accounts)?;
+            (id, unsigned, mp.sig_cache())
+        };
+
+        verify_signature(cache.as_deref(), &id, &tx, &unsigned)?;
+
+        let mut mp = self.write();
+        mp.check_policy(&tx, accounts)?;
+        let inserted_at = mp.now();
+        mp.admit(id, tx, inserted_at)
+    }
+
+    /// See [`TxMempool::drain_ready`].
+    pub fn drain_ready(&self, max: usize, nonce_provider: &impl NonceProvider) -> Vec<TxV1> {
+        self.write().drain_ready(max, nonce_provider)
+    }
+
+    pub fn remove(&self, id: &TxId) -> Option<TxV1> {
+        self.write().remove(id)
+    }
+
+    pub fn remove_included(&self, ids: &[TxId]) -> Vec<TxV1> {
+        self.write().remove_included(ids)
+    }
+
+    pub fn expire(&self, now: u64) -> Vec<TxId> {
+        self.write().expire(now)
+    }
+
+    pub fn subscribe(&self) -> Receiver<MempoolEvent> {
+        self.write().subscribe()
+    }
+
+    pub fn contains(&self, id: &TxId) -> bool {
+        self.read().contains(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.read().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    use novai_types::Address;
+
+    use crate::test_support::{make_signed_tx, test_keypair, TestNonceProvider};
+    use crate::{Mempool, RateLimitConfig, TxMempoolConfig};
+
+    #[test]
+    fn sharded_drain_matches_single_threaded() {
+        let mut single = Mempool::<u64, (u64, u32)>::new(|tx| tx.0);
+        let sharded = ShardedMempool::<u64, (u64, u32)>::new(4, |tx| tx.0);
+
+        // Includes duplicates and a remove/re-insert, which keeps the original
+        // queue position in the single-threaded pool.
+        let ops: Vec<(bool, u64, u32)> = vec![
+            (true, 5, 0),
+            (true, 1, 1),
+            (true, 9, 2),
+            (true, 1, 3),
+            (false, 5, 0),
+            (true, 7, 4),
+            (true, 5, 5),
+            (false, 9, 0),
+            (true, 3, 6),
+        ];
+        for (insert, id, tag) in ops {
+            if insert {
+                assert_eq!(single.insert((id, tag)), sharded.insert((id, tag)));
+            } else {
+                assert_eq!(single.remove(id), sharded.remove(id));
+            }
+        }
+
+        assert_eq!(single.len(), sharded.len());
+        assert_eq!(single.drain_ready(2), sharded.drain_ready(2));
+        assert_eq!(single.drain_ready(10), sharded.drain_ready(10));
+        assert!(sharded.is_empty());
+    }
+
+    #[test]
+    fn sharded_concurrent_inserts_keep_per_thread_fifo() {
+        let mp = ShardedMempool::<u64, u64>::new(8, |tx| *tx);
+
+        thread::scope(|s| {
+            for t in 0..4u64 {
+                let mp = &mp;
+                s.spawn(move || {
+                    for i in 0..250u64 {
+                        mp.insert(t * 1_000 + i).unwrap();
+                    }
+                });
+            }
+        });
+
+        let drained = mp.drain_ready(usize::MAX);
+        assert_eq!(drained.len(), 1_000);
+        for t in 0..4u64 {
+            let mine: Vec<u64> = drained
+                .iter()
+                .copied()
+                .filter(|id| id / 1_000 == t)
+                .collect();
+            let expected: Vec<u64> = (0..250).map(|i| t * 1_000 + i).collect();
+            assert_eq!(mine, expected);
+        }
+    }
+
+    #[test]
+    fn parallel_admission_drains_like_sequential() {
+        let mut np = TestNonceProvider::default();
+        let mut txs = Vec::new();
+        for seed in 40..48u8 {
+            let (sk, vk) = test_keypair(seed);
+            let from: Address = vk.to_bytes();
+            for nonce in 0..4u64 {
+                let fee = 1 + (seed as u64 * 7 + nonce * 3) % 11;
+                txs.push(make_signed_tx(&sk, from, nonce, fee, b"p"));
+            }
+        }
+
+        let mut single = TxMempool::new(1, 2);
+        for tx in &txs {
+            single.insert(tx.clone(), &np).unwrap();
+        }
+
+        let shared = SharedTxMempool::new(TxMempool::new(1, 2));
+        thread::scope(|s| {
+            for chunk in txs.chunks(5) {
+                let (shared, np) = (shared.clone(), &np);
+                s.spawn(move || {
+                    for tx in chunk {
+                        shared.insert(tx.clone(), np).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(shared.len(), txs.len());
+
+        while !single.is_empty() {
+            let a = single.drain_ready(5, &np);
+            let b = shared.drain_ready(5, &np);
+            assert_eq!(a, b);
+            assert!(!a.is_empty());
+            for tx in &a {
+                np.set(tx.from, tx.nonce + 1);
+            }
+        }
+        assert!(shared.is_empty());
+    }
+
+    #[test]
+    fn racing_duplicates_admit_once() {
+        let (sk, vk) = test_keypair(49);
+        let np = TestNonceProvider::default();
+        let tx = make_signed_tx(&sk, vk.to_bytes(), 0, 3, b"dup");
+
+        let shared = SharedTxMempool::new(TxMempool::new(1, 10));
+        let results: Vec<_> = thread::scope(|s| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| s.spawn(|| shared.insert(tx.clone(), &np)))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+        assert!(results
+            .iter()
+            .all(|r| r.is_ok() || *r == Err(TxMempoolError::Duplicate)));
+        assert_eq!(shared.len(), 1);
+    }
+
+    #[test]
+    fn racing_bad_txs_stay_within_rejection_budget() {
+        let (sk, vk) = test_keypair(50);
+        let np = TestNonceProvider::default();
+        let shared = SharedTxMempool::new(TxMempool::with_config(TxMempoolConfig {
+            rate_limit: RateLimitConfig {
+                window_secs: 10,
+                max_attempts_per_window: 100,
+                max_rejections_per_window: 3,
+                throttle_secs: 60,
+            },
+            ..TxMempoolConfig::default()
+        }));
+        let peer = TxSource::Peer(b"peer-a".to_vec());
+
+        thread::scope(|s| {
+            for nonce in 0..16 {
+                let (sk, peer, np, shared) = (&sk, &peer, &np, &shared);
+                s.spawn(move || {
+                    let mut tx = make_signed_tx(sk, vk.to_bytes(), nonce, 5, b"spam");
+                    tx.sig[0] ^= 0x01;
+                    let _ = shared.insert_from(tx, peer, np);
+                });
+            }
+        });
+
+        let score = shared.read().source_score(&peer).unwrap();
+        assert_eq!(score.rejected, 3);
+        assert_eq!(score.rate_limited, 13);
+    }
+}
@@ -0,0 +1,148 @@
+//! Congestion-driven minimum fee.
+//!
+//! EIP-1559-style base fee, adjusted once per block (each
+//! [`TxMempool::drain_ready`](crate::TxMempool::drain_ready) call) from the
+//! backlog left in the pool:
+//!
+//! ```text
+//! excess = min(|pending - target|, target)
+//! delta  = base * excess / target / change_denominator
+//! next   = base + max(delta, 1)        if pending > target
+//!          max(base - max(delta, 1), floor)
+//!                                      if pending < target
+//!          base                        otherwise
+//! ```
+//!
+//! Integer-only, so every node that drains the same pool computes the same
+//! floor. The step is bounded by `1 / change_denominator` of the current fee.
+
+/// Parameters for the dynamic fee floor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeeMarketConfig {
+    /// Backlog (txs left pending after a drain) at which the fee holds steady.
+    pub target_pending: usize,
+    /// Inverse of the maximum per-block change; 8 means at most 12.5%.
+    pub change_denominator: u64,
+}
+
+impl Default for FeeMarketConfig {
+    fn default() -> Self {
+        Self {
+            target_pending: 1_000,
+            change_denominator: 8,
+        }
+    }
+}
+
+/// Current base fee plus the rule for moving it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeeMarket {
+    config: FeeMarketConfig,
+    floor: u64,
+    base_fee: u64,
+}
+
+impl FeeMarket {
+    /// Start at `floor`, which the base fee never drops below.
+    pub fn new(config: FeeMarketConfig, floor: u64) -> Self {
+        Self {
+            config: FeeMarketConfig {
+                target_pending: config.target_pending.max(1),
+                change_denominator: config.change_denominator.max(1),
+            },
+            floor,
+            base_fee: floor,
+        }
+    }
+
+    pub fn base_fee(&self) -> u64 {
+        self.base_fee
+    }
+
+    pub fn floor(&self) -> u64 {
+        self.floor
+    }
+
+    /// The base fee that [`FeeMarket::update`] would produce for `pending`.
+    pub fn next_base_fee(&self, pending: usize) -> u64 {
+        let target = self.config.target_pending as u128;
+        let pending = pending as u128;
+        let base = self.base_fee as u128;
+        let step = |excess: u128| {
+            base * excess.min(target) / target / self.config.change_denominator as u128
+        };
+
+        let next = if pending > target {
+            base + step(pending - target).max(1)
+        } else if pending < target {
+            base.saturating_sub(step(target - pending).max(1))
+                .max(self.floor as u128)
+        } else {
+            base
+        };
+        u64::try_from(next).unwrap_or(u64::MAX)
+    }
+
+    /// Apply one block's adjustment given the backlog left in the pool.
+    pub fn update(&mut self, pending: usize) -> u64 {
+        self.base_fee = self.next_base_fee(pending);
+        self.base_fee
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn market(floor: u64) -> FeeMarket {
+        FeeMarket::new(
+            FeeMarketConfig {
+                target_pending: 100,
+                change_denominator: 8,
+            },
+            floor,
+        )
+    }
+
+    #[test]
+    fn rises_under_congestion_and_decays_to_floor() {
+        let mut m = market(10);
+
+        // Tiny base: the minimum step of 1 still applies.
+        assert_eq!(m.update(101), 11);
+        // Excess is capped at target, so +1/8 at most.
+        assert_eq!(m.update(10_000), 12);
+        m.base_fee = 800;
+        assert_eq!(m.update(200), 900);
+        assert_eq!(m.update(150), 956);
+        assert_eq!(m.update(100), 956);
+
+        // Half-empty pool: -1/16 per block, never below the floor.
+        assert_eq!(m.update(50), 897);
+        let mut last = m.base_fee();
+        for _ in 0..200 {
+            let next = m.update(0);
+            assert!(next <= last);
+            last = next;
+        }
+        assert_eq!(m.base_fee(), m.floor());
+    }
+
+    #[test]
+    fn small_fee_still_drains_to_floor() {
+        // Below change_denominator the proportional step rounds to 0.
+        let mut m = market(1);
+        m.base_fee = 7;
+        let drained: Vec<_> = (0..7).map(|_| m.update(0)).collect();
+        assert_eq!(drained, vec![6, 5, 4, 3, 2, 1, 1]);
+    }
+
+    #[test]
+    fn next_base_fee_is_a_pure_preview() {
+        let mut m = market(1);
+        m.base_fee = u64::MAX - 1;
+        assert_eq!(m.next_base_fee(1_000), u64::MAX);
+        assert_eq!(m.base_fee(), u64::MAX - 1);
+        assert_eq!(m.update(1_000), u64::MAX);
+    }
+}
@@ -0,0 +1,257 @@
+//! Append-only on-disk journal of mempool admissions and removals.
+//!
+//! Record layout (little-endian):
+//! - kind: u8 (1 = add, 2 = remove)
+//! - len: u32 (body length)
+//! - checksum: [u8; 4] = first 4 bytes of blake3(kind || body)
+//! - body:
+//!   - add: inserted_at u64 || encode_tx_v1_signed(tx)
+//!   - remove: txid [u8; 32]
+//!
+//! Replay keeps the adds that were not later removed, in journal order.
+//! Records with a bad checksum or undecodable body are counted and skipped;
+//! a short record at the end (crash mid-write) stops the replay.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use novai_codec::{decode_tx_v1_signed, encode_tx_v1_signed, txid_v1};
+use novai_types::{TxId, TxV1};
+
+const KIND_ADD: u8 = 1;
+const KIND_REMOVE: u8 = 2;
+const HEADER_LEN: usize = 1 + 4 + 4;
+
+/// Result of reading a journal back from disk.
+#[derive(Debug, Default)]
+pub struct JournalReplay {
+    /// Surviving `(inserted_at, tx)` pairs in admission order.
+    pub entries: Vec<(u64, TxV1)>,
+    /// Records skipped because of a checksum or decode failure.
+    pub corrupt: usize,
+    /// True if the file ended in the middle of a record.
+    pub truncated: bool,
+}
+
+/// Handle for appending to a journal file.
+pub struct TxJournal {
+    path: PathBuf,
+    file: File,
+}
+
+impl TxJournal {
+    /// Open (or create) a journal for appending.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self { path, file })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn append_add(&mut self, inserted_at: u64, tx: &TxV1) -> io::Result<()> {
+        let mut body = inserted_at.to_le_bytes().to_vec();
+        body.extend_from_slice(&encode_tx_v1_signed(tx).map_err(codec_err)?);
+        self.file.write_all(&record(KIND_ADD, &body))
+    }
+
+    pub fn append_remove(&mut self, id: &TxId) -> io::Result<()> {
+        self.file.write_all(&record(KIND_REMOVE, id))
+    }
+
+    /// Atomically replace the journal with exactly `entries` (compaction).
+    pub fn rewrite<'a>(
+        &mut self,
+        entries: impl IntoIterator<Item = (u64, &'a TxV1)>,
+    ) -> io::Result<()> {
+        let mut buf = Vec::new();
+        for (inserted_at, tx) in entries {
+            let mut body = inserted_at.to_le_bytes().to_vec();
+            body.extend_from_slice(&encode_tx_v1_signed(tx).map_err(codec_err)?);
+            buf.extend_from_slice(&record(KIND_ADD, &body));
+        }
+
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        {
+            let mut f = File::create(&tmp)?;
+            f.write_all(&buf)?;
+            f.sync_all()?;
+        }
+        fs::rename(&tmp, &self.path)?;
+
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        Ok(())
+    }
+
+    /// Read a journal back. A missing file replays as empty.
+    pub fn replay(path: impl AsRef<Path>) -> io::Result<JournalReplay> {
+        let bytes = match fs::read(path) {
+            Ok(b) => b,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(JournalReplay::default()),
+            Err(e) => return Err(e),
+        };
+
+        let mut out = JournalReplay::default();
+        // seq -> (inserted_at, tx); BTreeMap keeps journal order after removals.
+        let mut live: BTreeMap<u64, (u64, TxV1)> = BTreeMap::new();
+        let mut seq_of: BTreeMap<TxId, u64> = BTreeMap::new();
+        let mut seq = 0u64;
+
+        let mut input = bytes.as_slice();
+        while !input.is_empty() {
+            if input.len() < HEADER_LEN {
+                out.truncated = true;
+                break;
+            }
+            let kind = input[0];
+            let len = u32::from_le_bytes([input[1], input[2], input[3], input[4]]) as usize;
+            let checksum = [input[5], input[6], input[7], input[8]];
+            if input.len() - HEADER_LEN < len {
+                out.truncated = true;
+                break;
+            }
+            let body = &input[HEADER_LEN..HEADER_LEN + len];
+            input = &input[HEADER_LEN + len..];
+
+            if checksum_of(kind, body) != checksum {
+                out.corrupt += 1;
+                continue;
+            }
+
+            match (kind, body.len()) {
+                (KIND_ADD, n) if n > 8 => {
+                    let mut at = [0u8; 8];
+                    at.copy_from_slice(&body[..8]);
+                    let Ok(tx) = decode_tx_v1_signed(&body[8..]) else {
+                        out.corrupt += 1;
+                        continue;
+                    };
+                    let Ok(id) = txid_v1(&tx) else {
+                        out.corrupt += 1;
+                        continue;
+                    };
+                    if let Some(old) = seq_of.insert(id, seq) {
+                        live.remove(&old);
+                    }
+                    live.insert(seq, (u64::from_le_bytes(at), tx));
+                    seq += 1;
+                }
+                (KIND_REMOVE, 32) => {
+                    let mut id = [0u8; 32];
+                    id.copy_from_slice(body);
+                    if let Some(old) = seq_of.remove(&id) {
+                        live.remove(&old);
+                    }
+                }
+                _ => out.corrupt += 1,
+            }
+        }
+
+        out.entries = live.into_values().collect();
+        Ok(out)
+    }
+}
+
+fn record(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(HEADER_LEN + body.len());
+    out.push(kind);
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    out.extend_from_slice(&checksum_of(kind, body));
+    out.extend_from_slice(body);
+    out
+}
+
+fn checksum_of(kind: u8, body: &[u8]) -> [u8; 4] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[kind]);
+    hasher.update(body);
+    let h = hasher.finalize();
+    let mut out = [0u8; 4];
+    out.copy_from_slice(&h.as_bytes()[..4]);
+    out
+}
+
+fn codec_err(e: novai_codec::CodecError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use novai_types::TxVersion;
+
+    use crate::test_support::temp_path;
+
+    fn tx(nonce: u64) -> TxV1 {
+        TxV1 {
+            version: TxVersion::V1,
+            from: [7u8; 32],
+            nonce,
+            fee: 1,
+            payload: vec![nonce as u8; 3],
+            sig: [9u8; 64],
+        }
+    }
+
+    #[test]
+    fn replay_applies_removals_in_order() {
+        let path = temp_path("journal-replay");
+        let mut j = TxJournal::open(&path).unwrap();
+        j.append_add(10, &tx(0)).unwrap();
+        j.append_add(11, &tx(1)).unwrap();
+        j.append_add(12, &tx(2)).unwrap();
+        j.append_remove(&txid_v1(&tx(1)).unwrap()).unwrap();
+
+        let replay = TxJournal::replay(&path).unwrap();
+        assert_eq!(replay.entries, vec![(10, tx(0)), (12, tx(2))]);
+        assert_eq!(replay.corrupt, 0);
+        assert!(!replay.truncated);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn replay_skips_corrupt_and_stops_at_truncated_tail() {
+        let path = temp_path("journal-corrupt");
+        let mut j = TxJournal::open(&path).unwrap();
+        j.append_add(1, &tx(0)).unwrap();
+        j.append_add(2, &tx(1)).unwrap();
+        j.append_add(3, &tx(2)).unwrap();
+        drop(j);
+
+        let mut bytes = fs::read(&path).unwrap();
+        let rec_len = bytes.len() / 3;
+        // Flip a payload byte in the second record, cut the third in half.
+        bytes[rec_len + HEADER_LEN + 20] ^= 0xFF;
+        bytes.truncate(rec_len * 2 + rec_len / 2);
+        fs::write(&path, &bytes).unwrap();
+
+        let replay = TxJournal::replay(&path).unwrap();
+        assert_eq!(replay.entries, vec![(1, tx(0))]);
+        assert_eq!(replay.corrupt, 1);
+        assert!(replay.truncated);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rewrite_compacts_and_keeps_appending() {
+        let path = temp_path("journal-rewrite");
+        let mut j = TxJournal::open(&path).unwrap();
+        for n in 0..4 {
+            j.append_add(n, &tx(n)).unwrap();
+        }
+        let keep = tx(3);
+        j.rewrite([(3, &keep)]).unwrap();
+        j.append_add(4, &tx(4)).unwrap();
+
+        let replay = TxJournal::replay(&path).unwrap();
+        assert_eq!(replay.entries, vec![(3, tx(3)), (4, tx(4))]);
+        fs::remove_file(&path).unwrap();
+    }
+}
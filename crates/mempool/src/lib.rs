@@ -1,7 +1,27 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeSet, HashMap, VecDeque};
 use std::hash::Hash;
+use std::io;
+use std::path::Path;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
 use std::sync::Arc;
 
+pub mod concurrent;
+pub mod fee;
+pub mod journal;
+pub mod rate_limit;
+pub mod template;
+
+#[cfg(test)]
+mod test_support;
+
+pub use concurrent::{ShardedMempool, SharedTxMempool};
+pub use fee::{FeeMarket, FeeMarketConfig};
+pub use journal::{JournalReplay, TxJournal};
+pub use rate_limit::{RateLimitConfig, SourceScore, TxSource};
+pub use template::{BlockLimits, BlockTemplate, BlockTemplateBuilder, CostModel, FlatCost};
+
+use rate_limit::RateLimiter;
+
 /// Errors returned by [`Mempool`].
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum MempoolError {
@@ -99,9 +119,15 @@ where
 // Week 2 "real" mempool: TxV1 policy enforcement + deterministic fee-priority.
 // -----------------------------------------------------------------------------
 
-use novai_codec::{encode_tx_v1_unsigned, txid_v1};
-use novai_crypto::{pubkey_from_bytes, verify_bytes};
-use novai_types::{Address, TxId, TxV1};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use novai_codec::{encode_tx_v1_unsigned, txid_v1, DecodeLimits, TxV1Ref};
+use novai_crypto::{
+    pubkey_from_bytes, verify_bytes, verify_bytes_batch, verify_tx_v1_ref, verify_tx_v1_ref_cached,
+    SigCache,
+};
+use novai_types::{Address, Nonce, SignatureBytes, TxId, TxV1};
 
 /// Provides the current expected nonce for a sender address (state snapshot).
 ///
@@ -111,42 +137,450 @@ pub trait NonceProvider {
     fn expected_nonce(&self, from: &Address) -> u64;
 }
 
+/// Account view used for admission: nonces plus spendable balance.
+pub trait AccountProvider: NonceProvider {
+    fn balance(&self, from: &Address) -> u64;
+
+    /// Amount `tx` moves out of the sender's account on top of its fee.
+    ///
+    /// V1 payloads are opaque to the mempool, so the state layer decides.
+    /// Defaults to 0 (fee-only cost).
+    fn transfer_amount(&self, _tx: &TxV1) -> u64 {
+        0
+    }
+}
+
+/// Time source used to stamp mempool entries.
+///
+/// Units are chosen by the caller (unix seconds, block height, ...). The mempool
+/// only compares stamps against each other and against the configured TTL.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> u64;
+}
+
+/// Wall-clock seconds since the unix epoch.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+/// A clock that only moves when told to (tests, simulations).
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn new(now: u64) -> Self {
+        Self {
+            now: AtomicU64::new(now),
+        }
+    }
+
+    pub fn set(&self, now: u64) {
+        self.now.store(now, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, by: u64) {
+        self.now.fetch_add(by, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> u64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
+
+/// Tunables for [`TxMempool`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxMempoolConfig {
+    /// Fixed fee floor, or the lowest the dynamic floor can go with `fee_market`.
+    pub min_fee: u64,
+    /// Raise the floor with congestion (see [`fee`]). `None` keeps `min_fee` fixed.
+    pub fee_market: Option<FeeMarketConfig>,
+    pub fairness_cap_per_sender: usize,
+    /// Entries are evicted by [`TxMempool::expire`] once `now >= inserted_at + ttl`.
+    /// `None` keeps entries until they are drained or removed.
+    pub ttl: Option<u64>,
+    /// A tx with the same sender and nonce as a pending one replaces it if it
+    /// pays a strictly higher fee (and is rejected otherwise). When off, such
+    /// txs are kept side by side and compete in `drain_ready`.
+    pub replace_by_fee: bool,
+    /// Per-source budgets for [`TxMempool::insert_from`].
+    pub rate_limit: RateLimitConfig,
+}
+
+impl Default for TxMempoolConfig {
+    fn default() -> Self {
+        Self {
+            min_fee: 1,
+            fee_market: None,
+            fairness_cap_per_sender: 1000,
+            ttl: None,
+            replace_by_fee: false,
+            rate_limit: RateLimitConfig::default(),
+        }
+    }
+}
+
+/// Counters for entries that left the pool without being drained.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TxMempoolStats {
+    /// Entries evicted by [`TxMempool::expire`].
+    pub expired: u64,
+    /// Journal writes that failed (the in-memory pool is unaffected).
+    pub journal_errors: u64,
+}
+
+/// Why a tx left the pool without being included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionReason {
+    /// TTL elapsed (see [`TxMempool::expire`]).
+    Expired,
+    /// Explicitly removed via [`TxMempool::remove`].
+    Removed,
+}
+
+/// Events buffered per [`TxMempool::subscribe`] receiver before it counts
+/// as lagging and is disconnected.
+pub const SUBSCRIBER_BUFFER: usize = 1024;
+
+/// Pool changes delivered to [`TxMempool::subscribe`] receivers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MempoolEvent {
+    Added {
+        id: TxId,
+    },
+    /// `new` took the place of `old` under replace-by-fee.
+    Replaced {
+        old: TxId,
+        new: TxId,
+    },
+    Evicted {
+        id: TxId,
+        reason: EvictionReason,
+    },
+    /// Drained for a block or reported as included via [`TxMempool::remove_included`].
+    Included {
+        id: TxId,
+    },
+}
+
+/// Outcome of reloading a journal with [`TxMempool::attach_journal`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RestoreReport {
+    /// Txs re-admitted into the pool.
+    pub restored: usize,
+    /// Txs that no longer pass admission against current state.
+    pub stale: Vec<(TxId, TxMempoolError)>,
+    /// Records skipped because they failed checksum or decoding.
+    pub corrupt: usize,
+    /// The journal ended mid-record (e.g. crash during a write).
+    pub truncated: bool,
+}
+
 /// Errors for the V1 tx mempool.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TxMempoolError {
     Duplicate,
-    FeeTooLow { min_fee: u64, got: u64 },
-    NonceTooLow { expected: u64, got: u64 },
+    FeeTooLow {
+        min_fee: u64,
+        got: u64,
+    },
+    NonceTooLow {
+        expected: u64,
+        got: u64,
+    },
+    /// Sender's queued txs plus this one cost more than their balance.
+    InsufficientBalance {
+        balance: u64,
+        required: u64,
+    },
+    /// Replace-by-fee: a pending tx with the same sender and nonce pays at least as much.
+    ReplacementUnderpriced {
+        existing_fee: u64,
+        got: u64,
+    },
     InvalidSignature,
     InvalidPublicKey,
     CodecError,
+    /// Payload longer than peers decode ([`DecodeLimits::DEFAULT`]), so the
+    /// tx could never be gossiped or carried in a block.
+    PayloadTooLarge {
+        max: u32,
+        got: usize,
+    },
+    /// The submitting source is over its budget or throttled until `retry_at`.
+    RateLimited {
+        retry_at: u64,
+    },
 }
 
 /// A mempool specifically for canonical TxV1.
 ///
 /// Policy (Week 2):
 /// - Reject invalid signatures.
-/// - Reject fee < min_fee.
+/// - Reject payloads over [`DecodeLimits::DEFAULT`]'s `max_payload_bytes`.
+/// - Reject fee < min_fee (fixed, or a congestion-driven base fee updated on
+///   every drain; see [`TxMempool::min_fee`]). Txs already pending are kept
+///   when the floor rises.
 /// - Reject nonce < expected_nonce(from).
+/// - Reject if fees + transfer amounts of the sender's queued txs, including the
+///   new one, exceed the sender's balance.
 /// - Drain policy:
 ///   - Ready if nonce == expected_nonce(from)
 ///   - Sort by fee DESC, then txid ASC (deterministic)
 ///   - Fairness cap: at most K txs per sender per drain batch
+/// - Replace-by-fee (opt-in): same sender + nonce with a higher fee replaces.
+/// - Signatures: optionally checked through a shared [`SigCache`].
+/// - Persistence: optionally journals admissions/removals to disk so pending
+///   txs survive a restart (see [`TxMempool::attach_journal`]).
+/// - Expiry: entries are stamped from the injected [`Clock`] on insert and
+///   evicted by [`TxMempool::expire`] once older than the configured TTL.
+/// - Sources: [`TxMempool::insert_from`] charges each attempt to a
+///   [`TxSource`] budget and scores the outcome (see [`rate_limit`]).
 pub struct TxMempool {
     min_fee: u64,
+    fee_market: Option<FeeMarket>,
     fairness_cap_per_sender: usize,
-    by_id: HashMap<TxId, TxV1>,
+    ttl: Option<u64>,
+    replace_by_fee: bool,
+    clock: Arc<dyn Clock>,
+    sig_cache: Option<Arc<SigCache>>,
+    journal: Option<TxJournal>,
+    by_id: HashMap<TxId, PoolEntry>,
+    by_sender: HashMap<Address, BTreeSet<(Nonce, TxId)>>,
+    stats: TxMempoolStats,
+    subscribers: Vec<SyncSender<MempoolEvent>>,
+    limiter: RateLimiter,
+}
+
+struct PoolEntry {
+    tx: TxV1,
+    inserted_at: u64,
 }
 
 impl TxMempool {
     pub fn new(min_fee: u64, fairness_cap_per_sender: usize) -> Self {
-        Self {
+        Self::with_config(TxMempoolConfig {
             min_fee,
-            fairness_cap_per_sender: fairness_cap_per_sender.max(1),
+            fairness_cap_per_sender,
+            ..TxMempoolConfig::default()
+        })
+    }
+
+    /// Create a mempool from a full config, stamping entries with [`SystemClock`].
+    pub fn with_config(config: TxMempoolConfig) -> Self {
+        Self {
+            min_fee: config.min_fee,
+            fee_market: config.fee_market.map(|c| FeeMarket::new(c, config.min_fee)),
+            fairness_cap_per_sender: config.fairness_cap_per_sender.max(1),
+            ttl: config.ttl,
+            replace_by_fee: config.replace_by_fee,
+            clock: Arc::new(SystemClock),
+            sig_cache: None,
+            journal: None,
             by_id: HashMap::new(),
+            by_sender: HashMap::new(),
+            stats: TxMempoolStats::default(),
+            subscribers: Vec::new(),
+            limiter: RateLimiter::new(config.rate_limit),
+        }
+    }
+
+    /// Replace the clock used to stamp new entries.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Share a verified-signature cache with block validation.
+    ///
+    /// Admission answers from the cache when the exact (txid, sig) pair was
+    /// already verified and records every signature it verifies itself.
+    pub fn with_sig_cache(mut self, cache: Arc<SigCache>) -> Self {
+        self.sig_cache = Some(cache);
+        self
+    }
+
+    /// Reload pending txs from the journal at `path`, then keep journaling to it.
+    ///
+    /// Every journaled tx is re-admitted through the normal policy and signature
+    /// checks against `accounts` (keeping its original insertion stamp); txs that
+    /// are now invalid are reported as stale and dropped. The journal is then
+    /// compacted to the restored set.
+    pub fn attach_journal(
+        &mut self,
+        path: impl AsRef<Path>,
+        accounts: &impl AccountProvider,
+    ) -> io::Result<RestoreReport> {
+        let replay = TxJournal::replay(&path)?;
+        let mut report = RestoreReport {
+            corrupt: replay.corrupt,
+            truncated: replay.truncated,
+            ..RestoreReport::default()
+        };
+
+        // Detach while restoring so re-admission doesn't append duplicates.
+        self.journal = None;
+        for (inserted_at, tx) in replay.entries {
+            match self.admit_checked(tx.clone(), accounts, inserted_at) {
+                Ok(_) => report.restored += 1,
+                Err(e) => {
+                    if let Ok(id) = txid_v1(&tx) {
+                        report.stale.push((id, e));
+                    }
+                }
+            }
+        }
+
+        let mut journal = TxJournal::open(&path)?;
+        journal.rewrite(self.journal_entries())?;
+        self.journal = Some(journal);
+        Ok(report)
+    }
+
+    /// Rewrite the attached journal so it holds exactly the current pool.
+    pub fn compact_journal(&mut self) -> io::Result<()> {
+        let entries: Vec<(u64, TxV1)> = self
+            .journal_entries()
+            .map(|(at, tx)| (at, tx.clone()))
+            .collect();
+        match &mut self.journal {
+            Some(journal) => journal.rewrite(entries.iter().map(|(at, tx)| (*at, tx))),
+            None => Ok(()),
+        }
+    }
+
+    /// Current entries ordered by (inserted_at, txid), the order they replay in.
+    fn journal_entries(&self) -> impl Iterator<Item = (u64, &TxV1)> {
+        let mut entries: Vec<(u64, &TxId, &TxV1)> = self
+            .by_id
+            .iter()
+            .map(|(id, e)| (e.inserted_at, id, &e.tx))
+            .collect();
+        entries.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+        entries.into_iter().map(|(at, _, tx)| (at, tx))
+    }
+
+    pub fn stats(&self) -> TxMempoolStats {
+        self.stats
+    }
+
+    /// Fee a new tx must pay right now; use it for fee estimation.
+    pub fn min_fee(&self) -> u64 {
+        match &self.fee_market {
+            Some(market) => market.base_fee(),
+            None => self.min_fee,
+        }
+    }
+
+    /// Floor that will apply after the next drain if the backlog stays at
+    /// its current size.
+    pub fn next_min_fee(&self) -> u64 {
+        match &self.fee_market {
+            Some(market) => market.next_base_fee(self.len()),
+            None => self.min_fee,
         }
     }
 
+    /// Receive every subsequent [`MempoolEvent`]. Dropping the receiver unsubscribes.
+    ///
+    /// At most [`SUBSCRIBER_BUFFER`] events are held for a receiver. One
+    /// that falls further behind is disconnected: it still gets the buffered
+    /// events, then sees the channel close, and must resubscribe (and
+    /// resynchronise) to hear more.
+    pub fn subscribe(&mut self) -> Receiver<MempoolEvent> {
+        self.subscribe_with_buffer(SUBSCRIBER_BUFFER)
+    }
+
+    fn subscribe_with_buffer(&mut self, buffer: usize) -> Receiver<MempoolEvent> {
+        let (tx, rx) = sync_channel(buffer);
+        self.subscribers.push(tx);
+        rx
+    }
+
+    fn emit(&mut self, event: MempoolEvent) {
+        // A full buffer drops the sender just like a dropped receiver does.
+        self.subscribers
+            .retain(|s| s.try_send(event.clone()).is_ok());
+    }
+
+    /// Pending txs from `from`, ordered by (nonce ASC, txid ASC).
+    pub fn pending_by_sender(&self, from: &Address) -> Vec<&TxV1> {
+        self.by_sender
+            .get(from)
+            .into_iter()
+            .flatten()
+            .filter_map(|(_, id)| self.get(id))
+            .collect()
+    }
+
+    /// Txs whose nonce equals the sender's expected nonce, in drain priority
+    /// order (fee DESC, txid ASC). Ignores the fairness cap.
+    pub fn ready(&self, nonce_provider: &impl NonceProvider) -> Vec<&TxV1> {
+        let mut out: Vec<(&TxId, &TxV1)> = self
+            .by_id
+            .iter()
+            .filter(|(_, e)| e.tx.nonce == nonce_provider.expected_nonce(&e.tx.from))
+            .map(|(id, e)| (id, &e.tx))
+            .collect();
+        out.sort_by(|(id_a, a), (id_b, b)| b.fee.cmp(&a.fee).then_with(|| id_a.cmp(id_b)));
+        out.into_iter().map(|(_, tx)| tx).collect()
+    }
+
+    /// Txs waiting on a nonce gap, ordered by (sender, nonce, txid).
+    pub fn queued(&self, nonce_provider: &impl NonceProvider) -> Vec<&TxV1> {
+        let mut senders: Vec<&Address> = self.by_sender.keys().collect();
+        senders.sort();
+        senders
+            .into_iter()
+            .flat_map(|from| {
+                let expected = nonce_provider.expected_nonce(from);
+                self.by_sender[from]
+                    .iter()
+                    .filter(move |(nonce, _)| *nonce > expected)
+                    .filter_map(|(_, id)| self.get(id))
+            })
+            .collect()
+    }
+
+    /// Count pending fees per bucket.
+    ///
+    /// `bounds` must be ascending. Bucket 0 counts `fee < bounds[0]`, bucket `i`
+    /// counts `bounds[i-1] <= fee < bounds[i]`, and the last bucket counts
+    /// `fee >= bounds[last]`, so the result has `bounds.len() + 1` entries.
+    pub fn fee_histogram(&self, bounds: &[u64]) -> Vec<usize> {
+        let mut out = vec![0usize; bounds.len() + 1];
+        for e in self.by_id.values() {
+            out[bounds.partition_point(|b| *b <= e.tx.fee)] += 1;
+        }
+        out
+    }
+
+    /// Nearest-rank fee percentile over pending txs (`pct` clamped to 0..=100).
+    ///
+    /// Integer-only: the fee at rank `ceil(pct * n / 100)` (minimum rank 1) in
+    /// ascending order. `None` if the pool is empty.
+    pub fn fee_percentile(&self, pct: u8) -> Option<u64> {
+        if self.by_id.is_empty() {
+            return None;
+        }
+        let mut fees: Vec<u64> = self.by_id.values().map(|e| e.tx.fee).collect();
+        fees.sort_unstable();
+        let n = fees.len();
+        let rank = (pct.min(100) as usize * n).div_ceil(100).max(1);
+        Some(fees[rank - 1])
+    }
+
     pub fn len(&self) -> usize {
         self.by_id.len()
     }
@@ -160,11 +594,101 @@ impl TxMempool {
     }
 
     pub fn get(&self, id: &TxId) -> Option<&TxV1> {
-        self.by_id.get(id)
+        self.by_id.get(id).map(|e| &e.tx)
+    }
+
+    /// Clock stamp recorded when `id` was admitted.
+    pub fn inserted_at(&self, id: &TxId) -> Option<u64> {
+        self.by_id.get(id).map(|e| e.inserted_at)
     }
 
     pub fn remove(&mut self, id: &TxId) -> Option<TxV1> {
-        self.by_id.remove(id)
+        let entry = self.take(id)?;
+        self.emit(MempoolEvent::Evicted {
+            id: *id,
+            reason: EvictionReason::Removed,
+        });
+        Some(entry.tx)
+    }
+
+    /// Drop txs that were included in a block produced elsewhere.
+    ///
+    /// Returns the txs that were still pending, in the order of `ids`.
+    pub fn remove_included(&mut self, ids: &[TxId]) -> Vec<TxV1> {
+        let mut out = Vec::new();
+        for id in ids {
+            if let Some(entry) = self.take(id) {
+                self.emit(MempoolEvent::Included { id: *id });
+                out.push(entry.tx);
+            }
+        }
+        out
+    }
+
+    /// Remove an entry and keep the per-sender index in sync.
+    fn take(&mut self, id: &TxId) -> Option<PoolEntry> {
+        let entry = self.by_id.remove(id)?;
+        if let Some(journal) = &mut self.journal {
+            if journal.append_remove(id).is_err() {
+                self.stats.journal_errors += 1;
+            }
+        }
+        if let Some(queued) = self.by_sender.get_mut(&entry.tx.from) {
+            queued.remove(&(entry.tx.nonce, *id));
+            if queued.is_empty() {
+                self.by_sender.remove(&entry.tx.from);
+            }
+        }
+        Some(entry)
+    }
+
+    /// Total fees + transfer amounts of the sender's queued txs, skipping `tx`
+    /// itself and (under replace-by-fee) the same-nonce tx it would replace.
+    fn committed_cost(
+        &self,
+        from: &Address,
+        nonce: Nonce,
+        id: &TxId,
+        accounts: &impl AccountProvider,
+    ) -> u128 {
+        let Some(queued) = self.by_sender.get(from) else {
+            return 0;
+        };
+        queued
+            .iter()
+            .filter(|(n, other)| other != id && !(self.replace_by_fee && *n == nonce))
+            .filter_map(|(_, id)| self.by_id.get(id))
+            .map(|e| e.tx.fee as u128 + accounts.transfer_amount(&e.tx) as u128)
+            .sum()
+    }
+
+    /// Evict every entry whose TTL has elapsed at `now`.
+    ///
+    /// Returns the evicted ids ordered by (inserted_at ASC, txid ASC).
+    /// No-op when the mempool was configured without a TTL.
+    pub fn expire(&mut self, now: u64) -> Vec<TxId> {
+        let Some(ttl) = self.ttl else {
+            return Vec::new();
+        };
+
+        let mut expired: Vec<(u64, TxId)> = self
+            .by_id
+            .iter()
+            .filter(|(_, e)| now >= e.inserted_at.saturating_add(ttl))
+            .map(|(id, e)| (e.inserted_at, *id))
+            .collect();
+        expired.sort();
+
+        for (_, id) in &expired {
+            self.take(id);
+            self.emit(MempoolEvent::Evicted {
+                id: *id,
+                reason: EvictionReason::Expired,
+            });
+        }
+        self.stats.expired += expired.len() as u64;
+
+        expired.into_iter().map(|(_, id)| id).collect()
     }
 
     /// Insert a TxV1 after enforcing Week 2 policy rules.
@@ -173,56 +697,401 @@ impl TxMempool {
     pub fn insert(
         &mut self,
         tx: TxV1,
-        nonce_provider: &impl NonceProvider,
+        accounts: &impl AccountProvider,
     ) -> Result<TxId, TxMempoolError> {
-        // min fee
-        if tx.fee < self.min_fee {
-            return Err(TxMempoolError::FeeTooLow {
-                min_fee: self.min_fee,
-                got: tx.fee,
+        self.insert_from(tx, &TxSource::Local, accounts)
+    }
+
+    /// Insert a tx on behalf of `source`.
+    ///
+    /// Over-budget or throttled sources get [`TxMempoolError::RateLimited`]
+    /// before any encoding or signature work. Other outcomes feed the
+    /// source's [`SourceScore`]. [`TxMempool::insert`] is `insert_from` with
+    /// [`TxSource::Local`].
+    pub fn insert_from(
+        &mut self,
+        tx: TxV1,
+        source: &TxSource,
+        accounts: &impl AccountProvider,
+    ) -> Result<TxId, TxMempoolError> {
+        let now = self.clock.now();
+        self.check_source(source, now)?;
+        let result = self.admit_checked(tx, accounts, now);
+        self.record_source(source, now, &result);
+        result
+    }
+
+    /// [`TxMempool::insert_from`] for a tx still in its received bytes.
+    ///
+    /// Policy and the signature are checked on the borrowed view; the tx is
+    /// copied out only once they pass, so rejected input costs no allocation.
+    pub fn insert_ref_from(
+        &mut self,
+        tx: &TxV1Ref<'_>,
+        source: &TxSource,
+        accounts: &impl AccountProvider,
+    ) -> Result<TxId, TxMempoolError> {
+        let now = self.clock.now();
+        self.check_source(source, now)?;
+        let result = self.admit_ref(tx, accounts, now);
+        self.record_source(source, now, &result);
+        result
+    }
+
+    fn admit_ref(
+        &mut self,
+        tx: &TxV1Ref<'_>,
+        accounts: &impl AccountProvider,
+        inserted_at: u64,
+    ) -> Result<TxId, TxMempoolError> {
+        let id = self.check_policy_ref(tx, accounts)?;
+        verify_signature_ref(self.sig_cache.as_deref(), tx)?;
+        let tx = tx.to_owned_tx();
+        self.check_balance(&tx, &id, accounts)?;
+        self.admit(id, tx, inserted_at)
+    }
+
+    pub(crate) fn check_source(
+        &mut self,
+        source: &TxSource,
+        now: u64,
+    ) -> Result<(), TxMempoolError> {
+        self.limiter.check(source, now)
+    }
+
+    pub(crate) fn record_source(
+        &mut self,
+        source: &TxSource,
+        now: u64,
+        result: &Result<TxId, TxMempoolError>,
+    ) {
+        self.limiter.record(source, now, result);
+    }
+
+    /// Admission score of `source`, if it has submitted anything.
+    pub fn source_score(&self, source: &TxSource) -> Option<SourceScore> {
+        self.limiter.score(source)
+    }
+
+    /// Scores of every tracked source, ordered by source.
+    pub fn source_scores(&self) -> Vec<(TxSource, SourceScore)> {
+        self.limiter.scores()
+    }
+
+    /// Sources currently throttled for exceeding their rejection budget; the
+    /// p2p layer can use this to disconnect abusive peers.
+    pub fn throttled_sources(&self) -> Vec<TxSource> {
+        let now = self.clock.now();
+        self.limiter
+            .scores()
+            .into_iter()
+            .filter(|(_, score)| score.is_throttled(now))
+            .map(|(source, _)| source)
+            .collect()
+    }
+
+    /// Drop tracking state for `source` (e.g. when a peer disconnects).
+    pub fn forget_source(&mut self, source: &TxSource) -> bool {
+        self.limiter.forget(source)
+    }
+
+    /// Full admission path with an explicit insertion stamp.
+    fn admit_checked(
+        &mut self,
+        tx: TxV1,
+        accounts: &impl AccountProvider,
+        inserted_at: u64,
+    ) -> Result<TxId, TxMempoolError> {
+        let (id, unsigned) = self.check_policy(&tx, accounts)?;
+        verify_signature(self.sig_cache.as_deref(), &id, &tx, &unsigned)?;
+        self.admit(id, tx, inserted_at)
+    }
+
+    /// Insert many txs on behalf of `source`, verifying their signatures as
+    /// one ed25519 batch.
+    ///
+    /// Results are returned in input order and are identical to calling
+    /// [`TxMempool::insert_from`] on each tx in turn; only the signature work
+    /// is shared (with per-tx fallback when the batch contains a bad
+    /// signature). Txs are batched no further than `source` has budget left,
+    /// so a batch can't overshoot it.
+    pub fn insert_batch(
+        &mut self,
+        txs: Vec<TxV1>,
+        source: &TxSource,
+        accounts: &impl AccountProvider,
+    ) -> Vec<Result<TxId, TxMempoolError>> {
+        let now = self.clock.now();
+        let mut results = Vec::with_capacity(txs.len());
+        let mut txs = txs.into_iter();
+        while txs.len() > 0 {
+            match self.limiter.check_many(source, now, txs.len()) {
+                Ok(n) => {
+                    let chunk: Vec<TxV1> = txs.by_ref().take(n).collect();
+                    for result in self.admit_batch(chunk, accounts, now) {
+                        self.record_source(source, now, &result);
+                        results.push(result);
+                    }
+                }
+                Err(e) => {
+                    txs.next();
+                    results.push(Err(e));
+                }
+            }
+        }
+        results
+    }
+
+    fn admit_batch(
+        &mut self,
+        txs: Vec<TxV1>,
+        accounts: &impl AccountProvider,
+        inserted_at: u64,
+    ) -> Vec<Result<TxId, TxMempoolError>> {
+        // Stateless checks plus balance against the pre-batch pool. The pool only
+        // grows during the batch, so anything failing here fails in order too.
+        let mut results: Vec<Result<TxId, TxMempoolError>> = Vec::with_capacity(txs.len());
+        let mut pending = Vec::new();
+        for (i, tx) in txs.iter().enumerate() {
+            let checked = self.check_policy(tx, accounts).and_then(|(id, unsigned)| {
+                let vk =
+                    pubkey_from_bytes(&tx.from).map_err(|_| TxMempoolError::InvalidPublicKey)?;
+                Ok((id, unsigned, vk))
             });
+            match checked {
+                Ok((id, unsigned, vk)) => {
+                    pending.push((i, id, unsigned, vk));
+                    results.push(Ok(id));
+                }
+                Err(e) => results.push(Err(e)),
+            }
+        }
+
+        // Cached signatures skip the batch entirely.
+        let mut verdicts = vec![None; txs.len()];
+        if let Some(cache) = &self.sig_cache {
+            pending.retain(|(i, id, _, _)| {
+                let hit = cache.contains(id, &txs[*i].sig);
+                if hit {
+                    verdicts[*i] = Some((*id, true));
+                }
+                !hit
+            });
+        }
+
+        let items: Vec<(_, _, &[u8], SignatureBytes)> = pending
+            .iter()
+            .map(|(i, id, unsigned, vk)| (*id, *vk, unsigned.as_slice(), txs[*i].sig))
+            .collect();
+        let sig_ok = match &self.sig_cache {
+            Some(cache) => cache.verify_misses(&items),
+            None => verify_bytes_batch(
+                &items
+                    .iter()
+                    .map(|(_, vk, msg, sig)| (*vk, *msg, *sig))
+                    .collect::<Vec<_>>(),
+            ),
+        };
+
+        for ((i, id, _, _), ok) in pending.iter().zip(sig_ok) {
+            verdicts[*i] = Some((*id, ok));
+        }
+
+        // Stateful admission in input order, mirroring `insert`'s check order.
+        for (i, tx) in txs.into_iter().enumerate() {
+            let Some((id, sig_ok)) = verdicts[i] else {
+                continue;
+            };
+            results[i] = match self.check_balance(&tx, &id, accounts) {
+                Err(e) => Err(e),
+                Ok(()) if !sig_ok => Err(TxMempoolError::InvalidSignature),
+                Ok(()) => self.admit(id, tx, inserted_at),
+            };
+        }
+
+        results
+    }
+
+    /// Fee, nonce and balance rules. Returns the txid and canonical unsigned bytes.
+    pub(crate) fn check_policy(
+        &self,
+        tx: &TxV1,
+        accounts: &impl AccountProvider,
+    ) -> Result<(TxId, Vec<u8>), TxMempoolError> {
+        check_payload(tx.payload.len())?;
+        self.check_fee_and_nonce(&tx.from, tx.nonce, tx.fee, accounts)?;
+
+        // canonical unsigned bytes
+        let unsigned = encode_tx_v1_unsigned(tx).map_err(|_| TxMempoolError::CodecError)?;
+
+        // compute txid (hash of canonical unsigned bytes)
+        let id = txid_v1(tx).map_err(|_| TxMempoolError::CodecError)?;
+
+        // checked before the signature so unfunded senders are turned away cheaply
+        self.replacement_target(&tx.from, tx.nonce, tx.fee, &id)?;
+        self.check_balance(tx, &id, accounts)?;
+
+        Ok((id, unsigned))
+    }
+
+    /// [`TxMempool::check_policy`] on a borrowed tx, which is already
+    /// canonical. The transfer amount needs the owned tx, so only the fee is
+    /// checked against the balance here; returns the txid.
+    fn check_policy_ref(
+        &self,
+        tx: &TxV1Ref<'_>,
+        accounts: &impl AccountProvider,
+    ) -> Result<TxId, TxMempoolError> {
+        check_payload(tx.payload.len())?;
+        self.check_fee_and_nonce(tx.from, tx.nonce, tx.fee, accounts)?;
+        let id = tx.txid();
+        self.replacement_target(tx.from, tx.nonce, tx.fee, &id)?;
+        self.check_cost(tx.from, tx.nonce, tx.fee as u128, &id, accounts)?;
+        Ok(id)
+    }
+
+    fn check_fee_and_nonce(
+        &self,
+        from: &Address,
+        nonce: Nonce,
+        fee: u64,
+        accounts: &impl AccountProvider,
+    ) -> Result<(), TxMempoolError> {
+        // min fee
+        let min_fee = self.min_fee();
+        if fee < min_fee {
+            return Err(TxMempoolError::FeeTooLow { min_fee, got: fee });
         }
 
         // nonce sanity vs snapshot
-        let expected = nonce_provider.expected_nonce(&tx.from);
-        if tx.nonce < expected {
+        let expected = accounts.expected_nonce(from);
+        if nonce < expected {
             return Err(TxMempoolError::NonceTooLow {
                 expected,
-                got: tx.nonce,
+                got: nonce,
             });
         }
+        Ok(())
+    }
 
-        // canonical unsigned bytes
-        let unsigned = encode_tx_v1_unsigned(&tx).map_err(|_| TxMempoolError::CodecError)?;
+    /// Balance must cover everything this sender has queued plus `tx`.
+    fn check_balance(
+        &self,
+        tx: &TxV1,
+        id: &TxId,
+        accounts: &impl AccountProvider,
+    ) -> Result<(), TxMempoolError> {
+        let cost = tx.fee as u128 + accounts.transfer_amount(tx) as u128;
+        self.check_cost(&tx.from, tx.nonce, cost, id, accounts)
+    }
 
-        // verify signature (from is interpreted as ed25519 pubkey bytes in Week 2)
-        let vk = pubkey_from_bytes(&tx.from).map_err(|_| TxMempoolError::InvalidPublicKey)?;
-        if !verify_bytes(&vk, &unsigned, &tx.sig) {
-            return Err(TxMempoolError::InvalidSignature);
+    /// Balance must cover everything `from` has queued plus `cost`.
+    fn check_cost(
+        &self,
+        from: &Address,
+        nonce: Nonce,
+        cost: u128,
+        id: &TxId,
+        accounts: &impl AccountProvider,
+    ) -> Result<(), TxMempoolError> {
+        let balance = accounts.balance(from);
+        let required = self.committed_cost(from, nonce, id, accounts) + cost;
+        if required > balance as u128 {
+            return Err(TxMempoolError::InsufficientBalance {
+                balance,
+                required: u64::try_from(required).unwrap_or(u64::MAX),
+            });
         }
+        Ok(())
+    }
 
-        // compute txid (hash of canonical unsigned bytes)
-        let id = txid_v1(&tx).map_err(|_| TxMempoolError::CodecError)?;
+    /// Under replace-by-fee, the pending tx with the same sender and nonce that
+    /// `tx` would replace. Errors if that tx pays at least as much.
+    fn replacement_target(
+        &self,
+        from: &Address,
+        nonce: Nonce,
+        fee: u64,
+        id: &TxId,
+    ) -> Result<Option<TxId>, TxMempoolError> {
+        if !self.replace_by_fee {
+            return Ok(None);
+        }
+        let Some(queued) = self.by_sender.get(from) else {
+            return Ok(None);
+        };
+        let existing = queued
+            .range((nonce, [0u8; 32])..=(nonce, [0xFFu8; 32]))
+            .map(|(_, other)| *other)
+            .find(|other| other != id);
+        let Some(old) = existing else {
+            return Ok(None);
+        };
+        let existing_fee = self.by_id[&old].tx.fee;
+        if fee > existing_fee {
+            Ok(Some(old))
+        } else {
+            Err(TxMempoolError::ReplacementUnderpriced {
+                existing_fee,
+                got: fee,
+            })
+        }
+    }
 
-        // dedupe
+    pub(crate) fn sig_cache(&self) -> Option<Arc<SigCache>> {
+        self.sig_cache.clone()
+    }
+
+    pub(crate) fn now(&self) -> u64 {
+        self.clock.now()
+    }
+
+    /// Dedupe and store a tx that passed every policy and signature check.
+    pub(crate) fn admit(
+        &mut self,
+        id: TxId,
+        tx: TxV1,
+        inserted_at: u64,
+    ) -> Result<TxId, TxMempoolError> {
         if self.by_id.contains_key(&id) {
             return Err(TxMempoolError::Duplicate);
         }
+        let replaced = self.replacement_target(&tx.from, tx.nonce, tx.fee, &id)?;
+        if let Some(old) = replaced {
+            self.take(&old);
+        }
 
-        self.by_id.insert(id, tx);
+        if let Some(journal) = &mut self.journal {
+            if journal.append_add(inserted_at, &tx).is_err() {
+                self.stats.journal_errors += 1;
+            }
+        }
+        self.by_sender
+            .entry(tx.from)
+            .or_default()
+            .insert((tx.nonce, id));
+        self.by_id.insert(id, PoolEntry { tx, inserted_at });
+        self.emit(match replaced {
+            Some(old) => MempoolEvent::Replaced { old, new: id },
+            None => MempoolEvent::Added { id },
+        });
         Ok(id)
     }
 
     /// Drain up to `max` ready transactions under fee-priority + fairness.
+    ///
+    /// Each call counts as one block for the dynamic fee floor, even if
+    /// nothing is drained.
     pub fn drain_ready(&mut self, max: usize, nonce_provider: &impl NonceProvider) -> Vec<TxV1> {
         if max == 0 || self.by_id.is_empty() {
+            self.update_fee_market();
             return Vec::new();
         }
 
         // Gather ready candidates.
         let mut candidates: Vec<(u64, TxId, Address)> = Vec::with_capacity(self.by_id.len());
 
-        for (id, tx) in &self.by_id {
+        for (id, PoolEntry { tx, .. }) in &self.by_id {
             let expected = nonce_provider.expected_nonce(&tx.from);
             if tx.nonce == expected {
                 candidates.push((tx.fee, *id, tx.from));
@@ -254,13 +1123,69 @@ impl TxMempool {
         }
 
         for id in selected_ids {
-            if let Some(tx) = self.by_id.remove(&id) {
-                out.push(tx);
+            if let Some(entry) = self.take(&id) {
+                self.emit(MempoolEvent::Included { id });
+                out.push(entry.tx);
             }
         }
 
+        self.update_fee_market();
         out
     }
+
+    /// One drain is one block: move the fee floor toward the backlog.
+    fn update_fee_market(&mut self) {
+        let pending = self.by_id.len();
+        if let Some(market) = &mut self.fee_market {
+            market.update(pending);
+        }
+    }
+}
+
+/// Strict signature check of `tx` over its canonical unsigned bytes, answered
+/// from `cache` when possible. `from` is interpreted as ed25519 pubkey bytes.
+pub(crate) fn verify_signature(
+    cache: Option<&SigCache>,
+    id: &TxId,
+    tx: &TxV1,
+    unsigned: &[u8],
+) -> Result<(), TxMempoolError> {
+    let vk = pubkey_from_bytes(&tx.from).map_err(|_| TxMempoolError::InvalidPublicKey)?;
+    let sig_ok = match cache {
+        Some(cache) => cache.verify(id, &vk, unsigned, &tx.sig),
+        None => verify_bytes(&vk, unsigned, &tx.sig),
+    };
+    if sig_ok {
+        Ok(())
+    } else {
+        Err(TxMempoolError::InvalidSignature)
+    }
+}
+
+/// Payloads are held to what every peer's decoder accepts.
+fn check_payload(len: usize) -> Result<(), TxMempoolError> {
+    let max = DecodeLimits::DEFAULT.max_payload_bytes;
+    if len > max as usize {
+        return Err(TxMempoolError::PayloadTooLarge { max, got: len });
+    }
+    Ok(())
+}
+
+/// [`verify_signature`] on a borrowed tx.
+pub(crate) fn verify_signature_ref(
+    cache: Option<&SigCache>,
+    tx: &TxV1Ref<'_>,
+) -> Result<(), TxMempoolError> {
+    let vk = pubkey_from_bytes(tx.from).map_err(|_| TxMempoolError::InvalidPublicKey)?;
+    let sig_ok = match cache {
+        Some(cache) => verify_tx_v1_ref_cached(cache, &vk, tx),
+        None => verify_tx_v1_ref(&vk, tx),
+    };
+    if sig_ok {
+        Ok(())
+    } else {
+        Err(TxMempoolError::InvalidSignature)
+    }
 }
 
 #[cfg(test)]
@@ -389,55 +1314,11 @@ mod tests {
     // TxMempool (Week 2 policy) tests
     // -----------------------------
 
-    use ed25519_dalek::{SigningKey, VerifyingKey};
-    use novai_codec::encode_tx_v1_unsigned;
+    use novai_codec::{encode_tx_v1_signed, encode_tx_v1_unsigned};
     use novai_crypto::sign_bytes;
-    use novai_types::{SignatureBytes, TxVersion};
-
-    fn test_keypair(seed: u8) -> (SigningKey, VerifyingKey) {
-        let sk = SigningKey::from_bytes(&[seed; 32]);
-        let vk: VerifyingKey = sk.verifying_key();
-        (sk, vk)
-    }
+    use novai_types::TxVersion;
 
-    #[derive(Default)]
-    struct TestNonceProvider {
-        map: HashMap<Address, u64>,
-    }
-
-    impl TestNonceProvider {
-        fn set(&mut self, from: Address, nonce: u64) {
-            self.map.insert(from, nonce);
-        }
-    }
-
-    impl NonceProvider for TestNonceProvider {
-        fn expected_nonce(&self, from: &Address) -> u64 {
-            *self.map.get(from).unwrap_or(&0)
-        }
-    }
-
-    fn make_signed_tx(
-        from_sk: &SigningKey,
-        from_pk_bytes: Address,
-        nonce: u64,
-        fee: u64,
-        payload: &[u8],
-    ) -> TxV1 {
-        let mut tx = TxV1 {
-            version: TxVersion::V1,
-            from: from_pk_bytes,
-            nonce,
-            fee,
-            payload: payload.to_vec(),
-            sig: [0u8; 64],
-        };
-
-        let unsigned = encode_tx_v1_unsigned(&tx).expect("unsigned encode");
-        let sig: SignatureBytes = sign_bytes(from_sk, &unsigned);
-        tx.sig = sig;
-        tx
-    }
+    use crate::test_support::{make_signed_tx, test_keypair, TestNonceProvider};
 
     #[test]
     fn rejects_below_min_fee() {
@@ -453,6 +1334,26 @@ mod tests {
         assert!(matches!(err, TxMempoolError::FeeTooLow { .. }));
     }
 
+    #[test]
+    fn rejects_payloads_peers_would_not_decode() {
+        let (sk, vk) = test_keypair(8);
+        let from: Address = vk.to_bytes();
+        let np = TestNonceProvider::default();
+        let max = DecodeLimits::DEFAULT.max_payload_bytes as usize;
+
+        let mut mp = TxMempool::new(1, 2);
+        let over = make_signed_tx(&sk, from, 0, 1, &vec![0u8; max + 1]);
+        assert_eq!(
+            mp.insert(over, &np).unwrap_err(),
+            TxMempoolError::PayloadTooLarge {
+                max: max as u32,
+                got: max + 1
+            }
+        );
+        mp.insert(make_signed_tx(&sk, from, 0, 1, &vec![0u8; max]), &np)
+            .unwrap();
+    }
+
     #[test]
     fn rejects_nonce_too_low() {
         let (sk, vk) = test_keypair(9);
@@ -560,4 +1461,659 @@ mod tests {
         assert!(payloads.contains(&b"s2_mid".to_vec()));
         assert!(!payloads.contains(&b"s1_lo".to_vec()));
     }
+
+    #[test]
+    fn expire_evicts_entries_past_ttl() {
+        let (sk, vk) = test_keypair(11);
+        let from: Address = vk.to_bytes();
+
+        let mut np = TestNonceProvider::default();
+        np.set(from, 0);
+
+        let clock = Arc::new(ManualClock::new(100));
+        let mut mp = TxMempool::with_config(TxMempoolConfig {
+            ttl: Some(10),
+            ..TxMempoolConfig::default()
+        })
+        .with_clock(clock.clone());
+
+        let old_id = mp
+            .insert(make_signed_tx(&sk, from, 0, 1, b"old"), &np)
+            .unwrap();
+        clock.advance(5);
+        // Huge nonce gap: never becomes ready, only TTL can remove it.
+        let gap_id = mp
+            .insert(make_signed_tx(&sk, from, 1_000, 1, b"gap"), &np)
+            .unwrap();
+        assert_eq!(mp.inserted_at(&old_id), Some(100));
+        assert_eq!(mp.inserted_at(&gap_id), Some(105));
+
+        assert!(mp.expire(109).is_empty());
+        assert_eq!(mp.expire(110), vec![old_id]);
+        assert_eq!(mp.expire(115), vec![gap_id]);
+
+        assert!(mp.is_empty());
+        assert_eq!(mp.stats().expired, 2);
+    }
+
+    #[test]
+    fn expire_without_ttl_is_noop() {
+        let (sk, vk) = test_keypair(12);
+        let from: Address = vk.to_bytes();
+
+        let np = TestNonceProvider::default();
+        let mut mp = TxMempool::new(1, 2).with_clock(Arc::new(ManualClock::new(0)));
+        mp.insert(make_signed_tx(&sk, from, 0, 1, b"p"), &np)
+            .unwrap();
+
+        assert!(mp.expire(u64::MAX).is_empty());
+        assert_eq!(mp.len(), 1);
+        assert_eq!(mp.stats(), TxMempoolStats::default());
+    }
+
+    #[test]
+    fn expire_orders_by_insertion_then_txid() {
+        let (sk, vk) = test_keypair(13);
+        let from: Address = vk.to_bytes();
+
+        let np = TestNonceProvider::default();
+        let clock = Arc::new(ManualClock::new(0));
+        let mut mp = TxMempool::with_config(TxMempoolConfig {
+            ttl: Some(1),
+            ..TxMempoolConfig::default()
+        })
+        .with_clock(clock.clone());
+
+        clock.set(7);
+        let late = mp
+            .insert(make_signed_tx(&sk, from, 0, 1, b"late"), &np)
+            .unwrap();
+        clock.set(3);
+        let a = mp
+            .insert(make_signed_tx(&sk, from, 0, 1, b"a"), &np)
+            .unwrap();
+        let b = mp
+            .insert(make_signed_tx(&sk, from, 0, 1, b"b"), &np)
+            .unwrap();
+
+        let (first, second) = if a < b { (a, b) } else { (b, a) };
+        assert_eq!(mp.expire(100), vec![first, second, late]);
+    }
+
+    #[test]
+    fn rejects_zero_balance_sender() {
+        let (sk, vk) = test_keypair(14);
+        let from: Address = vk.to_bytes();
+
+        let mut np = TestNonceProvider::default();
+        np.set_balance(from, 0);
+
+        let mut mp = TxMempool::new(1, 2);
+        let err = mp
+            .insert(make_signed_tx(&sk, from, 0, 1, b"p"), &np)
+            .unwrap_err();
+        assert_eq!(
+            err,
+            TxMempoolError::InsufficientBalance {
+                balance: 0,
+                required: 1
+            }
+        );
+        assert!(mp.is_empty());
+    }
+
+    #[test]
+    fn balance_covers_cumulative_fees_and_amounts() {
+        let (sk, vk) = test_keypair(15);
+        let from: Address = vk.to_bytes();
+
+        let mut np = TestNonceProvider::default();
+        np.set_balance(from, 100);
+
+        let mut mp = TxMempool::new(1, 10);
+
+        // fee 10 + amount 50 = 60
+        let first = make_signed_tx(&sk, from, 0, 10, &50u64.to_le_bytes());
+        let first_id = mp.insert(first, &np).unwrap();
+
+        // 60 queued + fee 5 + amount 36 = 101 > 100
+        let too_much = make_signed_tx(&sk, from, 1, 5, &36u64.to_le_bytes());
+        let err = mp.insert(too_much, &np).unwrap_err();
+        assert_eq!(
+            err,
+            TxMempoolError::InsufficientBalance {
+                balance: 100,
+                required: 101
+            }
+        );
+
+        // 60 queued + fee 5 + amount 35 = 100 fits exactly
+        let fits = make_signed_tx(&sk, from, 1, 5, &35u64.to_le_bytes());
+        mp.insert(fits, &np).unwrap();
+
+        // Dropping the first tx frees its share of the balance again.
+        mp.remove(&first_id).unwrap();
+        let after = make_signed_tx(&sk, from, 2, 60, b"p");
+        mp.insert(after, &np).unwrap();
+        assert_eq!(mp.len(), 2);
+    }
+
+    #[test]
+    fn duplicate_not_double_counted_against_balance() {
+        let (sk, vk) = test_keypair(16);
+        let from: Address = vk.to_bytes();
+
+        let mut np = TestNonceProvider::default();
+        np.set_balance(from, 10);
+
+        let mut mp = TxMempool::new(1, 10);
+        let tx = make_signed_tx(&sk, from, 0, 10, b"p");
+        mp.insert(tx.clone(), &np).unwrap();
+        assert_eq!(mp.insert(tx, &np).unwrap_err(), TxMempoolError::Duplicate);
+    }
+
+    #[test]
+    fn insert_batch_matches_sequential_insert() {
+        let (sk1, vk1) = test_keypair(17);
+        let (sk2, vk2) = test_keypair(18);
+        let from1: Address = vk1.to_bytes();
+        let from2: Address = vk2.to_bytes();
+
+        let mut np = TestNonceProvider::default();
+        np.set(from1, 3);
+        np.set_balance(from2, 15);
+
+        let mut bad_sig = make_signed_tx(&sk1, from1, 3, 5, b"bad");
+        bad_sig.sig[10] ^= 0xFF;
+
+        let txs = vec![
+            make_signed_tx(&sk1, from1, 3, 5, b"ok1"),
+            bad_sig,
+            make_signed_tx(&sk1, from1, 2, 5, b"stale"),
+            make_signed_tx(&sk1, from1, 4, 0, b"cheap"),
+            make_signed_tx(&sk2, from2, 0, 10, b"funded"),
+            // Only fails balance because of the tx admitted just before it.
+            make_signed_tx(&sk2, from2, 1, 10, b"overdrawn"),
+            make_signed_tx(&sk1, from1, 3, 5, b"ok1"),
+            make_signed_tx(&sk1, from1, 4, 7, b"ok2"),
+        ];
+
+        let mut sequential = TxMempool::new(1, 10);
+        let expected: Vec<_> = txs
+            .iter()
+            .map(|tx| sequential.insert(tx.clone(), &np))
+            .collect();
+
+        let mut batched = TxMempool::new(1, 10);
+        let got = batched.insert_batch(txs, &TxSource::Local, &np);
+
+        assert_eq!(got, expected);
+        assert_eq!(got[1], Err(TxMempoolError::InvalidSignature));
+        assert!(matches!(
+            got[5],
+            Err(TxMempoolError::InsufficientBalance { .. })
+        ));
+        assert_eq!(got[6], Err(TxMempoolError::Duplicate));
+        assert_eq!(batched.len(), 3);
+        assert_eq!(batched.len(), sequential.len());
+    }
+
+    #[test]
+    fn insert_ref_matches_owned_insert() {
+        let (sk1, vk1) = test_keypair(17);
+        let (sk2, vk2) = test_keypair(18);
+        let from1: Address = vk1.to_bytes();
+        let from2: Address = vk2.to_bytes();
+
+        let mut np = TestNonceProvider::default();
+        np.set(from1, 3);
+        np.set_balance(from2, 15);
+
+        let mut bad_sig = make_signed_tx(&sk1, from1, 3, 5, b"bad");
+        bad_sig.sig[10] ^= 0xFF;
+
+        let txs = [
+            make_signed_tx(&sk1, from1, 3, 5, b"ok1"),
+            bad_sig,
+            make_signed_tx(&sk1, from1, 2, 5, b"stale"),
+            make_signed_tx(&sk1, from1, 4, 0, b"cheap"),
+            make_signed_tx(&sk2, from2, 0, 10, b"funded"),
+            make_signed_tx(&sk2, from2, 1, 10, b"overdrawn"),
+            make_signed_tx(&sk1, from1, 3, 5, b"ok1"),
+            make_signed_tx(&sk1, from1, 4, 7, b"ok2"),
+        ];
+        let peer = TxSource::Peer(vec![1]);
+
+        let mut owned = TxMempool::new(1, 10);
+        let expected: Vec<_> = txs
+            .iter()
+            .map(|tx| owned.insert_from(tx.clone(), &peer, &np))
+            .collect();
+
+        let mut borrowed = TxMempool::new(1, 10);
+        let got: Vec<_> = txs
+            .iter()
+            .map(|tx| {
+                let bytes = encode_tx_v1_signed(tx).unwrap();
+                borrowed.insert_ref_from(&TxV1Ref::decode(&bytes).unwrap(), &peer, &np)
+            })
+            .collect();
+
+        assert_eq!(got, expected);
+        assert_eq!(got[1], Err(TxMempoolError::InvalidSignature));
+        assert_eq!(borrowed.source_score(&peer), owned.source_score(&peer));
+        assert_eq!(borrowed.len(), 3);
+    }
+
+    #[test]
+    fn sig_cache_shared_across_insert_paths() {
+        let (sk, vk) = test_keypair(19);
+        let from: Address = vk.to_bytes();
+        let np = TestNonceProvider::default();
+
+        let cache = Arc::new(SigCache::new(16));
+        let tx_a = make_signed_tx(&sk, from, 0, 1, b"a");
+        let tx_b = make_signed_tx(&sk, from, 0, 1, b"b");
+
+        let mut first = TxMempool::new(1, 10).with_sig_cache(cache.clone());
+        first.insert(tx_a.clone(), &np).unwrap();
+        assert_eq!(cache.metrics().insertions, 1);
+
+        // A second pool (e.g. after restart) hits the cache for tx_a only.
+        let mut second = TxMempool::new(1, 10).with_sig_cache(cache.clone());
+        let results = second.insert_batch(vec![tx_a.clone(), tx_b], &TxSource::Local, &np);
+        assert!(results.iter().all(Result::is_ok));
+        let m = cache.metrics();
+        assert_eq!(m.hits, 1);
+        assert_eq!(m.insertions, 2);
+
+        // A forged signature on a cached txid is never accepted.
+        let mut forged = tx_a;
+        forged.sig[3] ^= 0x10;
+        let mut third = TxMempool::new(1, 10).with_sig_cache(cache.clone());
+        assert_eq!(
+            third.insert(forged, &np).unwrap_err(),
+            TxMempoolError::InvalidSignature
+        );
+    }
+
+    #[test]
+    fn journal_survives_restart_and_drops_stale() {
+        let (sk, vk) = test_keypair(20);
+        let from: Address = vk.to_bytes();
+        let path = crate::test_support::temp_path("mempool-restart");
+
+        let mut np = TestNonceProvider::default();
+        let clock = Arc::new(ManualClock::new(50));
+
+        let (kept, drained, removed, included) = {
+            let mut mp = TxMempool::new(1, 10).with_clock(clock.clone());
+            let report = mp.attach_journal(&path, &np).unwrap();
+            assert_eq!(report, RestoreReport::default());
+
+            let drained = mp
+                .insert(make_signed_tx(&sk, from, 0, 9, b"drained"), &np)
+                .unwrap();
+            let included = mp
+                .insert(make_signed_tx(&sk, from, 1, 1, b"included"), &np)
+                .unwrap();
+            let kept = mp
+                .insert(make_signed_tx(&sk, from, 2, 1, b"kept"), &np)
+                .unwrap();
+            let removed = mp
+                .insert(make_signed_tx(&sk, from, 3, 1, b"removed"), &np)
+                .unwrap();
+
+            assert_eq!(mp.drain_ready(1, &np).len(), 1);
+            mp.remove(&removed).unwrap();
+            (kept, drained, removed, included)
+        };
+
+        // After "restart" the chain has moved past nonce 1.
+        np.set(from, 2);
+        clock.set(99);
+        let mut mp = TxMempool::new(1, 10).with_clock(clock.clone());
+        let report = mp.attach_journal(&path, &np).unwrap();
+
+        assert_eq!(report.restored, 1);
+        assert_eq!(report.corrupt, 0);
+        assert_eq!(report.stale.len(), 1);
+        assert_eq!(report.stale[0].0, included);
+        assert!(matches!(
+            report.stale[0].1,
+            TxMempoolError::NonceTooLow { .. }
+        ));
+        assert!(mp.contains(&kept));
+        assert!(!mp.contains(&drained) && !mp.contains(&removed));
+        // Original insertion stamp is kept so TTL keeps counting.
+        assert_eq!(mp.inserted_at(&kept), Some(50));
+
+        // The restore compacted the file down to the surviving tx.
+        let replay = TxJournal::replay(&path).unwrap();
+        assert_eq!(replay.entries.len(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn journal_reports_corrupt_records() {
+        let (sk, vk) = test_keypair(21);
+        let from: Address = vk.to_bytes();
+        let path = crate::test_support::temp_path("mempool-corrupt");
+        let np = TestNonceProvider::default();
+
+        {
+            let mut mp = TxMempool::new(1, 10);
+            mp.attach_journal(&path, &np).unwrap();
+            mp.insert(make_signed_tx(&sk, from, 0, 1, b"a"), &np)
+                .unwrap();
+        }
+        let mut bytes = std::fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0x01;
+        std::fs::write(&path, &bytes).unwrap();
+
+        let mut mp = TxMempool::new(1, 10);
+        let report = mp.attach_journal(&path, &np).unwrap();
+        assert_eq!(report.restored, 0);
+        assert_eq!(report.corrupt, 1);
+        assert!(mp.is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn query_apis_split_ready_queued_and_by_sender() {
+        let (sk1, vk1) = test_keypair(22);
+        let (sk2, vk2) = test_keypair(23);
+        let from1: Address = vk1.to_bytes();
+        let from2: Address = vk2.to_bytes();
+
+        let mut np = TestNonceProvider::default();
+        np.set(from1, 1);
+
+        let mut mp = TxMempool::new(1, 10);
+        mp.insert(make_signed_tx(&sk1, from1, 3, 4, b"q3"), &np)
+            .unwrap();
+        mp.insert(make_signed_tx(&sk1, from1, 1, 2, b"r1"), &np)
+            .unwrap();
+        mp.insert(make_signed_tx(&sk1, from1, 2, 8, b"q2"), &np)
+            .unwrap();
+        mp.insert(make_signed_tx(&sk2, from2, 0, 6, b"r0"), &np)
+            .unwrap();
+
+        let payloads = |txs: Vec<&TxV1>| -> Vec<Vec<u8>> {
+            txs.into_iter().map(|t| t.payload.clone()).collect()
+        };
+        assert_eq!(
+            payloads(mp.pending_by_sender(&from1)),
+            vec![b"r1".to_vec(), b"q2".to_vec(), b"q3".to_vec()]
+        );
+        assert_eq!(
+            payloads(mp.ready(&np)),
+            vec![b"r0".to_vec(), b"r1".to_vec()]
+        );
+        assert_eq!(
+            payloads(mp.queued(&np)),
+            vec![b"q2".to_vec(), b"q3".to_vec()]
+        );
+        assert!(mp.pending_by_sender(&[0u8; 32]).is_empty());
+    }
+
+    #[test]
+    fn fee_histogram_and_percentiles() {
+        let (sk, vk) = test_keypair(24);
+        let from: Address = vk.to_bytes();
+        let np = TestNonceProvider::default();
+
+        let mut mp = TxMempool::new(1, 10);
+        assert_eq!(mp.fee_percentile(50), None);
+
+        for (nonce, fee) in [1u64, 2, 3, 5, 8, 13, 21, 34, 55, 89].iter().enumerate() {
+            mp.insert(make_signed_tx(&sk, from, nonce as u64, *fee, b"f"), &np)
+                .unwrap();
+        }
+
+        assert_eq!(mp.fee_histogram(&[5, 20, 50]), vec![3, 3, 2, 2]);
+        assert_eq!(mp.fee_histogram(&[]), vec![10]);
+        assert_eq!(mp.fee_percentile(0), Some(1));
+        assert_eq!(mp.fee_percentile(50), Some(8));
+        assert_eq!(mp.fee_percentile(90), Some(55));
+        assert_eq!(mp.fee_percentile(100), Some(89));
+        assert_eq!(mp.fee_percentile(255), Some(89));
+    }
+
+    #[test]
+    fn subscribers_see_every_lifecycle_event() {
+        let (sk, vk) = test_keypair(25);
+        let from: Address = vk.to_bytes();
+        let np = TestNonceProvider::default();
+
+        let clock = Arc::new(ManualClock::new(0));
+        let mut mp = TxMempool::with_config(TxMempoolConfig {
+            ttl: Some(10),
+            replace_by_fee: true,
+            ..TxMempoolConfig::default()
+        })
+        .with_clock(clock.clone());
+        let events = mp.subscribe();
+
+        let a = mp
+            .insert(make_signed_tx(&sk, from, 0, 5, b"a"), &np)
+            .unwrap();
+        let err = mp
+            .insert(make_signed_tx(&sk, from, 0, 5, b"a-same-fee"), &np)
+            .unwrap_err();
+        assert_eq!(
+            err,
+            TxMempoolError::ReplacementUnderpriced {
+                existing_fee: 5,
+                got: 5
+            }
+        );
+        let a2 = mp
+            .insert(make_signed_tx(&sk, from, 0, 6, b"a-bump"), &np)
+            .unwrap();
+        let b = mp
+            .insert(make_signed_tx(&sk, from, 1, 1, b"b"), &np)
+            .unwrap();
+        let c = mp
+            .insert(make_signed_tx(&sk, from, 2, 1, b"c"), &np)
+            .unwrap();
+        clock.set(5);
+        let d = mp
+            .insert(make_signed_tx(&sk, from, 3, 1, b"d"), &np)
+            .unwrap();
+
+        assert_eq!(mp.drain_ready(10, &np).len(), 1);
+        mp.remove(&b).unwrap();
+        assert_eq!(mp.remove_included(&[d, b]).len(), 1);
+        assert_eq!(mp.expire(10), vec![c]);
+
+        let got: Vec<MempoolEvent> = events.try_iter().collect();
+        assert_eq!(
+            got,
+            vec![
+                MempoolEvent::Added { id: a },
+                MempoolEvent::Replaced { old: a, new: a2 },
+                MempoolEvent::Added { id: b },
+                MempoolEvent::Added { id: c },
+                MempoolEvent::Added { id: d },
+                MempoolEvent::Included { id: a2 },
+                MempoolEvent::Evicted {
+                    id: b,
+                    reason: EvictionReason::Removed
+                },
+                MempoolEvent::Included { id: d },
+                MempoolEvent::Evicted {
+                    id: c,
+                    reason: EvictionReason::Expired
+                },
+            ]
+        );
+        assert!(mp.is_empty());
+    }
+
+    #[test]
+    fn lagging_subscriber_is_disconnected() {
+        let (sk, vk) = test_keypair(37);
+        let from: Address = vk.to_bytes();
+        let np = TestNonceProvider::default();
+
+        let mut mp = TxMempool::new(1, 10);
+        let lagging = mp.subscribe_with_buffer(2);
+        let live = mp.subscribe();
+        for nonce in 0..3 {
+            mp.insert(make_signed_tx(&sk, from, nonce, 1, b"x"), &np)
+                .unwrap();
+        }
+
+        // The lagging receiver keeps what was buffered, then sees the end.
+        assert_eq!(mp.subscribers.len(), 1);
+        assert_eq!(lagging.try_iter().count(), 2);
+        assert_eq!(
+            lagging.try_recv(),
+            Err(std::sync::mpsc::TryRecvError::Disconnected)
+        );
+        assert_eq!(live.try_iter().count(), 3);
+    }
+
+    #[test]
+    fn flooding_peer_is_throttled_and_scored() {
+        let (sk, vk) = test_keypair(26);
+        let from: Address = vk.to_bytes();
+        let np = TestNonceProvider::default();
+
+        let clock = Arc::new(ManualClock::new(1_000));
+        let mut mp = TxMempool::with_config(TxMempoolConfig {
+            rate_limit: RateLimitConfig {
+                window_secs: 10,
+                max_attempts_per_window: 100,
+                max_rejections_per_window: 3,
+                throttle_secs: 60,
+            },
+            ..TxMempoolConfig::default()
+        })
+        .with_clock(clock.clone());
+
+        let bad_peer = TxSource::Peer(b"peer-a".to_vec());
+        let good_peer = TxSource::Peer(b"peer-b".to_vec());
+        for nonce in 0..3 {
+            let mut tx = make_signed_tx(&sk, from, nonce, 5, b"spam");
+            tx.sig[0] ^= 0x01;
+            assert_eq!(
+                mp.insert_from(tx, &bad_peer, &np),
+                Err(TxMempoolError::InvalidSignature)
+            );
+        }
+
+        // Even a valid tx is refused while throttled, without touching the pool.
+        let good = make_signed_tx(&sk, from, 0, 5, b"ok");
+        assert_eq!(
+            mp.insert_from(good.clone(), &bad_peer, &np),
+            Err(TxMempoolError::RateLimited { retry_at: 1_060 })
+        );
+        assert!(mp.is_empty());
+        assert_eq!(mp.throttled_sources(), vec![bad_peer.clone()]);
+
+        mp.insert_from(good.clone(), &good_peer, &np).unwrap();
+        assert_eq!(mp.insert(good, &np), Err(TxMempoolError::Duplicate));
+
+        let bad = mp.source_score(&bad_peer).unwrap();
+        assert_eq!((bad.accepted, bad.rejected, bad.rate_limited), (0, 3, 1));
+        assert_eq!(bad.rejection_rate_pct(), 100);
+        let scores = mp.source_scores();
+        assert_eq!(
+            scores.iter().map(|(s, _)| s.clone()).collect::<Vec<_>>(),
+            vec![TxSource::Local, bad_peer.clone(), good_peer]
+        );
+
+        clock.advance(60);
+        assert!(mp.throttled_sources().is_empty());
+        assert!(mp.forget_source(&bad_peer));
+        assert_eq!(mp.source_score(&bad_peer), None);
+    }
+
+    #[test]
+    fn batched_peer_txs_are_charged_to_its_budget() {
+        let (sk, vk) = test_keypair(27);
+        let from: Address = vk.to_bytes();
+        let np = TestNonceProvider::default();
+
+        let mut mp = TxMempool::with_config(TxMempoolConfig {
+            rate_limit: RateLimitConfig {
+                window_secs: 10,
+                max_attempts_per_window: 100,
+                max_rejections_per_window: 2,
+                throttle_secs: 60,
+            },
+            ..TxMempoolConfig::default()
+        })
+        .with_clock(Arc::new(ManualClock::new(1_000)));
+
+        let peer = TxSource::Peer(b"peer-a".to_vec());
+        let spam: Vec<_> = (0..4)
+            .map(|nonce| {
+                let mut tx = make_signed_tx(&sk, from, nonce, 5, b"spam");
+                tx.sig[0] ^= 0x01;
+                tx
+            })
+            .collect();
+
+        // Only as many txs as the rejection budget can cover are verified.
+        assert_eq!(
+            mp.insert_batch(spam, &peer, &np),
+            vec![
+                Err(TxMempoolError::InvalidSignature),
+                Err(TxMempoolError::InvalidSignature),
+                Err(TxMempoolError::RateLimited { retry_at: 1_060 }),
+                Err(TxMempoolError::RateLimited { retry_at: 1_060 }),
+            ]
+        );
+        let score = mp.source_score(&peer).unwrap();
+        assert_eq!((score.rejected, score.rate_limited), (2, 2));
+        assert!(mp.is_empty());
+    }
+
+    #[test]
+    fn congestion_raises_min_fee_until_backlog_clears() {
+        let np = TestNonceProvider::default();
+        let mut mp = TxMempool::with_config(TxMempoolConfig {
+            min_fee: 16,
+            fee_market: Some(FeeMarketConfig {
+                target_pending: 2,
+                change_denominator: 8,
+            }),
+            ..TxMempoolConfig::default()
+        });
+        assert_eq!(mp.min_fee(), 16);
+
+        // Six senders, one ready tx each; blocks take two.
+        let senders: Vec<_> = (30..36u8).map(test_keypair).collect();
+        for (sk, vk) in &senders {
+            mp.insert(make_signed_tx(sk, vk.to_bytes(), 0, 100, b"c"), &np)
+                .unwrap();
+        }
+        assert_eq!(mp.next_min_fee(), 18);
+
+        mp.drain_ready(2, &np);
+        assert_eq!(mp.min_fee(), 18);
+
+        let (sk, vk) = test_keypair(36);
+        assert_eq!(
+            mp.insert(make_signed_tx(&sk, vk.to_bytes(), 0, 17, b"low"), &np),
+            Err(TxMempoolError::FeeTooLow {
+                min_fee: 18,
+                got: 17
+            })
+        );
+        mp.insert(make_signed_tx(&sk, vk.to_bytes(), 0, 18, b"ok"), &np)
+            .unwrap();
+
+        let mut fees = Vec::new();
+        for _ in 0..6 {
+            mp.drain_ready(2, &np);
+            fees.push(mp.min_fee());
+        }
+        // backlog 3, 1, 0, 0, 0, 0 after each block
+        assert_eq!(fees, vec![19, 18, 16, 16, 16, 16]);
+    }
 }
@@ -0,0 +1,309 @@
+//! Per-source admission budgets and spam scores.
+//!
+//! Every admission attempt is tagged with a [`TxSource`]. Non-local sources
+//! get a fixed window (in clock seconds) with two budgets:
+//! - attempts: once spent, further txs are refused before any decoding or
+//!   signature work until the window rolls over;
+//! - rejections: once spent, the source is throttled (refused outright) for
+//!   `throttle_secs`.
+//!
+//! An attempt holds a slot of the rejection budget from the moment it is
+//! charged until its outcome is recorded, so concurrent attempts from one
+//! source cannot together overshoot the budget.
+//!
+//! Duplicates are not counted as rejections since honest gossip produces
+//! them. Local submissions are scored but never limited.
+
+use std::collections::BTreeMap;
+
+use novai_types::TxId;
+
+use crate::TxMempoolError;
+
+/// Where a tx submitted to the mempool came from.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TxSource {
+    /// Produced by this node (CLI, wallet); exempt from limits.
+    Local,
+    /// Submitted over the RPC interface.
+    Rpc,
+    /// Relayed by a peer, identified by opaque peer-id bytes.
+    Peer(Vec<u8>),
+}
+
+/// Budgets applied to each non-local [`TxSource`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    /// Length of the accounting window, in clock seconds (minimum 1).
+    pub window_secs: u64,
+    /// Admission attempts allowed per window.
+    pub max_attempts_per_window: u32,
+    /// Rejections (other than duplicates) allowed per window before throttling.
+    pub max_rejections_per_window: u32,
+    /// How long a source that exhausted its rejection budget is refused.
+    pub throttle_secs: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            window_secs: 10,
+            max_attempts_per_window: 500,
+            max_rejections_per_window: 50,
+            throttle_secs: 60,
+        }
+    }
+}
+
+/// Lifetime counters for one source, for peer scoring.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SourceScore {
+    pub accepted: u64,
+    /// Policy/signature failures (excludes duplicates and rate limiting).
+    pub rejected: u64,
+    /// Attempts refused by the limiter.
+    pub rate_limited: u64,
+    /// Set while the source is throttled.
+    pub throttled_until: Option<u64>,
+}
+
+impl SourceScore {
+    /// Rejections as a percentage of scored attempts (0 with no history).
+    pub fn rejection_rate_pct(&self) -> u64 {
+        (self.rejected * 100)
+            .checked_div(self.accepted + self.rejected)
+            .unwrap_or(0)
+    }
+
+    pub fn is_throttled(&self, now: u64) -> bool {
+        matches!(self.throttled_until, Some(until) if now < until)
+    }
+}
+
+#[derive(Default)]
+struct SourceState {
+    score: SourceScore,
+    window_start: u64,
+    attempts: u32,
+    rejections: u32,
+    /// Charged attempts whose outcome is not recorded yet.
+    in_flight: u32,
+}
+
+pub(crate) struct RateLimiter {
+    config: RateLimitConfig,
+    sources: BTreeMap<TxSource, SourceState>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(mut config: RateLimitConfig) -> Self {
+        config.window_secs = config.window_secs.max(1);
+        config.max_rejections_per_window = config.max_rejections_per_window.max(1);
+        Self {
+            config,
+            sources: BTreeMap::new(),
+        }
+    }
+
+    /// Spend one attempt from `source`'s budget and reserve a rejection slot
+    /// for it, or refuse it. With every free slot reserved by attempts still
+    /// in flight, the retry time is `now`: a slot frees as soon as one settles.
+    pub(crate) fn check(&mut self, source: &TxSource, now: u64) -> Result<(), TxMempoolError> {
+        self.check_many(source, now, 1).map(|_| ())
+    }
+
+    /// [`RateLimiter::check`] up to `max` attempts at once, stopping before
+    /// the first one it would refuse. Returns how many were charged; refuses
+    /// only if not even one fits.
+    pub(crate) fn check_many(
+        &mut self,
+        source: &TxSource,
+        now: u64,
+        max: usize,
+    ) -> Result<usize, TxMempoolError> {
+        if *source == TxSource::Local {
+            return Ok(max);
+        }
+        let window = self.config.window_secs;
+        let st = self.sources.entry(source.clone()).or_default();
+        if now >= st.window_start.saturating_add(window) {
+            st.window_start = now;
+            st.attempts = 0;
+            st.rejections = 0;
+        }
+
+        if let Some(until) = st.score.throttled_until {
+            if now < until {
+                st.score.rate_limited += 1;
+                return Err(TxMempoolError::RateLimited { retry_at: until });
+            }
+            st.score.throttled_until = None;
+        }
+
+        if st.attempts >= self.config.max_attempts_per_window {
+            st.score.rate_limited += 1;
+            return Err(TxMempoolError::RateLimited {
+                retry_at: st.window_start.saturating_add(window),
+            });
+        }
+        let reserved = st.rejections + st.in_flight;
+        if reserved >= self.config.max_rejections_per_window {
+            st.score.rate_limited += 1;
+            return Err(TxMempoolError::RateLimited { retry_at: now });
+        }
+        let room = (self.config.max_attempts_per_window - st.attempts)
+            .min(self.config.max_rejections_per_window - reserved);
+        let n = u32::try_from(max).unwrap_or(u32::MAX).min(room);
+        st.attempts += n;
+        st.in_flight += n;
+        Ok(n as usize)
+    }
+
+    /// Score the outcome of an attempt that passed [`RateLimiter::check`].
+    pub(crate) fn record(
+        &mut self,
+        source: &TxSource,
+        now: u64,
+        result: &Result<TxId, TxMempoolError>,
+    ) {
+        let st = self.sources.entry(source.clone()).or_default();
+        if *source != TxSource::Local {
+            st.in_flight = st.in_flight.saturating_sub(1);
+        }
+        match result {
+            Ok(_) => st.score.accepted += 1,
+            Err(TxMempoolError::Duplicate) | Err(TxMempoolError::RateLimited { .. }) => {}
+            Err(_) => {
+                st.score.rejected += 1;
+                if *source == TxSource::Local {
+                    return;
+                }
+                st.rejections += 1;
+                if st.rejections >= self.config.max_rejections_per_window {
+                    st.score.throttled_until = Some(now.saturating_add(self.config.throttle_secs));
+                }
+            }
+        }
+    }
+
+    pub(crate) fn score(&self, source: &TxSource) -> Option<SourceScore> {
+        self.sources.get(source).map(|st| st.score)
+    }
+
+    pub(crate) fn scores(&self) -> Vec<(TxSource, SourceScore)> {
+        self.sources
+            .iter()
+            .map(|(source, st)| (source.clone(), st.score))
+            .collect()
+    }
+
+    pub(crate) fn forget(&mut self, source: &TxSource) -> bool {
+        self.sources.remove(source).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(attempts: u32, rejections: u32) -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            window_secs: 10,
+            max_attempts_per_window: attempts,
+            max_rejections_per_window: rejections,
+            throttle_secs: 30,
+        })
+    }
+
+    #[test]
+    fn attempt_budget_resets_each_window() {
+        let mut rl = limiter(2, 100);
+        let peer = TxSource::Peer(vec![1]);
+
+        assert!(rl.check(&peer, 100).is_ok());
+        assert!(rl.check(&peer, 101).is_ok());
+        assert_eq!(
+            rl.check(&peer, 105),
+            Err(TxMempoolError::RateLimited { retry_at: 110 })
+        );
+        assert!(rl.check(&peer, 110).is_ok());
+
+        // Other sources have their own budget; local has none.
+        assert!(rl.check(&TxSource::Peer(vec![2]), 105).is_ok());
+        for _ in 0..10 {
+            assert!(rl.check(&TxSource::Local, 105).is_ok());
+        }
+        assert_eq!(rl.score(&peer).unwrap().rate_limited, 1);
+    }
+
+    #[test]
+    fn unsettled_attempts_hold_rejection_slots() {
+        let mut rl = limiter(100, 2);
+        let peer = TxSource::Peer(vec![1]);
+
+        // Two attempts in flight could both fail, so a third must wait.
+        rl.check(&peer, 0).unwrap();
+        rl.check(&peer, 0).unwrap();
+        assert_eq!(
+            rl.check(&peer, 0),
+            Err(TxMempoolError::RateLimited { retry_at: 0 })
+        );
+
+        // Settling one frees its slot; both failing throttles the source.
+        rl.record(&peer, 0, &Ok([0u8; 32]));
+        rl.check(&peer, 0).unwrap();
+        rl.record(&peer, 0, &Err(TxMempoolError::InvalidSignature));
+        rl.record(&peer, 0, &Err(TxMempoolError::InvalidSignature));
+        assert!(rl.score(&peer).unwrap().is_throttled(0));
+    }
+
+    #[test]
+    fn check_many_charges_only_what_fits() {
+        let mut rl = limiter(5, 3);
+        let peer = TxSource::Peer(vec![1]);
+
+        assert_eq!(rl.check_many(&peer, 0, 10), Ok(3));
+        assert_eq!(
+            rl.check_many(&peer, 0, 10),
+            Err(TxMempoolError::RateLimited { retry_at: 0 })
+        );
+        for _ in 0..3 {
+            rl.record(&peer, 0, &Ok([0u8; 32]));
+        }
+        // Two attempts left in the window.
+        assert_eq!(rl.check_many(&peer, 0, 10), Ok(2));
+        assert_eq!(rl.check_many(&TxSource::Local, 0, 10), Ok(10));
+    }
+
+    #[test]
+    fn rejections_throttle_but_duplicates_do_not() {
+        let mut rl = limiter(100, 2);
+        let peer = TxSource::Rpc;
+
+        for _ in 0..5 {
+            rl.check(&peer, 0).unwrap();
+            rl.record(&peer, 0, &Err(TxMempoolError::Duplicate));
+        }
+        rl.check(&peer, 0).unwrap();
+        rl.record(&peer, 0, &Ok([0u8; 32]));
+        for _ in 0..2 {
+            rl.check(&peer, 1).unwrap();
+            rl.record(&peer, 1, &Err(TxMempoolError::InvalidSignature));
+        }
+
+        assert_eq!(
+            rl.check(&peer, 20),
+            Err(TxMempoolError::RateLimited { retry_at: 31 })
+        );
+        let score = rl.score(&peer).unwrap();
+        assert_eq!((score.accepted, score.rejected), (1, 2));
+        assert_eq!(score.rejection_rate_pct(), 66);
+        assert!(score.is_throttled(30));
+
+        // Throttle lifts on its own.
+        assert!(rl.check(&peer, 31).is_ok());
+        assert!(!rl.score(&peer).unwrap().is_throttled(31));
+        assert!(rl.forget(&peer));
+        assert!(rl.scores().is_empty());
+    }
+}
@@ -0,0 +1,315 @@
+//! Block template building on top of the mempool's drain ordering.
+//!
+//! Selection is greedy in the same priority as
+//! [`TxMempool::drain_ready`](crate::TxMempool::drain_ready) (fee DESC, txid
+//! ASC, per-sender fairness cap), starting from each sender's expected nonce.
+//! Including a sender's nonce `n` makes their `n + 1` eligible, so one block
+//! can carry a nonce chain. A tx that would overflow the byte or cost budget
+//! is skipped (along with the rest of that sender's chain) and packing
+//! continues with the next candidate.
+//!
+//! Building does not modify the pool; once the block commits, hand its txids
+//! to [`TxMempool::remove_included`](crate::TxMempool::remove_included).
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+use novai_codec::{encode_tx_v1_signed, tx_root_v1, CodecError, DecodeLimits};
+use novai_types::{Address, Hash32, Nonce, TxId, TxV1};
+
+use crate::{NonceProvider, TxMempool};
+
+/// Per-block packing limits.
+///
+/// [`BlockTemplateBuilder::new`] caps them at what peers decode
+/// ([`DecodeLimits::DEFAULT`]), so a template always makes a block that can
+/// be synced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockLimits {
+    pub max_txs: usize,
+    /// Sum of the txs' canonical signed encodings (header excluded).
+    pub max_bytes: usize,
+    /// Sum of [`CostModel::cost`] over the txs.
+    pub max_cost: u64,
+}
+
+/// Execution cost charged against [`BlockLimits::max_cost`].
+pub trait CostModel {
+    fn cost(&self, tx: &TxV1) -> u64;
+}
+
+/// Every tx costs the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlatCost(pub u64);
+
+impl CostModel for FlatCost {
+    fn cost(&self, _tx: &TxV1) -> u64 {
+        self.0
+    }
+}
+
+/// Txs selected for a block, in block order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockTemplate {
+    pub txs: Vec<TxV1>,
+    /// [`tx_root_v1`] of `txs`, ready for `BlockHeaderV1::tx_root`.
+    pub tx_root: Hash32,
+    pub total_bytes: usize,
+    pub total_cost: u64,
+}
+
+pub struct BlockTemplateBuilder<C = FlatCost> {
+    limits: BlockLimits,
+    cost_model: C,
+}
+
+impl BlockTemplateBuilder {
+    /// Builder charging a flat cost of 1 per tx.
+    pub fn new(limits: BlockLimits) -> Self {
+        let decodable = DecodeLimits::DEFAULT;
+        Self {
+            limits: BlockLimits {
+                max_txs: limits.max_txs.min(decodable.max_txs_per_block as usize),
+                max_bytes: limits.max_bytes.min(decodable.max_block_tx_bytes()),
+                max_cost: limits.max_cost,
+            },
+            cost_model: FlatCost(1),
+        }
+    }
+}
+
+impl<C: CostModel> BlockTemplateBuilder<C> {
+    pub fn with_cost_model<D: CostModel>(self, cost_model: D) -> BlockTemplateBuilder<D> {
+        BlockTemplateBuilder {
+            limits: self.limits,
+            cost_model,
+        }
+    }
+
+    pub fn limits(&self) -> BlockLimits {
+        self.limits
+    }
+
+    /// Pack a template from `mempool` against a nonce snapshot.
+    pub fn build(
+        &self,
+        mempool: &TxMempool,
+        nonce_provider: &impl NonceProvider,
+    ) -> Result<BlockTemplate, CodecError> {
+        // Max-heap on (fee, Reverse(txid)) pops fee DESC, txid ASC.
+        let mut heap: BinaryHeap<(u64, Reverse<TxId>)> = BinaryHeap::new();
+        let mut next_nonce: HashMap<Address, Nonce> = HashMap::new();
+        for from in mempool.by_sender.keys() {
+            let expected = nonce_provider.expected_nonce(from);
+            next_nonce.insert(*from, expected);
+            push_nonce(&mut heap, mempool, from, expected);
+        }
+
+        let mut txs = Vec::new();
+        let mut total_bytes = 0usize;
+        let mut total_cost = 0u64;
+        let mut per_sender: HashMap<Address, usize> = HashMap::new();
+
+        while txs.len() < self.limits.max_txs {
+            let Some((_, Reverse(id))) = heap.pop() else {
+                break;
+            };
+            let tx = &mempool.by_id[&id].tx;
+
+            // A same-nonce sibling may already have been packed.
+            if next_nonce.get(&tx.from) != Some(&tx.nonce) {
+                continue;
+            }
+            let count = per_sender.entry(tx.from).or_insert(0);
+            if *count >= mempool.fairness_cap_per_sender {
+                continue;
+            }
+
+            let bytes = encode_tx_v1_signed(tx)?.len();
+            let cost = self.cost_model.cost(tx);
+            let fits_bytes = total_bytes
+                .checked_add(bytes)
+                .is_some_and(|b| b <= self.limits.max_bytes);
+            let fits_cost = total_cost
+                .checked_add(cost)
+                .is_some_and(|c| c <= self.limits.max_cost);
+            if !fits_bytes || !fits_cost {
+                continue;
+            }
+
+            *count += 1;
+            total_bytes += bytes;
+            total_cost += cost;
+            next_nonce.insert(tx.from, tx.nonce + 1);
+            push_nonce(&mut heap, mempool, &tx.from, tx.nonce + 1);
+            txs.push(tx.clone());
+        }
+
+        Ok(BlockTemplate {
+            tx_root: tx_root_v1(&txs)?,
+            txs,
+            total_bytes,
+            total_cost,
+        })
+    }
+}
+
+fn push_nonce(
+    heap: &mut BinaryHeap<(u64, Reverse<TxId>)>,
+    mempool: &TxMempool,
+    from: &Address,
+    nonce: Nonce,
+) {
+    let Some(queued) = mempool.by_sender.get(from) else {
+        return;
+    };
+    for (_, id) in queued.range((nonce, [0u8; 32])..=(nonce, [0xFFu8; 32])) {
+        heap.push((mempool.by_id[id].tx.fee, Reverse(*id)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::{make_signed_tx, test_keypair, TestNonceProvider};
+
+    const UNLIMITED: BlockLimits = BlockLimits {
+        max_txs: usize::MAX,
+        max_bytes: usize::MAX,
+        max_cost: u64::MAX,
+    };
+
+    struct PayloadCost;
+
+    impl CostModel for PayloadCost {
+        fn cost(&self, tx: &TxV1) -> u64 {
+            tx.payload.len() as u64
+        }
+    }
+
+    fn payloads(txs: &[TxV1]) -> Vec<Vec<u8>> {
+        txs.iter().map(|t| t.payload.clone()).collect()
+    }
+
+    #[test]
+    fn unconstrained_template_matches_drain_order() {
+        let np = TestNonceProvider::default();
+        let mut mp = TxMempool::new(1, 10);
+        for (i, seed) in (50..56u8).enumerate() {
+            let (sk, vk) = test_keypair(seed);
+            let fee = [4, 9, 4, 1, 7, 9][i];
+            mp.insert(make_signed_tx(&sk, vk.to_bytes(), 0, fee, b"t"), &np)
+                .unwrap();
+        }
+
+        let template = BlockTemplateBuilder::new(UNLIMITED)
+            .build(&mp, &np)
+            .unwrap();
+        assert_eq!(mp.len(), 6, "building must not drain");
+        assert_eq!(template.tx_root, tx_root_v1(&template.txs).unwrap());
+        assert_eq!(template.total_cost, 6);
+
+        assert_eq!(template.txs, mp.drain_ready(usize::MAX, &np));
+    }
+
+    #[test]
+    fn oversized_txs_are_skipped_for_smaller_ones() {
+        let np = TestNonceProvider::default();
+        let mut mp = TxMempool::new(1, 10);
+        let (sk1, vk1) = test_keypair(56);
+        let (sk2, vk2) = test_keypair(57);
+        let (sk3, vk3) = test_keypair(58);
+        mp.insert(
+            make_signed_tx(&sk1, vk1.to_bytes(), 0, 50, &[1u8; 400]),
+            &np,
+        )
+        .unwrap();
+        mp.insert(make_signed_tx(&sk2, vk2.to_bytes(), 0, 20, &[2u8; 10]), &np)
+            .unwrap();
+        mp.insert(make_signed_tx(&sk3, vk3.to_bytes(), 0, 10, &[3u8; 10]), &np)
+            .unwrap();
+
+        // Signed overhead is 117 bytes: the two small txs fit, the big one doesn't.
+        let template = BlockTemplateBuilder::new(BlockLimits {
+            max_bytes: 300,
+            ..UNLIMITED
+        })
+        .build(&mp, &np)
+        .unwrap();
+        assert_eq!(payloads(&template.txs), vec![vec![2u8; 10], vec![3u8; 10]]);
+        assert_eq!(template.total_bytes, 254);
+    }
+
+    #[test]
+    fn limits_are_capped_at_what_peers_decode() {
+        let decodable = DecodeLimits::DEFAULT;
+        let builder = BlockTemplateBuilder::new(UNLIMITED).with_cost_model(PayloadCost);
+        assert_eq!(
+            builder.limits(),
+            BlockLimits {
+                max_txs: decodable.max_txs_per_block as usize,
+                max_bytes: decodable.max_block_tx_bytes(),
+                max_cost: u64::MAX,
+            }
+        );
+        let tight = BlockLimits {
+            max_txs: 3,
+            max_bytes: 300,
+            max_cost: 9,
+        };
+        assert_eq!(BlockTemplateBuilder::new(tight).limits(), tight);
+    }
+
+    #[test]
+    fn packs_nonce_chains_under_cost_and_count_limits() {
+        let mut np = TestNonceProvider::default();
+        let mut mp = TxMempool::new(1, 10);
+        let (sk1, vk1) = test_keypair(59);
+        let (sk2, vk2) = test_keypair(60);
+        let (a, b) = (vk1.to_bytes(), vk2.to_bytes());
+        np.set(a, 5);
+
+        mp.insert(make_signed_tx(&sk1, a, 5, 3, b"a5"), &np)
+            .unwrap();
+        mp.insert(make_signed_tx(&sk1, a, 6, 8, b"a6"), &np)
+            .unwrap();
+        mp.insert(make_signed_tx(&sk1, a, 8, 9, b"a8-gap"), &np)
+            .unwrap();
+        mp.insert(make_signed_tx(&sk2, b, 0, 5, b"b0-expensive"), &np)
+            .unwrap();
+        mp.insert(make_signed_tx(&sk2, b, 1, 9, b"b1"), &np)
+            .unwrap();
+
+        let builder = BlockTemplateBuilder::new(UNLIMITED).with_cost_model(PayloadCost);
+        let all = builder.build(&mp, &np).unwrap();
+        assert_eq!(
+            payloads(&all.txs),
+            vec![
+                b"b0-expensive".to_vec(),
+                b"b1".to_vec(),
+                b"a5".to_vec(),
+                b"a6".to_vec()
+            ]
+        );
+
+        // b0 no longer fits, which also strands b1 behind it.
+        let cheap = BlockTemplateBuilder::new(BlockLimits {
+            max_cost: 10,
+            ..UNLIMITED
+        })
+        .with_cost_model(PayloadCost)
+        .build(&mp, &np)
+        .unwrap();
+        assert_eq!(payloads(&cheap.txs), vec![b"a5".to_vec(), b"a6".to_vec()]);
+        assert_eq!(cheap.total_cost, 4);
+
+        let one = BlockTemplateBuilder::new(BlockLimits {
+            max_txs: 1,
+            ..UNLIMITED
+        })
+        .build(&mp, &np)
+        .unwrap();
+        assert_eq!(payloads(&one.txs), vec![b"b0-expensive".to_vec()]);
+    }
+}
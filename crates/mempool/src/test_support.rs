@@ -0,0 +1,85 @@
+//! Helpers shared by unit tests across mempool modules.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use novai_codec::encode_tx_v1_unsigned;
+use novai_crypto::sign_bytes;
+use novai_types::{Address, SignatureBytes, TxV1, TxVersion};
+
+use crate::{AccountProvider, NonceProvider};
+
+/// A fresh path under the system temp dir, unique per process and call.
+pub(crate) fn temp_path(name: &str) -> PathBuf {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!("novai-{name}-{}-{n}", std::process::id()))
+}
+
+pub(crate) fn test_keypair(seed: u8) -> (SigningKey, VerifyingKey) {
+    let sk = SigningKey::from_bytes(&[seed; 32]);
+    let vk: VerifyingKey = sk.verifying_key();
+    (sk, vk)
+}
+
+/// Accounts without an explicit balance are treated as fully funded so
+/// policy tests that don't care about balances stay focused.
+#[derive(Default)]
+pub(crate) struct TestNonceProvider {
+    map: HashMap<Address, u64>,
+    balances: HashMap<Address, u64>,
+}
+
+impl TestNonceProvider {
+    pub(crate) fn set(&mut self, from: Address, nonce: u64) {
+        self.map.insert(from, nonce);
+    }
+
+    pub(crate) fn set_balance(&mut self, from: Address, balance: u64) {
+        self.balances.insert(from, balance);
+    }
+}
+
+impl NonceProvider for TestNonceProvider {
+    fn expected_nonce(&self, from: &Address) -> u64 {
+        *self.map.get(from).unwrap_or(&0)
+    }
+}
+
+impl AccountProvider for TestNonceProvider {
+    fn balance(&self, from: &Address) -> u64 {
+        *self.balances.get(from).unwrap_or(&u64::MAX)
+    }
+
+    /// Test convention: a payload of exactly 8 bytes is a LE transfer amount.
+    fn transfer_amount(&self, tx: &TxV1) -> u64 {
+        match <[u8; 8]>::try_from(tx.payload.as_slice()) {
+            Ok(b) => u64::from_le_bytes(b),
+            Err(_) => 0,
+        }
+    }
+}
+
+pub(crate) fn make_signed_tx(
+    from_sk: &SigningKey,
+    from_pk_bytes: Address,
+    nonce: u64,
+    fee: u64,
+    payload: &[u8],
+) -> TxV1 {
+    let mut tx = TxV1 {
+        version: TxVersion::V1,
+        from: from_pk_bytes,
+        nonce,
+        fee,
+        payload: payload.to_vec(),
+        sig: [0u8; 64],
+    };
+
+    let unsigned = encode_tx_v1_unsigned(&tx).expect("unsigned encode");
+    let sig: SignatureBytes = sign_bytes(from_sk, &unsigned);
+    tx.sig = sig;
+    tx
+}
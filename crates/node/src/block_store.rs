@@ -0,0 +1,304 @@
+//! Append-only chain of validated blocks, optionally persisted to disk.
+//!
+//! The chain starts at a trusted base block: genesis, or the anchor block of
+//! a state snapshot when the node was bootstrapped by snapshot sync.
+//!
+//! On-disk layout under the store directory:
+//!
+//! ```text
+//! blocks/<height, 20 digits>.bin   encode_block_v1 bytes
+//! PROGRESS                         height:u64 LE || block_hash:[u8;32] of the tip
+//! ```
+//!
+//! Each block file is written (tmp + rename) before `PROGRESS` advances, so
+//! a crash in between leaves an orphan above the tip that is ignored on
+//! open and overwritten by the next append. `PROGRESS` is what makes block
+//! sync resumable: reopening the store resumes from the recorded tip.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use novai_codec::{block_hash_v1, decode_block_v1, encode_block_v1, CodecError};
+use novai_types::{BlockV1, Hash32};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockStoreError {
+    Io(String),
+    Codec(CodecError),
+    /// Appended block is not at `tip + 1`.
+    NotNext {
+        expected: u64,
+        got: u64,
+    },
+    /// Appended block's `prev_hash` is not the tip's hash.
+    ParentMismatch {
+        height: u64,
+    },
+    /// Stored base block differs from the one the node was started with.
+    BaseMismatch,
+    /// Files on disk do not form the chain `PROGRESS` describes.
+    Corrupt(String),
+}
+
+fn io_err(e: std::io::Error) -> BlockStoreError {
+    BlockStoreError::Io(e.to_string())
+}
+
+pub struct BlockStore {
+    dir: Option<PathBuf>,
+    base: u64,
+    blocks: Vec<BlockV1>,
+    hashes: Vec<Hash32>,
+    by_hash: HashMap<Hash32, u64>,
+}
+
+impl BlockStore {
+    /// Store holding only `base`, kept in memory.
+    pub fn in_memory(base: BlockV1) -> Result<Self, BlockStoreError> {
+        let hash = block_hash_v1(&base.header).map_err(BlockStoreError::Codec)?;
+        let height = base.header.height;
+        Ok(Self {
+            dir: None,
+            base: height,
+            blocks: vec![base],
+            hashes: vec![hash],
+            by_hash: HashMap::from([(hash, height)]),
+        })
+    }
+
+    /// Open (or create) a store in `dir` starting at `base`, loading every
+    /// block up to the recorded tip.
+    pub fn open(dir: impl AsRef<Path>, base: BlockV1) -> Result<Self, BlockStoreError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(dir.join("blocks")).map_err(io_err)?;
+
+        let mut store = Self::in_memory(base)?;
+        store.dir = Some(dir.clone());
+
+        let progress = match fs::read(dir.join("PROGRESS")) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                store.persist(&store.blocks[0], &store.hashes[0])?;
+                return Ok(store);
+            }
+            Err(e) => return Err(io_err(e)),
+        };
+        let Ok(progress) = <[u8; 40]>::try_from(progress.as_slice()) else {
+            return Err(BlockStoreError::Corrupt("PROGRESS is not 40 bytes".into()));
+        };
+        let tip_height = u64::from_le_bytes(progress[..8].try_into().expect("8 bytes"));
+        let tip_hash: Hash32 = progress[8..].try_into().expect("32 bytes");
+
+        if !Self::block_path(&dir, store.base).exists() {
+            return Err(BlockStoreError::BaseMismatch);
+        }
+        let stored_base = store.read_block(store.base)?;
+        if block_hash_v1(&stored_base.header).map_err(BlockStoreError::Codec)? != store.hashes[0] {
+            return Err(BlockStoreError::BaseMismatch);
+        }
+        for height in store.base + 1..=tip_height {
+            let block = store.read_block(height)?;
+            store
+                .push(block)
+                .map_err(|e| BlockStoreError::Corrupt(format!("block {height}: {e:?}")))?;
+        }
+        if store.tip().1 != tip_hash {
+            return Err(BlockStoreError::Corrupt(
+                "tip hash differs from PROGRESS".into(),
+            ));
+        }
+        Ok(store)
+    }
+
+    /// Height and hash of the last block.
+    pub fn tip(&self) -> (u64, Hash32) {
+        let last = self.blocks.len() - 1;
+        (self.base + last as u64, self.hashes[last])
+    }
+
+    /// Height of the first block held; blocks below it are unknown.
+    pub fn base_height(&self) -> u64 {
+        self.base
+    }
+
+    pub fn block_by_height(&self, height: u64) -> Option<&BlockV1> {
+        self.blocks
+            .get(usize::try_from(height.checked_sub(self.base)?).ok()?)
+    }
+
+    pub fn block_by_hash(&self, hash: &Hash32) -> Option<&BlockV1> {
+        self.block_by_height(*self.by_hash.get(hash)?)
+    }
+
+    /// Append the next block, persisting it first if the store is on disk.
+    ///
+    /// Only linkage is checked here; validate blocks before appending.
+    pub fn append(&mut self, block: BlockV1) -> Result<(), BlockStoreError> {
+        let hash = self.check_next(&block)?;
+        self.persist(&block, &hash)?;
+        self.insert(block, hash);
+        Ok(())
+    }
+
+    fn push(&mut self, block: BlockV1) -> Result<(), BlockStoreError> {
+        let hash = self.check_next(&block)?;
+        self.insert(block, hash);
+        Ok(())
+    }
+
+    fn check_next(&self, block: &BlockV1) -> Result<Hash32, BlockStoreError> {
+        let (tip_height, tip_hash) = self.tip();
+        if block.header.height != tip_height + 1 {
+            return Err(BlockStoreError::NotNext {
+                expected: tip_height + 1,
+                got: block.header.height,
+            });
+        }
+        if block.header.prev_hash != tip_hash {
+            return Err(BlockStoreError::ParentMismatch {
+                height: block.header.height,
+            });
+        }
+        block_hash_v1(&block.header).map_err(BlockStoreError::Codec)
+    }
+
+    fn insert(&mut self, block: BlockV1, hash: Hash32) {
+        self.by_hash.insert(hash, block.header.height);
+        self.hashes.push(hash);
+        self.blocks.push(block);
+    }
+
+    fn block_path(dir: &Path, height: u64) -> PathBuf {
+        dir.join("blocks").join(format!("{height:020}.bin"))
+    }
+
+    fn read_block(&self, height: u64) -> Result<BlockV1, BlockStoreError> {
+        let dir = self
+            .dir
+            .as_ref()
+            .expect("only called on disk-backed stores");
+        let bytes = fs::read(Self::block_path(dir, height)).map_err(io_err)?;
+        decode_block_v1(&bytes).map_err(BlockStoreError::Codec)
+    }
+
+    fn persist(&self, block: &BlockV1, hash: &Hash32) -> Result<(), BlockStoreError> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        let bytes = encode_block_v1(block).map_err(BlockStoreError::Codec)?;
+        write_atomic(&Self::block_path(dir, block.header.height), &bytes)?;
+
+        let mut progress = block.header.height.to_le_bytes().to_vec();
+        progress.extend_from_slice(hash);
+        write_atomic(&dir.join("PROGRESS"), &progress)
+    }
+}
+
+fn write_atomic(path: &Path, bytes: &[u8]) -> Result<(), BlockStoreError> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, bytes).map_err(io_err)?;
+    fs::rename(&tmp, path).map_err(io_err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use novai_types::{BlockHeaderV1, BlockHeaderVersion, QuorumCertV1};
+
+    fn block(height: u64, prev_hash: Hash32) -> BlockV1 {
+        BlockV1 {
+            header: BlockHeaderV1 {
+                version: BlockHeaderVersion::V1,
+                height,
+                prev_hash,
+                state_root: [0u8; 32],
+                tx_root: [0u8; 32],
+                proposer: [0u8; 32],
+                qc_hash: [0u8; 32],
+            },
+            txs: Vec::new(),
+            qc: QuorumCertV1 {
+                height,
+                round: 0,
+                block_hash: [0u8; 32],
+                votes: Vec::new(),
+            },
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("novai-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn reopening_resumes_from_progress_and_ignores_orphans() {
+        let dir = temp_dir("block-store");
+        let genesis = block(0, [0u8; 32]);
+
+        let mut store = BlockStore::open(&dir, genesis.clone()).unwrap();
+        for height in 1..=3 {
+            let next = block(height, store.tip().1);
+            store.append(next).unwrap();
+        }
+        let tip = store.tip();
+        assert_eq!(tip.0, 3);
+
+        let wrong = block(5, tip.1);
+        assert_eq!(
+            store.append(wrong),
+            Err(BlockStoreError::NotNext {
+                expected: 4,
+                got: 5
+            })
+        );
+        assert_eq!(
+            store.append(block(4, [9u8; 32])),
+            Err(BlockStoreError::ParentMismatch { height: 4 })
+        );
+
+        // A block file written without its PROGRESS update (crash mid-append).
+        let orphan = block(4, tip.1);
+        fs::write(
+            BlockStore::block_path(&dir, 4),
+            encode_block_v1(&orphan).unwrap(),
+        )
+        .unwrap();
+
+        let reopened = BlockStore::open(&dir, genesis.clone()).unwrap();
+        assert_eq!(reopened.tip(), tip);
+        assert_eq!(reopened.block_by_hash(&tip.1), store.block_by_height(3));
+        assert!(reopened.block_by_height(4).is_none());
+
+        let mut other_genesis = genesis;
+        other_genesis.header.state_root = [1u8; 32];
+        assert_eq!(
+            BlockStore::open(&dir, other_genesis).err(),
+            Some(BlockStoreError::BaseMismatch)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn store_can_start_at_a_snapshot_anchor() {
+        let dir = temp_dir("block-store-anchor");
+        let anchor = block(100, [5u8; 32]);
+
+        let mut store = BlockStore::open(&dir, anchor.clone()).unwrap();
+        store.append(block(101, store.tip().1)).unwrap();
+        assert_eq!(store.base_height(), 100);
+        assert!(store.block_by_height(99).is_none());
+        assert_eq!(store.block_by_height(100), Some(&anchor));
+
+        let reopened = BlockStore::open(&dir, anchor).unwrap();
+        assert_eq!(reopened.tip(), store.tip());
+        assert_eq!(
+            BlockStore::open(&dir, block(0, [0u8; 32])).err(),
+            Some(BlockStoreError::BaseMismatch)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,482 @@
+//! Block sync: serve historical blocks to peers and catch up from them.
+//!
+//! Serving answers [`SyncRequestV1`]s from the local [`BlockStore`], capped
+//! per response. Catching up ([`sync_to`]) splits the missing height range
+//! into batches fetched in parallel from several peers. Batches may arrive
+//! out of order but are imported strictly in height order, and only after
+//! [`validate_block`] accepts them. A peer that fails a request, answers
+//! with something other than what was asked, or serves a block that fails
+//! validation is dropped for the rest of the run and its batch re-queued
+//! for another peer. Bad data, as opposed to a mere network failure, is
+//! also reported to the reputation system.
+//!
+//! Imported blocks go through [`BlockStore::append`], so with a
+//! disk-backed store an interrupted sync resumes from the last imported
+//! block.
+//!
+//! Snapshot chunk requests are answered here too, from [`Snapshots`]; the
+//! client side lives in [`crate::state_sync`].
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use futures::stream::{FuturesUnordered, StreamExt};
+use novai_codec::{
+    block_hash_v1, consensus_signing_bytes_v1, decode_sync_request_v1, decode_sync_response_v1,
+    encode_block_v1, encode_sync_request_v1, encode_sync_response_v1, tx_root_v1, CodecError,
+};
+use novai_crypto::{pubkey_from_bytes, verify_bytes, SigCache};
+use novai_execution::{verify_block_signatures, ExecutionError};
+use novai_p2p::{Misbehaviour, P2pError, P2pEvent, P2pHandle, PeerId, MAX_SYNC_MESSAGE};
+use novai_smt::SmtError;
+use novai_types::{
+    Address, BlockHeaderV1, BlockV1, ConsensusMessage, Hash32, SyncRequestV1, SyncResponseV1,
+    VoteV1,
+};
+
+use crate::block_store::{BlockStore, BlockStoreError};
+use crate::state_sync::{serve_chunk, Snapshots};
+
+/// Most blocks served in one response.
+pub const MAX_BLOCKS_PER_RESPONSE: u32 = 64;
+/// Most headers served in one response.
+pub const MAX_HEADERS_PER_RESPONSE: u32 = 1024;
+
+/// Validators whose votes count towards a QC.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidatorSet {
+    members: BTreeSet<Address>,
+}
+
+impl ValidatorSet {
+    pub fn new(members: impl IntoIterator<Item = Address>) -> Self {
+        Self {
+            members: members.into_iter().collect(),
+        }
+    }
+
+    pub fn contains(&self, voter: &Address) -> bool {
+        self.members.contains(voter)
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// Votes needed for a QC: more than two thirds of the set.
+    pub fn quorum(&self) -> usize {
+        self.members.len() * 2 / 3 + 1
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncError {
+    Codec(CodecError),
+    Network(P2pError),
+    Store(BlockStoreError),
+    /// The response was not an answer to the request that was sent.
+    BadResponse,
+    /// Height or `prev_hash` does not extend our tip.
+    NotChild {
+        height: u64,
+    },
+    TxRootMismatch {
+        height: u64,
+    },
+    /// The QC certifies a different height or block.
+    QcMismatch {
+        height: u64,
+    },
+    /// Voter not in the set, listed out of order (or twice), or a bad signature.
+    InvalidVote {
+        height: u64,
+        voter: Address,
+    },
+    InsufficientQuorum {
+        height: u64,
+        votes: usize,
+        needed: usize,
+    },
+    /// A tx in the block fails signature checks.
+    InvalidTx {
+        height: u64,
+        error: ExecutionError,
+    },
+    /// Snapshot chunk does not prove against the anchor's state root.
+    InvalidChunk(SmtError),
+    /// Every peer was dropped before reaching the target.
+    NoPeers {
+        height: u64,
+    },
+}
+
+impl SyncError {
+    /// What this error says about the peer that caused it, if anything.
+    pub fn misbehaviour(&self) -> Option<Misbehaviour> {
+        match self {
+            SyncError::Codec(_) | SyncError::BadResponse => Some(Misbehaviour::Malformed),
+            SyncError::InvalidVote { .. } | SyncError::InvalidTx { .. } => {
+                Some(Misbehaviour::InvalidSignature)
+            }
+            SyncError::NotChild { .. }
+            | SyncError::TxRootMismatch { .. }
+            | SyncError::QcMismatch { .. }
+            | SyncError::InsufficientQuorum { .. }
+            | SyncError::InvalidChunk(_) => Some(Misbehaviour::InvalidBlock),
+            SyncError::Network(_) | SyncError::Store(_) | SyncError::NoPeers { .. } => None,
+        }
+    }
+}
+
+/// Report `peer` if `error` shows it sent bad data.
+pub(crate) async fn penalise(p2p: &P2pHandle, peer: PeerId, error: &SyncError) {
+    if let Some(misbehaviour) = error.misbehaviour() {
+        // Only fails once the swarm has shut down.
+        let _ = p2p.report_peer(peer, misbehaviour).await;
+    }
+}
+
+/// Parallel download settings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncConfig {
+    /// Blocks per request.
+    pub batch_size: u32,
+    /// Requests outstanding at once, across all peers.
+    pub max_in_flight: usize,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 32,
+            max_in_flight: 8,
+        }
+    }
+}
+
+/// Check that `block` extends `parent` (height, hash) and passes
+/// [`validate_certified`].
+pub fn validate_block(
+    block: &BlockV1,
+    parent: (u64, Hash32),
+    validators: &ValidatorSet,
+    sig_cache: &SigCache,
+) -> Result<(), SyncError> {
+    let header = &block.header;
+    if header.height != parent.0 + 1 || header.prev_hash != parent.1 {
+        return Err(SyncError::NotChild {
+            height: header.height,
+        });
+    }
+    validate_certified(block, validators, sig_cache)
+}
+
+/// Check that `block`'s txs match `tx_root`, that its QC carries a quorum of
+/// valid votes for it and that every tx is correctly signed. No parent is
+/// needed, so this is how a snapshot anchor is trusted.
+///
+/// Tx signatures are checked last, through `sig_cache` (shared with the
+/// mempool), so txs admitted earlier are not verified again.
+pub fn validate_certified(
+    block: &BlockV1,
+    validators: &ValidatorSet,
+    sig_cache: &SigCache,
+) -> Result<(), SyncError> {
+    let header = &block.header;
+    let height = header.height;
+    if tx_root_v1(&block.txs).map_err(SyncError::Codec)? != header.tx_root {
+        return Err(SyncError::TxRootMismatch { height });
+    }
+
+    let qc = &block.qc;
+    let block_hash = block_hash_v1(header).map_err(SyncError::Codec)?;
+    if qc.height != height || qc.block_hash != block_hash {
+        return Err(SyncError::QcMismatch { height });
+    }
+    if qc.votes.len() < validators.quorum() {
+        return Err(SyncError::InsufficientQuorum {
+            height,
+            votes: qc.votes.len(),
+            needed: validators.quorum(),
+        });
+    }
+
+    // Votes are checked one by one with strict verification: whether a
+    // block is accepted must not depend on how its votes would batch.
+    let mut previous: Option<&Address> = None;
+    for (voter, sig) in &qc.votes {
+        let invalid = SyncError::InvalidVote {
+            height,
+            voter: *voter,
+        };
+        if !validators.contains(voter) || previous.is_some_and(|p| p >= voter) {
+            return Err(invalid);
+        }
+        previous = Some(voter);
+        let pk = pubkey_from_bytes(voter).map_err(|_| invalid.clone())?;
+        let vote = ConsensusMessage::Vote(VoteV1 {
+            height,
+            round: qc.round,
+            block_hash,
+            voter: *voter,
+            sig: *sig,
+        });
+        let msg = consensus_signing_bytes_v1(&vote).map_err(SyncError::Codec)?;
+        if !verify_bytes(&pk, &msg, sig) {
+            return Err(invalid);
+        }
+    }
+    verify_block_signatures(&block.txs, sig_cache)
+        .map_err(|error| SyncError::InvalidTx { height, error })
+}
+
+/// Answer a sync request from `store` and `snapshots`, within the
+/// per-response caps.
+pub fn serve(
+    store: &BlockStore,
+    snapshots: &Snapshots,
+    request: &SyncRequestV1,
+) -> Result<SyncResponseV1, CodecError> {
+    match request {
+        SyncRequestV1::BlocksByRange { start, count } => {
+            let mut blocks = Vec::new();
+            // Version, kind and count bytes of the response envelope.
+            let mut size = 6;
+            let count = (*count).min(MAX_BLOCKS_PER_RESPONSE) as u64;
+            for height in (*start..=u64::MAX).take(count as usize) {
+                let Some(block) = store.block_by_height(height) else {
+                    break;
+                };
+                size += encode_block_v1(block)?.len();
+                if size > MAX_SYNC_MESSAGE {
+                    break;
+                }
+                blocks.push(block.clone());
+            }
+            Ok(SyncResponseV1::Blocks(blocks))
+        }
+        SyncRequestV1::BlockByHash(hash) => Ok(SyncResponseV1::Blocks(
+            store.block_by_hash(hash).cloned().into_iter().collect(),
+        )),
+        SyncRequestV1::Headers { start, count } => {
+            let count = (*count).min(MAX_HEADERS_PER_RESPONSE) as usize;
+            let headers = (*start..=u64::MAX)
+                .take(count)
+                .map_while(|height| store.block_by_height(height))
+                .map(|block| block.header.clone())
+                .collect();
+            Ok(SyncResponseV1::Headers(headers))
+        }
+        SyncRequestV1::SnapshotChunk {
+            height,
+            start,
+            max_leaves,
+        } => serve_chunk(snapshots, *height, start, *max_leaves),
+    }
+}
+
+/// Handle a [`P2pEvent`] if it is a sync request: answer it from `store`
+/// and `snapshots`.
+///
+/// Undecodable requests get an empty response and their sender is
+/// reported. Returns `None` for any other event.
+pub async fn handle_event(
+    p2p: &P2pHandle,
+    store: &BlockStore,
+    snapshots: &Snapshots,
+    event: &P2pEvent,
+) -> Option<Result<SyncRequestV1, CodecError>> {
+    let P2pEvent::SyncRequest {
+        request_id,
+        peer,
+        data,
+    } = event
+    else {
+        return None;
+    };
+
+    let result = decode_sync_request_v1(data);
+    let response = match &result {
+        Ok(request) => {
+            serve(store, snapshots, request).and_then(|res| encode_sync_response_v1(&res))
+        }
+        Err(e) => Err(e.clone()),
+    };
+    // Only fails once the swarm has shut down.
+    let _ = p2p
+        .respond_sync(*request_id, response.unwrap_or_default())
+        .await;
+    if let Err(e) = &result {
+        penalise(p2p, *peer, &SyncError::Codec(e.clone())).await;
+    }
+    Some(result)
+}
+
+pub(crate) async fn request(
+    p2p: &P2pHandle,
+    peer: PeerId,
+    req: &SyncRequestV1,
+) -> Result<SyncResponseV1, SyncError> {
+    let bytes = encode_sync_request_v1(req).map_err(SyncError::Codec)?;
+    let response = p2p
+        .send_sync(peer, bytes)
+        .await
+        .map_err(SyncError::Network)?;
+    decode_sync_response_v1(&response).map_err(SyncError::Codec)
+}
+
+/// Up to `count` consecutive blocks from `start`. Not validated.
+pub async fn fetch_blocks(
+    p2p: &P2pHandle,
+    peer: PeerId,
+    start: u64,
+    count: u32,
+) -> Result<Vec<BlockV1>, SyncError> {
+    let SyncResponseV1::Blocks(blocks) =
+        request(p2p, peer, &SyncRequestV1::BlocksByRange { start, count }).await?
+    else {
+        return Err(SyncError::BadResponse);
+    };
+    let consecutive = blocks
+        .iter()
+        .zip(start..)
+        .all(|(block, height)| block.header.height == height);
+    if blocks.len() > count as usize || !consecutive {
+        return Err(SyncError::BadResponse);
+    }
+    Ok(blocks)
+}
+
+/// The block with header hash `hash`, if `peer` has it. Not validated.
+pub async fn fetch_block_by_hash(
+    p2p: &P2pHandle,
+    peer: PeerId,
+    hash: Hash32,
+) -> Result<Option<BlockV1>, SyncError> {
+    let SyncResponseV1::Blocks(mut blocks) =
+        request(p2p, peer, &SyncRequestV1::BlockByHash(hash)).await?
+    else {
+        return Err(SyncError::BadResponse);
+    };
+    match blocks.pop() {
+        None => Ok(None),
+        Some(block)
+            if blocks.is_empty()
+                && block_hash_v1(&block.header).map_err(SyncError::Codec)? == hash =>
+        {
+            Ok(Some(block))
+        }
+        Some(_) => Err(SyncError::BadResponse),
+    }
+}
+
+/// Up to `count` consecutive, linked headers from `start`.
+pub async fn fetch_headers(
+    p2p: &P2pHandle,
+    peer: PeerId,
+    start: u64,
+    count: u32,
+) -> Result<Vec<BlockHeaderV1>, SyncError> {
+    let SyncResponseV1::Headers(headers) =
+        request(p2p, peer, &SyncRequestV1::Headers { start, count }).await?
+    else {
+        return Err(SyncError::BadResponse);
+    };
+    if headers.len() > count as usize {
+        return Err(SyncError::BadResponse);
+    }
+    for (i, header) in headers.iter().enumerate() {
+        if header.height != start + i as u64 {
+            return Err(SyncError::BadResponse);
+        }
+        if i > 0 && header.prev_hash != block_hash_v1(&headers[i - 1]).map_err(SyncError::Codec)? {
+            return Err(SyncError::BadResponse);
+        }
+    }
+    Ok(headers)
+}
+
+type BatchResult = (PeerId, u64, u32, Result<Vec<BlockV1>, SyncError>);
+
+async fn fetch_batch(p2p: P2pHandle, peer: PeerId, start: u64, count: u32) -> BatchResult {
+    let result = fetch_blocks(&p2p, peer, start, count).await;
+    (peer, start, count, result)
+}
+
+/// Download, validate and import blocks until `store` reaches `target`.
+///
+/// Returns the new tip height. Fails with [`SyncError::NoPeers`] if every
+/// peer is dropped first; blocks imported up to that point are kept.
+pub async fn sync_to(
+    p2p: &P2pHandle,
+    store: &mut BlockStore,
+    validators: &ValidatorSet,
+    sig_cache: &SigCache,
+    peers: &[PeerId],
+    target: u64,
+    config: &SyncConfig,
+) -> Result<u64, SyncError> {
+    let batch_size = config.batch_size.max(1);
+    let mut next = store.tip().0 + 1;
+    let mut queue: VecDeque<(u64, u32)> = VecDeque::new();
+    let mut start = next;
+    while start <= target {
+        let count = (target - start + 1).min(batch_size as u64) as u32;
+        queue.push_back((start, count));
+        start += count as u64;
+    }
+
+    let mut active: Vec<PeerId> = peers.to_vec();
+    let mut turn = 0usize;
+    let mut in_flight = FuturesUnordered::new();
+    let mut ready: BTreeMap<u64, (PeerId, Vec<BlockV1>)> = BTreeMap::new();
+
+    while next <= target {
+        while in_flight.len() < config.max_in_flight.max(1) && !active.is_empty() {
+            let Some((start, count)) = queue.pop_front() else {
+                break;
+            };
+            let peer = active[turn % active.len()];
+            turn += 1;
+            in_flight.push(fetch_batch(p2p.clone(), peer, start, count));
+        }
+
+        let Some((peer, start, count, result)) = in_flight.next().await else {
+            return Err(SyncError::NoPeers { height: next });
+        };
+        match result {
+            Ok(blocks) if !blocks.is_empty() => {
+                let got = blocks.len() as u32;
+                if got < count {
+                    queue.push_front((start + got as u64, count - got));
+                }
+                ready.insert(start, (peer, blocks));
+            }
+            other => {
+                if let Err(e) = other {
+                    penalise(p2p, peer, &e).await;
+                }
+                active.retain(|p| *p != peer);
+                queue.push_front((start, count));
+            }
+        }
+
+        while let Some((peer, blocks)) = ready.remove(&next) {
+            let total = blocks.len() as u32;
+            for (i, block) in blocks.into_iter().enumerate() {
+                if let Err(e) = validate_block(&block, store.tip(), validators, sig_cache) {
+                    penalise(p2p, peer, &e).await;
+                    active.retain(|p| *p != peer);
+                    queue.push_front((next, total - i as u32));
+                    break;
+                }
+                store.append(block).map_err(SyncError::Store)?;
+                next += 1;
+            }
+        }
+    }
+    Ok(store.tip().0)
+}
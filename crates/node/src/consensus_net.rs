@@ -0,0 +1,168 @@
+//! Consensus message transport.
+//!
+//! Proposals and timeouts are broadcast on [`CONSENSUS_TOPIC`]; votes go
+//! straight to one validator (the next leader) over the direct consensus
+//! protocol and are acknowledged with a one-byte status. Every message is
+//! decoded from the canonical envelope and its domain-separated signature
+//! checked before it is forwarded, acknowledged or handed to the caller;
+//! senders of undecodable or badly signed messages are reported to the
+//! reputation system.
+//! Whether the signer is in the validator set is for consensus to decide.
+
+use novai_codec::{decode_consensus_message_v1, encode_consensus_message_v1, CodecError};
+use novai_crypto::{verify_consensus_v1, CryptoError};
+use novai_p2p::{
+    GossipMessageId, GossipValidation, Misbehaviour, P2pError, P2pEvent, P2pHandle, PeerId,
+    CONSENSUS_TOPIC,
+};
+use novai_types::{ConsensusMessage, VoteV1};
+
+/// Direct response: the vote decoded and its signature verified.
+pub const VOTE_ACCEPTED: u8 = 0;
+/// Direct response: the request was not a validly signed vote.
+pub const VOTE_REJECTED: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConsensusNetError {
+    Codec(CodecError),
+    Crypto(CryptoError),
+    InvalidSignature,
+    /// A vote on gossip, or a proposal/timeout on the direct protocol.
+    WrongTransport,
+    Network(P2pError),
+    /// The receiving validator answered [`VOTE_REJECTED`] (or garbage).
+    Refused,
+}
+
+impl ConsensusNetError {
+    /// How gossipsub should treat the message that produced this error.
+    pub fn validation(&self) -> GossipValidation {
+        match self {
+            ConsensusNetError::Network(_) => GossipValidation::Ignore,
+            _ => GossipValidation::Reject,
+        }
+    }
+
+    /// What this error says about the sending peer, if anything.
+    pub fn misbehaviour(&self) -> Option<Misbehaviour> {
+        match self {
+            ConsensusNetError::Codec(_) | ConsensusNetError::WrongTransport => {
+                Some(Misbehaviour::Malformed)
+            }
+            ConsensusNetError::Crypto(_) | ConsensusNetError::InvalidSignature => {
+                Some(Misbehaviour::InvalidSignature)
+            }
+            ConsensusNetError::Network(_) | ConsensusNetError::Refused => None,
+        }
+    }
+}
+
+/// Decode an envelope and check its signature.
+pub fn decode_verified(data: &[u8]) -> Result<ConsensusMessage, ConsensusNetError> {
+    let msg = decode_consensus_message_v1(data).map_err(ConsensusNetError::Codec)?;
+    if !verify_consensus_v1(&msg).map_err(ConsensusNetError::Crypto)? {
+        return Err(ConsensusNetError::InvalidSignature);
+    }
+    Ok(msg)
+}
+
+/// Publish a signed proposal or timeout to every validator.
+pub async fn broadcast(p2p: &P2pHandle, msg: &ConsensusMessage) -> Result<(), ConsensusNetError> {
+    if matches!(msg, ConsensusMessage::Vote(_)) {
+        return Err(ConsensusNetError::WrongTransport);
+    }
+    let bytes = encode_consensus_message_v1(msg).map_err(ConsensusNetError::Codec)?;
+    p2p.publish(CONSENSUS_TOPIC, bytes)
+        .await
+        .map_err(ConsensusNetError::Network)
+}
+
+/// Send a signed vote to `leader` and wait for its acknowledgement.
+pub async fn send_vote(
+    p2p: &P2pHandle,
+    leader: PeerId,
+    vote: VoteV1,
+) -> Result<(), ConsensusNetError> {
+    let bytes = encode_consensus_message_v1(&ConsensusMessage::Vote(vote))
+        .map_err(ConsensusNetError::Codec)?;
+    let response = p2p
+        .send_consensus(leader, bytes)
+        .await
+        .map_err(ConsensusNetError::Network)?;
+    match response.as_slice() {
+        [VOTE_ACCEPTED] => Ok(()),
+        _ => Err(ConsensusNetError::Refused),
+    }
+}
+
+/// Handle a [`P2pEvent`] if it carries a consensus message: verify it,
+/// report the gossip verdict or acknowledge the vote, and return it with
+/// the peer it came from.
+///
+/// Returns `None` for any other event.
+pub async fn handle_event(
+    p2p: &P2pHandle,
+    event: &P2pEvent,
+) -> Option<Result<(PeerId, ConsensusMessage), ConsensusNetError>> {
+    match event {
+        P2pEvent::Gossip {
+            message_id,
+            source,
+            topic,
+            data,
+        } if topic == CONSENSUS_TOPIC => {
+            let result = decode_verified(data).and_then(|msg| match msg {
+                ConsensusMessage::Vote(_) => Err(ConsensusNetError::WrongTransport),
+                msg => Ok((*source, msg)),
+            });
+            let validation = match &result {
+                Ok(_) => GossipValidation::Accept,
+                Err(e) => e.validation(),
+            };
+            report(p2p, message_id, source, validation).await;
+            penalise(p2p, source, &result).await;
+            Some(result)
+        }
+        P2pEvent::ConsensusRequest {
+            request_id,
+            peer,
+            data,
+        } => {
+            let result = decode_verified(data).and_then(|msg| match msg {
+                ConsensusMessage::Vote(_) => Ok((*peer, msg)),
+                _ => Err(ConsensusNetError::WrongTransport),
+            });
+            let status = if result.is_ok() {
+                VOTE_ACCEPTED
+            } else {
+                VOTE_REJECTED
+            };
+            // Only fails once the swarm has shut down.
+            let _ = p2p.respond_consensus(*request_id, vec![status]).await;
+            penalise(p2p, peer, &result).await;
+            Some(result)
+        }
+        _ => None,
+    }
+}
+
+async fn report(
+    p2p: &P2pHandle,
+    message_id: &GossipMessageId,
+    source: &PeerId,
+    validation: GossipValidation,
+) {
+    let _ = p2p
+        .report_validation(message_id.clone(), *source, validation)
+        .await;
+}
+
+async fn penalise<T>(p2p: &P2pHandle, peer: &PeerId, result: &Result<T, ConsensusNetError>) {
+    if let Some(misbehaviour) = result
+        .as_ref()
+        .err()
+        .and_then(ConsensusNetError::misbehaviour)
+    {
+        let _ = p2p.report_peer(*peer, misbehaviour).await;
+    }
+}
@@ -1,5 +1,15 @@
 //! novai-node
 //!
-//! Purpose: placeholder crate for clean-room workspace bootstrap.
-//! Invariants: no external dependencies (Week 1).
-//! Failure modes: none (placeholder).
+//! Purpose: wire networking to the mempool, consensus and block storage.
+//! `novai-p2p` moves opaque bytes; this crate decodes them and decides what
+//! they mean.
+//! Invariants: gossip is only forwarded, votes only acknowledged, and synced
+//! blocks and snapshot chunks only imported after local validation succeeds.
+//! Failure modes: invalid gossip is reported back to the swarm (so the
+//! sender is penalised) rather than surfaced as an error.
+
+pub mod block_store;
+pub mod block_sync;
+pub mod consensus_net;
+pub mod state_sync;
+pub mod tx_gossip;
@@ -1,21 +1,42 @@
-use mempool::{NonceProvider, TxMempool};
+use mempool::{AccountProvider, NonceProvider, SharedTxMempool, TxMempool};
 use novai_codec::txid_v1;
-use novai_crypto::{generate_keypair, sign_tx_v1};
-use novai_types::{Address, TxId, TxV1, TxVersion};
+use novai_crypto::{generate_keypair, sign_tx_v1, SigCache};
+use novai_node::block_store::BlockStore;
+use novai_node::block_sync::ValidatorSet;
+use novai_node::state_sync::{bootstrap, SnapshotConfig, Snapshots};
+use novai_node::{block_sync, consensus_net, tx_gossip};
+use novai_p2p::{Keypair, Multiaddr, P2pConfig, P2pEvent, TX_TOPIC};
+use novai_smt::{SparseMerkleTree, EMPTY_ROOT};
+use novai_types::{
+    Address, BlockHeaderV1, BlockHeaderVersion, BlockV1, QuorumCertV1, TxId, TxV1, TxVersion,
+};
 use std::collections::HashMap;
 use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
 
 fn usage() {
     eprintln!(
         "usage:
-  novai-node submit-tx <payload> [--nonce <u64>] [--fee <u64>] [--min-fee <u64>] [--cap <u64>]
-  novai-node drain-mempool <payload> [<payload> ...] [--max <u64>] [--min-fee <u64>] [--cap <u64>]
+  novai-node submit-tx <payload> [--nonce <u64>] [--fee <u64>] [--min-fee <u64>] [--cap <u64>] [--journal <path>]
+  novai-node drain-mempool <payload> [<payload> ...] [--max <u64>] [--min-fee <u64>] [--cap <u64>] [--journal <path>]
+  novai-node run [--listen <multiaddr>] [--dial <multiaddr> ...] [--no-mdns] [--submit <payload> ...] [--chain-id <u64>]
+                 [--bootnode <multiaddr> ...] [--peer <multiaddr>/p2p/<peer id> ...] [--peer-store <path>]
+                 [--blocks <dir>] [--journal <path>] [--data-dir <dir>] [--ban-list <path>]
+                 [--snapshot <height> --validator <hex address> ... [--sync-to <height>]]
 
 examples:
   novai-node submit-tx hello
   novai-node submit-tx hello --fee 10 --nonce 0
   novai-node drain-mempool a b c
   novai-node drain-mempool a b c --max 2
+  novai-node submit-tx hello --journal mempool.journal
+  novai-node run --listen /ip4/127.0.0.1/tcp/30333
+  novai-node run --dial /ip4/127.0.0.1/tcp/30333 --no-mdns --submit hello
+  novai-node run --no-mdns --bootnode /ip4/127.0.0.1/tcp/30333 --peer-store peers.txt
+  novai-node run --blocks blocks/ --journal mempool.journal
+  novai-node run --data-dir node-data/
+  novai-node run --dial /ip4/127.0.0.1/tcp/30333 --blocks blocks/ --snapshot 100 --validator <hex> --sync-to 120
 "
     );
 }
@@ -28,23 +49,66 @@ fn parse_u64(opt: Option<String>, what: &str) -> u64 {
         .unwrap_or_else(|_| panic!("invalid {what}: {s}"))
 }
 
+/// Week 2 node has no state yet: nonces are tracked in memory and every
+/// account is treated as funded, so txs restored from a journal written by an
+/// earlier run (with a different dev key) are still admissible.
 #[derive(Default)]
-struct InMemoryNonceProvider {
+struct InMemoryAccounts {
     expected: HashMap<Address, u64>,
 }
 
-impl InMemoryNonceProvider {
+impl InMemoryAccounts {
     fn set(&mut self, from: Address, nonce: u64) {
         self.expected.insert(from, nonce);
     }
 }
 
-impl NonceProvider for InMemoryNonceProvider {
+impl NonceProvider for InMemoryAccounts {
     fn expected_nonce(&self, from: &Address) -> u64 {
         *self.expected.get(from).unwrap_or(&0)
     }
 }
 
+impl AccountProvider for InMemoryAccounts {
+    fn balance(&self, _from: &Address) -> u64 {
+        u64::MAX
+    }
+}
+
+/// Files kept under `--data-dir` unless given their own path.
+const BAN_LIST_FILE: &str = "bans.txt";
+const PEER_STORE_FILE: &str = "peers.txt";
+
+/// State snapshots kept for serving state sync.
+const SNAPSHOTS_RETAINED: usize = 2;
+
+/// Verified signatures remembered between mempool admission and block
+/// validation: a full block's worth.
+const SIG_CACHE_CAPACITY: usize = 65_536;
+
+/// Week 2 node has no genesis config: the chain starts at an empty block at
+/// height 0 with all-zero hashes.
+fn genesis_block() -> BlockV1 {
+    BlockV1 {
+        header: BlockHeaderV1 {
+            version: BlockHeaderVersion::V1,
+            height: 0,
+            prev_hash: [0u8; 32],
+            state_root: [0u8; 32],
+            tx_root: [0u8; 32],
+            proposer: [0u8; 32],
+            qc_hash: [0u8; 32],
+        },
+        txs: Vec::new(),
+        qc: QuorumCertV1 {
+            height: 0,
+            round: 0,
+            block_hash: [0u8; 32],
+            votes: Vec::new(),
+        },
+    }
+}
+
 fn build_tx(from: Address, nonce: u64, fee: u64, payload: String) -> TxV1 {
     TxV1 {
         version: TxVersion::V1,
@@ -65,6 +129,204 @@ fn short_id(id: &TxId) -> String {
     s
 }
 
+/// Reload pending txs from a previous run and keep journaling to `path`.
+fn restore_journal(mp: &mut TxMempool, path: &str, accounts: &InMemoryAccounts) {
+    let report = mp
+        .attach_journal(path, accounts)
+        .unwrap_or_else(|e| panic!("open journal {path}: {e}"));
+    println!(
+        "restored {} txs from {path} (stale={} corrupt={} truncated={})",
+        report.restored,
+        report.stale.len(),
+        report.corrupt,
+        report.truncated
+    );
+    for (id, reason) in &report.stale {
+        println!("  skipped stale tx id={} reason={reason:?}", short_id(id));
+    }
+}
+
+/// 32-byte address from 64 hex digits.
+fn parse_address(opt: Option<String>, what: &str) -> Address {
+    let Some(s) = opt else {
+        panic!("missing value for {what}");
+    };
+    let invalid = || -> ! { panic!("invalid {what}: {s}") };
+    if s.len() != 64 || !s.is_ascii() {
+        invalid();
+    }
+    let mut address = [0u8; 32];
+    for (i, byte) in address.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).unwrap_or_else(|_| invalid());
+    }
+    address
+}
+
+fn parse_multiaddr(opt: Option<String>, what: &str) -> Multiaddr {
+    let Some(s) = opt else {
+        panic!("missing value for {what}");
+    };
+    s.parse::<Multiaddr>()
+        .unwrap_or_else(|_| panic!("invalid {what}: {s}"))
+}
+
+/// Start from the certified state at `height` instead of genesis, then block
+/// sync up to `target`.
+struct SnapshotStart {
+    height: u64,
+    target: u64,
+    validators: ValidatorSet,
+}
+
+/// Start networking with `config`, relay tx gossip through a mempool, answer
+/// sync requests from the block store (kept in `blocks` if given, else in
+/// memory), acknowledge votes and print events until Ctrl-C. Pending txs are
+/// restored from and journaled to `journal` if given. `submit` payloads are
+/// signed with a fresh dev key and broadcast once the first peer joins the
+/// tx topic.
+///
+/// With `snapshot`, the store in `blocks` is bootstrapped from the peers
+/// that become ready first. States are served for state sync at every
+/// height the node knows one for: genesis, or the bootstrapped anchor.
+/// Later heights need an executor, which the node does not have yet.
+fn run_node(
+    mut config: P2pConfig,
+    dial: Vec<Multiaddr>,
+    submit: Vec<String>,
+    blocks: Option<String>,
+    journal: Option<String>,
+    snapshot: Option<SnapshotStart>,
+) {
+    let mut snapshots = Snapshots::new(SNAPSHOTS_RETAINED);
+
+    let rt = tokio::runtime::Runtime::new().expect("tokio runtime");
+    rt.block_on(async move {
+        config.topics = vec![TX_TOPIC.to_string()];
+
+        let sig_cache = Arc::new(SigCache::new(SIG_CACHE_CAPACITY));
+        let accounts = InMemoryAccounts::default();
+        let mut mp = TxMempool::new(1, 1000).with_sig_cache(sig_cache.clone());
+        if let Some(path) = &journal {
+            restore_journal(&mut mp, path, &accounts);
+        }
+        let mempool = SharedTxMempool::new(mp);
+        let (sk, vk) = generate_keypair();
+        let mut pending: Vec<TxV1> = submit
+            .into_iter()
+            .enumerate()
+            .map(|(nonce, payload)| {
+                let mut tx = build_tx(vk.to_bytes(), nonce as u64, 1, payload);
+                sign_tx_v1(&sk, &mut tx).expect("sign tx");
+                tx
+            })
+            .collect();
+
+        let (p2p, mut events) = novai_p2p::spawn(config).expect("start p2p");
+        println!("local peer id {}", p2p.local_peer_id());
+        for addr in dial {
+            if let Err(e) = p2p.dial(addr.clone()).await {
+                eprintln!("dial {addr} failed: {e:?}");
+            }
+        }
+
+        let store = match (&snapshot, &blocks) {
+            (Some(start), Some(dir)) => {
+                let mut peers = Vec::new();
+                while peers.is_empty() {
+                    match events.recv().await {
+                        Some(P2pEvent::PeerReady { peer, .. }) => peers.push(peer),
+                        Some(event) => println!("p2p: {event:?}"),
+                        None => return,
+                    }
+                }
+                // Take whoever else is already ready, so one bad peer can't
+                // stall the download.
+                while let Ok(event) = events.try_recv() {
+                    if let P2pEvent::PeerReady { peer, .. } = event {
+                        peers.push(peer);
+                    }
+                }
+                println!(
+                    "state sync: fetching snapshot at height {} from {} peers",
+                    start.height,
+                    peers.len()
+                );
+                let (store, tree) = bootstrap(
+                    &p2p,
+                    dir,
+                    &start.validators,
+                    &sig_cache,
+                    &peers,
+                    start.height,
+                    start.target,
+                    &SnapshotConfig::default(),
+                )
+                .await
+                .unwrap_or_else(|e| panic!("state sync from height {}: {e:?}", start.height));
+                snapshots.insert(store.base_height(), tree);
+                store
+            }
+            (Some(_), None) => unreachable!("--snapshot requires --blocks"),
+            (None, Some(dir)) => BlockStore::open(dir, genesis_block())
+                .unwrap_or_else(|e| panic!("open block store {dir}: {e:?}")),
+            (None, None) => BlockStore::in_memory(genesis_block()).expect("genesis block store"),
+        };
+        println!("block store tip height {}", store.tip().0);
+        // Genesis commits to the empty state. The state at the base of a
+        // store opened from an earlier snapshot sync is not kept on disk.
+        if store.base_height() == 0 && genesis_block().header.state_root == EMPTY_ROOT {
+            snapshots.insert(0, SparseMerkleTree::new());
+        }
+
+        loop {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => break,
+                event = events.recv() => {
+                    let Some(event) = event else {
+                        break;
+                    };
+                    if let Some(result) =
+                        tx_gossip::handle_event(&p2p, &mempool, &accounts, &event).await
+                    {
+                        match result {
+                            Ok(id) => println!("gossip: admitted tx id={}", short_id(&id)),
+                            Err(e) => println!("gossip: dropped tx ({:?}): {e:?}", e.validation()),
+                        }
+                        continue;
+                    }
+                    if let Some(result) =
+                        block_sync::handle_event(&p2p, &store, &snapshots, &event).await
+                    {
+                        match result {
+                            Ok(request) => println!("sync: served {request:?}"),
+                            Err(e) => println!("sync: bad request: {e}"),
+                        }
+                        continue;
+                    }
+                    if let Some(result) = consensus_net::handle_event(&p2p, &event).await {
+                        match result {
+                            Ok((peer, msg)) => println!("consensus: {msg:?} from {peer}"),
+                            Err(e) => println!("consensus: dropped message: {e:?}"),
+                        }
+                        continue;
+                    }
+                    println!("p2p: {event:?}");
+
+                    if matches!(&event, P2pEvent::PeerSubscribed { topic, .. } if topic == TX_TOPIC) {
+                        for tx in pending.drain(..) {
+                            match tx_gossip::submit_tx(&p2p, &mempool, &accounts, tx).await {
+                                Ok(id) => println!("submitted tx id={}", short_id(&id)),
+                                Err(e) => eprintln!("submit failed: {e:?}"),
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        let _ = p2p.shutdown().await;
+    });
+}
+
 fn main() {
     let mut args = env::args().skip(1);
     let Some(cmd) = args.next() else {
@@ -84,6 +346,7 @@ fn main() {
             let mut fee: u64 = 1;
             let mut min_fee: u64 = 1;
             let mut cap: usize = 1000;
+            let mut journal: Option<String> = None;
 
             // parse simple flags
             let rest: Vec<String> = args.collect();
@@ -106,6 +369,13 @@ fn main() {
                         cap = parse_u64(rest.get(i + 1).cloned(), "--cap") as usize;
                         i += 2;
                     }
+                    "--journal" => {
+                        let Some(path) = rest.get(i + 1).cloned() else {
+                            panic!("missing value for --journal");
+                        };
+                        journal = Some(path);
+                        i += 2;
+                    }
                     other => {
                         panic!("unknown flag: {other}");
                     }
@@ -119,13 +389,16 @@ fn main() {
             let (sk, pk) = generate_keypair();
             let from = pk.to_bytes();
 
-            let mut nonce_provider = InMemoryNonceProvider::default();
-            nonce_provider.set(from, nonce);
+            let mut accounts = InMemoryAccounts::default();
+            accounts.set(from, nonce);
+            if let Some(path) = &journal {
+                restore_journal(&mut mp, path, &accounts);
+            }
 
             let mut tx = build_tx(from, nonce, fee, payload);
             sign_tx_v1(&sk, &mut tx).expect("sign tx");
 
-            let id = mp.insert(tx, &nonce_provider).expect("mempool insert");
+            let id = mp.insert(tx, &accounts).expect("mempool insert");
             println!(
                 "submitted tx id={} (mempool size={})",
                 short_id(&id),
@@ -160,6 +433,7 @@ fn main() {
             let mut max: usize = 100;
             let mut min_fee: u64 = 1;
             let mut cap: usize = 1000;
+            let mut journal: Option<String> = None;
 
             // parse flags
             let mut i = 0;
@@ -177,6 +451,13 @@ fn main() {
                         cap = parse_u64(rest.get(i + 1).cloned(), "--cap") as usize;
                         i += 2;
                     }
+                    "--journal" => {
+                        let Some(path) = rest.get(i + 1).cloned() else {
+                            panic!("missing value for --journal");
+                        };
+                        journal = Some(path);
+                        i += 2;
+                    }
                     other => {
                         panic!("unknown flag: {other}");
                     }
@@ -184,23 +465,26 @@ fn main() {
             }
 
             let mut mp = TxMempool::new(min_fee, cap);
-            let mut nonce_provider = InMemoryNonceProvider::default();
+            let mut accounts = InMemoryAccounts::default();
 
             // Insert txs with increasing fees so drain shows fee-priority deterministically.
             let (sk, pk) = generate_keypair();
             let from = pk.to_bytes();
-            nonce_provider.set(from, 0);
+            accounts.set(from, 0);
+            if let Some(path) = &journal {
+                restore_journal(&mut mp, path, &accounts);
+            }
 
             for (idx, payload) in payloads.into_iter().enumerate() {
                 let fee = (idx as u64) + 1;
                 let mut tx = build_tx(from, 0, fee, payload);
                 sign_tx_v1(&sk, &mut tx).expect("sign tx");
 
-                mp.insert(tx, &nonce_provider).expect("mempool insert");
+                mp.insert(tx, &accounts).expect("mempool insert");
             }
 
             let before = mp.len();
-            let drained = mp.drain_ready(max, &nonce_provider);
+            let drained = mp.drain_ready(max, &accounts);
             let after = mp.len();
 
             let ids: Vec<String> = drained
@@ -221,6 +505,144 @@ fn main() {
             );
         }
 
+        "run" => {
+            let mut config = P2pConfig::new(Keypair::generate_ed25519());
+            let mut listen = Vec::new();
+            let mut dial = Vec::new();
+            let mut submit = Vec::new();
+            let mut blocks = None;
+            let mut journal = None;
+            let mut snapshot_height = None;
+            let mut sync_to = None;
+            let mut validators = Vec::new();
+            let mut data_dir: Option<PathBuf> = None;
+
+            let rest: Vec<String> = args.collect();
+            let mut i = 0;
+            while i < rest.len() {
+                match rest[i].as_str() {
+                    "--listen" => {
+                        listen.push(parse_multiaddr(rest.get(i + 1).cloned(), "--listen"));
+                        i += 2;
+                    }
+                    "--dial" => {
+                        dial.push(parse_multiaddr(rest.get(i + 1).cloned(), "--dial"));
+                        i += 2;
+                    }
+                    "--no-mdns" => {
+                        config.enable_mdns = false;
+                        i += 1;
+                    }
+                    "--submit" => {
+                        let Some(payload) = rest.get(i + 1).cloned() else {
+                            panic!("missing value for --submit");
+                        };
+                        submit.push(payload);
+                        i += 2;
+                    }
+                    "--chain-id" => {
+                        config.handshake.chain_id =
+                            parse_u64(rest.get(i + 1).cloned(), "--chain-id");
+                        i += 2;
+                    }
+                    "--bootnode" => {
+                        config
+                            .bootnodes
+                            .push(parse_multiaddr(rest.get(i + 1).cloned(), "--bootnode"));
+                        i += 2;
+                    }
+                    "--peer" => {
+                        config
+                            .persistent_peers
+                            .push(parse_multiaddr(rest.get(i + 1).cloned(), "--peer"));
+                        i += 2;
+                    }
+                    "--peer-store" => {
+                        let Some(path) = rest.get(i + 1).cloned() else {
+                            panic!("missing value for --peer-store");
+                        };
+                        config.peer_store_path = Some(path.into());
+                        i += 2;
+                    }
+                    "--blocks" => {
+                        let Some(path) = rest.get(i + 1).cloned() else {
+                            panic!("missing value for --blocks");
+                        };
+                        blocks = Some(path);
+                        i += 2;
+                    }
+                    "--journal" => {
+                        let Some(path) = rest.get(i + 1).cloned() else {
+                            panic!("missing value for --journal");
+                        };
+                        journal = Some(path);
+                        i += 2;
+                    }
+                    "--data-dir" => {
+                        let Some(path) = rest.get(i + 1).cloned() else {
+                            panic!("missing value for --data-dir");
+                        };
+                        data_dir = Some(path.into());
+                        i += 2;
+                    }
+                    "--ban-list" => {
+                        let Some(path) = rest.get(i + 1).cloned() else {
+                            panic!("missing value for --ban-list");
+                        };
+                        config.ban_list_path = Some(path.into());
+                        i += 2;
+                    }
+                    "--snapshot" => {
+                        snapshot_height = Some(parse_u64(rest.get(i + 1).cloned(), "--snapshot"));
+                        i += 2;
+                    }
+                    "--sync-to" => {
+                        sync_to = Some(parse_u64(rest.get(i + 1).cloned(), "--sync-to"));
+                        i += 2;
+                    }
+                    "--validator" => {
+                        validators.push(parse_address(rest.get(i + 1).cloned(), "--validator"));
+                        i += 2;
+                    }
+                    other => {
+                        eprintln!("unknown flag: {other}");
+                        usage();
+                        return;
+                    }
+                }
+            }
+
+            if !listen.is_empty() {
+                config.listen_addrs = listen;
+            }
+            if let Some(dir) = &data_dir {
+                std::fs::create_dir_all(dir)
+                    .unwrap_or_else(|e| panic!("create data dir {}: {e}", dir.display()));
+                config
+                    .ban_list_path
+                    .get_or_insert_with(|| dir.join(BAN_LIST_FILE));
+                config
+                    .peer_store_path
+                    .get_or_insert_with(|| dir.join(PEER_STORE_FILE));
+            }
+            let snapshot = match snapshot_height {
+                Some(height) => {
+                    if blocks.is_none() || validators.is_empty() {
+                        eprintln!("--snapshot needs --blocks and at least one --validator");
+                        usage();
+                        return;
+                    }
+                    Some(SnapshotStart {
+                        height,
+                        target: sync_to.unwrap_or(height).max(height),
+                        validators: ValidatorSet::new(validators),
+                    })
+                }
+                None => None,
+            };
+            run_node(config, dial, submit, blocks, journal, snapshot);
+        }
+
         _ => {
             usage();
         }
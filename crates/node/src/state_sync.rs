@@ -0,0 +1,246 @@
+//! State snapshot sync: bootstrap from a recent state instead of replaying
+//! every block since genesis.
+//!
+//! The anchor is a block at the snapshot height, trusted because its QC
+//! carries a quorum of the validator set ([`validate_certified`]). Its
+//! `state_root` commits to the whole state, so every chunk of leaves is
+//! checked with [`verify_chunk`] as it arrives; a peer serving a chunk
+//! that does not prove against the root is dropped (and reported) and the
+//! chunk asked of another peer. Chunks are contiguous key ranges fetched in order, since
+//! where one ends depends on the serving peer's leaf cap.
+//!
+//! Once the state is complete, [`bootstrap`] opens a [`BlockStore`] based
+//! at the anchor and hands over to block sync from the next height.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use novai_codec::{encode_sync_response_v1, CodecError};
+use novai_crypto::SigCache;
+use novai_p2p::{P2pHandle, PeerId, MAX_SYNC_MESSAGE};
+use novai_smt::{next_start, verify_chunk, HashedTree, SmtError, SparseMerkleTree, MAX_KEY};
+use novai_types::{BlockV1, Hash32, SnapshotChunkV1, SyncRequestV1, SyncResponseV1};
+
+use crate::block_store::BlockStore;
+use crate::block_sync::{
+    fetch_blocks, penalise, request, sync_to, validate_certified, SyncConfig, SyncError,
+    ValidatorSet,
+};
+
+/// Most leaves served in one chunk.
+pub const MAX_LEAVES_PER_CHUNK: u32 = 4096;
+
+/// State trees kept for serving, by height. Only the most recent
+/// `retain` heights are kept, each hashed once on insertion so chunk
+/// requests don't rehash the whole state.
+#[derive(Debug, Clone)]
+pub struct Snapshots {
+    retain: usize,
+    trees: BTreeMap<u64, HashedTree>,
+}
+
+impl Snapshots {
+    pub fn new(retain: usize) -> Self {
+        Self {
+            retain,
+            trees: BTreeMap::new(),
+        }
+    }
+
+    /// Keep `tree` as the state at `height`, evicting the oldest snapshots
+    /// beyond the retention limit.
+    pub fn insert(&mut self, height: u64, tree: SparseMerkleTree) {
+        self.trees.insert(height, HashedTree::new(tree));
+        while self.trees.len() > self.retain {
+            self.trees.pop_first();
+        }
+    }
+
+    pub fn get(&self, height: u64) -> Option<&HashedTree> {
+        self.trees.get(&height)
+    }
+
+    /// Highest height a snapshot is held for.
+    pub fn latest(&self) -> Option<u64> {
+        self.trees.last_key_value().map(|(height, _)| *height)
+    }
+}
+
+/// Answer a snapshot chunk request. Unknown heights get an empty list;
+/// chunks are shrunk until the response fits in one sync message.
+pub fn serve_chunk(
+    snapshots: &Snapshots,
+    height: u64,
+    start: &Hash32,
+    max_leaves: u32,
+) -> Result<SyncResponseV1, CodecError> {
+    let Some(tree) = snapshots.get(height) else {
+        return Ok(SyncResponseV1::SnapshotChunks(Vec::new()));
+    };
+    let mut max_leaves = max_leaves.clamp(1, MAX_LEAVES_PER_CHUNK);
+    loop {
+        let response = SyncResponseV1::SnapshotChunks(vec![tree.chunk(start, max_leaves as usize)]);
+        if max_leaves == 1 || encode_sync_response_v1(&response)?.len() <= MAX_SYNC_MESSAGE {
+            return Ok(response);
+        }
+        max_leaves /= 2;
+    }
+}
+
+/// The chunk of the state at `height` starting at `start`, or `None` if
+/// `peer` holds no snapshot at that height. Not verified against the root,
+/// but a chunk must make progress: it either reaches [`MAX_KEY`] or ends at
+/// its last leaf, so an empty or short-ended chunk is a bad response.
+pub async fn fetch_chunk(
+    p2p: &P2pHandle,
+    peer: PeerId,
+    height: u64,
+    start: Hash32,
+    max_leaves: u32,
+) -> Result<Option<SnapshotChunkV1>, SyncError> {
+    let req = SyncRequestV1::SnapshotChunk {
+        height,
+        start,
+        max_leaves,
+    };
+    let SyncResponseV1::SnapshotChunks(mut chunks) = request(p2p, peer, &req).await? else {
+        return Err(SyncError::BadResponse);
+    };
+    match chunks.pop() {
+        None => Ok(None),
+        Some(chunk)
+            if chunks.is_empty()
+                && chunk.start == start
+                && chunk.leaves.len() <= max_leaves.max(1) as usize
+                && (chunk.end == MAX_KEY
+                    || chunk
+                        .leaves
+                        .last()
+                        .is_some_and(|(key, _)| *key == chunk.end)) =>
+        {
+            Ok(Some(chunk))
+        }
+        Some(_) => Err(SyncError::BadResponse),
+    }
+}
+
+/// Snapshot download settings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotConfig {
+    /// Leaves asked for per chunk.
+    pub max_leaves: u32,
+    /// Settings for the block sync that follows.
+    pub blocks: SyncConfig,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self {
+            max_leaves: 1024,
+            blocks: SyncConfig::default(),
+        }
+    }
+}
+
+/// Download the certified block at `height` and the full state its
+/// `state_root` commits to.
+///
+/// Peers that fail a request or serve anything that does not verify are
+/// dropped; fails with [`SyncError::NoPeers`] once none are left.
+pub async fn fetch_snapshot(
+    p2p: &P2pHandle,
+    validators: &ValidatorSet,
+    sig_cache: &SigCache,
+    peers: &[PeerId],
+    height: u64,
+    config: &SnapshotConfig,
+) -> Result<(BlockV1, SparseMerkleTree), SyncError> {
+    let mut active: Vec<PeerId> = peers.to_vec();
+
+    let anchor = loop {
+        let Some(&peer) = active.first() else {
+            return Err(SyncError::NoPeers { height });
+        };
+        match fetch_blocks(p2p, peer, height, 1).await {
+            Ok(mut blocks) if blocks.len() == 1 => {
+                let block = blocks.remove(0);
+                match validate_certified(&block, validators, sig_cache) {
+                    Ok(()) => break block,
+                    Err(e) => penalise(p2p, peer, &e).await,
+                }
+            }
+            Ok(_) => {}
+            Err(e) => penalise(p2p, peer, &e).await,
+        }
+        active.remove(0);
+    };
+    let root = anchor.header.state_root;
+
+    let mut tree = SparseMerkleTree::new();
+    let mut start = Some([0u8; 32]);
+    let mut turn = 0usize;
+    while let Some(from) = start {
+        if active.is_empty() {
+            return Err(SyncError::NoPeers { height });
+        }
+        let peer = active[turn % active.len()];
+        turn += 1;
+        let verified = fetch_chunk(p2p, peer, height, from, config.max_leaves)
+            .await
+            .and_then(|chunk| {
+                let Some(chunk) = chunk else {
+                    return Ok(None);
+                };
+                verify_chunk(&root, &chunk).map_err(SyncError::InvalidChunk)?;
+                Ok(Some(chunk))
+            });
+        let chunk = match verified {
+            Ok(Some(chunk)) => chunk,
+            other => {
+                if let Err(e) = other {
+                    penalise(p2p, peer, &e).await;
+                }
+                active.retain(|p| *p != peer);
+                continue;
+            }
+        };
+        start = next_start(&chunk.end);
+        for (key, value) in chunk.leaves {
+            tree.insert(key, value);
+        }
+    }
+
+    // Implied by the chunk proofs covering the key space; checked anyway.
+    if tree.root() != root {
+        return Err(SyncError::InvalidChunk(SmtError::RootMismatch));
+    }
+    Ok((anchor, tree))
+}
+
+/// Fetch the snapshot at `height`, open a block store in `dir` based at its
+/// anchor, and block sync from there up to `target`.
+#[allow(clippy::too_many_arguments)]
+pub async fn bootstrap(
+    p2p: &P2pHandle,
+    dir: impl AsRef<Path>,
+    validators: &ValidatorSet,
+    sig_cache: &SigCache,
+    peers: &[PeerId],
+    height: u64,
+    target: u64,
+    config: &SnapshotConfig,
+) -> Result<(BlockStore, SparseMerkleTree), SyncError> {
+    let (anchor, tree) = fetch_snapshot(p2p, validators, sig_cache, peers, height, config).await?;
+    let mut store = BlockStore::open(dir, anchor).map_err(SyncError::Store)?;
+    sync_to(
+        p2p,
+        &mut store,
+        validators,
+        sig_cache,
+        peers,
+        target,
+        &config.blocks,
+    )
+    .await?;
+    Ok((store, tree))
+}
@@ -0,0 +1,164 @@
+//! Transaction gossip on [`TX_TOPIC`].
+//!
+//! Incoming: decode the canonical signed bytes, admit through the shared
+//! mempool tagged with the propagating peer, and report the verdict so
+//! gossipsub forwards valid txs. Peers relaying undecodable or badly signed
+//! txs are also reported to the reputation system, as are peers whose
+//! rejections get them throttled by the mempool. A peer's rate-limit state is
+//! forgotten once it disconnects.
+//! Outgoing: admit locally first, then publish.
+
+use mempool::{AccountProvider, SharedTxMempool, TxMempoolError, TxSource};
+use novai_codec::{encode_tx_v1_signed, CodecError, TxV1Ref};
+use novai_p2p::{
+    GossipMessageId, GossipValidation, Misbehaviour, P2pError, P2pEvent, P2pHandle, PeerId,
+    TX_TOPIC,
+};
+use novai_types::{TxId, TxV1};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxGossipError {
+    Codec(CodecError),
+    Mempool(TxMempoolError),
+    /// Admitted locally but could not be published (e.g. no peers yet).
+    Publish(P2pError),
+}
+
+impl TxGossipError {
+    /// How gossipsub should treat the message that produced this error.
+    ///
+    /// Only failures that prove the payload itself is bad are `Reject`;
+    /// anything that depends on our state or local policy is `Ignore`.
+    pub fn validation(&self) -> GossipValidation {
+        match self {
+            TxGossipError::Codec(_)
+            | TxGossipError::Mempool(TxMempoolError::InvalidSignature)
+            | TxGossipError::Mempool(TxMempoolError::InvalidPublicKey)
+            | TxGossipError::Mempool(TxMempoolError::CodecError) => GossipValidation::Reject,
+            TxGossipError::Mempool(_) | TxGossipError::Publish(_) => GossipValidation::Ignore,
+        }
+    }
+
+    /// What this error says about the peer that relayed the tx, if anything.
+    pub fn misbehaviour(&self) -> Option<Misbehaviour> {
+        match self {
+            TxGossipError::Codec(_) | TxGossipError::Mempool(TxMempoolError::CodecError) => {
+                Some(Misbehaviour::Malformed)
+            }
+            TxGossipError::Mempool(TxMempoolError::InvalidSignature)
+            | TxGossipError::Mempool(TxMempoolError::InvalidPublicKey) => {
+                Some(Misbehaviour::InvalidSignature)
+            }
+            TxGossipError::Mempool(_) | TxGossipError::Publish(_) => None,
+        }
+    }
+}
+
+/// Decode and admit a tx received from `source`.
+///
+/// The bytes are parsed in place, and policy and the signature are checked on
+/// that borrowed view; the tx is copied out only once they pass, so malformed,
+/// underpriced or badly signed input is rejected without allocating.
+pub fn admit_gossip_tx(
+    mempool: &SharedTxMempool,
+    accounts: &impl AccountProvider,
+    source: &PeerId,
+    data: &[u8],
+) -> Result<TxId, TxGossipError> {
+    let tx = TxV1Ref::decode(data).map_err(TxGossipError::Codec)?;
+    mempool
+        .insert_ref_from(&tx, &TxSource::Peer(source.to_bytes()), accounts)
+        .map_err(TxGossipError::Mempool)
+}
+
+/// Handle a [`P2pEvent`] if it is tx gossip: admit it and report the verdict.
+///
+/// Returns `None` for any other event; a disconnect also drops the peer's
+/// rate-limit state from the mempool.
+pub async fn handle_event(
+    p2p: &P2pHandle,
+    mempool: &SharedTxMempool,
+    accounts: &impl AccountProvider,
+    event: &P2pEvent,
+) -> Option<Result<TxId, TxGossipError>> {
+    if let P2pEvent::PeerDisconnected { peer } = event {
+        mempool
+            .write()
+            .forget_source(&TxSource::Peer(peer.to_bytes()));
+        return None;
+    }
+    let P2pEvent::Gossip {
+        message_id,
+        source,
+        topic,
+        data,
+    } = event
+    else {
+        return None;
+    };
+    if topic != TX_TOPIC {
+        return None;
+    }
+
+    let result = admit_gossip_tx(mempool, accounts, source, data);
+    let validation = match &result {
+        Ok(_) => GossipValidation::Accept,
+        Err(e) => e.validation(),
+    };
+    report(p2p, message_id, source, validation).await;
+    if let Some(misbehaviour) = result.as_ref().err().and_then(TxGossipError::misbehaviour) {
+        let _ = p2p.report_peer(*source, misbehaviour).await;
+    }
+    if became_throttled(mempool, source, &result) {
+        let _ = p2p.report_peer(*source, Misbehaviour::Flooding).await;
+    }
+    Some(result)
+}
+
+/// Whether the mempool's verdict in `result` spent the last of `source`'s
+/// rejection budget. Txs refused while it stays throttled come back
+/// `RateLimited`, so this holds once per throttling.
+fn became_throttled(
+    mempool: &SharedTxMempool,
+    source: &PeerId,
+    result: &Result<TxId, TxGossipError>,
+) -> bool {
+    match result {
+        Err(TxGossipError::Mempool(TxMempoolError::RateLimited { .. })) => false,
+        Err(TxGossipError::Mempool(_)) => mempool
+            .read()
+            .throttled_sources()
+            .contains(&TxSource::Peer(source.to_bytes())),
+        // Undecodable bytes never reach the budget.
+        Err(_) | Ok(_) => false,
+    }
+}
+
+async fn report(
+    p2p: &P2pHandle,
+    message_id: &GossipMessageId,
+    source: &PeerId,
+    validation: GossipValidation,
+) {
+    // Only fails once the swarm has shut down, when nothing is left to forward.
+    let _ = p2p
+        .report_validation(message_id.clone(), *source, validation)
+        .await;
+}
+
+/// Admit a locally submitted tx and publish it to peers.
+pub async fn submit_tx(
+    p2p: &P2pHandle,
+    mempool: &SharedTxMempool,
+    accounts: &impl AccountProvider,
+    tx: TxV1,
+) -> Result<TxId, TxGossipError> {
+    let bytes = encode_tx_v1_signed(&tx).map_err(TxGossipError::Codec)?;
+    let id = mempool
+        .insert(tx, accounts)
+        .map_err(TxGossipError::Mempool)?;
+    p2p.publish(TX_TOPIC, bytes)
+        .await
+        .map_err(TxGossipError::Publish)?;
+    Ok(id)
+}
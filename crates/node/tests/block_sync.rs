@@ -0,0 +1,492 @@
+//! Block and snapshot sync from several peers, one of which serves bad data.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use ed25519_dalek::SigningKey;
+use novai_codec::{block_hash_v1, decode_sync_request_v1, encode_sync_response_v1, tx_root_v1};
+use novai_crypto::{sign_consensus_v1, sign_tx_v1, SigCache};
+use novai_execution::ExecutionError;
+use novai_node::block_store::BlockStore;
+use novai_node::block_sync::{
+    fetch_block_by_hash, fetch_headers, handle_event, sync_to, validate_block, SyncConfig,
+    SyncError, ValidatorSet,
+};
+use novai_node::state_sync::{bootstrap, fetch_chunk, SnapshotConfig, Snapshots};
+use novai_p2p::{spawn, Keypair, Multiaddr, P2pConfig, P2pEvent, P2pHandle, PeerId};
+use novai_smt::SparseMerkleTree;
+use novai_types::{
+    BlockHeaderV1, BlockHeaderVersion, BlockV1, ConsensusMessage, Hash32, QuorumCertV1,
+    SnapshotChunkV1, SyncRequestV1, SyncResponseV1, TxV1, TxVersion, VoteV1,
+};
+use tokio::sync::mpsc::Receiver;
+use tokio::time::timeout;
+
+const WAIT: Duration = Duration::from_secs(20);
+
+fn validator_keys() -> Vec<SigningKey> {
+    (31..35u8)
+        .map(|i| SigningKey::from_bytes(&[i; 32]))
+        .collect()
+}
+
+fn validators() -> ValidatorSet {
+    ValidatorSet::new(
+        validator_keys()
+            .iter()
+            .map(|k| k.verifying_key().to_bytes()),
+    )
+}
+
+/// QC for `header` signed by the first `signers` validators.
+fn certify(header: &BlockHeaderV1, signers: usize) -> QuorumCertV1 {
+    let block_hash = block_hash_v1(header).unwrap();
+    let mut votes: Vec<_> = validator_keys()
+        .iter()
+        .take(signers)
+        .map(|key| {
+            let mut vote = ConsensusMessage::Vote(VoteV1 {
+                height: header.height,
+                round: 0,
+                block_hash,
+                voter: key.verifying_key().to_bytes(),
+                sig: [0u8; 64],
+            });
+            sign_consensus_v1(key, &mut vote).unwrap();
+            (*vote.signer(), *vote.sig())
+        })
+        .collect();
+    votes.sort();
+    QuorumCertV1 {
+        height: header.height,
+        round: 0,
+        block_hash,
+        votes,
+    }
+}
+
+fn make_block(parent: Option<&BlockV1>, height: u64) -> BlockV1 {
+    let sender = SigningKey::from_bytes(&[40u8; 32]);
+    let txs: Vec<TxV1> = (0..height % 3)
+        .map(|i| {
+            let mut tx = TxV1 {
+                version: TxVersion::V1,
+                from: sender.verifying_key().to_bytes(),
+                nonce: height * 3 + i,
+                fee: 1,
+                payload: vec![height as u8; 8],
+                sig: [0u8; 64],
+            };
+            sign_tx_v1(&sender, &mut tx).unwrap();
+            tx
+        })
+        .collect();
+    let header = BlockHeaderV1 {
+        version: BlockHeaderVersion::V1,
+        height,
+        prev_hash: parent.map_or([0u8; 32], |p| block_hash_v1(&p.header).unwrap()),
+        state_root: [height as u8; 32],
+        tx_root: tx_root_v1(&txs).unwrap(),
+        proposer: validator_keys()[0].verifying_key().to_bytes(),
+        qc_hash: [0u8; 32],
+    };
+    let qc = certify(&header, 3);
+    BlockV1 { header, txs, qc }
+}
+
+fn make_chain(len: u64) -> Vec<BlockV1> {
+    let mut chain = vec![make_block(None, 0)];
+    for height in 1..=len {
+        let block = make_block(chain.last(), height);
+        chain.push(block);
+    }
+    chain
+}
+
+fn store_from(chain: &[BlockV1]) -> BlockStore {
+    let mut store = BlockStore::in_memory(chain[0].clone()).unwrap();
+    for block in &chain[1..] {
+        store.append(block.clone()).unwrap();
+    }
+    store
+}
+
+async fn start() -> (P2pHandle, Receiver<P2pEvent>, Multiaddr) {
+    let mut config = P2pConfig::new(Keypair::generate_ed25519());
+    config.listen_addrs = vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()];
+    config.enable_mdns = false;
+    let (p2p, mut events) = spawn(config).unwrap();
+    let addr = timeout(WAIT, async {
+        loop {
+            if let Some(P2pEvent::Listening { address }) = events.recv().await {
+                return address;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    (p2p, events, addr)
+}
+
+/// A peer answering sync requests from `store` and `snapshots` in the
+/// background.
+async fn serve_with(store: BlockStore, snapshots: Snapshots) -> (P2pHandle, Multiaddr) {
+    let (p2p, mut events, addr) = start().await;
+    let handle = p2p.clone();
+    tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            handle_event(&handle, &store, &snapshots, &event).await;
+        }
+    });
+    (p2p, addr)
+}
+
+/// A peer answering every sync request with `respond(request)`.
+async fn serve_raw(
+    respond: impl Fn(SyncRequestV1) -> SyncResponseV1 + Send + 'static,
+) -> (P2pHandle, Multiaddr) {
+    let (p2p, mut events, addr) = start().await;
+    let handle = p2p.clone();
+    tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            if let P2pEvent::SyncRequest {
+                request_id, data, ..
+            } = event
+            {
+                let response = respond(decode_sync_request_v1(&data).unwrap());
+                let bytes = encode_sync_response_v1(&response).unwrap();
+                let _ = handle.respond_sync(request_id, bytes).await;
+            }
+        }
+    });
+    (p2p, addr)
+}
+
+async fn serve(store: BlockStore) -> (P2pHandle, Multiaddr) {
+    serve_with(store, Snapshots::new(0)).await
+}
+
+/// Connect `p2p` to every address in `addrs` and wait for each handshake;
+/// peers only answer requests from ready peers.
+async fn connect(p2p: &P2pHandle, events: &mut Receiver<P2pEvent>, addrs: Vec<Multiaddr>) {
+    let expected = addrs.len();
+    for addr in addrs {
+        p2p.dial(addr).await.unwrap();
+    }
+    timeout(WAIT, async {
+        let mut ready = 0;
+        while ready < expected {
+            if let Some(P2pEvent::PeerReady { .. }) = events.recv().await {
+                ready += 1;
+            }
+        }
+    })
+    .await
+    .unwrap();
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("novai-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn validation_checks_linkage_tx_root_and_quorum() {
+    let chain = make_chain(2);
+    let set = validators();
+    let cache = SigCache::new(64);
+    let parent = |b: &BlockV1| (b.header.height, block_hash_v1(&b.header).unwrap());
+    validate_block(&chain[2], parent(&chain[1]), &set, &cache).unwrap();
+
+    assert_eq!(
+        validate_block(&chain[2], parent(&chain[0]), &set, &cache),
+        Err(SyncError::NotChild { height: 2 })
+    );
+
+    let mut block = chain[2].clone();
+    block.txs[1].payload.push(0);
+    assert_eq!(
+        validate_block(&block, parent(&chain[1]), &set, &cache),
+        Err(SyncError::TxRootMismatch { height: 2 })
+    );
+
+    let mut block = chain[2].clone();
+    block.qc = certify(&block.header, 2);
+    assert_eq!(
+        validate_block(&block, parent(&chain[1]), &set, &cache),
+        Err(SyncError::InsufficientQuorum {
+            height: 2,
+            votes: 2,
+            needed: 3
+        })
+    );
+
+    // Duplicate voter padding out a short QC.
+    let mut block = chain[2].clone();
+    block.qc.votes[2] = block.qc.votes[1];
+    assert!(matches!(
+        validate_block(&block, parent(&chain[1]), &set, &cache),
+        Err(SyncError::InvalidVote { height: 2, .. })
+    ));
+
+    // The QC for block 1 does not certify block 2.
+    let mut block = chain[2].clone();
+    block.qc = chain[1].qc.clone();
+    assert_eq!(
+        validate_block(&block, parent(&chain[1]), &set, &cache),
+        Err(SyncError::QcMismatch { height: 2 })
+    );
+
+    let mut block = chain[2].clone();
+    block.qc.votes[0].1[5] ^= 0x01;
+    assert_eq!(
+        validate_block(&block, parent(&chain[1]), &set, &cache),
+        Err(SyncError::InvalidVote {
+            height: 2,
+            voter: block.qc.votes[0].0
+        })
+    );
+    // A certified block whose tx is not signed by its sender.
+    let mut block = chain[2].clone();
+    block.txs[1].sig[0] ^= 0x01;
+    block.header.tx_root = tx_root_v1(&block.txs).unwrap();
+    block.qc = certify(&block.header, 3);
+    assert_eq!(
+        validate_block(&block, parent(&chain[1]), &set, &cache),
+        Err(SyncError::InvalidTx {
+            height: 2,
+            error: ExecutionError::InvalidSignature { index: 1 }
+        })
+    );
+
+    // Signatures verified once (e.g. on mempool admission) are not redone.
+    let hits = cache.metrics().hits;
+    validate_block(&chain[2], parent(&chain[1]), &set, &cache).unwrap();
+    assert_eq!(cache.metrics().hits, hits + 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn syncs_in_parallel_skips_bad_peers_and_resumes_from_disk() {
+    let chain = make_chain(40);
+    let (a, a_addr) = serve(store_from(&chain)).await;
+    let (b, b_addr) = serve(store_from(&chain)).await;
+
+    // Same headers and QCs, but txs that don't match tx_root.
+    let mut bad_chain = chain.clone();
+    for block in &mut bad_chain[1..] {
+        block.txs.push(block.txs.first().cloned().unwrap_or(TxV1 {
+            version: TxVersion::V1,
+            from: [0u8; 32],
+            nonce: 0,
+            fee: 0,
+            payload: Vec::new(),
+            sig: [0u8; 64],
+        }));
+    }
+    let (m, m_addr) = serve(store_from(&bad_chain)).await;
+
+    let (c, mut c_events, _) = start().await;
+    connect(&c, &mut c_events, vec![a_addr, m_addr, b_addr]).await;
+
+    let tip_hash = block_hash_v1(&chain[40].header).unwrap();
+    assert_eq!(
+        fetch_block_by_hash(&c, a.local_peer_id(), tip_hash)
+            .await
+            .unwrap(),
+        Some(chain[40].clone())
+    );
+    assert_eq!(
+        fetch_block_by_hash(&c, a.local_peer_id(), [7u8; 32])
+            .await
+            .unwrap(),
+        None
+    );
+    let headers = fetch_headers(&c, b.local_peer_id(), 38, 10).await.unwrap();
+    assert_eq!(headers.len(), 3);
+    assert_eq!(headers[2], chain[40].header);
+
+    let dir = temp_dir("block-sync");
+    let config = SyncConfig {
+        batch_size: 4,
+        max_in_flight: 3,
+    };
+    let peers = [a.local_peer_id(), m.local_peer_id(), b.local_peer_id()];
+    let set = validators();
+    let cache = SigCache::new(1024);
+
+    let mut store = BlockStore::open(&dir, chain[0].clone()).unwrap();
+    assert_eq!(
+        sync_to(&c, &mut store, &set, &cache, &peers, 25, &config)
+            .await
+            .unwrap(),
+        25
+    );
+    assert_eq!(
+        store.block_by_height(25),
+        Some(&chain[25]),
+        "bad peer's blocks must not be imported"
+    );
+    drop(store);
+
+    // Restart: progress is read back from disk and sync continues from 26.
+    let mut store = BlockStore::open(&dir, chain[0].clone()).unwrap();
+    assert_eq!(store.tip().0, 25);
+    assert_eq!(
+        sync_to(
+            &c,
+            &mut store,
+            &set,
+            &cache,
+            &[b.local_peer_id()],
+            40,
+            &config
+        )
+        .await
+        .unwrap(),
+        40
+    );
+    assert_eq!(store.tip(), (40, tip_hash));
+
+    // Only the bad peer left: nothing can be imported.
+    let mut store = BlockStore::in_memory(chain[0].clone()).unwrap();
+    assert_eq!(
+        sync_to(
+            &c,
+            &mut store,
+            &set,
+            &cache,
+            &[m.local_peer_id()],
+            10,
+            &config
+        )
+        .await,
+        Err(SyncError::NoPeers { height: 1 })
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+fn state(n: u32) -> SparseMerkleTree {
+    let mut tree = SparseMerkleTree::new();
+    for i in 0..n {
+        // Spread over the key space so the tree has some depth.
+        let mut key: Hash32 = [0u8; 32];
+        key[..4].copy_from_slice(&i.wrapping_mul(2_654_435_761).to_be_bytes());
+        key[31] = i as u8;
+        tree.insert(key, vec![i as u8; 1 + i as usize % 5]);
+    }
+    tree
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn snapshot_sync_verifies_chunks_then_hands_over_to_block_sync() {
+    let tree = state(300);
+    let mut chain = make_chain(5);
+    let mut anchor = make_block(chain.last(), 6);
+    anchor.header.state_root = tree.root();
+    anchor.qc = certify(&anchor.header, 3);
+    chain.push(anchor.clone());
+    for height in 7..=12 {
+        let block = make_block(chain.last(), height);
+        chain.push(block);
+    }
+
+    let mut snapshots = Snapshots::new(2);
+    snapshots.insert(6, tree.clone());
+    let (a, a_addr) = serve_with(store_from(&chain), snapshots).await;
+
+    // Right blocks, wrong state. With 32-leaf chunks handed out in turn
+    // starting with the bad peer, the forged leaf is in one it serves.
+    let mut forged = tree.clone();
+    let key = *forged.iter().nth(130).unwrap().0;
+    forged.insert(key, b"forged".to_vec());
+    let mut bad_snapshots = Snapshots::new(2);
+    bad_snapshots.insert(6, forged);
+    let (m, m_addr) = serve_with(store_from(&chain), bad_snapshots).await;
+
+    let (c, mut c_events, _) = start().await;
+    connect(&c, &mut c_events, vec![a_addr, m_addr]).await;
+
+    assert_eq!(
+        fetch_chunk(&c, a.local_peer_id(), 5, [0u8; 32], 10)
+            .await
+            .unwrap(),
+        None
+    );
+    let chunk = fetch_chunk(&c, a.local_peer_id(), 6, [0u8; 32], 10)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(chunk.leaves.len(), 10);
+
+    let config = SnapshotConfig {
+        max_leaves: 32,
+        blocks: SyncConfig {
+            batch_size: 2,
+            max_in_flight: 2,
+        },
+    };
+    let set = validators();
+    let cache = SigCache::new(1024);
+    let dir = temp_dir("snapshot-sync");
+    let peers: [PeerId; 2] = [m.local_peer_id(), a.local_peer_id()];
+    let (store, synced) = bootstrap(&c, &dir, &set, &cache, &peers, 6, 12, &config)
+        .await
+        .unwrap();
+    assert_eq!(synced, tree);
+    assert_eq!(store.base_height(), 6);
+    assert!(store.block_by_height(5).is_none());
+    assert_eq!(store.tip(), (12, block_hash_v1(&chain[12].header).unwrap()));
+    drop(store);
+
+    // The anchored store reopens from disk like any other.
+    let store = BlockStore::open(&dir, anchor).unwrap();
+    assert_eq!(store.tip().0, 12);
+
+    // Only the peer with forged state left.
+    assert_eq!(
+        bootstrap(&c, &dir, &set, &cache, &[m.local_peer_id()], 6, 12, &config)
+            .await
+            .err(),
+        Some(SyncError::NoPeers { height: 6 })
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn chunks_that_make_no_progress_are_refused() {
+    let tree = state(40);
+    // Ends wherever the request asked it to start, with no leaves.
+    let (stall, stall_addr) = serve_raw(|request| {
+        let SyncRequestV1::SnapshotChunk { start, .. } = request else {
+            return SyncResponseV1::SnapshotChunks(Vec::new());
+        };
+        SyncResponseV1::SnapshotChunks(vec![SnapshotChunkV1 {
+            start,
+            end: start,
+            leaves: Vec::new(),
+            proof: Vec::new(),
+        }])
+    })
+    .await;
+    // Ends past its last leaf, short of the end of the key space.
+    let short = tree.clone();
+    let (skip, skip_addr) = serve_raw(move |request| {
+        let SyncRequestV1::SnapshotChunk { start, .. } = request else {
+            return SyncResponseV1::SnapshotChunks(Vec::new());
+        };
+        let mut chunk = short.chunk(&start, 4);
+        chunk.end[31] ^= 0x01;
+        SyncResponseV1::SnapshotChunks(vec![chunk])
+    })
+    .await;
+
+    let (c, mut c_events, _) = start().await;
+    connect(&c, &mut c_events, vec![stall_addr, skip_addr]).await;
+    for peer in [stall.local_peer_id(), skip.local_peer_id()] {
+        assert_eq!(
+            fetch_chunk(&c, peer, 6, [0u8; 32], 4).await,
+            Err(SyncError::BadResponse)
+        );
+    }
+}
@@ -0,0 +1,155 @@
+//! Consensus transport between a leader and a validator.
+
+use std::time::Duration;
+
+use ed25519_dalek::SigningKey;
+use novai_codec::{block_hash_v1, encode_consensus_message_v1};
+use novai_crypto::sign_consensus_v1;
+use novai_node::consensus_net::{
+    broadcast, handle_event, send_vote, ConsensusNetError, VOTE_ACCEPTED,
+};
+use novai_p2p::{
+    spawn, Keypair, Multiaddr, P2pConfig, P2pEvent, P2pHandle, PeerId, CONSENSUS_TOPIC,
+};
+use novai_types::{BlockHeaderV1, BlockHeaderVersion, ConsensusMessage, ProposalV1, VoteV1};
+use tokio::sync::mpsc::Receiver;
+use tokio::time::timeout;
+
+const WAIT: Duration = Duration::from_secs(20);
+
+async fn start() -> (P2pHandle, Receiver<P2pEvent>, Multiaddr) {
+    let mut config = P2pConfig::new(Keypair::generate_ed25519());
+    config.listen_addrs = vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()];
+    config.enable_mdns = false;
+    config.topics = vec![CONSENSUS_TOPIC.to_string()];
+    config.gossip_heartbeat = Duration::from_millis(100);
+    let (p2p, mut events) = spawn(config).unwrap();
+
+    let addr = timeout(WAIT, async {
+        loop {
+            if let Some(P2pEvent::Listening { address }) = events.recv().await {
+                return address;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    (p2p, events, addr)
+}
+
+/// Run `p2p`'s consensus handling until one message has been handled.
+async fn next_message(
+    p2p: &P2pHandle,
+    events: &mut Receiver<P2pEvent>,
+) -> Result<(PeerId, ConsensusMessage), ConsensusNetError> {
+    timeout(WAIT, async {
+        loop {
+            let event = events.recv().await.unwrap();
+            if let Some(result) = handle_event(p2p, &event).await {
+                return result;
+            }
+        }
+    })
+    .await
+    .unwrap()
+}
+
+fn signed(sk: &SigningKey, mut msg: ConsensusMessage) -> ConsensusMessage {
+    sign_consensus_v1(sk, &mut msg).unwrap();
+    msg
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn proposals_gossip_and_votes_go_direct() {
+    let (leader, mut leader_events, leader_addr) = start().await;
+    let (validator, mut validator_events, _) = start().await;
+    let leader_key = SigningKey::from_bytes(&[21u8; 32]);
+    let validator_key = SigningKey::from_bytes(&[22u8; 32]);
+
+    validator.dial(leader_addr).await.unwrap();
+    timeout(WAIT, async {
+        while !matches!(
+            leader_events.recv().await,
+            Some(P2pEvent::PeerSubscribed { .. })
+        ) {}
+        // Messages are only heard from peers whose handshake checked out.
+        while !matches!(
+            validator_events.recv().await,
+            Some(P2pEvent::PeerReady { .. })
+        ) {}
+    })
+    .await
+    .unwrap();
+
+    let header = BlockHeaderV1 {
+        version: BlockHeaderVersion::V1,
+        height: 1,
+        prev_hash: [0u8; 32],
+        state_root: [1u8; 32],
+        tx_root: [0u8; 32],
+        proposer: leader_key.verifying_key().to_bytes(),
+        qc_hash: [0u8; 32],
+    };
+    let proposal = signed(
+        &leader_key,
+        ConsensusMessage::Proposal(ProposalV1 {
+            round: 0,
+            header: header.clone(),
+            sig: [0u8; 64],
+        }),
+    );
+    broadcast(&leader, &proposal).await.unwrap();
+    let (from, got) = next_message(&validator, &mut validator_events)
+        .await
+        .unwrap();
+    assert_eq!((from, &got), (leader.local_peer_id(), &proposal));
+
+    let ConsensusMessage::Vote(vote) = signed(
+        &validator_key,
+        ConsensusMessage::Vote(VoteV1 {
+            height: 1,
+            round: 0,
+            block_hash: block_hash_v1(&header).unwrap(),
+            voter: validator_key.verifying_key().to_bytes(),
+            sig: [0u8; 64],
+        }),
+    ) else {
+        unreachable!()
+    };
+    let leader_id = leader.local_peer_id();
+
+    let sender = validator.clone();
+    let good = vote.clone();
+    let sent = tokio::spawn(async move { send_vote(&sender, leader_id, good).await });
+    let (from, got) = next_message(&leader, &mut leader_events).await.unwrap();
+    assert_eq!(from, validator.local_peer_id());
+    assert_eq!(got, ConsensusMessage::Vote(vote.clone()));
+    sent.await.unwrap().unwrap();
+
+    // A vote signed under the wrong key is refused, and votes don't gossip.
+    let forged = VoteV1 {
+        round: 1,
+        ..vote.clone()
+    };
+    let sender = validator.clone();
+    let sent = tokio::spawn(async move { send_vote(&sender, leader_id, forged).await });
+    assert_eq!(
+        next_message(&leader, &mut leader_events).await,
+        Err(ConsensusNetError::InvalidSignature)
+    );
+    assert_eq!(sent.await.unwrap(), Err(ConsensusNetError::Refused));
+    assert_eq!(
+        broadcast(&validator, &ConsensusMessage::Vote(vote.clone())).await,
+        Err(ConsensusNetError::WrongTransport)
+    );
+
+    // Proposals are not accepted on the direct protocol.
+    let bytes = encode_consensus_message_v1(&proposal).unwrap();
+    let sender = validator.clone();
+    let sent = tokio::spawn(async move { sender.send_consensus(leader_id, bytes).await });
+    assert_eq!(
+        next_message(&leader, &mut leader_events).await,
+        Err(ConsensusNetError::WrongTransport)
+    );
+    assert_ne!(sent.await.unwrap().unwrap(), vec![VOTE_ACCEPTED]);
+}
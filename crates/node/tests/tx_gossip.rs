@@ -0,0 +1,215 @@
+//! Tx gossip across three in-process nodes in a line: A - B - C.
+
+use std::time::Duration;
+
+use mempool::{
+    AccountProvider, NonceProvider, RateLimitConfig, SharedTxMempool, TxMempool, TxMempoolConfig,
+    TxMempoolError, TxSource,
+};
+use novai_codec::encode_tx_v1_signed;
+use novai_crypto::{generate_keypair, sign_tx_v1};
+use novai_node::tx_gossip::{handle_event, submit_tx, TxGossipError};
+use novai_p2p::{
+    spawn, GossipValidation, Keypair, Multiaddr, P2pConfig, P2pEvent, P2pHandle, TX_TOPIC,
+};
+use novai_types::{Address, TxId, TxV1, TxVersion};
+use tokio::sync::mpsc::Receiver;
+use tokio::time::timeout;
+
+const WAIT: Duration = Duration::from_secs(20);
+
+struct Funded;
+
+impl NonceProvider for Funded {
+    fn expected_nonce(&self, _from: &Address) -> u64 {
+        0
+    }
+}
+
+impl AccountProvider for Funded {
+    fn balance(&self, _from: &Address) -> u64 {
+        u64::MAX
+    }
+}
+
+struct Node {
+    p2p: P2pHandle,
+    events: Receiver<P2pEvent>,
+    mempool: SharedTxMempool,
+}
+
+async fn start() -> (Node, Multiaddr) {
+    let mut config = P2pConfig::new(Keypair::generate_ed25519());
+    config.listen_addrs = vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()];
+    config.enable_mdns = false;
+    config.topics = vec![TX_TOPIC.to_string()];
+    config.gossip_heartbeat = Duration::from_millis(100);
+    let (p2p, mut events) = spawn(config).unwrap();
+
+    let addr = timeout(WAIT, async {
+        loop {
+            if let Some(P2pEvent::Listening { address }) = events.recv().await {
+                return address;
+            }
+        }
+    })
+    .await
+    .unwrap();
+
+    let node = Node {
+        p2p,
+        events,
+        mempool: SharedTxMempool::new(TxMempool::new(1, 10)),
+    };
+    (node, addr)
+}
+
+/// Drain `node`'s events until `n` peers have joined the tx topic and
+/// passed the handshake; gossip from anyone else is ignored.
+async fn wait_subscribed(node: &mut Node, n: usize) {
+    timeout(WAIT, async {
+        let (mut subscribed, mut ready) = (0, 0);
+        while subscribed < n || ready < n {
+            match node.events.recv().await {
+                Some(P2pEvent::PeerSubscribed { .. }) => subscribed += 1,
+                Some(P2pEvent::PeerReady { .. }) => ready += 1,
+                _ => {}
+            }
+        }
+    })
+    .await
+    .unwrap();
+}
+
+/// Feed `node`'s gossip through the mempool until one tx has been handled.
+async fn next_tx(node: &mut Node) -> Result<TxId, TxGossipError> {
+    timeout(WAIT, async {
+        loop {
+            let event = node.events.recv().await.unwrap();
+            if let Some(result) = handle_event(&node.p2p, &node.mempool, &Funded, &event).await {
+                return result;
+            }
+        }
+    })
+    .await
+    .unwrap()
+}
+
+fn signed_tx(payload: &[u8]) -> TxV1 {
+    signed_tx_paying(5, payload)
+}
+
+fn signed_tx_paying(fee: u64, payload: &[u8]) -> TxV1 {
+    let (sk, vk) = generate_keypair();
+    let mut tx = TxV1 {
+        version: TxVersion::V1,
+        from: vk.to_bytes(),
+        nonce: 0,
+        fee,
+        payload: payload.to_vec(),
+        sig: [0u8; 64],
+    };
+    sign_tx_v1(&sk, &mut tx).unwrap();
+    tx
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn valid_txs_propagate_and_invalid_ones_stop_at_first_hop() {
+    let (mut a, a_addr) = start().await;
+    let (mut b, b_addr) = start().await;
+    let (mut c, _) = start().await;
+
+    b.p2p.dial(a_addr).await.unwrap();
+    c.p2p.dial(b_addr).await.unwrap();
+    wait_subscribed(&mut a, 1).await;
+    wait_subscribed(&mut b, 2).await;
+    wait_subscribed(&mut c, 1).await;
+    // Let a few heartbeats graft the mesh so B forwards to C.
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    // A publishes a forged tx directly, bypassing its own mempool.
+    let mut forged = signed_tx(b"forged");
+    forged.sig[0] ^= 0x01;
+    a.p2p
+        .publish(TX_TOPIC, encode_tx_v1_signed(&forged).unwrap())
+        .await
+        .unwrap();
+    let err = next_tx(&mut b).await.unwrap_err();
+    assert_eq!(err.validation(), GossipValidation::Reject);
+
+    let good = signed_tx(b"good");
+    let id = submit_tx(&a.p2p, &a.mempool, &Funded, good).await.unwrap();
+    assert_eq!(next_tx(&mut b).await.unwrap(), id);
+
+    // C's first tx is the good one: the forged one was never forwarded.
+    assert_eq!(next_tx(&mut c).await.unwrap(), id);
+    for node in [&a, &b, &c] {
+        assert!(node.mempool.contains(&id));
+        assert_eq!(node.mempool.len(), 1);
+    }
+
+    let from_a = TxSource::Peer(a.p2p.local_peer_id().to_bytes());
+    let score = b.mempool.read().source_score(&from_a).unwrap();
+    assert_eq!((score.accepted, score.rejected), (1, 1));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn throttled_peers_are_reported_and_forgotten_on_disconnect() {
+    let (a, a_addr) = start().await;
+    let (mut b, _) = start().await;
+    b.mempool = SharedTxMempool::new(TxMempool::with_config(TxMempoolConfig {
+        rate_limit: RateLimitConfig {
+            max_rejections_per_window: 2,
+            ..RateLimitConfig::default()
+        },
+        ..TxMempoolConfig::default()
+    }));
+    let a_id = a.p2p.local_peer_id();
+
+    b.p2p.dial(a_addr).await.unwrap();
+    wait_subscribed(&mut b, 1).await;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    // Underpriced txs are only ignored, until they use up A's budget.
+    for payload in [b"cheap-1", b"cheap-2"] {
+        let tx = signed_tx_paying(0, payload);
+        a.p2p
+            .publish(TX_TOPIC, encode_tx_v1_signed(&tx).unwrap())
+            .await
+            .unwrap();
+        let err = next_tx(&mut b).await.unwrap_err();
+        assert_eq!(err.validation(), GossipValidation::Ignore);
+        assert_eq!(err.misbehaviour(), None);
+    }
+    let from_a = TxSource::Peer(a_id.to_bytes());
+    assert_eq!(b.mempool.read().throttled_sources(), vec![from_a.clone()]);
+    assert!(b.p2p.peer_score(a_id).await.unwrap() < 0);
+
+    // Still throttled: refused without another report.
+    let before = b.p2p.peer_score(a_id).await.unwrap();
+    let tx = signed_tx(b"good");
+    a.p2p
+        .publish(TX_TOPIC, encode_tx_v1_signed(&tx).unwrap())
+        .await
+        .unwrap();
+    assert!(matches!(
+        next_tx(&mut b).await,
+        Err(TxGossipError::Mempool(TxMempoolError::RateLimited { .. }))
+    ));
+    assert!(b.p2p.peer_score(a_id).await.unwrap() >= before);
+
+    a.p2p.shutdown().await.unwrap();
+    timeout(WAIT, async {
+        loop {
+            let event = b.events.recv().await.unwrap();
+            let gone = matches!(event, P2pEvent::PeerDisconnected { peer } if peer == a_id);
+            handle_event(&b.p2p, &b.mempool, &Funded, &event).await;
+            if gone {
+                return;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(b.mempool.read().source_score(&from_a), None);
+}
@@ -0,0 +1,120 @@
+//! The combined libp2p behaviour and swarm construction.
+
+use libp2p::allow_block_list::{self, BlockedPeers};
+use libp2p::swarm::behaviour::toggle::Toggle;
+use libp2p::swarm::NetworkBehaviour;
+use libp2p::{
+    gossipsub, identify, mdns, noise, ping, request_response, tcp, yamux, Swarm, SwarmBuilder,
+};
+
+use crate::consensus::{CONSENSUS_PROTOCOL, MAX_CONSENSUS_MESSAGE};
+use crate::frame::FrameCodec;
+use crate::gossip::message_id;
+use crate::handshake::{HANDSHAKE_PROTOCOL, MAX_HANDSHAKE_MESSAGE};
+use crate::peers::peer_id_of;
+use crate::slots::Slots;
+use crate::sync::{MAX_SYNC_MESSAGE, SYNC_PROTOCOL};
+use crate::{P2pConfig, P2pError};
+
+#[derive(NetworkBehaviour)]
+pub(crate) struct NodeBehaviour {
+    /// Refuses banned peers before any other behaviour sees them.
+    pub(crate) blocked: allow_block_list::Behaviour<BlockedPeers>,
+    /// Turns away inbound connections that cannot get a slot, before the
+    /// behaviours below set up for them.
+    pub(crate) slots: Slots,
+    pub(crate) gossipsub: gossipsub::Behaviour,
+    pub(crate) mdns: Toggle<mdns::tokio::Behaviour>,
+    pub(crate) identify: identify::Behaviour,
+    pub(crate) ping: ping::Behaviour,
+    pub(crate) handshake: request_response::Behaviour<FrameCodec>,
+    pub(crate) consensus: request_response::Behaviour<FrameCodec>,
+    pub(crate) sync: request_response::Behaviour<FrameCodec>,
+}
+
+pub(crate) fn build_swarm(config: &P2pConfig) -> Result<Swarm<NodeBehaviour>, P2pError> {
+    let transport_err = |e: &dyn std::fmt::Display| P2pError::Transport(e.to_string());
+
+    let swarm = SwarmBuilder::with_existing_identity(config.keypair.clone())
+        .with_tokio()
+        .with_tcp(
+            tcp::Config::default().nodelay(true),
+            noise::Config::new,
+            yamux::Config::default,
+        )
+        .map_err(|e| transport_err(&e))?
+        .with_dns()
+        .map_err(|e| transport_err(&e))?
+        .with_behaviour(|key| {
+            let gossip_config = gossipsub::ConfigBuilder::default()
+                .heartbeat_interval(config.gossip_heartbeat)
+                .validation_mode(gossipsub::ValidationMode::Strict)
+                .validate_messages()
+                .message_id_fn(message_id)
+                .build()
+                .map_err(|e| e.to_string())?;
+            let gossipsub = gossipsub::Behaviour::new(
+                gossipsub::MessageAuthenticity::Signed(key.clone()),
+                gossip_config,
+            )
+            .map_err(|e| e.to_string())?;
+
+            let mdns = if config.enable_mdns {
+                Some(mdns::tokio::Behaviour::new(
+                    mdns::Config::default(),
+                    key.public().to_peer_id(),
+                )?)
+            } else {
+                None
+            };
+
+            let identify = identify::Behaviour::new(identify::Config::new(
+                config.protocol_version.clone(),
+                key.public(),
+            ));
+
+            let handshake = request_response::Behaviour::with_codec(
+                FrameCodec {
+                    max_len: MAX_HANDSHAKE_MESSAGE,
+                },
+                [(HANDSHAKE_PROTOCOL, request_response::ProtocolSupport::Full)],
+                request_response::Config::default().with_request_timeout(config.handshake_timeout),
+            );
+            let consensus = request_response::Behaviour::with_codec(
+                FrameCodec {
+                    max_len: MAX_CONSENSUS_MESSAGE,
+                },
+                [(CONSENSUS_PROTOCOL, request_response::ProtocolSupport::Full)],
+                request_response::Config::default()
+                    .with_request_timeout(config.consensus_request_timeout),
+            );
+            let sync = request_response::Behaviour::with_codec(
+                FrameCodec {
+                    max_len: MAX_SYNC_MESSAGE,
+                },
+                [(SYNC_PROTOCOL, request_response::ProtocolSupport::Full)],
+                request_response::Config::default()
+                    .with_request_timeout(config.sync_request_timeout),
+            );
+
+            Ok(NodeBehaviour {
+                blocked: allow_block_list::Behaviour::default(),
+                slots: Slots::new(
+                    config.limits.clone(),
+                    config.persistent_peers.iter().filter_map(peer_id_of),
+                ),
+                gossipsub,
+                mdns: Toggle::from(mdns),
+                identify,
+                ping: ping::Behaviour::default(),
+                handshake,
+                consensus,
+                sync,
+            })
+        })
+        .map_err(|e| transport_err(&e))?
+        .with_swarm_config(|c| c.with_idle_connection_timeout(config.idle_connection_timeout))
+        .build();
+
+    Ok(swarm)
+}
@@ -0,0 +1,24 @@
+//! Consensus transport: gossip for proposals and timeouts, direct
+//! request-response for votes.
+//!
+//! Votes go to one peer (the next leader), so flooding them is wasted
+//! bandwidth and latency. [`CONSENSUS_PROTOCOL`] carries one envelope per
+//! request and one short reply per response as length-prefixed frames
+//! capped at [`MAX_CONSENSUS_MESSAGE`]. As with gossip, payloads are opaque
+//! here.
+
+use libp2p::request_response::InboundRequestId;
+use libp2p::StreamProtocol;
+
+/// Proposals and timeouts, as consensus envelope bytes.
+pub const CONSENSUS_TOPIC: &str = "/novai/consensus/1";
+
+/// Direct validator-to-validator messages (votes).
+pub const CONSENSUS_PROTOCOL: StreamProtocol = StreamProtocol::new("/novai/consensus/direct/1");
+
+/// Largest request or response accepted on [`CONSENSUS_PROTOCOL`].
+pub const MAX_CONSENSUS_MESSAGE: usize = 64 * 1024;
+
+/// Inbound direct request awaiting [`P2pHandle::respond_consensus`](crate::P2pHandle::respond_consensus).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ConsensusRequestId(pub(crate) InboundRequestId);
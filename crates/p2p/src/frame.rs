@@ -0,0 +1,123 @@
+//! Length-prefixed frames for the request-response protocols.
+//!
+//! Each request and response is a `u32` little-endian length followed by
+//! that many bytes. The cap is per protocol; a peer announcing a longer
+//! frame is cut off before anything is allocated for it.
+
+use std::io;
+
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p::request_response;
+use libp2p::StreamProtocol;
+
+/// Opaque frames of at most `max_len` bytes.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FrameCodec {
+    pub(crate) max_len: usize,
+}
+
+async fn read_frame<T: AsyncRead + Unpin + Send>(
+    io: &mut T,
+    max_len: usize,
+) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    io.read_exact(&mut len).await?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > max_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {len} bytes exceeds {max_len}"),
+        ));
+    }
+    let mut buf = vec![0u8; len];
+    io.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+async fn write_frame<T: AsyncWrite + Unpin + Send>(
+    io: &mut T,
+    data: &[u8],
+    max_len: usize,
+) -> io::Result<()> {
+    if data.len() > max_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("frame of {} bytes exceeds {max_len}", data.len()),
+        ));
+    }
+    io.write_all(&(data.len() as u32).to_le_bytes()).await?;
+    io.write_all(data).await?;
+    io.close().await
+}
+
+#[async_trait]
+impl request_response::Codec for FrameCodec {
+    type Protocol = StreamProtocol;
+    type Request = Vec<u8>;
+    type Response = Vec<u8>;
+
+    async fn read_request<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Vec<u8>>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_frame(io, self.max_len).await
+    }
+
+    async fn read_response<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Vec<u8>>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_frame(io, self.max_len).await
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        req: Vec<u8>,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_frame(io, &req, self.max_len).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        res: Vec<u8>,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_frame(io, &res, self.max_len).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::block_on;
+    use futures::io::Cursor;
+
+    #[test]
+    fn frames_round_trip_and_oversized_frames_are_refused() {
+        block_on(async {
+            let mut buf = Cursor::new(Vec::new());
+            write_frame(&mut buf, b"vote", 16).await.unwrap();
+            assert_eq!(buf.get_ref()[..4], 4u32.to_le_bytes());
+            buf.set_position(0);
+            assert_eq!(read_frame(&mut buf, 16).await.unwrap(), b"vote");
+
+            let mut out = Cursor::new(Vec::new());
+            assert!(write_frame(&mut out, &[0u8; 17], 16).await.is_err());
+
+            let mut lying = Cursor::new(17u32.to_le_bytes().to_vec());
+            let err = read_frame(&mut lying, 16).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        });
+    }
+}
@@ -0,0 +1,119 @@
+//! Gossip topics, message ids and validation verdicts.
+//!
+//! Every gossip message is validated by the consumer: the swarm holds a
+//! received message until [`P2pHandle::report_validation`](crate::P2pHandle::report_validation)
+//! says whether to forward it (`Accept`), drop it silently (`Ignore`) or drop
+//! it and penalise the peer that sent it (`Reject`).
+
+use libp2p::gossipsub;
+use novai_codec::TxV1Ref;
+
+use crate::consensus::CONSENSUS_TOPIC;
+
+/// Transactions, as `encode_tx_v1_signed` bytes.
+pub const TX_TOPIC: &str = "/novai/tx/1";
+
+/// Opaque id of a received gossip message, echoed back when validating it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GossipMessageId(pub(crate) Vec<u8>);
+
+impl GossipMessageId {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GossipValidation {
+    /// Valid: deliver and forward.
+    Accept,
+    /// Provably invalid: drop and penalise the propagating peer.
+    Reject,
+    /// Not useful to us (duplicate, stale, below local policy): drop quietly.
+    Ignore,
+}
+
+impl From<GossipValidation> for gossipsub::MessageAcceptance {
+    fn from(v: GossipValidation) -> Self {
+        match v {
+            GossipValidation::Accept => gossipsub::MessageAcceptance::Accept,
+            GossipValidation::Reject => gossipsub::MessageAcceptance::Reject,
+            GossipValidation::Ignore => gossipsub::MessageAcceptance::Ignore,
+        }
+    }
+}
+
+/// Message id for a tx gossip payload: `txid_v1 || blake3(sig)[..8]`.
+///
+/// The txid makes copies of one tx published by different nodes collapse
+/// into one message. Since the txid does not cover the signature, the sig
+/// digest is appended so a copy with a forged signature cannot occupy the
+/// id of the valid tx in the duplicate cache. `None` if `data` is not a
+/// canonical signed TxV1. Parsed in place, since this runs on every copy
+/// received.
+pub fn tx_message_id(data: &[u8]) -> Option<Vec<u8>> {
+    let tx = TxV1Ref::decode(data).ok()?;
+    let mut id = tx.txid().to_vec();
+    id.extend_from_slice(&blake3::hash(tx.sig).as_bytes()[..8]);
+    Some(id)
+}
+
+/// `message_id_fn` for the swarm: [`tx_message_id`] on the tx topic,
+/// blake3 of the envelope on the consensus topic (signed messages relayed
+/// by several validators collapse into one), and gossipsub's default
+/// (author || sequence number) everywhere else.
+pub(crate) fn message_id(msg: &gossipsub::Message) -> gossipsub::MessageId {
+    match msg.topic.as_str() {
+        TX_TOPIC => {
+            if let Some(id) = tx_message_id(&msg.data) {
+                return gossipsub::MessageId::new(&id);
+            }
+        }
+        CONSENSUS_TOPIC => {
+            return gossipsub::MessageId::new(blake3::hash(&msg.data).as_bytes());
+        }
+        _ => {}
+    }
+    let mut id = msg.source.map(|p| p.to_bytes()).unwrap_or_default();
+    id.extend_from_slice(
+        msg.sequence_number
+            .unwrap_or_default()
+            .to_string()
+            .as_bytes(),
+    );
+    gossipsub::MessageId::from(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use novai_codec::{encode_tx_v1_signed, txid_v1};
+    use novai_types::{TxV1, TxVersion};
+
+    #[test]
+    fn tx_message_id_tracks_txid_and_signature() {
+        let tx = TxV1 {
+            version: TxVersion::V1,
+            from: [3u8; 32],
+            nonce: 1,
+            fee: 2,
+            payload: b"p".to_vec(),
+            sig: [4u8; 64],
+        };
+        let bytes = encode_tx_v1_signed(&tx).unwrap();
+        let id = tx_message_id(&bytes).unwrap();
+        assert_eq!(id.len(), 40);
+        assert_eq!(&id[..32], &txid_v1(&tx).unwrap());
+
+        let forged = TxV1 {
+            sig: [5u8; 64],
+            ..tx.clone()
+        };
+        let forged_id = tx_message_id(&encode_tx_v1_signed(&forged).unwrap()).unwrap();
+        assert_eq!(id[..32], forged_id[..32]);
+        assert_ne!(id, forged_id);
+
+        assert_eq!(tx_message_id(&bytes[1..]), None);
+    }
+}
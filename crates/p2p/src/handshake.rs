@@ -0,0 +1,249 @@
+//! Peer handshake on [`HANDSHAKE_PROTOCOL`].
+//!
+//! When a connection to a new peer opens, the dialing side sends its
+//! [`HandshakeV1`] and the listening side answers with its own; each side
+//! checks the other's with [`check_handshake`]. A peer is ready once the
+//! check passes ([`P2pEvent::PeerReady`](crate::P2pEvent::PeerReady)). On a
+//! mismatch, a failed exchange, or no handshake within
+//! [`P2pConfig::handshake_timeout`](crate::P2pConfig::handshake_timeout),
+//! the peer is reported ([`P2pEvent::PeerRejected`](crate::P2pEvent::PeerRejected))
+//! and disconnected. The listener still answers a mismatched handshake, so
+//! the dialer learns why; it leaves the dialer [`REJECT_GRACE`] to hang up
+//! before disconnecting itself.
+//!
+//! Unlike gossip and sync payloads, handshakes are decoded here: network
+//! identity is the swarm's concern, not consensus's.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use libp2p::{Multiaddr, PeerId, StreamProtocol};
+use novai_codec::{encode_handshake_v1, CodecError};
+use novai_types::{BlockHeaderVersion, HandshakeV1, TxVersion};
+use tokio::time::Instant;
+
+pub const HANDSHAKE_PROTOCOL: StreamProtocol = StreamProtocol::new("/novai/handshake/1");
+
+/// Largest handshake accepted on [`HANDSHAKE_PROTOCOL`].
+pub const MAX_HANDSHAKE_MESSAGE: usize = 1024;
+
+/// How long a listener waits for a rejected dialer to disconnect after
+/// reading the answer.
+pub const REJECT_GRACE: Duration = Duration::from_secs(1);
+
+/// Chain id of local development networks.
+pub const DEV_CHAIN_ID: u64 = 0;
+
+/// Handshake for a development network: [`DEV_CHAIN_ID`], an all-zero
+/// genesis hash and every version this build supports.
+pub fn dev_handshake() -> HandshakeV1 {
+    HandshakeV1 {
+        chain_id: DEV_CHAIN_ID,
+        genesis_hash: [0u8; 32],
+        tx_versions: vec![TxVersion::V1 as u8],
+        header_versions: vec![BlockHeaderVersion::V1 as u8],
+        best_finalized_height: 0,
+    }
+}
+
+/// Why a peer was rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandshakeError {
+    Malformed(CodecError),
+    ChainIdMismatch {
+        ours: u64,
+        theirs: u64,
+    },
+    GenesisMismatch,
+    NoCommonTxVersion,
+    NoCommonHeaderVersion,
+    /// The exchange itself failed (stream error, no answer).
+    Failed(String),
+    /// No handshake arrived within the configured timeout.
+    Timeout,
+}
+
+/// What a ready peer told us, as of its handshake.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerInfo {
+    pub address: Multiaddr,
+    /// We dialed the peer (rather than it dialing us).
+    pub outbound: bool,
+    pub best_finalized_height: u64,
+    pub tx_versions: Vec<u8>,
+    pub header_versions: Vec<u8>,
+}
+
+/// Check a peer's handshake against ours.
+pub fn check_handshake(ours: &HandshakeV1, theirs: &HandshakeV1) -> Result<(), HandshakeError> {
+    if ours.chain_id != theirs.chain_id {
+        return Err(HandshakeError::ChainIdMismatch {
+            ours: ours.chain_id,
+            theirs: theirs.chain_id,
+        });
+    }
+    if ours.genesis_hash != theirs.genesis_hash {
+        return Err(HandshakeError::GenesisMismatch);
+    }
+    if !ours
+        .tx_versions
+        .iter()
+        .any(|v| theirs.tx_versions.contains(v))
+    {
+        return Err(HandshakeError::NoCommonTxVersion);
+    }
+    if !ours
+        .header_versions
+        .iter()
+        .any(|v| theirs.header_versions.contains(v))
+    {
+        return Err(HandshakeError::NoCommonHeaderVersion);
+    }
+    Ok(())
+}
+
+/// Per-peer handshake state, owned by the swarm task.
+pub(crate) struct Handshakes {
+    local: HandshakeV1,
+    /// `local`, encoded.
+    encoded: Vec<u8>,
+    timeout: Duration,
+    /// Connected but not yet ready: first address, direction and deadline.
+    pending: HashMap<PeerId, (Multiaddr, bool, Instant)>,
+    ready: HashMap<PeerId, PeerInfo>,
+    /// Rejected peers we answered, to disconnect after [`REJECT_GRACE`].
+    rejecting: HashMap<PeerId, Instant>,
+}
+
+impl Handshakes {
+    pub(crate) fn new(local: HandshakeV1, timeout: Duration) -> Result<Self, CodecError> {
+        Ok(Self {
+            encoded: encode_handshake_v1(&local)?,
+            local,
+            timeout,
+            pending: HashMap::new(),
+            ready: HashMap::new(),
+            rejecting: HashMap::new(),
+        })
+    }
+
+    pub(crate) fn encoded(&self) -> &[u8] {
+        &self.encoded
+    }
+
+    pub(crate) fn set_best_finalized_height(&mut self, height: u64) {
+        self.local.best_finalized_height = height;
+        self.encoded = encode_handshake_v1(&self.local).expect("encoded once at startup");
+    }
+
+    pub(crate) fn connected(&mut self, peer: PeerId, address: Multiaddr, outbound: bool) {
+        let deadline = Instant::now() + self.timeout;
+        self.pending.insert(peer, (address, outbound, deadline));
+    }
+
+    pub(crate) fn disconnected(&mut self, peer: &PeerId) {
+        self.pending.remove(peer);
+        self.ready.remove(peer);
+        self.rejecting.remove(peer);
+    }
+
+    /// Settle `peer`'s handshake. Returns the outcome the first time only;
+    /// a peer that already settled (or is gone) yields `None`.
+    pub(crate) fn settle(
+        &mut self,
+        peer: PeerId,
+        theirs: Result<HandshakeV1, HandshakeError>,
+    ) -> Option<Result<PeerInfo, HandshakeError>> {
+        let (address, outbound, _) = self.pending.remove(&peer)?;
+        let result = theirs.and_then(|theirs| {
+            check_handshake(&self.local, &theirs)?;
+            Ok(PeerInfo {
+                address,
+                outbound,
+                best_finalized_height: theirs.best_finalized_height,
+                tx_versions: theirs.tx_versions,
+                header_versions: theirs.header_versions,
+            })
+        });
+        if let Ok(info) = &result {
+            self.ready.insert(peer, info.clone());
+        }
+        Some(result)
+    }
+
+    pub(crate) fn reject_after_answer(&mut self, peer: PeerId) {
+        self.rejecting.insert(peer, Instant::now() + REJECT_GRACE);
+    }
+
+    /// Peers whose deadline passed without a settled handshake.
+    pub(crate) fn expired(&mut self, now: Instant) -> Vec<PeerId> {
+        let expired: Vec<PeerId> = self
+            .pending
+            .iter()
+            .filter(|(_, (_, _, deadline))| *deadline <= now)
+            .map(|(peer, _)| *peer)
+            .collect();
+        for peer in &expired {
+            self.pending.remove(peer);
+        }
+        expired
+    }
+
+    /// Rejected peers still connected after their grace period.
+    pub(crate) fn overstayed(&mut self, now: Instant) -> Vec<PeerId> {
+        let overstayed: Vec<PeerId> = self
+            .rejecting
+            .iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(peer, _)| *peer)
+            .collect();
+        for peer in &overstayed {
+            self.rejecting.remove(peer);
+        }
+        overstayed
+    }
+
+    pub(crate) fn is_ready(&self, peer: &PeerId) -> bool {
+        self.ready.contains_key(peer)
+    }
+
+    pub(crate) fn info(&self, peer: &PeerId) -> Option<PeerInfo> {
+        self.ready.get(peer).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mismatches_are_detected_in_order() {
+        let ours = dev_handshake();
+        let mut theirs = dev_handshake();
+        theirs.best_finalized_height = 99;
+        theirs.tx_versions = vec![2, 1];
+        assert_eq!(check_handshake(&ours, &theirs), Ok(()));
+
+        theirs.tx_versions = vec![2];
+        assert_eq!(
+            check_handshake(&ours, &theirs),
+            Err(HandshakeError::NoCommonTxVersion)
+        );
+        theirs.header_versions.clear();
+        theirs.tx_versions = vec![1];
+        assert_eq!(
+            check_handshake(&ours, &theirs),
+            Err(HandshakeError::NoCommonHeaderVersion)
+        );
+        theirs.genesis_hash = [1u8; 32];
+        assert_eq!(
+            check_handshake(&ours, &theirs),
+            Err(HandshakeError::GenesisMismatch)
+        );
+        theirs.chain_id = 5;
+        assert_eq!(
+            check_handshake(&ours, &theirs),
+            Err(HandshakeError::ChainIdMismatch { ours: 0, theirs: 5 })
+        );
+    }
+}
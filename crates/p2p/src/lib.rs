@@ -1,5 +1,233 @@
 //! novai-p2p
 //!
-//! Purpose: placeholder crate for clean-room workspace bootstrap.
-//! Invariants: no external dependencies (Week 1).
-//! Failure modes: none (placeholder).
+//! Purpose: own the libp2p swarm (tcp + noise + yamux, gossipsub, mdns,
+//! identify, ping, handshake, consensus and sync request-response, peer
+//! reputation with a persisted ban list, bootnodes, persistent peers, an
+//! on-disk address book and inbound/outbound connection slots) and
+//! expose it to the rest of the node as typed commands ([`P2pHandle`]) and
+//! events ([`P2pEvent`]).
+//! Invariants:
+//! - Networking is isolated from consensus: payloads cross this boundary as
+//!   opaque bytes; decoding and validation belong to the caller.
+//! - Only `PeerId`, `Multiaddr` and `Keypair` are re-exported; no other
+//!   libp2p type leaks into the node.
+//! - Peers on another chain or with incompatible versions are disconnected
+//!   as soon as their handshake is checked.
+//!
+//! Failure modes: swarm construction and listen errors are returned by
+//! [`spawn`]; dial/subscribe/publish/request errors are returned on the command that
+//! caused them. Once the swarm task stops, every command fails with
+//! [`P2pError::Shutdown`].
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use novai_types::HandshakeV1;
+
+mod behaviour;
+pub mod consensus;
+mod frame;
+pub mod gossip;
+pub mod handshake;
+pub mod peers;
+pub mod reputation;
+mod service;
+pub mod slots;
+pub mod sync;
+
+pub use consensus::{
+    ConsensusRequestId, CONSENSUS_PROTOCOL, CONSENSUS_TOPIC, MAX_CONSENSUS_MESSAGE,
+};
+pub use gossip::{tx_message_id, GossipMessageId, GossipValidation, TX_TOPIC};
+pub use handshake::{
+    check_handshake, dev_handshake, HandshakeError, PeerInfo, DEV_CHAIN_ID, HANDSHAKE_PROTOCOL,
+    MAX_HANDSHAKE_MESSAGE,
+};
+pub use libp2p::identity::Keypair;
+pub use libp2p::{Multiaddr, PeerId};
+pub use peers::{PeerStore, PEER_STORE_CAPACITY};
+pub use reputation::{Misbehaviour, Reputation, ReputationConfig};
+pub use service::{spawn, P2pHandle};
+pub use slots::{ConnectionLimits, RefusalReason};
+pub use sync::{SyncRequestId, MAX_SYNC_MESSAGE, SYNC_PROTOCOL};
+
+/// Swarm settings. [`P2pConfig::new`] gives production defaults.
+#[derive(Debug, Clone)]
+pub struct P2pConfig {
+    pub keypair: Keypair,
+    pub listen_addrs: Vec<Multiaddr>,
+    /// Discover peers on the local network.
+    pub enable_mdns: bool,
+    /// Gossip topics joined at startup.
+    pub topics: Vec<String>,
+    pub gossip_heartbeat: Duration,
+    pub idle_connection_timeout: Duration,
+    /// How long [`P2pHandle::send_consensus`] waits for a response.
+    pub consensus_request_timeout: Duration,
+    /// How long [`P2pHandle::send_sync`] waits for a response.
+    pub sync_request_timeout: Duration,
+    /// Advertised via identify; peers on a different protocol are still
+    /// connected (version checks happen at handshake, not here).
+    pub protocol_version: String,
+    /// What we tell peers about ourselves. Defaults to [`dev_handshake`];
+    /// nodes on a real network must set their chain id and genesis hash.
+    pub handshake: HandshakeV1,
+    /// Peers not ready this long after connecting are disconnected.
+    pub handshake_timeout: Duration,
+    pub reputation: ReputationConfig,
+    /// Where the ban list is kept across restarts; `None` keeps it in
+    /// memory only.
+    pub ban_list_path: Option<PathBuf>,
+    /// Dialed once at startup to join the network.
+    pub bootnodes: Vec<Multiaddr>,
+    /// Kept connected: redialed with backoff whenever the connection is
+    /// lost or a dial fails. Each must end in `/p2p/<peer id>`.
+    pub persistent_peers: Vec<Multiaddr>,
+    /// First wait before redialing a persistent peer; doubles with each
+    /// failed attempt up to `max_reconnect_backoff`.
+    pub reconnect_backoff: Duration,
+    pub max_reconnect_backoff: Duration,
+    /// Where the address book of known peers is kept; `None` keeps it in
+    /// memory only.
+    pub peer_store_path: Option<PathBuf>,
+    /// How many of the most recently seen known peers are dialed at
+    /// startup.
+    pub peer_store_dials: usize,
+    /// Peer slots; persistent peers are always reserved.
+    pub limits: ConnectionLimits,
+    /// Capacity of the event channel returned by [`spawn`]. Gossip and
+    /// requests that don't fit are dropped and counted, never waited on
+    /// (see [`P2pHandle::dropped_events`]); peer events queue until there is
+    /// room.
+    pub event_buffer: usize,
+}
+
+impl P2pConfig {
+    pub fn new(keypair: Keypair) -> Self {
+        Self {
+            keypair,
+            listen_addrs: vec!["/ip4/0.0.0.0/tcp/0".parse().expect("static multiaddr")],
+            enable_mdns: true,
+            topics: Vec::new(),
+            gossip_heartbeat: Duration::from_secs(1),
+            idle_connection_timeout: Duration::from_secs(60),
+            consensus_request_timeout: Duration::from_secs(5),
+            sync_request_timeout: Duration::from_secs(30),
+            protocol_version: "/novai/0.1.0".to_string(),
+            handshake: dev_handshake(),
+            handshake_timeout: Duration::from_secs(10),
+            reputation: ReputationConfig::default(),
+            ban_list_path: None,
+            bootnodes: Vec::new(),
+            persistent_peers: Vec::new(),
+            reconnect_backoff: Duration::from_secs(1),
+            max_reconnect_backoff: Duration::from_secs(60),
+            peer_store_path: None,
+            peer_store_dials: 16,
+            limits: ConnectionLimits::default(),
+            event_buffer: 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum P2pError {
+    /// Building the transport or behaviours failed.
+    Transport(String),
+    /// A config value cannot be used (e.g. an unencodable handshake).
+    Config(String),
+    Listen(String),
+    Dial(String),
+    Subscribe(String),
+    Publish(String),
+    /// A direct consensus or sync request got no response.
+    Request(String),
+    /// The swarm task is no longer running.
+    Shutdown,
+}
+
+/// Everything the rest of the node hears from the network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum P2pEvent {
+    Listening {
+        address: Multiaddr,
+    },
+    /// First connection to `peer` opened.
+    PeerConnected {
+        peer: PeerId,
+        address: Multiaddr,
+    },
+    /// Last connection to `peer` closed.
+    PeerDisconnected {
+        peer: PeerId,
+    },
+    /// `peer`'s handshake checked out; it is on our chain.
+    PeerReady {
+        peer: PeerId,
+        info: PeerInfo,
+    },
+    /// `peer` failed the handshake and is being disconnected.
+    PeerRejected {
+        peer: PeerId,
+        reason: HandshakeError,
+    },
+    /// `peer` got no slot: its connection was turned away, or is being
+    /// disconnected. No [`P2pEvent::PeerConnected`] or
+    /// [`P2pEvent::PeerDisconnected`] is sent for it. Connections turned
+    /// away before the peer identified itself are not reported.
+    PeerRefused {
+        peer: PeerId,
+        reason: RefusalReason,
+    },
+    /// `peer` lost its slot to the better-scoring `by` and is being
+    /// disconnected.
+    PeerEvicted {
+        peer: PeerId,
+        by: PeerId,
+    },
+    /// `peer`'s score fell below the ban threshold; it is disconnected and
+    /// refused until `until` (unix seconds).
+    PeerBanned {
+        peer: PeerId,
+        until: u64,
+    },
+    PeerIdentified {
+        peer: PeerId,
+        protocol_version: String,
+        agent_version: String,
+        listen_addrs: Vec<Multiaddr>,
+    },
+    /// Found via mDNS; the swarm dials it automatically.
+    PeerDiscovered {
+        peer: PeerId,
+        address: Multiaddr,
+    },
+    /// `peer` joined gossip `topic`.
+    PeerSubscribed {
+        peer: PeerId,
+        topic: String,
+    },
+    /// From a ready peer only. Must be answered with
+    /// [`P2pHandle::report_validation`].
+    Gossip {
+        message_id: GossipMessageId,
+        /// Peer that forwarded the message (not necessarily its author).
+        source: PeerId,
+        topic: String,
+        data: Vec<u8>,
+    },
+    /// Direct message on [`CONSENSUS_PROTOCOL`] from a ready peer; answer
+    /// with [`P2pHandle::respond_consensus`] or the sender sees a failure.
+    ConsensusRequest {
+        request_id: ConsensusRequestId,
+        peer: PeerId,
+        data: Vec<u8>,
+    },
+    /// Request on [`SYNC_PROTOCOL`] from a ready peer; answer with
+    /// [`P2pHandle::respond_sync`].
+    SyncRequest {
+        request_id: SyncRequestId,
+        peer: PeerId,
+        data: Vec<u8>,
+    },
+}
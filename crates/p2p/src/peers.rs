@@ -0,0 +1,306 @@
+//! Known peers: the on-disk address book and the always-connected set.
+//!
+//! [`PeerStore`] remembers one dialable address per peer and when it was
+//! last seen connected, so a restarted node can find the network again
+//! without bootnodes or mDNS. It is persisted as one
+//! `<peer id> <last seen, unix seconds> <multiaddr>` line per peer,
+//! rewritten (tmp + rename) whenever it changes, and capped at
+//! [`PEER_STORE_CAPACITY`] entries by evicting the least recently seen.
+//!
+//! Persistent peers (`P2pConfig::persistent_peers`) are redialed whenever
+//! they are not connected, with exponential backoff between failed
+//! attempts.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+use tokio::time::Instant;
+
+/// Most peers kept in a [`PeerStore`].
+pub const PEER_STORE_CAPACITY: usize = 1024;
+
+/// The peer id in a `/p2p/<peer id>` suffix, if `address` has one.
+pub fn peer_id_of(address: &Multiaddr) -> Option<PeerId> {
+    address.iter().find_map(|protocol| match protocol {
+        Protocol::P2p(peer) => Some(peer),
+        _ => None,
+    })
+}
+
+/// Persistent address book of peers we have been connected to.
+#[derive(Debug, Default)]
+pub struct PeerStore {
+    /// Address and last seen (unix seconds).
+    peers: BTreeMap<PeerId, (Multiaddr, u64)>,
+    path: Option<PathBuf>,
+}
+
+impl PeerStore {
+    /// Store kept in memory only.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store loaded from, and saved to, `path`. A missing file is an empty
+    /// store.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut store = Self::new();
+        match fs::read_to_string(&path) {
+            Ok(text) => {
+                for line in text.lines().filter(|l| !l.trim().is_empty()) {
+                    let mut fields = line.splitn(3, ' ');
+                    let parsed = (|| {
+                        let peer = fields.next()?.parse::<PeerId>().ok()?;
+                        let last_seen = fields.next()?.parse::<u64>().ok()?;
+                        let address = fields.next()?.parse::<Multiaddr>().ok()?;
+                        Some((peer, address, last_seen))
+                    })();
+                    let Some((peer, address, last_seen)) = parsed else {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("bad peer store line: {line}"),
+                        ));
+                    };
+                    store.peers.insert(peer, (address, last_seen));
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        store.path = Some(path);
+        Ok(store)
+    }
+
+    /// Record `peer` as seen at `now`, at `address` if given (otherwise the
+    /// address already known, if any). A `/p2p/<peer id>` suffix is not
+    /// kept.
+    pub fn seen(&mut self, peer: PeerId, address: Option<Multiaddr>, now: u64) {
+        let address = address.map(|a| {
+            a.into_iter()
+                .filter(|p| !matches!(p, Protocol::P2p(_)))
+                .collect()
+        });
+        match (self.peers.get_mut(&peer), address) {
+            (Some(entry), address) => {
+                if let Some(address) = address {
+                    entry.0 = address;
+                }
+                entry.1 = now;
+            }
+            (None, Some(address)) => {
+                self.peers.insert(peer, (address, now));
+                if self.peers.len() > PEER_STORE_CAPACITY {
+                    let oldest = self
+                        .peers
+                        .iter()
+                        .min_by_key(|(_, (_, last_seen))| *last_seen)
+                        .map(|(peer, _)| *peer)
+                        .expect("store is not empty");
+                    self.peers.remove(&oldest);
+                }
+            }
+            (None, None) => return,
+        }
+        self.persist();
+    }
+
+    pub fn get(&self, peer: &PeerId) -> Option<(&Multiaddr, u64)> {
+        self.peers.get(peer).map(|(address, seen)| (address, *seen))
+    }
+
+    /// Known peers, most recently seen first.
+    pub fn recent(&self) -> Vec<(PeerId, Multiaddr, u64)> {
+        let mut peers: Vec<_> = self
+            .peers
+            .iter()
+            .map(|(peer, (address, seen))| (*peer, address.clone(), *seen))
+            .collect();
+        peers.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+        peers
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Best effort, like the ban list: an unwritable store still works for
+    /// this run.
+    fn persist(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let text: String = self
+            .peers
+            .iter()
+            .map(|(peer, (address, seen))| format!("{peer} {seen} {address}\n"))
+            .collect();
+        let tmp = path.with_extension("tmp");
+        if fs::write(&tmp, text).is_ok() {
+            let _ = fs::rename(&tmp, path);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Persistent {
+    address: Multiaddr,
+    /// Failed attempts since the last successful connection.
+    failures: u32,
+    /// When to dial next; `None` while connected or dialing.
+    next_dial: Option<Instant>,
+}
+
+/// Redial schedule for the always-connected peers, owned by the swarm task.
+#[derive(Debug)]
+pub(crate) struct PersistentPeers {
+    peers: HashMap<PeerId, Persistent>,
+    backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl PersistentPeers {
+    /// All `addresses` must end in `/p2p/<peer id>`; they are due for a
+    /// dial straight away.
+    pub(crate) fn new(
+        addresses: &[Multiaddr],
+        backoff: Duration,
+        max_backoff: Duration,
+    ) -> Result<Self, Multiaddr> {
+        let now = Instant::now();
+        let mut peers = HashMap::new();
+        for address in addresses {
+            let peer = peer_id_of(address).ok_or_else(|| address.clone())?;
+            peers.insert(
+                peer,
+                Persistent {
+                    address: address.clone(),
+                    failures: 0,
+                    next_dial: Some(now),
+                },
+            );
+        }
+        Ok(Self {
+            peers,
+            backoff,
+            max_backoff,
+        })
+    }
+
+    /// Wait before the next attempt after `failures` failures in a row.
+    fn delay(&self, failures: u32) -> Duration {
+        let factor = 1u32 << failures.saturating_sub(1).min(16);
+        self.backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+
+    pub(crate) fn connected(&mut self, peer: &PeerId) {
+        if let Some(p) = self.peers.get_mut(peer) {
+            p.failures = 0;
+            p.next_dial = None;
+        }
+    }
+
+    /// The connection closed, or a dial failed: schedule another attempt.
+    pub(crate) fn lost(&mut self, peer: &PeerId, failed: bool, now: Instant) {
+        let Some(failures) = self.peers.get(peer).map(|p| p.failures + u32::from(failed)) else {
+            return;
+        };
+        let delay = self.delay(failures.max(1));
+        let p = self.peers.get_mut(peer).expect("checked above");
+        p.failures = failures;
+        p.next_dial = Some(now + delay);
+    }
+
+    /// Peers due for a dial, marked as dialing.
+    pub(crate) fn due(&mut self, now: Instant) -> Vec<(PeerId, Multiaddr)> {
+        self.peers
+            .iter_mut()
+            .filter(|(_, p)| p.next_dial.is_some_and(|at| at <= now))
+            .map(|(peer, p)| {
+                p.next_dial = None;
+                (*peer, p.address.clone())
+            })
+            .collect()
+    }
+
+    pub(crate) fn contains(&self, peer: &PeerId) -> bool {
+        self.peers.contains_key(peer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_round_trips_and_orders_by_last_seen() {
+        let path = std::env::temp_dir().join(format!("novai-peers-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+
+        let mut store = PeerStore::open(&path).unwrap();
+        store.seen(a, Some(addr.clone().with(Protocol::P2p(a))), 10);
+        store.seen(b, Some(addr.clone()), 20);
+        // No address and not known yet: nothing to remember.
+        store.seen(PeerId::random(), None, 30);
+        store.seen(a, None, 40);
+        drop(store);
+
+        let store = PeerStore::open(&path).unwrap();
+        assert_eq!(store.recent(), vec![(a, addr.clone(), 40), (b, addr, 20)]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn redials_back_off_exponentially_up_to_the_cap() {
+        let peer = PeerId::random();
+        let address: Multiaddr = format!("/ip4/127.0.0.1/tcp/4001/p2p/{peer}")
+            .parse()
+            .unwrap();
+        assert!(PersistentPeers::new(
+            &["/ip4/127.0.0.1/tcp/1".parse().unwrap()],
+            Duration::from_secs(1),
+            Duration::from_secs(5),
+        )
+        .is_err());
+
+        let mut peers = PersistentPeers::new(
+            std::slice::from_ref(&address),
+            Duration::from_secs(1),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        let t0 = Instant::now();
+        assert_eq!(peers.due(t0), vec![(peer, address.clone())]);
+        assert!(peers.due(t0).is_empty(), "dialing, not due again");
+
+        let mut waits = Vec::new();
+        let mut now = t0;
+        for _ in 0..5 {
+            peers.lost(&peer, true, now);
+            let at = peers.peers[&peer].next_dial.unwrap();
+            waits.push((at - now).as_secs());
+            now = at;
+            assert_eq!(peers.due(now).len(), 1);
+        }
+        assert_eq!(waits, vec![1, 2, 4, 5, 5]);
+
+        // A connection resets the backoff; losing it waits the base delay.
+        peers.connected(&peer);
+        peers.lost(&peer, false, now);
+        assert_eq!(
+            peers.peers[&peer].next_dial,
+            Some(now + Duration::from_secs(1))
+        );
+    }
+}
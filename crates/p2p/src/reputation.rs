@@ -0,0 +1,276 @@
+//! Peer reputation and the ban list.
+//!
+//! Every peer starts at score 0. Each reported [`Misbehaviour`] subtracts a
+//! configured penalty, and scores recover linearly towards 0 over time. A
+//! peer whose score falls to [`ReputationConfig::ban_threshold`] or below
+//! is banned for [`ReputationConfig::ban_duration`]: disconnected, and
+//! refused on reconnect until the ban expires. Its score is forgotten, so
+//! it starts over afterwards.
+//!
+//! Arithmetic is integer only. Time is whole unix seconds passed in by the
+//! caller, which keeps this module deterministic under test; bans use wall
+//! clock time so they survive restarts. The ban list is persisted as one
+//! `<peer id> <banned until, unix seconds>` line per peer, rewritten (tmp +
+//! rename) whenever it changes.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libp2p::PeerId;
+
+/// Misbehaviour the node can report against a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Misbehaviour {
+    /// Bytes that fail to decode (a `CodecError`).
+    Malformed,
+    /// A tx, vote or other message with a bad signature.
+    InvalidSignature,
+    /// A block (or state chunk) that fails validation.
+    InvalidBlock,
+    /// Relayed more rejected txs than the mempool's budget allows.
+    Flooding,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReputationConfig {
+    pub malformed_penalty: i64,
+    pub invalid_signature_penalty: i64,
+    pub invalid_block_penalty: i64,
+    pub flooding_penalty: i64,
+    /// Points recovered per minute, towards 0.
+    pub recovery_per_minute: i64,
+    /// Scores at or below this ban the peer.
+    pub ban_threshold: i64,
+    pub ban_duration: Duration,
+}
+
+impl Default for ReputationConfig {
+    fn default() -> Self {
+        Self {
+            malformed_penalty: 10,
+            invalid_signature_penalty: 25,
+            invalid_block_penalty: 50,
+            flooding_penalty: 25,
+            recovery_per_minute: 5,
+            ban_threshold: -100,
+            ban_duration: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl ReputationConfig {
+    fn penalty(&self, misbehaviour: Misbehaviour) -> i64 {
+        match misbehaviour {
+            Misbehaviour::Malformed => self.malformed_penalty,
+            Misbehaviour::InvalidSignature => self.invalid_signature_penalty,
+            Misbehaviour::InvalidBlock => self.invalid_block_penalty,
+            Misbehaviour::Flooding => self.flooding_penalty,
+        }
+    }
+}
+
+/// Current wall clock time in unix seconds.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Score {
+    value: i64,
+    /// When `value` last recovered (or was first set).
+    updated: u64,
+}
+
+/// Scores and bans for every peer we have heard about.
+#[derive(Debug)]
+pub struct Reputation {
+    config: ReputationConfig,
+    scores: HashMap<PeerId, Score>,
+    /// Banned until (unix seconds).
+    bans: BTreeMap<PeerId, u64>,
+    path: Option<PathBuf>,
+}
+
+impl Reputation {
+    /// Reputation kept in memory only.
+    pub fn new(config: ReputationConfig) -> Self {
+        Self {
+            config,
+            scores: HashMap::new(),
+            bans: BTreeMap::new(),
+            path: None,
+        }
+    }
+
+    /// Reputation whose ban list is loaded from, and saved to, `path`. A
+    /// missing file is an empty ban list.
+    pub fn open(config: ReputationConfig, path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut reputation = Self::new(config);
+        match fs::read_to_string(&path) {
+            Ok(text) => {
+                for line in text.lines().filter(|l| !l.trim().is_empty()) {
+                    let parsed = line.split_once(' ').and_then(|(peer, until)| {
+                        Some((peer.parse::<PeerId>().ok()?, until.parse::<u64>().ok()?))
+                    });
+                    let Some((peer, until)) = parsed else {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("bad ban list line: {line}"),
+                        ));
+                    };
+                    reputation.bans.insert(peer, until);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        reputation.path = Some(path);
+        Ok(reputation)
+    }
+
+    /// `peer`'s score at `now`, after recovery.
+    pub fn score(&mut self, peer: &PeerId, now: u64) -> i64 {
+        let per_minute = self.config.recovery_per_minute;
+        let Some(score) = self.scores.get_mut(peer) else {
+            return 0;
+        };
+        let elapsed = now.saturating_sub(score.updated);
+        let recovered = (elapsed as i64).saturating_mul(per_minute) / 60;
+        if recovered > 0 {
+            score.value = (score.value + recovered).min(0);
+            // Only the time those points took, so the remainder isn't lost
+            // to frequent polling.
+            score.updated += (recovered.saturating_mul(60) / per_minute) as u64;
+        }
+        let value = score.value;
+        if value == 0 {
+            self.scores.remove(peer);
+        }
+        value
+    }
+
+    /// Penalise `peer`. Returns when its ban ends if this report banned it.
+    pub fn report(&mut self, peer: PeerId, misbehaviour: Misbehaviour, now: u64) -> Option<u64> {
+        if self.is_banned(&peer, now) {
+            return None;
+        }
+        let value = self.score(&peer, now) - self.config.penalty(misbehaviour);
+        if value > self.config.ban_threshold {
+            self.scores.insert(
+                peer,
+                Score {
+                    value,
+                    updated: now,
+                },
+            );
+            return None;
+        }
+        self.scores.remove(&peer);
+        let until = now.saturating_add(self.config.ban_duration.as_secs());
+        self.bans.insert(peer, until);
+        self.persist();
+        Some(until)
+    }
+
+    pub fn is_banned(&self, peer: &PeerId, now: u64) -> bool {
+        self.bans.get(peer).is_some_and(|until| *until > now)
+    }
+
+    /// Banned peers and when each ban ends.
+    pub fn bans(&self) -> impl Iterator<Item = (&PeerId, u64)> {
+        self.bans.iter().map(|(peer, until)| (peer, *until))
+    }
+
+    /// Drop bans that have ended by `now`, returning the peers released.
+    pub fn expire_bans(&mut self, now: u64) -> Vec<PeerId> {
+        let expired: Vec<PeerId> = self
+            .bans
+            .iter()
+            .filter(|(_, until)| **until <= now)
+            .map(|(peer, _)| *peer)
+            .collect();
+        if !expired.is_empty() {
+            for peer in &expired {
+                self.bans.remove(peer);
+            }
+            self.persist();
+        }
+        expired
+    }
+
+    /// Best effort: a ban list that can't be written still applies for
+    /// this run.
+    fn persist(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let text: String = self
+            .bans
+            .iter()
+            .map(|(peer, until)| format!("{peer} {until}\n"))
+            .collect();
+        let tmp = path.with_extension("tmp");
+        if fs::write(&tmp, text).is_ok() {
+            let _ = fs::rename(&tmp, path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frequent_polling_does_not_slow_recovery() {
+        let peer = PeerId::random();
+        let mut rep = Reputation::new(ReputationConfig::default());
+        rep.report(peer, Misbehaviour::InvalidBlock, 1000);
+        // One point per 12 s; polls every 20 s carry the leftover 8 s over.
+        for now in [1020, 1040, 1060] {
+            rep.score(&peer, now);
+        }
+        assert_eq!(rep.score(&peer, 1060), -45);
+    }
+
+    #[test]
+    fn penalties_decay_and_bans_persist_until_expiry() {
+        let peer = PeerId::random();
+        let path = std::env::temp_dir().join(format!("novai-bans-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let config = ReputationConfig::default();
+
+        let mut rep = Reputation::open(config.clone(), &path).unwrap();
+        assert_eq!(rep.report(peer, Misbehaviour::InvalidBlock, 1000), None);
+        assert_eq!(rep.score(&peer, 1000), -50);
+        // Five points a minute back towards zero.
+        assert_eq!(rep.score(&peer, 1000 + 120), -40);
+        assert_eq!(rep.score(&peer, 1000 + 60 * 60), 0);
+
+        assert_eq!(rep.report(peer, Misbehaviour::InvalidSignature, 5000), None);
+        assert_eq!(rep.report(peer, Misbehaviour::InvalidBlock, 5000), None);
+        assert_eq!(
+            rep.report(peer, Misbehaviour::InvalidSignature, 5000),
+            Some(5000 + 3600)
+        );
+        assert!(rep.is_banned(&peer, 5001));
+        assert_eq!(rep.score(&peer, 5001), 0);
+        // Reports against a banned peer don't extend the ban.
+        assert_eq!(rep.report(peer, Misbehaviour::Malformed, 5002), None);
+        drop(rep);
+
+        let mut rep = Reputation::open(config, &path).unwrap();
+        assert!(rep.is_banned(&peer, 5000 + 3599));
+        assert_eq!(rep.expire_bans(5000 + 3599), Vec::<PeerId>::new());
+        assert_eq!(rep.expire_bans(5000 + 3600), vec![peer]);
+        assert!(!rep.is_banned(&peer, 5000 + 3600));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        fs::remove_file(&path).unwrap();
+    }
+}
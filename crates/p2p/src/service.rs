@@ -0,0 +1,944 @@
+//! The swarm task and the handle used to drive it.
+//!
+//! [`spawn`] moves the swarm onto a tokio task that multiplexes commands from
+//! [`P2pHandle`]s with swarm events, translating the latter into
+//! [`P2pEvent`]s. Replies to in-flight consensus and sync requests are
+//! tracked by the task until the response (or failure) arrives, as is each
+//! new peer's handshake. Peer scores and bans live here too; banned peers
+//! are refused by the swarm's block list. So do the address book and the
+//! redial schedule of persistent peers: bootnodes, persistent peers and the
+//! most recently seen known peers are dialed at startup, and persistent
+//! peers again whenever they drop. Every new peer must win a connection
+//! slot before its handshake starts. The task stops on
+//! [`P2pHandle::shutdown`] or once every handle is dropped.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use futures::StreamExt;
+use libp2p::gossipsub::{self, IdentTopic};
+use libp2p::request_response::{self, InboundRequestId, OutboundRequestId, ResponseChannel};
+use libp2p::swarm::SwarmEvent;
+use libp2p::{identify, mdns, Multiaddr, PeerId, Swarm};
+use novai_codec::decode_handshake_v1;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+
+use crate::behaviour::{build_swarm, NodeBehaviour, NodeBehaviourEvent};
+use crate::handshake::Handshakes;
+use crate::peers::{peer_id_of, PeerStore, PersistentPeers};
+use crate::reputation::{unix_now, Misbehaviour, Reputation};
+use crate::slots::{Admission, Refused};
+use crate::{
+    ConsensusRequestId, GossipMessageId, GossipValidation, HandshakeError, P2pConfig, P2pError,
+    P2pEvent, PeerInfo, SyncRequestId,
+};
+
+const COMMAND_BUFFER: usize = 64;
+
+/// How often overdue handshakes, expired bans and due redials are checked
+/// for.
+const HOUSEKEEPING_INTERVAL: Duration = Duration::from_millis(250);
+
+pub(crate) enum Command {
+    Dial {
+        address: Multiaddr,
+        reply: oneshot::Sender<Result<(), P2pError>>,
+    },
+    Subscribe {
+        topic: String,
+        reply: oneshot::Sender<Result<(), P2pError>>,
+    },
+    Publish {
+        topic: String,
+        data: Vec<u8>,
+        reply: oneshot::Sender<Result<(), P2pError>>,
+    },
+    ReportValidation {
+        message_id: GossipMessageId,
+        source: PeerId,
+        validation: GossipValidation,
+    },
+    SendConsensus {
+        peer: PeerId,
+        data: Vec<u8>,
+        reply: oneshot::Sender<Result<Vec<u8>, P2pError>>,
+    },
+    RespondConsensus {
+        request_id: ConsensusRequestId,
+        data: Vec<u8>,
+    },
+    SendSync {
+        peer: PeerId,
+        data: Vec<u8>,
+        reply: oneshot::Sender<Result<Vec<u8>, P2pError>>,
+    },
+    RespondSync {
+        request_id: SyncRequestId,
+        data: Vec<u8>,
+    },
+    ConnectedPeers {
+        reply: oneshot::Sender<Vec<PeerId>>,
+    },
+    PeerInfo {
+        peer: PeerId,
+        reply: oneshot::Sender<Option<PeerInfo>>,
+    },
+    SetBestFinalizedHeight {
+        height: u64,
+    },
+    ReportPeer {
+        peer: PeerId,
+        misbehaviour: Misbehaviour,
+    },
+    PeerScore {
+        peer: PeerId,
+        reply: oneshot::Sender<i64>,
+    },
+    BannedPeers {
+        reply: oneshot::Sender<Vec<(PeerId, u64)>>,
+    },
+    DroppedEvents {
+        reply: oneshot::Sender<u64>,
+    },
+    ListenAddrs {
+        reply: oneshot::Sender<Vec<Multiaddr>>,
+    },
+    KnownPeers {
+        reply: oneshot::Sender<Vec<(PeerId, Multiaddr, u64)>>,
+    },
+    SetReservedPeers {
+        peers: Vec<PeerId>,
+    },
+    Shutdown,
+}
+
+/// Cloneable handle for sending commands to the swarm task.
+#[derive(Debug, Clone)]
+pub struct P2pHandle {
+    local_peer_id: PeerId,
+    commands: mpsc::Sender<Command>,
+}
+
+impl P2pHandle {
+    pub fn local_peer_id(&self) -> PeerId {
+        self.local_peer_id
+    }
+
+    async fn request<T>(
+        &self,
+        make: impl FnOnce(oneshot::Sender<T>) -> Command,
+    ) -> Result<T, P2pError> {
+        let (reply, rx) = oneshot::channel();
+        self.commands
+            .send(make(reply))
+            .await
+            .map_err(|_| P2pError::Shutdown)?;
+        rx.await.map_err(|_| P2pError::Shutdown)
+    }
+
+    /// Start dialing `address`; [`P2pEvent::PeerConnected`] follows on success.
+    pub async fn dial(&self, address: Multiaddr) -> Result<(), P2pError> {
+        self.request(|reply| Command::Dial { address, reply })
+            .await?
+    }
+
+    pub async fn subscribe(&self, topic: impl Into<String>) -> Result<(), P2pError> {
+        let topic = topic.into();
+        self.request(|reply| Command::Subscribe { topic, reply })
+            .await?
+    }
+
+    /// Publish to a gossip topic. Fails if no connected peer is subscribed.
+    pub async fn publish(&self, topic: impl Into<String>, data: Vec<u8>) -> Result<(), P2pError> {
+        let topic = topic.into();
+        self.request(|reply| Command::Publish { topic, data, reply })
+            .await?
+    }
+
+    /// Verdict on a [`P2pEvent::Gossip`] message; only accepted messages
+    /// are forwarded to other peers.
+    pub async fn report_validation(
+        &self,
+        message_id: GossipMessageId,
+        source: PeerId,
+        validation: GossipValidation,
+    ) -> Result<(), P2pError> {
+        self.commands
+            .send(Command::ReportValidation {
+                message_id,
+                source,
+                validation,
+            })
+            .await
+            .map_err(|_| P2pError::Shutdown)
+    }
+
+    /// Send `data` to `peer` on the direct consensus protocol and wait for
+    /// its response. `peer` should already be connected or dialable.
+    pub async fn send_consensus(&self, peer: PeerId, data: Vec<u8>) -> Result<Vec<u8>, P2pError> {
+        self.request(|reply| Command::SendConsensus { peer, data, reply })
+            .await?
+    }
+
+    /// Answer a [`P2pEvent::ConsensusRequest`]. Unknown or timed-out ids
+    /// are dropped.
+    pub async fn respond_consensus(
+        &self,
+        request_id: ConsensusRequestId,
+        data: Vec<u8>,
+    ) -> Result<(), P2pError> {
+        self.commands
+            .send(Command::RespondConsensus { request_id, data })
+            .await
+            .map_err(|_| P2pError::Shutdown)
+    }
+
+    /// Send a block sync request to `peer` and wait for its response.
+    pub async fn send_sync(&self, peer: PeerId, data: Vec<u8>) -> Result<Vec<u8>, P2pError> {
+        self.request(|reply| Command::SendSync { peer, data, reply })
+            .await?
+    }
+
+    /// Answer a [`P2pEvent::SyncRequest`]. Unknown or timed-out ids are
+    /// dropped.
+    pub async fn respond_sync(
+        &self,
+        request_id: SyncRequestId,
+        data: Vec<u8>,
+    ) -> Result<(), P2pError> {
+        self.commands
+            .send(Command::RespondSync { request_id, data })
+            .await
+            .map_err(|_| P2pError::Shutdown)
+    }
+
+    pub async fn connected_peers(&self) -> Result<Vec<PeerId>, P2pError> {
+        self.request(|reply| Command::ConnectedPeers { reply })
+            .await
+    }
+
+    /// Handshake metadata of a ready peer; `None` for peers that are not
+    /// connected or have not completed the handshake.
+    pub async fn peer_info(&self, peer: PeerId) -> Result<Option<PeerInfo>, P2pError> {
+        self.request(|reply| Command::PeerInfo { peer, reply })
+            .await
+    }
+
+    /// Height announced in handshakes from now on.
+    pub async fn set_best_finalized_height(&self, height: u64) -> Result<(), P2pError> {
+        self.commands
+            .send(Command::SetBestFinalizedHeight { height })
+            .await
+            .map_err(|_| P2pError::Shutdown)
+    }
+
+    /// Penalise `peer`; enough penalties ban it ([`P2pEvent::PeerBanned`]).
+    pub async fn report_peer(
+        &self,
+        peer: PeerId,
+        misbehaviour: Misbehaviour,
+    ) -> Result<(), P2pError> {
+        self.commands
+            .send(Command::ReportPeer { peer, misbehaviour })
+            .await
+            .map_err(|_| P2pError::Shutdown)
+    }
+
+    /// `peer`'s current reputation; 0 is neutral, lower is worse.
+    pub async fn peer_score(&self, peer: PeerId) -> Result<i64, P2pError> {
+        self.request(|reply| Command::PeerScore { peer, reply })
+            .await
+    }
+
+    /// Banned peers and when each ban ends (unix seconds).
+    pub async fn banned_peers(&self) -> Result<Vec<(PeerId, u64)>, P2pError> {
+        self.request(|reply| Command::BannedPeers { reply }).await
+    }
+
+    /// Gossip and requests dropped so far because the event channel was full.
+    pub async fn dropped_events(&self) -> Result<u64, P2pError> {
+        self.request(|reply| Command::DroppedEvents { reply }).await
+    }
+
+    pub async fn listen_addrs(&self) -> Result<Vec<Multiaddr>, P2pError> {
+        self.request(|reply| Command::ListenAddrs { reply }).await
+    }
+
+    /// The address book: each known peer's address and when it was last
+    /// seen (unix seconds), most recent first.
+    pub async fn known_peers(&self) -> Result<Vec<(PeerId, Multiaddr, u64)>, P2pError> {
+        self.request(|reply| Command::KnownPeers { reply }).await
+    }
+
+    /// Peers (e.g. the current validators) admitted regardless of the
+    /// connection limits, replacing the previous set. Peers reserved in
+    /// the config stay reserved.
+    pub async fn set_reserved_peers(&self, peers: Vec<PeerId>) -> Result<(), P2pError> {
+        self.commands
+            .send(Command::SetReservedPeers { peers })
+            .await
+            .map_err(|_| P2pError::Shutdown)
+    }
+
+    /// Stop the swarm task; connections are closed when it drops.
+    pub async fn shutdown(&self) -> Result<(), P2pError> {
+        self.commands
+            .send(Command::Shutdown)
+            .await
+            .map_err(|_| P2pError::Shutdown)
+    }
+}
+
+/// Build the swarm, start listening and move it onto a tokio task.
+///
+/// Must be called from within a tokio runtime. Events are delivered on the
+/// returned receiver; if it is dropped, events are discarded.
+pub fn spawn(config: P2pConfig) -> Result<(P2pHandle, mpsc::Receiver<P2pEvent>), P2pError> {
+    let handshakes = Handshakes::new(config.handshake.clone(), config.handshake_timeout)
+        .map_err(|e| P2pError::Config(format!("handshake: {e:?}")))?;
+    let reputation = match &config.ban_list_path {
+        Some(path) => Reputation::open(config.reputation.clone(), path)
+            .map_err(|e| P2pError::Config(format!("ban list: {e}")))?,
+        None => Reputation::new(config.reputation.clone()),
+    };
+    let store = match &config.peer_store_path {
+        Some(path) => {
+            PeerStore::open(path).map_err(|e| P2pError::Config(format!("peer store: {e}")))?
+        }
+        None => PeerStore::new(),
+    };
+    let persistent = PersistentPeers::new(
+        &config.persistent_peers,
+        config.reconnect_backoff,
+        config.max_reconnect_backoff,
+    )
+    .map_err(|address| {
+        P2pError::Config(format!("persistent peer {address} has no /p2p/<peer id>"))
+    })?;
+    let mut swarm = build_swarm(&config)?;
+    for (peer, _) in reputation.bans() {
+        swarm.behaviour_mut().blocked.block_peer(*peer);
+    }
+
+    for topic in &config.topics {
+        swarm
+            .behaviour_mut()
+            .gossipsub
+            .subscribe(&IdentTopic::new(topic))
+            .map_err(|e| P2pError::Subscribe(e.to_string()))?;
+    }
+    for address in &config.listen_addrs {
+        swarm
+            .listen_on(address.clone())
+            .map_err(|e| P2pError::Listen(e.to_string()))?;
+    }
+
+    // Failed dials surface as connection errors. Persistent peers are
+    // dialed by the first housekeeping tick.
+    for address in &config.bootnodes {
+        let _ = swarm.dial(address.clone());
+    }
+    let now = unix_now();
+    let known = store
+        .recent()
+        .into_iter()
+        .filter(|(peer, _, _)| !persistent.contains(peer) && !reputation.is_banned(peer, now))
+        .take(config.peer_store_dials);
+    for (peer, address, _) in known {
+        let _ = swarm.dial(with_peer_id(address, peer));
+    }
+
+    let (commands, command_rx) = mpsc::channel(COMMAND_BUFFER);
+    let (event_tx, events) = mpsc::channel(config.event_buffer.max(1));
+    let handle = P2pHandle {
+        local_peer_id: *swarm.local_peer_id(),
+        commands,
+    };
+    let state = State {
+        handshakes,
+        reputation,
+        store,
+        persistent,
+        listen_addrs: HashMap::new(),
+        consensus: Requests::default(),
+        sync: Requests::default(),
+        dropped_events: 0,
+        backlog: VecDeque::new(),
+    };
+    tokio::spawn(run(swarm, state, command_rx, event_tx));
+    Ok((handle, events))
+}
+
+/// Request-response state for one protocol, owned by the swarm task.
+#[derive(Default)]
+struct Requests {
+    /// Our outbound requests, answered when the response arrives.
+    outbound: HashMap<OutboundRequestId, oneshot::Sender<Result<Vec<u8>, P2pError>>>,
+    /// Inbound requests waiting for the node to respond.
+    inbound: HashMap<InboundRequestId, ResponseChannel<Vec<u8>>>,
+}
+
+/// Everything the swarm task tracks besides the swarm itself.
+struct State {
+    handshakes: Handshakes,
+    reputation: Reputation,
+    store: PeerStore,
+    persistent: PersistentPeers,
+    /// First address each connected peer says it listens on (identify),
+    /// recorded in the address book for peers that dialed us.
+    listen_addrs: HashMap<PeerId, Multiaddr>,
+    consensus: Requests,
+    sync: Requests,
+    /// Events the node was too slow to take; see [`drop_event`].
+    dropped_events: u64,
+    /// Events the node was too slow to take but must not lose, delivered
+    /// in order as the channel frees up; see [`deliver`].
+    backlog: VecDeque<P2pEvent>,
+}
+
+async fn run(
+    mut swarm: Swarm<NodeBehaviour>,
+    mut state: State,
+    mut commands: mpsc::Receiver<Command>,
+    events: mpsc::Sender<P2pEvent>,
+) {
+    let mut housekeeping = tokio::time::interval(HOUSEKEEPING_INTERVAL);
+    loop {
+        let out = tokio::select! {
+            command = commands.recv() => match command {
+                None | Some(Command::Shutdown) => break,
+                Some(command) => handle_command(&mut swarm, &mut state, command),
+            },
+            _ = housekeeping.tick() => housekeep(&mut swarm, &mut state),
+            event = swarm.select_next_some() => translate(&mut swarm, &mut state, event),
+            permit = events.reserve(), if !state.backlog.is_empty() => {
+                match permit {
+                    Ok(permit) => permit.send(state.backlog.pop_front().expect("backlog")),
+                    // A dropped receiver just means nobody is listening.
+                    Err(_) => state.backlog.clear(),
+                }
+                Vec::new()
+            }
+        };
+        for event in out {
+            deliver(&mut swarm, &mut state, &events, event);
+        }
+    }
+}
+
+/// Hand `event` to the node without ever waiting for room. Gossip and
+/// inbound requests that don't fit are shed; peer lifecycle events queue in
+/// [`State::backlog`] instead, since the node tracks peers by them, and
+/// stay in order behind those already queued.
+fn deliver(
+    swarm: &mut Swarm<NodeBehaviour>,
+    state: &mut State,
+    events: &mpsc::Sender<P2pEvent>,
+    event: P2pEvent,
+) {
+    let sheddable = matches!(
+        event,
+        P2pEvent::Gossip { .. } | P2pEvent::ConsensusRequest { .. } | P2pEvent::SyncRequest { .. }
+    );
+    if !sheddable && !state.backlog.is_empty() {
+        state.backlog.push_back(event);
+        return;
+    }
+    match events.try_send(event) {
+        Ok(()) => {}
+        // A dropped receiver just means nobody is listening.
+        Err(TrySendError::Closed(_)) => {}
+        Err(TrySendError::Full(event)) if sheddable => drop_event(swarm, state, event),
+        Err(TrySendError::Full(event)) => state.backlog.push_back(event),
+    }
+}
+
+/// Shed an event the node has no room for rather than stall the swarm
+/// waiting on it. Gossip is ignored and inbound requests are closed, so
+/// neither lingers until it times out.
+fn drop_event(swarm: &mut Swarm<NodeBehaviour>, state: &mut State, event: P2pEvent) {
+    state.dropped_events += 1;
+    match event {
+        P2pEvent::Gossip {
+            message_id, source, ..
+        } => {
+            let _ = swarm
+                .behaviour_mut()
+                .gossipsub
+                .report_message_validation_result(
+                    &gossipsub::MessageId::new(&message_id.0),
+                    &source,
+                    GossipValidation::Ignore.into(),
+                );
+        }
+        P2pEvent::ConsensusRequest { request_id, .. } => {
+            state.consensus.inbound.remove(&request_id.0);
+        }
+        P2pEvent::SyncRequest { request_id, .. } => {
+            state.sync.inbound.remove(&request_id.0);
+        }
+        _ => {}
+    }
+}
+
+/// Drop peers whose handshake is overdue, lift expired bans and redial
+/// persistent peers that are due.
+fn housekeep(swarm: &mut Swarm<NodeBehaviour>, state: &mut State) -> Vec<P2pEvent> {
+    let now = Instant::now();
+    for peer in state.handshakes.overstayed(now) {
+        let _ = swarm.disconnect_peer_id(peer);
+    }
+    for peer in state.reputation.expire_bans(unix_now()) {
+        swarm.behaviour_mut().blocked.unblock_peer(peer);
+    }
+    for (peer, address) in state.persistent.due(now) {
+        if swarm.is_connected(&peer) {
+            state.persistent.connected(&peer);
+        } else if state.reputation.is_banned(&peer, unix_now()) || swarm.dial(address).is_err() {
+            state.persistent.lost(&peer, true, now);
+        }
+    }
+    state
+        .handshakes
+        .expired(now)
+        .into_iter()
+        .map(|peer| {
+            let _ = swarm.disconnect_peer_id(peer);
+            P2pEvent::PeerRejected {
+                peer,
+                reason: HandshakeError::Timeout,
+            }
+        })
+        .collect()
+}
+
+/// Penalise `peer`, banning (and so disconnecting) it if that takes its
+/// score over the threshold.
+fn report(
+    swarm: &mut Swarm<NodeBehaviour>,
+    reputation: &mut Reputation,
+    peer: PeerId,
+    misbehaviour: Misbehaviour,
+) -> Option<P2pEvent> {
+    swarm.behaviour_mut().slots.penalise(&peer);
+    let until = reputation.report(peer, misbehaviour, unix_now())?;
+    swarm.behaviour_mut().blocked.block_peer(peer);
+    Some(P2pEvent::PeerBanned { peer, until })
+}
+
+fn handle_command(
+    swarm: &mut Swarm<NodeBehaviour>,
+    state: &mut State,
+    command: Command,
+) -> Vec<P2pEvent> {
+    match command {
+        Command::Dial { address, reply } => {
+            let result = swarm
+                .dial(address)
+                .map_err(|e| P2pError::Dial(e.to_string()));
+            let _ = reply.send(result);
+        }
+        Command::Subscribe { topic, reply } => {
+            let result = swarm
+                .behaviour_mut()
+                .gossipsub
+                .subscribe(&IdentTopic::new(topic))
+                .map(|_| ())
+                .map_err(|e| P2pError::Subscribe(e.to_string()));
+            let _ = reply.send(result);
+        }
+        Command::Publish { topic, data, reply } => {
+            let result = swarm
+                .behaviour_mut()
+                .gossipsub
+                .publish(IdentTopic::new(topic), data)
+                .map(|_| ())
+                .map_err(|e| P2pError::Publish(e.to_string()));
+            let _ = reply.send(result);
+        }
+        Command::ReportValidation {
+            message_id,
+            source,
+            validation,
+        } => {
+            // Unknown ids (already expired from the cache) are fine to drop.
+            let _ = swarm
+                .behaviour_mut()
+                .gossipsub
+                .report_message_validation_result(
+                    &gossipsub::MessageId::new(&message_id.0),
+                    &source,
+                    validation.into(),
+                );
+        }
+        Command::SendConsensus { peer, data, reply } => {
+            let id = swarm.behaviour_mut().consensus.send_request(&peer, data);
+            state.consensus.outbound.insert(id, reply);
+        }
+        Command::RespondConsensus { request_id, data } => {
+            if let Some(channel) = state.consensus.inbound.remove(&request_id.0) {
+                // Fails only if the requester already gave up.
+                let _ = swarm.behaviour_mut().consensus.send_response(channel, data);
+            }
+        }
+        Command::SendSync { peer, data, reply } => {
+            let id = swarm.behaviour_mut().sync.send_request(&peer, data);
+            state.sync.outbound.insert(id, reply);
+        }
+        Command::RespondSync { request_id, data } => {
+            if let Some(channel) = state.sync.inbound.remove(&request_id.0) {
+                let _ = swarm.behaviour_mut().sync.send_response(channel, data);
+            }
+        }
+        Command::ConnectedPeers { reply } => {
+            let _ = reply.send(swarm.connected_peers().copied().collect());
+        }
+        Command::PeerInfo { peer, reply } => {
+            let _ = reply.send(state.handshakes.info(&peer));
+        }
+        Command::SetBestFinalizedHeight { height } => {
+            state.handshakes.set_best_finalized_height(height);
+        }
+        Command::ReportPeer { peer, misbehaviour } => {
+            return report(swarm, &mut state.reputation, peer, misbehaviour)
+                .into_iter()
+                .collect();
+        }
+        Command::PeerScore { peer, reply } => {
+            let _ = reply.send(state.reputation.score(&peer, unix_now()));
+        }
+        Command::BannedPeers { reply } => {
+            let now = unix_now();
+            let bans = state
+                .reputation
+                .bans()
+                .filter(|(_, until)| *until > now)
+                .map(|(peer, until)| (*peer, until))
+                .collect();
+            let _ = reply.send(bans);
+        }
+        Command::DroppedEvents { reply } => {
+            let _ = reply.send(state.dropped_events);
+        }
+        Command::ListenAddrs { reply } => {
+            let _ = reply.send(swarm.listeners().cloned().collect());
+        }
+        Command::KnownPeers { reply } => {
+            let _ = reply.send(state.store.recent());
+        }
+        Command::SetReservedPeers { peers } => swarm.behaviour_mut().slots.set_reserved(peers),
+        Command::Shutdown => unreachable!("handled by the run loop"),
+    }
+    Vec::new()
+}
+
+fn translate(
+    swarm: &mut Swarm<NodeBehaviour>,
+    state: &mut State,
+    event: SwarmEvent<NodeBehaviourEvent>,
+) -> Vec<P2pEvent> {
+    match event {
+        SwarmEvent::NewListenAddr { address, .. } => vec![P2pEvent::Listening { address }],
+        SwarmEvent::ConnectionEstablished {
+            peer_id,
+            endpoint,
+            num_established,
+            ..
+        } if num_established.get() == 1 => {
+            let address = endpoint.get_remote_address().clone();
+            let now = unix_now();
+            let reputation = &mut state.reputation;
+            let admission =
+                swarm
+                    .behaviour_mut()
+                    .slots
+                    .admit(peer_id, !endpoint.is_dialer(), &address, |p| {
+                        reputation.score(p, now)
+                    });
+            let mut out = Vec::new();
+            match admission {
+                Admission::Admit => {}
+                Admission::Evict(victim) => {
+                    let _ = swarm.disconnect_peer_id(victim);
+                    out.push(P2pEvent::PeerEvicted {
+                        peer: victim,
+                        by: peer_id,
+                    });
+                }
+                Admission::Refuse(reason) => {
+                    let _ = swarm.disconnect_peer_id(peer_id);
+                    return vec![P2pEvent::PeerRefused {
+                        peer: peer_id,
+                        reason,
+                    }];
+                }
+            }
+            state
+                .handshakes
+                .connected(peer_id, address.clone(), endpoint.is_dialer());
+            state.persistent.connected(&peer_id);
+            if endpoint.is_dialer() {
+                let local = state.handshakes.encoded().to_vec();
+                swarm
+                    .behaviour_mut()
+                    .handshake
+                    .send_request(&peer_id, local);
+            }
+            out.push(P2pEvent::PeerConnected {
+                peer: peer_id,
+                address,
+            });
+            out
+        }
+        SwarmEvent::ConnectionClosed {
+            peer_id,
+            num_established: 0,
+            ..
+        } => {
+            if !swarm.behaviour_mut().slots.remove(&peer_id) {
+                // Refused a slot; never reported as connected.
+                return Vec::new();
+            }
+            state.handshakes.disconnected(&peer_id);
+            state.listen_addrs.remove(&peer_id);
+            state.store.seen(peer_id, None, unix_now());
+            state.persistent.lost(&peer_id, false, Instant::now());
+            vec![P2pEvent::PeerDisconnected { peer: peer_id }]
+        }
+        SwarmEvent::OutgoingConnectionError {
+            peer_id: Some(peer),
+            ..
+        } if !swarm.is_connected(&peer) => {
+            state.persistent.lost(&peer, true, Instant::now());
+            Vec::new()
+        }
+        SwarmEvent::Behaviour(NodeBehaviourEvent::Identify(identify::Event::Received {
+            peer_id,
+            info,
+            ..
+        })) => {
+            if let Some(address) = info.listen_addrs.first() {
+                state.listen_addrs.insert(peer_id, address.clone());
+                if state.handshakes.info(&peer_id).is_some_and(|i| !i.outbound) {
+                    state.store.seen(peer_id, Some(address.clone()), unix_now());
+                }
+            }
+            vec![P2pEvent::PeerIdentified {
+                peer: peer_id,
+                protocol_version: info.protocol_version,
+                agent_version: info.agent_version,
+                listen_addrs: info.listen_addrs,
+            }]
+        }
+        SwarmEvent::Behaviour(NodeBehaviourEvent::Mdns(mdns::Event::Discovered(found))) => found
+            .into_iter()
+            .map(|(peer, address)| {
+                // Failed dials surface as connection errors; nothing to report here.
+                let _ = swarm.dial(address.clone());
+                P2pEvent::PeerDiscovered { peer, address }
+            })
+            .collect(),
+        SwarmEvent::Behaviour(NodeBehaviourEvent::Gossipsub(gossipsub::Event::Subscribed {
+            peer_id,
+            topic,
+        })) => vec![P2pEvent::PeerSubscribed {
+            peer: peer_id,
+            topic: topic.as_str().to_string(),
+        }],
+        SwarmEvent::Behaviour(NodeBehaviourEvent::Gossipsub(gossipsub::Event::Message {
+            propagation_source,
+            message_id,
+            message,
+            ..
+        })) => {
+            // Only peers on our chain are heard; anything else is ignored
+            // so gossipsub neither forwards it nor waits on a verdict.
+            if !state.handshakes.is_ready(&propagation_source) {
+                let _ = swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .report_message_validation_result(
+                        &message_id,
+                        &propagation_source,
+                        GossipValidation::Ignore.into(),
+                    );
+                return Vec::new();
+            }
+            vec![P2pEvent::Gossip {
+                message_id: GossipMessageId(message_id.0),
+                source: propagation_source,
+                topic: message.topic.as_str().to_string(),
+                data: message.data,
+            }]
+        }
+        SwarmEvent::Behaviour(NodeBehaviourEvent::Slots(Refused { peer, reason })) => {
+            vec![P2pEvent::PeerRefused { peer, reason }]
+        }
+        SwarmEvent::Behaviour(NodeBehaviourEvent::Handshake(event)) => {
+            let mut out: Vec<P2pEvent> = handshake_event(swarm, &mut state.handshakes, event)
+                .into_iter()
+                .collect();
+            if let Some(P2pEvent::PeerReady { peer, info }) = out.first() {
+                // Only peers on our chain go in the address book: the address
+                // we dialed, or the one a peer that dialed us listens on.
+                let address = if info.outbound {
+                    Some(info.address.clone())
+                } else {
+                    state.listen_addrs.get(peer).cloned()
+                };
+                state.store.seen(*peer, address, unix_now());
+            }
+            if let Some(&P2pEvent::PeerRejected {
+                peer,
+                reason: HandshakeError::Malformed(_),
+            }) = out.first()
+            {
+                out.extend(report(
+                    swarm,
+                    &mut state.reputation,
+                    peer,
+                    Misbehaviour::Malformed,
+                ));
+            }
+            out
+        }
+        SwarmEvent::Behaviour(NodeBehaviourEvent::Consensus(event)) => {
+            translate_requests(&mut state.consensus, &state.handshakes, event)
+                .map(|(request_id, peer, data)| P2pEvent::ConsensusRequest {
+                    request_id: ConsensusRequestId(request_id),
+                    peer,
+                    data,
+                })
+                .into_iter()
+                .collect()
+        }
+        SwarmEvent::Behaviour(NodeBehaviourEvent::Sync(event)) => {
+            translate_requests(&mut state.sync, &state.handshakes, event)
+                .map(|(request_id, peer, data)| P2pEvent::SyncRequest {
+                    request_id: SyncRequestId(request_id),
+                    peer,
+                    data,
+                })
+                .into_iter()
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Answer and check handshakes; returns the outcome the first time a
+/// peer's handshake settles.
+fn handshake_event(
+    swarm: &mut Swarm<NodeBehaviour>,
+    handshakes: &mut Handshakes,
+    event: request_response::Event<Vec<u8>, Vec<u8>>,
+) -> Option<P2pEvent> {
+    let decode = |bytes: &[u8]| decode_handshake_v1(bytes).map_err(HandshakeError::Malformed);
+    let (peer, outcome, answered) = match event {
+        request_response::Event::Message {
+            peer,
+            message:
+                request_response::Message::Request {
+                    request, channel, ..
+                },
+            ..
+        } => {
+            let local = handshakes.encoded().to_vec();
+            // Fails only if the dialer already gave up.
+            let _ = swarm
+                .behaviour_mut()
+                .handshake
+                .send_response(channel, local);
+            (peer, handshakes.settle(peer, decode(&request)), true)
+        }
+        request_response::Event::Message {
+            peer,
+            message: request_response::Message::Response { response, .. },
+            ..
+        } => (peer, handshakes.settle(peer, decode(&response)), false),
+        request_response::Event::OutboundFailure { peer, error, .. } => (
+            peer,
+            handshakes.settle(peer, Err(HandshakeError::Failed(error.to_string()))),
+            false,
+        ),
+        request_response::Event::InboundFailure { .. }
+        | request_response::Event::ResponseSent { .. } => return None,
+    };
+    match outcome? {
+        Ok(info) => Some(P2pEvent::PeerReady { peer, info }),
+        Err(reason) => {
+            if answered {
+                handshakes.reject_after_answer(peer);
+            } else {
+                let _ = swarm.disconnect_peer_id(peer);
+            }
+            Some(P2pEvent::PeerRejected { peer, reason })
+        }
+    }
+}
+
+/// Settle outbound requests and park inbound ones; returns an inbound
+/// request for the node to answer. Requests from peers that are not ready
+/// are dropped unanswered, which closes the stream.
+fn translate_requests(
+    pending: &mut Requests,
+    handshakes: &Handshakes,
+    event: request_response::Event<Vec<u8>, Vec<u8>>,
+) -> Option<(InboundRequestId, PeerId, Vec<u8>)> {
+    match event {
+        request_response::Event::Message {
+            peer,
+            message:
+                request_response::Message::Request {
+                    request_id,
+                    request,
+                    channel,
+                },
+            ..
+        } => {
+            if !handshakes.is_ready(&peer) {
+                return None;
+            }
+            pending.inbound.insert(request_id, channel);
+            Some((request_id, peer, request))
+        }
+        request_response::Event::Message {
+            message:
+                request_response::Message::Response {
+                    request_id,
+                    response,
+                },
+            ..
+        } => {
+            if let Some(reply) = pending.outbound.remove(&request_id) {
+                let _ = reply.send(Ok(response));
+            }
+            None
+        }
+        request_response::Event::OutboundFailure {
+            request_id, error, ..
+        } => {
+            if let Some(reply) = pending.outbound.remove(&request_id) {
+                let _ = reply.send(Err(P2pError::Request(error.to_string())));
+            }
+            None
+        }
+        request_response::Event::InboundFailure { request_id, .. } => {
+            pending.inbound.remove(&request_id);
+            None
+        }
+        request_response::Event::ResponseSent { .. } => None,
+    }
+}
+
+/// `address` ending in `/p2p/<peer>`, so the dial fails if someone else
+/// answers.
+fn with_peer_id(address: Multiaddr, peer: PeerId) -> Multiaddr {
+    if peer_id_of(&address).is_some() {
+        address
+    } else {
+        address.with(libp2p::multiaddr::Protocol::P2p(peer))
+    }
+}
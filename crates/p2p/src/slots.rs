@@ -0,0 +1,495 @@
+//! Connection slots: how many peers we hold, and which ones.
+//!
+//! Peers are counted once each, as inbound or outbound by the direction of
+//! their first connection, against [`ConnectionLimits::max_inbound`] and
+//! [`ConnectionLimits::max_outbound`]. Inbound peers are also limited per
+//! remote IP so one host cannot take every inbound slot. Reserved peers
+//! (validators, persistent peers and anything listed in
+//! [`ConnectionLimits::reserved_peers`]) are always admitted and never
+//! counted or evicted.
+//!
+//! When a direction is full, the newcomer takes the slot of the
+//! lowest-scoring peer in that direction if that peer scores strictly
+//! lower; otherwise the newcomer is refused. The per-IP limit never evicts.
+//!
+//! [`Slots`] is also a behaviour, so inbound connections that cannot get a
+//! slot are turned away before the rest of the swarm sets up for them:
+//! while still upgrading, at most `max_inbound` of them at once and
+//! `max_inbound_per_ip` per IP; once the peer is known, any that would be
+//! refused outright. Newcomers score at most 0, so a full direction with
+//! no penalised peer in it can evict nobody. Whoever gets through is
+//! admitted or refused when the connection is established, where eviction
+//! happens.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::net::IpAddr;
+use std::task::{Context, Poll, Waker};
+
+use libp2p::core::transport::PortUse;
+use libp2p::core::Endpoint;
+use libp2p::multiaddr::Protocol;
+use libp2p::swarm::behaviour::ListenFailure;
+use libp2p::swarm::{
+    dummy, ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler, THandlerInEvent,
+    THandlerOutEvent, ToSwarm,
+};
+use libp2p::{Multiaddr, PeerId};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionLimits {
+    pub max_inbound: usize,
+    pub max_outbound: usize,
+    /// Most inbound peers from one IP address.
+    pub max_inbound_per_ip: usize,
+    /// Admitted regardless of the limits, e.g. validators.
+    pub reserved_peers: Vec<PeerId>,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_inbound: 50,
+            max_outbound: 25,
+            max_inbound_per_ip: 4,
+            reserved_peers: Vec::new(),
+        }
+    }
+}
+
+/// Why a new peer was not given a slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefusalReason {
+    InboundFull,
+    OutboundFull,
+    TooManyFromIp,
+}
+
+impl fmt::Display for RefusalReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RefusalReason::InboundFull => write!(f, "inbound slots full"),
+            RefusalReason::OutboundFull => write!(f, "outbound slots full"),
+            RefusalReason::TooManyFromIp => write!(f, "too many peers from one IP"),
+        }
+    }
+}
+
+impl std::error::Error for RefusalReason {}
+
+/// A peer turned away by [`Slots`] before its connection was established.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Refused {
+    pub(crate) peer: PeerId,
+    pub(crate) reason: RefusalReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Admission {
+    Admit,
+    /// Admitted in place of the given peer, which must be disconnected.
+    Evict(PeerId),
+    Refuse(RefusalReason),
+}
+
+#[derive(Debug)]
+struct Slot {
+    inbound: bool,
+    ip: Option<IpAddr>,
+    /// Lost its slot to a better peer; still connected until the
+    /// disconnect lands, but no longer counted.
+    evicted: bool,
+    /// Reported since it was admitted, so it may score below a newcomer.
+    penalised: bool,
+}
+
+fn ip_of(address: &Multiaddr) -> Option<IpAddr> {
+    address.iter().find_map(|protocol| match protocol {
+        Protocol::Ip4(ip) => Some(IpAddr::V4(ip)),
+        Protocol::Ip6(ip) => Some(IpAddr::V6(ip)),
+        _ => None,
+    })
+}
+
+/// Slot accounting, owned by the swarm task.
+#[derive(Debug)]
+pub(crate) struct Slots {
+    limits: ConnectionLimits,
+    /// From the config: `reserved_peers` and persistent peers.
+    fixed: HashSet<PeerId>,
+    /// Set at runtime, e.g. the current validators.
+    dynamic: HashSet<PeerId>,
+    peers: HashMap<PeerId, Slot>,
+    /// Inbound connections still upgrading, with their remote IP.
+    pending: HashMap<ConnectionId, Option<IpAddr>>,
+    refused: VecDeque<Refused>,
+    waker: Option<Waker>,
+}
+
+impl Slots {
+    pub(crate) fn new(
+        limits: ConnectionLimits,
+        persistent: impl IntoIterator<Item = PeerId>,
+    ) -> Self {
+        let fixed = limits
+            .reserved_peers
+            .iter()
+            .copied()
+            .chain(persistent)
+            .collect();
+        Self {
+            limits,
+            fixed,
+            dynamic: HashSet::new(),
+            peers: HashMap::new(),
+            pending: HashMap::new(),
+            refused: VecDeque::new(),
+            waker: None,
+        }
+    }
+
+    /// Replace the reserved peers set at runtime. Peers reserved by the
+    /// config stay reserved.
+    pub(crate) fn set_reserved(&mut self, peers: Vec<PeerId>) {
+        self.dynamic = peers.into_iter().collect();
+    }
+
+    fn is_reserved(&self, peer: &PeerId) -> bool {
+        self.fixed.contains(peer) || self.dynamic.contains(peer)
+    }
+
+    /// Counted (non-reserved, non-evicted) peers matching `f`.
+    fn counted(&self, f: impl Fn(&Slot) -> bool) -> impl Iterator<Item = &PeerId> {
+        self.peers
+            .iter()
+            .filter(move |(peer, slot)| !slot.evicted && !self.is_reserved(peer) && f(slot))
+            .map(|(peer, _)| peer)
+    }
+
+    /// Decide whether a newly connected `peer` gets a slot, recording it if
+    /// so. `score` gives a peer's current reputation.
+    pub(crate) fn admit(
+        &mut self,
+        peer: PeerId,
+        inbound: bool,
+        address: &Multiaddr,
+        mut score: impl FnMut(&PeerId) -> i64,
+    ) -> Admission {
+        let ip = ip_of(address);
+        let slot = Slot {
+            inbound,
+            ip,
+            evicted: false,
+            penalised: false,
+        };
+        if self.is_reserved(&peer) {
+            self.peers.insert(peer, slot);
+            return Admission::Admit;
+        }
+        if inbound
+            && ip.is_some()
+            && self.counted(|s| s.inbound && s.ip == ip).count() >= self.limits.max_inbound_per_ip
+        {
+            return Admission::Refuse(RefusalReason::TooManyFromIp);
+        }
+        let (max, full) = if inbound {
+            (self.limits.max_inbound, RefusalReason::InboundFull)
+        } else {
+            (self.limits.max_outbound, RefusalReason::OutboundFull)
+        };
+        let same_direction: Vec<PeerId> = self.counted(|s| s.inbound == inbound).copied().collect();
+        if same_direction.len() < max {
+            self.peers.insert(peer, slot);
+            return Admission::Admit;
+        }
+        let newcomer = score(&peer);
+        let victim = same_direction
+            .into_iter()
+            .map(|p| (score(&p), p))
+            .min()
+            .filter(|(worst, _)| *worst < newcomer);
+        match victim {
+            Some((_, victim)) => {
+                if let Some(slot) = self.peers.get_mut(&victim) {
+                    slot.evicted = true;
+                }
+                self.peers.insert(peer, slot);
+                Admission::Evict(victim)
+            }
+            None => Admission::Refuse(full),
+        }
+    }
+
+    /// `peer`'s last connection closed; `false` if it never had a slot.
+    pub(crate) fn remove(&mut self, peer: &PeerId) -> bool {
+        self.peers.remove(peer).is_some()
+    }
+
+    /// `peer` was reported, so its slot may now go to a newcomer.
+    pub(crate) fn penalise(&mut self, peer: &PeerId) {
+        if let Some(slot) = self.peers.get_mut(peer) {
+            slot.penalised = true;
+        }
+    }
+
+    /// Whether an inbound connection from `ip` may start upgrading.
+    fn check_pending(&self, ip: Option<IpAddr>) -> Result<(), RefusalReason> {
+        if self.pending.len() >= self.limits.max_inbound {
+            return Err(RefusalReason::InboundFull);
+        }
+        if ip.is_some()
+            && self.pending.values().filter(|p| **p == ip).count() >= self.limits.max_inbound_per_ip
+        {
+            return Err(RefusalReason::TooManyFromIp);
+        }
+        Ok(())
+    }
+
+    /// Whether [`Slots::admit`] could give inbound `peer` from `ip` a slot.
+    fn check_inbound(&self, peer: &PeerId, ip: Option<IpAddr>) -> Result<(), RefusalReason> {
+        if self.is_reserved(peer) || self.peers.contains_key(peer) {
+            return Ok(());
+        }
+        if ip.is_some()
+            && self.counted(|s| s.inbound && s.ip == ip).count() >= self.limits.max_inbound_per_ip
+        {
+            return Err(RefusalReason::TooManyFromIp);
+        }
+        if self.counted(|s| s.inbound).count() >= self.limits.max_inbound
+            && self.counted(|s| s.inbound && s.penalised).next().is_none()
+        {
+            return Err(RefusalReason::InboundFull);
+        }
+        Ok(())
+    }
+}
+
+impl NetworkBehaviour for Slots {
+    type ConnectionHandler = dummy::ConnectionHandler;
+    type ToSwarm = Refused;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        _: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        let ip = ip_of(remote_addr);
+        self.check_pending(ip).map_err(ConnectionDenied::new)?;
+        self.pending.insert(connection_id, ip);
+        Ok(())
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        _: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.pending.remove(&connection_id);
+        if let Err(reason) = self.check_inbound(&peer, ip_of(remote_addr)) {
+            self.refused.push_back(Refused { peer, reason });
+            if let Some(waker) = self.waker.take() {
+                waker.wake();
+            }
+            return Err(ConnectionDenied::new(reason));
+        }
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        _: ConnectionId,
+        _: PeerId,
+        _: &Multiaddr,
+        _: Endpoint,
+        _: PortUse,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        if let FromSwarm::ListenFailure(ListenFailure { connection_id, .. }) = event {
+            self.pending.remove(&connection_id);
+        }
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        _: PeerId,
+        _: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        match event {}
+    }
+
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<ToSwarm<Refused, THandlerInEvent<Self>>> {
+        if let Some(refused) = self.refused.pop_front() {
+            return Poll::Ready(ToSwarm::GenerateEvent(refused));
+        }
+        self.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(ip: &str) -> Multiaddr {
+        format!("/ip4/{ip}/tcp/4001").parse().unwrap()
+    }
+
+    #[test]
+    fn limits_reserve_and_evict_the_lowest_scoring_peer() {
+        let reserved = PeerId::random();
+        let mut slots = Slots::new(
+            ConnectionLimits {
+                max_inbound: 2,
+                max_outbound: 1,
+                max_inbound_per_ip: 1,
+                reserved_peers: vec![reserved],
+            },
+            [],
+        );
+        let (a, b, c, d, e) = (
+            PeerId::random(),
+            PeerId::random(),
+            PeerId::random(),
+            PeerId::random(),
+            PeerId::random(),
+        );
+        let mut scores = HashMap::from([(a, -30), (b, -10)]);
+        let score = |scores: &HashMap<PeerId, i64>| {
+            let scores = scores.clone();
+            move |p: &PeerId| scores.get(p).copied().unwrap_or(0)
+        };
+
+        assert_eq!(
+            slots.admit(a, true, &addr("10.0.0.1"), score(&scores)),
+            Admission::Admit
+        );
+        assert_eq!(
+            slots.admit(c, true, &addr("10.0.0.1"), score(&scores)),
+            Admission::Refuse(RefusalReason::TooManyFromIp)
+        );
+        assert_eq!(
+            slots.admit(b, true, &addr("10.0.0.2"), score(&scores)),
+            Admission::Admit
+        );
+
+        // Full: the newcomer (score 0) replaces the worst peer, a at -30.
+        assert_eq!(
+            slots.admit(c, true, &addr("10.0.0.3"), score(&scores)),
+            Admission::Evict(a)
+        );
+        // a's IP is free again once it no longer counts.
+        scores.insert(d, -10);
+        assert_eq!(
+            slots.admit(d, true, &addr("10.0.0.1"), score(&scores)),
+            Admission::Refuse(RefusalReason::InboundFull),
+            "equal scores do not evict"
+        );
+        assert!(slots.remove(&a));
+        assert!(!slots.remove(&d), "refused peers never held a slot");
+
+        // Reserved peers are admitted over the limits and never evicted.
+        assert_eq!(
+            slots.admit(reserved, true, &addr("10.0.0.3"), score(&scores)),
+            Admission::Admit
+        );
+        assert_eq!(
+            slots.admit(e, false, &addr("10.0.0.9"), score(&scores)),
+            Admission::Admit
+        );
+        slots.set_reserved(vec![d]);
+        assert_eq!(
+            slots.admit(d, false, &addr("10.0.0.9"), score(&scores)),
+            Admission::Admit
+        );
+        assert_eq!(
+            slots.admit(PeerId::random(), false, &addr("10.0.0.9"), score(&scores)),
+            Admission::Refuse(RefusalReason::OutboundFull)
+        );
+        assert!(slots.remove(&e));
+    }
+
+    #[test]
+    fn hopeless_inbound_connections_are_turned_away_early() {
+        let reserved = PeerId::random();
+        let mut slots = Slots::new(
+            ConnectionLimits {
+                max_inbound: 2,
+                max_outbound: 1,
+                max_inbound_per_ip: 1,
+                reserved_peers: vec![reserved],
+            },
+            [],
+        );
+        let local = addr("10.0.0.100");
+        let pending = |slots: &mut Slots, id: usize, ip: &str| {
+            slots
+                .handle_pending_inbound_connection(
+                    ConnectionId::new_unchecked(id),
+                    &local,
+                    &addr(ip),
+                )
+                .is_ok()
+        };
+
+        // While upgrading: one per IP, two in total.
+        assert!(pending(&mut slots, 1, "10.0.0.1"));
+        assert!(!pending(&mut slots, 2, "10.0.0.1"));
+        assert!(pending(&mut slots, 3, "10.0.0.2"));
+        assert!(!pending(&mut slots, 4, "10.0.0.3"));
+
+        let (a, b) = (PeerId::random(), PeerId::random());
+        for (id, peer, ip) in [(1, a, "10.0.0.1"), (3, b, "10.0.0.2")] {
+            assert!(slots
+                .handle_established_inbound_connection(
+                    ConnectionId::new_unchecked(id),
+                    peer,
+                    &local,
+                    &addr(ip)
+                )
+                .is_ok());
+            assert_eq!(slots.admit(peer, true, &addr(ip), |_| 0), Admission::Admit);
+        }
+
+        // Full, and nobody scores below a newcomer.
+        let c = PeerId::random();
+        assert_eq!(
+            slots.check_inbound(&c, ip_of(&addr("10.0.0.3"))),
+            Err(RefusalReason::InboundFull)
+        );
+        assert_eq!(
+            slots.check_inbound(&c, ip_of(&addr("10.0.0.1"))),
+            Err(RefusalReason::TooManyFromIp)
+        );
+        assert_eq!(
+            slots.check_inbound(&reserved, ip_of(&addr("10.0.0.1"))),
+            Ok(())
+        );
+        slots.penalise(&a);
+        assert_eq!(slots.check_inbound(&c, ip_of(&addr("10.0.0.3"))), Ok(()));
+
+        // Refusals once the peer is known are reported.
+        assert!(pending(&mut slots, 5, "10.0.0.1"));
+        assert!(slots
+            .handle_established_inbound_connection(
+                ConnectionId::new_unchecked(5),
+                c,
+                &local,
+                &addr("10.0.0.1")
+            )
+            .is_err());
+        assert_eq!(
+            slots.refused.pop_front(),
+            Some(Refused {
+                peer: c,
+                reason: RefusalReason::TooManyFromIp
+            })
+        );
+    }
+}
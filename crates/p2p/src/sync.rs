@@ -0,0 +1,19 @@
+//! Block sync transport: request-response on [`SYNC_PROTOCOL`].
+//!
+//! Frames are much larger than on the consensus protocol since one response
+//! may carry many full blocks. Payloads are opaque here; the node encodes
+//! requests and serves responses from its block store.
+
+use libp2p::request_response::InboundRequestId;
+use libp2p::StreamProtocol;
+use novai_codec::DecodeLimits;
+
+pub const SYNC_PROTOCOL: StreamProtocol = StreamProtocol::new("/novai/sync/1");
+
+/// Largest request or response accepted on [`SYNC_PROTOCOL`]: whatever the
+/// default decode limits accept.
+pub const MAX_SYNC_MESSAGE: usize = DecodeLimits::DEFAULT.max_total_bytes;
+
+/// Inbound sync request awaiting [`P2pHandle::respond_sync`](crate::P2pHandle::respond_sync).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SyncRequestId(pub(crate) InboundRequestId);
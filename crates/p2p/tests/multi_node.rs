@@ -0,0 +1,466 @@
+//! Several in-process nodes talking over localhost TCP.
+
+use std::time::Duration;
+
+use novai_p2p::{
+    spawn, HandshakeError, Keypair, Misbehaviour, Multiaddr, P2pConfig, P2pError, P2pEvent,
+    P2pHandle, PeerId, RefusalReason,
+};
+use tokio::sync::mpsc::Receiver;
+use tokio::time::timeout;
+
+const WAIT: Duration = Duration::from_secs(20);
+
+fn test_config(topics: &[&str]) -> P2pConfig {
+    let mut config = P2pConfig::new(Keypair::generate_ed25519());
+    config.listen_addrs = vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()];
+    config.enable_mdns = false;
+    config.topics = topics.iter().map(|t| t.to_string()).collect();
+    config.gossip_heartbeat = Duration::from_millis(100);
+    config
+}
+
+/// Wait for the first event matching `f`, discarding the rest.
+async fn wait_for<T>(
+    events: &mut Receiver<P2pEvent>,
+    mut f: impl FnMut(P2pEvent) -> Option<T>,
+) -> T {
+    timeout(WAIT, async {
+        loop {
+            let event = events.recv().await.expect("swarm task stopped");
+            if let Some(out) = f(event) {
+                return out;
+            }
+        }
+    })
+    .await
+    .expect("timed out waiting for p2p event")
+}
+
+async fn start(topics: &[&str]) -> (P2pHandle, Receiver<P2pEvent>, Multiaddr) {
+    start_with(test_config(topics)).await
+}
+
+async fn start_with(config: P2pConfig) -> (P2pHandle, Receiver<P2pEvent>, Multiaddr) {
+    let (handle, mut events) = spawn(config).expect("spawn");
+    let addr = wait_for(&mut events, |e| match e {
+        P2pEvent::Listening { address } => Some(address),
+        _ => None,
+    })
+    .await;
+    (handle, events, addr)
+}
+
+async fn ready_with(events: &mut Receiver<P2pEvent>, peer: PeerId) {
+    wait_for(events, |e| match e {
+        P2pEvent::PeerReady { peer: p, .. } if p == peer => Some(()),
+        _ => None,
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn nodes_connect_identify_and_disconnect() {
+    let (a, mut a_events, a_addr) = start(&[]).await;
+    let (b, mut b_events, _) = start(&[]).await;
+
+    b.dial(a_addr).await.unwrap();
+    let peer = wait_for(&mut a_events, |e| match e {
+        P2pEvent::PeerConnected { peer, .. } => Some(peer),
+        _ => None,
+    })
+    .await;
+    assert_eq!(peer, b.local_peer_id());
+
+    let (peer, protocol) = wait_for(&mut b_events, |e| match e {
+        P2pEvent::PeerIdentified {
+            peer,
+            protocol_version,
+            ..
+        } => Some((peer, protocol_version)),
+        _ => None,
+    })
+    .await;
+    assert_eq!(peer, a.local_peer_id());
+    assert_eq!(protocol, "/novai/0.1.0");
+    assert_eq!(b.connected_peers().await.unwrap(), vec![a.local_peer_id()]);
+
+    b.shutdown().await.unwrap();
+    let gone = wait_for(&mut a_events, |e| match e {
+        P2pEvent::PeerDisconnected { peer } => Some(peer),
+        _ => None,
+    })
+    .await;
+    assert_eq!(gone, b.local_peer_id());
+    assert!(b.connected_peers().await.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn gossip_reaches_every_subscriber() {
+    let topic = "novai/test/1";
+    let (a, mut a_events, a_addr) = start(&[topic]).await;
+    let (b, mut b_events, _) = start(&[topic]).await;
+    let (c, mut c_events, _) = start(&[topic]).await;
+
+    b.dial(a_addr.clone()).await.unwrap();
+    c.dial(a_addr).await.unwrap();
+
+    let mut subscribed: Vec<PeerId> = Vec::new();
+    while subscribed.len() < 2 {
+        let peer = wait_for(&mut a_events, |e| match e {
+            P2pEvent::PeerSubscribed { peer, topic: t } if t == topic => Some(peer),
+            _ => None,
+        })
+        .await;
+        subscribed.push(peer);
+    }
+
+    // Gossip is only heard from peers whose handshake checked out.
+    for events in [&mut b_events, &mut c_events] {
+        ready_with(events, a.local_peer_id()).await;
+    }
+    a.publish(topic, b"hello".to_vec()).await.unwrap();
+    for events in [&mut b_events, &mut c_events] {
+        let (source, data) = wait_for(events, |e| match e {
+            P2pEvent::Gossip { source, data, .. } => Some((source, data)),
+            _ => None,
+        })
+        .await;
+        assert_eq!(source, a.local_peer_id());
+        assert_eq!(data, b"hello");
+    }
+
+    // Nobody subscribed to this one.
+    assert!(a.publish("novai/test/none", b"x".to_vec()).await.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn consensus_requests_get_responses() {
+    let (a, mut a_events, a_addr) = start(&[]).await;
+    let (b, mut b_events, _) = start(&[]).await;
+
+    b.dial(a_addr).await.unwrap();
+    // a settles b's handshake before answering it, so a is ready for b too.
+    ready_with(&mut b_events, a.local_peer_id()).await;
+
+    let sender = b.clone();
+    let to = a.local_peer_id();
+    let response = tokio::spawn(async move { sender.send_consensus(to, b"vote".to_vec()).await });
+
+    // Answer from a's event loop.
+    let (request_id, peer, data) = wait_for(&mut a_events, |e| match e {
+        P2pEvent::ConsensusRequest {
+            request_id,
+            peer,
+            data,
+        } => Some((request_id, peer, data)),
+        _ => None,
+    })
+    .await;
+    assert_eq!(peer, b.local_peer_id());
+    assert_eq!(data, b"vote");
+    a.respond_consensus(request_id, b"ack".to_vec())
+        .await
+        .unwrap();
+    assert_eq!(response.await.unwrap().unwrap(), b"ack");
+
+    // Nobody to deliver to.
+    let stranger = Keypair::generate_ed25519().public().to_peer_id();
+    assert!(matches!(
+        b.send_consensus(stranger, b"vote".to_vec()).await,
+        Err(P2pError::Request(_))
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_stalled_event_reader_never_stalls_the_swarm() {
+    let mut config = test_config(&[]);
+    config.event_buffer = 1;
+    // Nobody reads a's events past this point.
+    let (a, _a_events, a_addr) = start_with(config).await;
+    let (b, mut b_events, _) = start(&[]).await;
+
+    b.dial(a_addr).await.unwrap();
+    ready_with(&mut b_events, a.local_peer_id()).await;
+
+    // a sheds the request instead of waiting for room, so b hears back.
+    let response = timeout(WAIT, b.send_consensus(a.local_peer_id(), b"vote".to_vec()))
+        .await
+        .expect("a's swarm stalled");
+    assert!(matches!(response, Err(P2pError::Request(_))));
+    assert!(a.dropped_events().await.unwrap() > 0);
+    assert_eq!(a.connected_peers().await.unwrap(), vec![b.local_peer_id()]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn peer_lifecycle_events_outlast_a_full_event_channel() {
+    let mut config = test_config(&[]);
+    config.event_buffer = 1;
+    let (a, mut a_events, a_addr) = start_with(config).await;
+    let (b, mut b_events, _) = start(&[]).await;
+
+    // b comes and goes while nobody reads a's events.
+    b.dial(a_addr).await.unwrap();
+    ready_with(&mut b_events, a.local_peer_id()).await;
+    b.shutdown().await.unwrap();
+    timeout(WAIT, async {
+        while !a.connected_peers().await.unwrap().is_empty() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("b never left");
+
+    let mut seen = Vec::new();
+    wait_for(&mut a_events, |e| {
+        let (name, done) = match e {
+            P2pEvent::PeerConnected { .. } => ("connected", false),
+            P2pEvent::PeerReady { .. } => ("ready", false),
+            P2pEvent::PeerDisconnected { .. } => ("disconnected", true),
+            _ => return None,
+        };
+        seen.push(name);
+        done.then_some(())
+    })
+    .await;
+    assert_eq!(seen, ["connected", "ready", "disconnected"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn handshake_admits_same_chain_and_rejects_others() {
+    let mut config = test_config(&[]);
+    config.handshake.best_finalized_height = 7;
+    let (a, mut a_events, a_addr) = start_with(config).await;
+    let (b, mut b_events, _) = start(&[]).await;
+
+    b.dial(a_addr.clone()).await.unwrap();
+    let info = wait_for(&mut b_events, |e| match e {
+        P2pEvent::PeerReady { peer, info } => Some((peer, info)),
+        _ => None,
+    })
+    .await;
+    assert_eq!(info.0, a.local_peer_id());
+    assert!(info.1.outbound);
+    assert_eq!(info.1.best_finalized_height, 7);
+    assert_eq!(b.peer_info(a.local_peer_id()).await.unwrap(), Some(info.1));
+
+    let inbound = wait_for(&mut a_events, |e| match e {
+        P2pEvent::PeerReady { info, .. } => Some(info),
+        _ => None,
+    })
+    .await;
+    assert!(!inbound.outbound);
+    assert_eq!(inbound.best_finalized_height, 0);
+
+    // Another chain: both sides reject and the connection is dropped.
+    let mut config = test_config(&[]);
+    config.handshake.chain_id = 9;
+    let (c, mut c_events, _) = start_with(config).await;
+    c.dial(a_addr).await.unwrap();
+    let reason = wait_for(&mut c_events, |e| match e {
+        P2pEvent::PeerRejected { reason, .. } => Some(reason),
+        _ => None,
+    })
+    .await;
+    assert_eq!(
+        reason,
+        HandshakeError::ChainIdMismatch { ours: 9, theirs: 0 }
+    );
+    let (peer, reason) = wait_for(&mut a_events, |e| match e {
+        P2pEvent::PeerRejected { peer, reason } => Some((peer, reason)),
+        _ => None,
+    })
+    .await;
+    assert_eq!(peer, c.local_peer_id());
+    assert_eq!(
+        reason,
+        HandshakeError::ChainIdMismatch { ours: 0, theirs: 9 }
+    );
+    let gone = wait_for(&mut a_events, |e| match e {
+        P2pEvent::PeerDisconnected { peer } => Some(peer),
+        _ => None,
+    })
+    .await;
+    assert_eq!(gone, c.local_peer_id());
+    assert_eq!(a.peer_info(c.local_peer_id()).await.unwrap(), None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn misbehaving_peers_are_banned_across_restarts() {
+    let path = std::env::temp_dir().join(format!("novai-p2p-bans-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut config = test_config(&[]);
+    config.ban_list_path = Some(path.clone());
+    let (a, mut a_events, a_addr) = start_with(config.clone()).await;
+    let (b, mut b_events, _) = start(&[]).await;
+    let b_id = b.local_peer_id();
+
+    b.dial(a_addr.clone()).await.unwrap();
+    wait_for(&mut a_events, |e| {
+        matches!(e, P2pEvent::PeerReady { .. }).then_some(())
+    })
+    .await;
+
+    a.report_peer(b_id, Misbehaviour::InvalidBlock)
+        .await
+        .unwrap();
+    assert_eq!(a.peer_score(b_id).await.unwrap(), -50);
+    a.report_peer(b_id, Misbehaviour::InvalidBlock)
+        .await
+        .unwrap();
+    let until = wait_for(&mut a_events, |e| match e {
+        P2pEvent::PeerBanned { peer, until } if peer == b_id => Some(until),
+        _ => None,
+    })
+    .await;
+    assert_eq!(a.banned_peers().await.unwrap(), vec![(b_id, until)]);
+    wait_for(&mut b_events, |e| {
+        matches!(e, P2pEvent::PeerDisconnected { .. }).then_some(())
+    })
+    .await;
+
+    // The ban is on disk: a restarted node still refuses the peer.
+    a.shutdown().await.unwrap();
+    drop(a_events);
+    config.listen_addrs = vec![a_addr.clone()];
+    let (a, mut a_events, _) = start_with(config).await;
+    assert_eq!(a.banned_peers().await.unwrap(), vec![(b_id, until)]);
+
+    b.dial(a_addr).await.unwrap();
+    let refused = timeout(Duration::from_secs(2), async {
+        loop {
+            if let Some(P2pEvent::PeerConnected { .. }) = a_events.recv().await {
+                return;
+            }
+        }
+    })
+    .await;
+    assert!(refused.is_err(), "banned peer was let back in");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn configured_and_known_peers_are_dialed_and_persistent_ones_redialed() {
+    let path = std::env::temp_dir().join(format!("novai-p2p-peers-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let a_config = test_config(&[]);
+    let a_id = PeerId::from(a_config.keypair.public());
+    let (a, a_events, a_addr) = start_with(a_config.clone()).await;
+
+    // A persistent peer is dialed without being asked to, and again after
+    // it restarts.
+    let mut b_config = test_config(&[]);
+    b_config.persistent_peers = vec![format!("{a_addr}/p2p/{a_id}").parse().unwrap()];
+    b_config.reconnect_backoff = Duration::from_millis(100);
+    b_config.peer_store_path = Some(path.clone());
+    let (b, mut b_events, _) = start_with(b_config).await;
+    ready_with(&mut b_events, a_id).await;
+
+    a.shutdown().await.unwrap();
+    drop(a_events);
+    wait_for(&mut b_events, |e| {
+        matches!(e, P2pEvent::PeerDisconnected { .. }).then_some(())
+    })
+    .await;
+    let mut restarted = a_config;
+    restarted.listen_addrs = vec![a_addr.clone()];
+    let (a, mut a_events, _) = start_with(restarted).await;
+    ready_with(&mut b_events, a_id).await;
+
+    let known = b.known_peers().await.unwrap();
+    assert_eq!(known.len(), 1);
+    assert_eq!((known[0].0, &known[0].1), (a_id, &a_addr));
+
+    // A bootnode is dialed once at startup; the node it dialed learns the
+    // dialer's listen address for its own address book.
+    let mut c_config = test_config(&[]);
+    c_config.bootnodes = vec![a_addr.clone()];
+    let (c, mut c_events, c_addr) = start_with(c_config).await;
+    ready_with(&mut c_events, a_id).await;
+    let c_id = c.local_peer_id();
+    timeout(WAIT, async {
+        while !a
+            .known_peers()
+            .await
+            .unwrap()
+            .iter()
+            .any(|(peer, address, _)| *peer == c_id && *address == c_addr)
+        {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("dialer never entered the address book");
+
+    // The address book outlives the node: a node started from it finds the
+    // network with no bootnodes or persistent peers.
+    b.shutdown().await.unwrap();
+    drop(b_events);
+    wait_for(&mut a_events, |e| {
+        matches!(e, P2pEvent::PeerDisconnected { .. }).then_some(())
+    })
+    .await;
+    let mut d_config = test_config(&[]);
+    d_config.peer_store_path = Some(path.clone());
+    let (_d, mut d_events, _) = start_with(d_config).await;
+    ready_with(&mut d_events, a_id).await;
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn full_slots_evict_low_scores_refuse_the_rest_and_admit_reserved() {
+    let reserved = Keypair::generate_ed25519();
+    let mut config = test_config(&[]);
+    config.limits.max_inbound = 1;
+    config.limits.max_inbound_per_ip = 8;
+    config.limits.reserved_peers = vec![PeerId::from(reserved.public())];
+    let (a, mut a_events, a_addr) = start_with(config).await;
+    let a_id = a.local_peer_id();
+
+    let (b, mut b_events, _) = start(&[]).await;
+    b.dial(a_addr.clone()).await.unwrap();
+    ready_with(&mut b_events, a_id).await;
+    a.report_peer(b.local_peer_id(), Misbehaviour::Malformed)
+        .await
+        .unwrap();
+
+    // The only inbound slot is B's; C outscores it and takes the slot.
+    let (c, mut c_events, _) = start(&[]).await;
+    c.dial(a_addr.clone()).await.unwrap();
+    let evicted = wait_for(&mut a_events, |e| match e {
+        P2pEvent::PeerEvicted { peer, by } => Some((peer, by)),
+        _ => None,
+    })
+    .await;
+    assert_eq!(evicted, (b.local_peer_id(), c.local_peer_id()));
+    ready_with(&mut c_events, a_id).await;
+    wait_for(&mut b_events, |e| {
+        matches!(e, P2pEvent::PeerDisconnected { .. }).then_some(())
+    })
+    .await;
+
+    // D scores no better than C: refused, never reported as connected.
+    let (d, _d_events, _) = start(&[]).await;
+    d.dial(a_addr.clone()).await.unwrap();
+    let refused = wait_for(&mut a_events, |e| match e {
+        P2pEvent::PeerConnected { peer, .. } if peer == d.local_peer_id() => {
+            panic!("refused peer reported as connected")
+        }
+        P2pEvent::PeerRefused { peer, reason } => Some((peer, reason)),
+        _ => None,
+    })
+    .await;
+    assert_eq!(refused, (d.local_peer_id(), RefusalReason::InboundFull));
+
+    // A reserved peer gets in over the limit.
+    let mut e_config = test_config(&[]);
+    e_config.keypair = reserved;
+    let (e, mut e_events, _) = start_with(e_config).await;
+    e.dial(a_addr).await.unwrap();
+    ready_with(&mut e_events, a_id).await;
+    let mut connected = a.connected_peers().await.unwrap();
+    connected.sort();
+    let mut expected = vec![c.local_peer_id(), e.local_peer_id()];
+    expected.sort();
+    assert_eq!(connected, expected);
+}
@@ -0,0 +1,398 @@
+//! novai-sim
+//!
+//! Purpose: run many nodes' message handling in one process over a simulated
+//! network, so bugs that depend on message ordering can be reproduced from
+//! a seed. Nodes implement [`Node`]; the network delays, drops, duplicates
+//! and partitions their messages as configured by [`NetworkConfig`] and
+//! [`Simulation::partition`].
+//! Invariants: a run is a pure function of the seed, the config, the nodes'
+//! initial state and the calls made on the [`Simulation`]. Time is virtual
+//! (milliseconds, advanced only by the event queue); there are no threads,
+//! no wall clock and no floats (probabilities are per mille). Events due at
+//! the same time run in the order they were scheduled.
+//! Reordering falls out of the delay jitter: a message sent later can draw a
+//! shorter delay and arrive first.
+//! Failure modes: none at runtime. Messages to unknown nodes panic, since
+//! that is a bug in the node under test.
+
+use std::collections::BTreeMap;
+
+mod rng;
+
+pub use rng::SimRng;
+
+/// Index of a node in the simulation.
+pub type NodeId = usize;
+
+/// Virtual time in milliseconds since the start of the run.
+pub type Time = u64;
+
+/// Logic under test: reacts to messages and timers through a [`Context`].
+pub trait Node {
+    type Message: Clone;
+
+    /// Called once, in node order, at time 0.
+    fn start(&mut self, ctx: &mut Context<'_, Self::Message>);
+
+    fn receive(
+        &mut self,
+        from: NodeId,
+        message: Self::Message,
+        ctx: &mut Context<'_, Self::Message>,
+    );
+
+    /// A timer set with [`Context::set_timer`] fired.
+    fn timer(&mut self, tag: u64, ctx: &mut Context<'_, Self::Message>);
+}
+
+enum Action<M> {
+    Send { to: NodeId, message: M },
+    Timer { after: Time, tag: u64 },
+}
+
+/// What a node can do while handling an event. Actions take effect once
+/// the handler returns.
+pub struct Context<'a, M> {
+    id: NodeId,
+    now: Time,
+    nodes: usize,
+    actions: &'a mut Vec<Action<M>>,
+}
+
+impl<M: Clone> Context<'_, M> {
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    pub fn now(&self) -> Time {
+        self.now
+    }
+
+    /// Number of nodes in the simulation.
+    pub fn nodes(&self) -> usize {
+        self.nodes
+    }
+
+    /// Send `message` to `to` over the simulated network. Messages to self
+    /// arrive immediately and are never dropped.
+    pub fn send(&mut self, to: NodeId, message: M) {
+        self.actions.push(Action::Send { to, message });
+    }
+
+    /// Send `message` to every other node.
+    pub fn broadcast(&mut self, message: M) {
+        for to in 0..self.nodes {
+            if to != self.id {
+                self.send(to, message.clone());
+            }
+        }
+    }
+
+    /// Call [`Node::timer`] with `tag` after `after` ms.
+    pub fn set_timer(&mut self, after: Time, tag: u64) {
+        self.actions.push(Action::Timer { after, tag });
+    }
+}
+
+/// Link behaviour, applied to every message independently.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkConfig {
+    pub min_delay: Time,
+    /// Inclusive; each message's delay is uniform in `min_delay..=max_delay`.
+    pub max_delay: Time,
+    pub drop_per_mille: u16,
+    /// Chance a delivered message also arrives a second time, with its own
+    /// delay.
+    pub duplicate_per_mille: u16,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            min_delay: 10,
+            max_delay: 100,
+            drop_per_mille: 0,
+            duplicate_per_mille: 0,
+        }
+    }
+}
+
+/// Message counters for a run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub sent: u64,
+    pub delivered: u64,
+    /// Lost to the drop rate or to a partition.
+    pub dropped: u64,
+    pub duplicated: u64,
+}
+
+enum Event<M> {
+    Deliver {
+        from: NodeId,
+        to: NodeId,
+        message: M,
+    },
+    Timer {
+        node: NodeId,
+        tag: u64,
+    },
+}
+
+/// The nodes, the network between them and the event queue.
+pub struct Simulation<N: Node> {
+    nodes: Vec<N>,
+    config: NetworkConfig,
+    rng: SimRng,
+    now: Time,
+    /// Keyed by (due time, scheduling order).
+    queue: BTreeMap<(Time, u64), Event<N::Message>>,
+    next_seq: u64,
+    /// Each node's side of the current partition, if any.
+    groups: Option<Vec<usize>>,
+    stats: Stats,
+}
+
+impl<N: Node> Simulation<N> {
+    /// Start `nodes` (node `i` gets id `i`) on a network seeded with `seed`.
+    pub fn new(seed: u64, config: NetworkConfig, nodes: Vec<N>) -> Self {
+        assert!(
+            config.min_delay <= config.max_delay,
+            "min_delay > max_delay"
+        );
+        let mut sim = Self {
+            nodes,
+            config,
+            rng: SimRng::new(seed),
+            now: 0,
+            queue: BTreeMap::new(),
+            next_seq: 0,
+            groups: None,
+            stats: Stats::default(),
+        };
+        for id in 0..sim.nodes.len() {
+            sim.dispatch(id, |node, ctx| node.start(ctx));
+        }
+        sim
+    }
+
+    pub fn now(&self) -> Time {
+        self.now
+    }
+
+    pub fn nodes(&self) -> &[N] {
+        &self.nodes
+    }
+
+    pub fn node(&self, id: NodeId) -> &N {
+        &self.nodes[id]
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Change link behaviour for messages sent from now on.
+    pub fn set_network(&mut self, config: NetworkConfig) {
+        assert!(
+            config.min_delay <= config.max_delay,
+            "min_delay > max_delay"
+        );
+        self.config = config;
+    }
+
+    /// Cut the network into `groups`; nodes not listed are each cut off
+    /// alone. Messages are checked when they arrive, so ones in flight
+    /// across the cut are lost.
+    pub fn partition(&mut self, groups: &[&[NodeId]]) {
+        let mut sides: Vec<usize> = (0..self.nodes.len()).map(|id| groups.len() + id).collect();
+        for (side, group) in groups.iter().enumerate() {
+            for &id in *group {
+                sides[id] = side;
+            }
+        }
+        self.groups = Some(sides);
+    }
+
+    /// Remove any partition.
+    pub fn heal(&mut self) {
+        self.groups = None;
+    }
+
+    fn connected(&self, a: NodeId, b: NodeId) -> bool {
+        self.groups
+            .as_ref()
+            .is_none_or(|sides| sides[a] == sides[b])
+    }
+
+    fn schedule(&mut self, at: Time, event: Event<N::Message>) {
+        self.queue.insert((at, self.next_seq), event);
+        self.next_seq += 1;
+    }
+
+    fn delay(&mut self) -> Time {
+        let spread = self.config.max_delay - self.config.min_delay;
+        self.config.min_delay + self.rng.below(spread + 1)
+    }
+
+    /// Run one handler on node `id` and apply what it asked for.
+    fn dispatch(&mut self, id: NodeId, f: impl FnOnce(&mut N, &mut Context<'_, N::Message>)) {
+        let mut actions = Vec::new();
+        let mut ctx = Context {
+            id,
+            now: self.now,
+            nodes: self.nodes.len(),
+            actions: &mut actions,
+        };
+        f(&mut self.nodes[id], &mut ctx);
+        for action in actions {
+            match action {
+                Action::Send { to, message } => {
+                    assert!(to < self.nodes.len(), "node {id} sent to unknown node {to}");
+                    self.stats.sent += 1;
+                    let deliver = |message| Event::Deliver {
+                        from: id,
+                        to,
+                        message,
+                    };
+                    if to == id {
+                        self.schedule(self.now, deliver(message));
+                        continue;
+                    }
+                    if self.rng.chance(self.config.drop_per_mille) {
+                        self.stats.dropped += 1;
+                        continue;
+                    }
+                    if self.rng.chance(self.config.duplicate_per_mille) {
+                        self.stats.duplicated += 1;
+                        let at = self.now + self.delay();
+                        self.schedule(at, deliver(message.clone()));
+                    }
+                    let at = self.now + self.delay();
+                    self.schedule(at, deliver(message));
+                }
+                Action::Timer { after, tag } => {
+                    self.schedule(self.now + after, Event::Timer { node: id, tag });
+                }
+            }
+        }
+    }
+
+    /// Handle the next event; `false` if there is none.
+    pub fn step(&mut self) -> bool {
+        let Some(((at, _), event)) = self.queue.pop_first() else {
+            return false;
+        };
+        self.now = at;
+        match event {
+            Event::Deliver { from, to, message } => {
+                if !self.connected(from, to) {
+                    self.stats.dropped += 1;
+                    return true;
+                }
+                self.stats.delivered += 1;
+                self.dispatch(to, |node, ctx| node.receive(from, message, ctx));
+            }
+            Event::Timer { node, tag } => self.dispatch(node, |n, ctx| n.timer(tag, ctx)),
+        }
+        true
+    }
+
+    /// Handle every event due up to `until`, then advance the clock to it.
+    pub fn run_until(&mut self, until: Time) {
+        while self
+            .queue
+            .first_key_value()
+            .is_some_and(|((at, _), _)| *at <= until)
+        {
+            self.step();
+        }
+        self.now = self.now.max(until);
+    }
+
+    pub fn run_for(&mut self, duration: Time) {
+        self.run_until(self.now + duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Floods a counter: each node broadcasts on a timer and records what
+    /// it receives.
+    #[derive(Default)]
+    struct Flood {
+        received: Vec<(Time, NodeId, u64)>,
+        sent: u64,
+    }
+
+    impl Node for Flood {
+        type Message = u64;
+
+        fn start(&mut self, ctx: &mut Context<'_, u64>) {
+            ctx.set_timer(5, 0);
+        }
+
+        fn receive(&mut self, from: NodeId, message: u64, ctx: &mut Context<'_, u64>) {
+            self.received.push((ctx.now(), from, message));
+        }
+
+        fn timer(&mut self, _: u64, ctx: &mut Context<'_, u64>) {
+            ctx.broadcast(self.sent);
+            self.sent += 1;
+            if self.sent < 20 {
+                ctx.set_timer(5, 0);
+            }
+        }
+    }
+
+    fn run(seed: u64, config: NetworkConfig, partition: bool) -> Simulation<Flood> {
+        let mut sim = Simulation::new(seed, config, (0..4).map(|_| Flood::default()).collect());
+        if partition {
+            sim.partition(&[&[0, 1]]);
+        }
+        sim.run_until(1_000);
+        sim
+    }
+
+    #[test]
+    fn runs_are_reproducible_from_the_seed() {
+        let config = NetworkConfig {
+            drop_per_mille: 100,
+            duplicate_per_mille: 50,
+            ..NetworkConfig::default()
+        };
+        let a = run(7, config.clone(), false);
+        let b = run(7, config.clone(), false);
+        let c = run(8, config, false);
+        let trace = |sim: &Simulation<Flood>| -> Vec<_> {
+            sim.nodes().iter().map(|n| n.received.clone()).collect()
+        };
+        assert_eq!(trace(&a), trace(&b));
+        assert_eq!(a.stats(), b.stats());
+        assert_ne!(trace(&a), trace(&c));
+
+        let stats = a.stats();
+        assert_eq!(stats.sent, 4 * 3 * 20);
+        assert_eq!(
+            stats.delivered,
+            stats.sent - stats.dropped + stats.duplicated
+        );
+        assert!(stats.dropped > 0 && stats.duplicated > 0);
+        // Jitter reorders: some node sees a sender's counter go backwards.
+        assert!(trace(&a).iter().any(|received| received
+            .windows(2)
+            .any(|w| w[0].1 == w[1].1 && w[0].2 > w[1].2)));
+    }
+
+    #[test]
+    fn partitions_cut_traffic_between_sides() {
+        let sim = run(1, NetworkConfig::default(), true);
+        // 0 and 1 hear only each other; 2 and 3 are each alone.
+        assert!(sim.node(0).received.iter().all(|(_, from, _)| *from == 1));
+        assert_eq!(sim.node(1).received.len(), 20);
+        assert!(sim.node(2).received.is_empty());
+        assert_eq!(sim.stats().dropped, 4 * 3 * 20 - 2 * 20);
+    }
+}
@@ -0,0 +1,300 @@
+//! Mempool convergence under drops, duplicates and partitions.
+//!
+//! Each node wraps a real [`SharedTxMempool`] and admits relayed txs through
+//! the node's gossip path ([`admit_gossip_tx`]), tagged with the sending
+//! peer. Like gossipsub, a tx is forwarded once it is admitted, and a
+//! periodic `Have` / `Want` exchange of txids repairs what the lossy network
+//! dropped. Mempool clocks follow virtual time, so source budgets and
+//! throttling behave as they would in a run.
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use ed25519_dalek::SigningKey;
+use mempool::{
+    AccountProvider, ManualClock, NonceProvider, SharedTxMempool, TxMempool, TxMempoolError,
+    TxSource,
+};
+use novai_codec::{encode_tx_v1_signed, txid_v1};
+use novai_crypto::{sign_tx_v1, SigCache};
+use novai_node::tx_gossip::{admit_gossip_tx, TxGossipError};
+use novai_p2p::{Keypair, PeerId};
+use novai_sim::{Context, NetworkConfig, Node, NodeId, Simulation, Stats, Time};
+use novai_types::{Address, TxId, TxV1, TxVersion};
+
+const NODES: usize = 5;
+const TXS_PER_NODE: u64 = 4;
+const HEARTBEAT: Time = 250;
+/// Timer tags at or above this submit the tx with nonce `tag - SUBMIT`.
+const SUBMIT: u64 = 1;
+const FORGE: u64 = 100;
+
+#[derive(Debug, Clone)]
+enum Msg {
+    /// Canonical signed tx bytes, as published on the tx topic.
+    Tx(Vec<u8>),
+    Have(Vec<TxId>),
+    Want(Vec<TxId>),
+}
+
+struct Funded;
+
+impl NonceProvider for Funded {
+    fn expected_nonce(&self, _from: &Address) -> u64 {
+        0
+    }
+}
+
+impl AccountProvider for Funded {
+    fn balance(&self, _from: &Address) -> u64 {
+        u64::MAX
+    }
+}
+
+fn peer_id(node: NodeId) -> PeerId {
+    Keypair::ed25519_from_bytes([node as u8 + 1; 32])
+        .expect("32-byte seed")
+        .public()
+        .to_peer_id()
+}
+
+fn signing_key(node: NodeId) -> SigningKey {
+    SigningKey::from_bytes(&[node as u8 + 101; 32])
+}
+
+fn signed_tx(node: NodeId, nonce: u64) -> TxV1 {
+    let sk = signing_key(node);
+    let mut tx = TxV1 {
+        version: TxVersion::V1,
+        from: sk.verifying_key().to_bytes(),
+        nonce,
+        fee: 1 + nonce,
+        payload: format!("tx {node}/{nonce}").into_bytes(),
+        sig: [0u8; 64],
+    };
+    sign_tx_v1(&sk, &mut tx).expect("tx signs");
+    tx
+}
+
+/// A copy of one of `node`'s txs with a different payload and the old
+/// signature.
+fn forged_tx(node: NodeId, nonce: u64) -> TxV1 {
+    let mut tx = signed_tx(node, nonce);
+    tx.payload = b"forged".to_vec();
+    tx
+}
+
+fn txid(tx: &TxV1) -> TxId {
+    txid_v1(tx).expect("tx encodes")
+}
+
+struct GossipNode {
+    mempool: SharedTxMempool,
+    clock: Arc<ManualClock>,
+    peers: Vec<PeerId>,
+    /// Txids this node admitted, announced every heartbeat.
+    known: BTreeSet<TxId>,
+    /// Admissions in order, for comparing runs.
+    admitted: Vec<(Time, TxId)>,
+    /// Send forged txs before any real ones.
+    byzantine: bool,
+}
+
+impl GossipNode {
+    fn new(byzantine: bool) -> Self {
+        let clock = Arc::new(ManualClock::new(0));
+        let mempool = TxMempool::new(1, 10)
+            .with_clock(clock.clone())
+            .with_sig_cache(Arc::new(SigCache::new(1_024)));
+        Self {
+            mempool: SharedTxMempool::new(mempool),
+            clock,
+            peers: (0..NODES).map(peer_id).collect(),
+            known: BTreeSet::new(),
+            admitted: Vec::new(),
+            byzantine,
+        }
+    }
+
+    fn sync_clock(&self, ctx: &Context<'_, Msg>) {
+        self.clock.set(ctx.now() / 1_000);
+    }
+
+    fn record(&mut self, id: TxId, ctx: &Context<'_, Msg>) {
+        self.known.insert(id);
+        self.admitted.push((ctx.now(), id));
+    }
+
+    fn relay(&self, from: NodeId, bytes: &[u8], ctx: &mut Context<'_, Msg>) {
+        for to in 0..ctx.nodes() {
+            if to != from && to != ctx.id() {
+                ctx.send(to, Msg::Tx(bytes.to_vec()));
+            }
+        }
+    }
+
+    fn score(&self, peer: NodeId) -> (u64, u64) {
+        let score = self
+            .mempool
+            .read()
+            .source_score(&TxSource::Peer(self.peers[peer].to_bytes()))
+            .unwrap_or_default();
+        (score.accepted, score.rejected)
+    }
+}
+
+impl Node for GossipNode {
+    type Message = Msg;
+
+    fn start(&mut self, ctx: &mut Context<'_, Msg>) {
+        ctx.set_timer(HEARTBEAT, 0);
+        if self.byzantine {
+            ctx.set_timer(50, FORGE);
+        }
+        // Staggered so nodes' txs interleave on the wire.
+        for nonce in 0..TXS_PER_NODE {
+            ctx.set_timer(100 + 300 * nonce + 37 * ctx.id() as Time, SUBMIT + nonce);
+        }
+    }
+
+    fn receive(&mut self, from: NodeId, message: Msg, ctx: &mut Context<'_, Msg>) {
+        self.sync_clock(ctx);
+        match message {
+            Msg::Tx(bytes) => {
+                match admit_gossip_tx(&self.mempool, &Funded, &self.peers[from], &bytes) {
+                    Ok(id) => {
+                        self.record(id, ctx);
+                        self.relay(from, &bytes, ctx);
+                    }
+                    Err(TxGossipError::Mempool(TxMempoolError::Duplicate)) => {}
+                    Err(e) => assert!(
+                        e.misbehaviour().is_some(),
+                        "honest tx refused by node {}: {e:?}",
+                        ctx.id()
+                    ),
+                }
+            }
+            Msg::Have(ids) => {
+                let missing: Vec<TxId> = ids
+                    .into_iter()
+                    .filter(|id| !self.known.contains(id))
+                    .collect();
+                if !missing.is_empty() {
+                    ctx.send(from, Msg::Want(missing));
+                }
+            }
+            Msg::Want(ids) => {
+                let mp = self.mempool.read();
+                for id in ids {
+                    if let Some(tx) = mp.get(&id) {
+                        ctx.send(from, Msg::Tx(encode_tx_v1_signed(tx).expect("tx encodes")));
+                    }
+                }
+            }
+        }
+    }
+
+    fn timer(&mut self, tag: u64, ctx: &mut Context<'_, Msg>) {
+        self.sync_clock(ctx);
+        match tag {
+            0 => {
+                if !self.known.is_empty() {
+                    ctx.broadcast(Msg::Have(self.known.iter().copied().collect()));
+                }
+                ctx.set_timer(HEARTBEAT, 0);
+            }
+            FORGE => {
+                for nonce in 0..TXS_PER_NODE {
+                    let tx = forged_tx(ctx.id(), nonce);
+                    ctx.broadcast(Msg::Tx(encode_tx_v1_signed(&tx).expect("tx encodes")));
+                }
+            }
+            _ => {
+                let tx = signed_tx(ctx.id(), tag - SUBMIT);
+                let bytes = encode_tx_v1_signed(&tx).expect("tx encodes");
+                let id = self
+                    .mempool
+                    .insert(tx, &Funded)
+                    .expect("own tx is admitted");
+                self.record(id, ctx);
+                ctx.broadcast(Msg::Tx(bytes));
+            }
+        }
+    }
+}
+
+fn lossy() -> NetworkConfig {
+    NetworkConfig {
+        min_delay: 10,
+        max_delay: 150,
+        drop_per_mille: 100,
+        duplicate_per_mille: 20,
+    }
+}
+
+/// Txids submitted by `senders`.
+fn txids_from(senders: &[NodeId]) -> BTreeSet<TxId> {
+    senders
+        .iter()
+        .flat_map(|node| (0..TXS_PER_NODE).map(|nonce| txid(&signed_tx(*node, nonce))))
+        .collect()
+}
+
+fn assert_pools(sim: &Simulation<GossipNode>, nodes: &[NodeId], expected: &BTreeSet<TxId>) {
+    for node in nodes {
+        let gossip = sim.node(*node);
+        let mp = gossip.mempool.read();
+        assert_eq!(mp.len(), expected.len(), "node {node}");
+        assert!(expected.iter().all(|id| mp.contains(id)), "node {node}");
+        assert_eq!(&gossip.known, expected, "node {node}");
+    }
+}
+
+/// 5 nodes, node 0 byzantine, split 2|3 while every node submits its txs,
+/// then healed. Returns each node's admissions and the message stats.
+fn split_and_heal(seed: u64) -> (Vec<Vec<(Time, TxId)>>, Stats) {
+    let nodes = (0..NODES).map(|id| GossipNode::new(id == 0)).collect();
+    let mut sim = Simulation::new(seed, lossy(), nodes);
+    sim.partition(&[&[0, 1], &[2, 3, 4]]);
+
+    // Each side converges on its own txs despite the drops.
+    sim.run_until(5_000);
+    assert_pools(&sim, &[0, 1], &txids_from(&[0, 1]));
+    assert_pools(&sim, &[2, 3, 4], &txids_from(&[2, 3, 4]));
+
+    sim.heal();
+    sim.run_until(12_000);
+    let all = txids_from(&(0..NODES).collect::<Vec<_>>());
+    assert_pools(&sim, &(0..NODES).collect::<Vec<_>>(), &all);
+
+    // Forgeries reached only node 1, were scored against node 0 and were
+    // never relayed; node 0's real txs were accepted from it all the same.
+    let forged: BTreeSet<TxId> = (0..TXS_PER_NODE).map(|n| txid(&forged_tx(0, n))).collect();
+    assert!(forged.is_disjoint(&all));
+    let (accepted, rejected) = sim.node(1).score(0);
+    assert!(rejected > 0);
+    assert!(accepted > 0);
+    for node in 2..NODES {
+        assert_eq!(sim.node(node).score(0).1, 0, "node {node}");
+    }
+
+    (
+        sim.nodes().iter().map(|n| n.admitted.clone()).collect(),
+        sim.stats(),
+    )
+}
+
+#[test]
+fn pools_converge_after_drops_and_a_partition() {
+    for seed in 0..3 {
+        split_and_heal(seed);
+    }
+}
+
+#[test]
+fn same_seed_same_admissions() {
+    let (admitted, stats) = split_and_heal(42);
+    assert!(stats.dropped > 0 && stats.duplicated > 0);
+    assert_eq!(split_and_heal(42), (admitted.clone(), stats));
+    assert_ne!(split_and_heal(43).0, admitted);
+}
@@ -0,0 +1,504 @@
+//! Consensus messages under partitions, duplicates and reordering.
+//!
+//! The node has no consensus engine yet, so nothing here chooses what to
+//! vote for beyond the leader's proposal for the current round. What runs is
+//! the node's side of consensus: every message crosses the network as
+//! canonical envelope bytes and is only acted on once
+//! [`decode_verified`] accepts it, votes are signed [`VoteV1`]s, and a QC
+//! counts only when [`validate_certified`] accepts the block carrying it,
+//! which is also how the other validators take certified blocks in. One
+//! extra node outside the validator set votes with its own key and forges
+//! votes in the validators' names.
+//!
+//! Locking, which stops two blocks being certified at one height in
+//! different rounds, belongs to the engine. Until then, round timeouts are
+//! kept well above the network delay so a certified block reaches everyone
+//! before they give up on its round.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use ed25519_dalek::SigningKey;
+use novai_codec::{
+    block_hash_v1, decode_block_v1, decode_consensus_message_v1, encode_block_v1,
+    encode_consensus_message_v1, tx_root_v1,
+};
+use novai_crypto::{sign_consensus_v1, SigCache};
+use novai_node::block_sync::{validate_certified, SyncError, ValidatorSet};
+use novai_node::consensus_net::decode_verified;
+use novai_sim::{Context, NetworkConfig, Node, NodeId, Simulation, Stats, Time};
+use novai_types::{
+    Address, BlockHeaderV1, BlockHeaderVersion, BlockV1, ConsensusMessage, Hash32, ProposalV1,
+    QuorumCertV1, SignatureBytes, TimeoutV1, VoteV1,
+};
+
+const VALIDATORS: usize = 4;
+/// Node id of the node outside the validator set.
+const OUTSIDER: NodeId = VALIDATORS;
+/// How long a leader waits before proposing.
+const BLOCK_TIME: Time = 500;
+/// Round timeout, and how often a timed-out validator repeats its timeout.
+const ROUND_TIMEOUT: Time = 2_000;
+/// Most certified blocks sent back to a lagging node at once.
+const CATCH_UP_BATCH: usize = 16;
+/// Most verified messages held for a round we have not reached yet.
+const AHEAD_CAP: usize = 64;
+
+#[derive(Debug, Clone)]
+enum Msg {
+    /// Canonical consensus envelope, as sent on the consensus transport.
+    Consensus(Vec<u8>),
+    /// Canonical certified blocks in height order, as block sync serves
+    /// them.
+    Certified(Vec<Vec<u8>>),
+}
+
+fn key(node: NodeId) -> SigningKey {
+    SigningKey::from_bytes(&[node as u8 + 1; 32])
+}
+
+fn address(node: NodeId) -> Address {
+    key(node).verifying_key().to_bytes()
+}
+
+fn signed(key: &SigningKey, mut msg: ConsensusMessage) -> Vec<u8> {
+    sign_consensus_v1(key, &mut msg).expect("sign consensus message");
+    encode_consensus_message_v1(&msg).expect("encode consensus message")
+}
+
+fn tag(height: u64, round: u64, propose: bool) -> u64 {
+    (height << 32) | (round << 1) | propose as u64
+}
+
+/// The (height, round) `msg` belongs to.
+fn position(msg: &ConsensusMessage) -> (u64, u64) {
+    match msg {
+        ConsensusMessage::Proposal(p) => (p.header.height, p.round),
+        ConsensusMessage::Vote(v) => (v.height, v.round),
+        ConsensusMessage::Timeout(t) => (t.height, t.round),
+    }
+}
+
+struct Validator {
+    key: SigningKey,
+    validators: ValidatorSet,
+    sig_cache: SigCache,
+    /// Certified blocks from height 1.
+    chain: Vec<BlockV1>,
+    height: u64,
+    round: u64,
+    /// Our proposal, while we lead the current round.
+    proposal: Option<BlockHeaderV1>,
+    voted: bool,
+    votes: BTreeMap<Address, SignatureBytes>,
+    timeouts: BTreeSet<Address>,
+    /// Verified messages for the next height or a later round, handled once
+    /// we get there.
+    ahead: Vec<(NodeId, ConsensusMessage)>,
+    /// Messages [`decode_verified`] turned away.
+    rejected: u64,
+    /// Votes [`validate_certified`] turned away.
+    refused_votes: u64,
+}
+
+impl Validator {
+    fn new(node: NodeId) -> Self {
+        Self {
+            key: key(node),
+            validators: ValidatorSet::new((0..VALIDATORS).map(address)),
+            sig_cache: SigCache::new(64),
+            chain: Vec::new(),
+            height: 1,
+            round: 0,
+            proposal: None,
+            voted: false,
+            votes: BTreeMap::new(),
+            timeouts: BTreeSet::new(),
+            ahead: Vec::new(),
+            rejected: 0,
+            refused_votes: 0,
+        }
+    }
+
+    fn address(&self) -> Address {
+        self.key.verifying_key().to_bytes()
+    }
+
+    fn leader(&self, round: u64) -> NodeId {
+        ((self.height + round) % VALIDATORS as u64) as usize
+    }
+
+    fn tip(&self) -> Hash32 {
+        self.chain.last().map_or([0u8; 32], |block| {
+            block_hash_v1(&block.header).expect("hash header")
+        })
+    }
+
+    fn start_round(&mut self, round: u64, ctx: &mut Context<'_, Msg>) {
+        self.round = round;
+        self.proposal = None;
+        self.voted = false;
+        self.votes.clear();
+        self.timeouts.clear();
+        ctx.set_timer(ROUND_TIMEOUT, tag(self.height, round, false));
+        if self.leader(round) == ctx.id() {
+            ctx.set_timer(BLOCK_TIME, tag(self.height, round, true));
+        }
+        for (from, msg) in std::mem::take(&mut self.ahead) {
+            self.handle(from, msg, ctx);
+        }
+    }
+
+    fn propose(&mut self, ctx: &mut Context<'_, Msg>) {
+        let round = self.round;
+        let header = BlockHeaderV1 {
+            version: BlockHeaderVersion::V1,
+            height: self.height,
+            prev_hash: self.tip(),
+            state_root: [0u8; 32],
+            tx_root: tx_root_v1(&[]).expect("empty tx root"),
+            proposer: self.address(),
+            qc_hash: [0u8; 32],
+        };
+        self.proposal = Some(header.clone());
+        self.voted = true;
+        let vote = self.vote(&header, round);
+        self.votes.insert(vote.voter, vote.sig);
+        let bytes = signed(
+            &self.key,
+            ConsensusMessage::Proposal(ProposalV1 {
+                round,
+                header,
+                sig: [0u8; 64],
+            }),
+        );
+        ctx.broadcast(Msg::Consensus(bytes));
+    }
+
+    fn vote(&self, header: &BlockHeaderV1, round: u64) -> VoteV1 {
+        let mut vote = ConsensusMessage::Vote(VoteV1 {
+            height: header.height,
+            round,
+            block_hash: block_hash_v1(header).expect("hash header"),
+            voter: self.address(),
+            sig: [0u8; 64],
+        });
+        sign_consensus_v1(&self.key, &mut vote).expect("sign vote");
+        let ConsensusMessage::Vote(vote) = vote else {
+            unreachable!()
+        };
+        vote
+    }
+
+    fn apply(&mut self, block: BlockV1, ctx: &mut Context<'_, Msg>) {
+        self.chain.push(block);
+        self.height += 1;
+        self.start_round(0, ctx);
+    }
+
+    /// Certify our proposal once a quorum of votes passes validation,
+    /// dropping any vote it refuses.
+    fn try_certify(&mut self, ctx: &mut Context<'_, Msg>) {
+        let Some(header) = self.proposal.clone() else {
+            return;
+        };
+        while self.votes.len() >= self.validators.quorum() {
+            let block = BlockV1 {
+                qc: QuorumCertV1 {
+                    height: header.height,
+                    round: self.round,
+                    block_hash: block_hash_v1(&header).expect("hash header"),
+                    votes: self.votes.iter().map(|(v, s)| (*v, *s)).collect(),
+                },
+                header: header.clone(),
+                txs: Vec::new(),
+            };
+            match validate_certified(&block, &self.validators, &self.sig_cache) {
+                Ok(()) => {
+                    ctx.broadcast(Msg::Certified(vec![
+                        encode_block_v1(&block).expect("encode")
+                    ]));
+                    self.apply(block, ctx);
+                    return;
+                }
+                Err(SyncError::InvalidVote { voter, .. }) => {
+                    self.votes.remove(&voter);
+                    self.refused_votes += 1;
+                }
+                Err(e) => panic!("own block refused: {e:?}"),
+            }
+        }
+    }
+
+    fn on_consensus(&mut self, from: NodeId, bytes: &[u8], ctx: &mut Context<'_, Msg>) {
+        match decode_verified(bytes) {
+            Ok(msg) => self.handle(from, msg, ctx),
+            Err(_) => self.rejected += 1,
+        }
+    }
+
+    fn handle(&mut self, from: NodeId, msg: ConsensusMessage, ctx: &mut Context<'_, Msg>) {
+        let (height, round) = position(&msg);
+        if height < self.height {
+            let behind = self.chain[height as usize - 1..]
+                .iter()
+                .take(CATCH_UP_BATCH)
+                .map(|block| encode_block_v1(block).expect("encode"))
+                .collect();
+            ctx.send(from, Msg::Certified(behind));
+            return;
+        }
+        if height > self.height || round > self.round {
+            if height <= self.height + 1 && self.ahead.len() < AHEAD_CAP {
+                self.ahead.push((from, msg));
+            }
+            return;
+        }
+        match msg {
+            ConsensusMessage::Proposal(p)
+                if p.round == self.round
+                    && !self.voted
+                    && p.header.proposer == address(self.leader(p.round)) =>
+            {
+                self.voted = true;
+                let vote = ConsensusMessage::Vote(self.vote(&p.header, p.round));
+                let bytes = encode_consensus_message_v1(&vote).expect("encode vote");
+                ctx.send(self.leader(p.round), Msg::Consensus(bytes));
+            }
+            ConsensusMessage::Vote(v) if v.round == self.round => {
+                let ours = self
+                    .proposal
+                    .as_ref()
+                    .map(|h| block_hash_v1(h).expect("hash"));
+                if ours == Some(v.block_hash) {
+                    self.votes.insert(v.voter, v.sig);
+                    self.try_certify(ctx);
+                }
+            }
+            ConsensusMessage::Timeout(t)
+                if t.round == self.round && self.validators.contains(&t.voter) =>
+            {
+                self.time_out(t.voter, ctx);
+            }
+            _ => {}
+        }
+    }
+
+    fn time_out(&mut self, voter: Address, ctx: &mut Context<'_, Msg>) {
+        self.timeouts.insert(voter);
+        if self.timeouts.len() >= self.validators.quorum() {
+            self.start_round(self.round + 1, ctx);
+        }
+    }
+
+    fn on_certified(&mut self, blocks: &[Vec<u8>], ctx: &mut Context<'_, Msg>) {
+        for bytes in blocks {
+            let Ok(block) = decode_block_v1(bytes) else {
+                self.rejected += 1;
+                return;
+            };
+            if block.header.height != self.height || block.header.prev_hash != self.tip() {
+                continue;
+            }
+            match validate_certified(&block, &self.validators, &self.sig_cache) {
+                Ok(()) => self.apply(block, ctx),
+                Err(e) => panic!("honest leader certified an invalid block: {e:?}"),
+            }
+        }
+    }
+}
+
+impl Node for Validator {
+    type Message = Msg;
+
+    fn start(&mut self, ctx: &mut Context<'_, Msg>) {
+        self.start_round(0, ctx);
+    }
+
+    fn receive(&mut self, from: NodeId, msg: Msg, ctx: &mut Context<'_, Msg>) {
+        if ctx.id() == OUTSIDER {
+            return self.meddle(msg, ctx);
+        }
+        match msg {
+            Msg::Consensus(bytes) => self.on_consensus(from, &bytes, ctx),
+            Msg::Certified(blocks) => self.on_certified(&blocks, ctx),
+        }
+    }
+
+    fn timer(&mut self, fired: u64, ctx: &mut Context<'_, Msg>) {
+        if ctx.id() == OUTSIDER {
+            return;
+        }
+        if fired == tag(self.height, self.round, true) {
+            return self.propose(ctx);
+        }
+        if fired != tag(self.height, self.round, false) {
+            return;
+        }
+        let timeout = signed(
+            &self.key,
+            ConsensusMessage::Timeout(TimeoutV1 {
+                height: self.height,
+                round: self.round,
+                voter: self.address(),
+                sig: [0u8; 64],
+            }),
+        );
+        ctx.set_timer(ROUND_TIMEOUT, fired);
+        ctx.broadcast(Msg::Consensus(timeout));
+        self.time_out(self.address(), ctx);
+    }
+}
+
+impl Validator {
+    /// The outsider answers every proposal with a vote under its own key
+    /// and one claiming to be validator 0's, signed with its own key.
+    fn meddle(&mut self, msg: Msg, ctx: &mut Context<'_, Msg>) {
+        let Msg::Consensus(bytes) = msg else {
+            return;
+        };
+        let Ok(ConsensusMessage::Proposal(p)) = decode_consensus_message_v1(&bytes) else {
+            return;
+        };
+        let leader = ((p.header.height + p.round) % VALIDATORS as u64) as usize;
+        for voter in [self.address(), address(0)] {
+            let vote = signed(
+                &self.key,
+                ConsensusMessage::Vote(VoteV1 {
+                    height: p.header.height,
+                    round: p.round,
+                    block_hash: block_hash_v1(&p.header).expect("hash header"),
+                    voter,
+                    sig: [0u8; 64],
+                }),
+            );
+            ctx.send(leader, Msg::Consensus(vote));
+        }
+    }
+}
+
+fn network() -> NetworkConfig {
+    NetworkConfig {
+        min_delay: 10,
+        max_delay: 150,
+        drop_per_mille: 0,
+        duplicate_per_mille: 20,
+    }
+}
+
+fn nodes() -> Vec<Validator> {
+    (0..=OUTSIDER).map(Validator::new).collect()
+}
+
+/// Every pair of validators agrees on every height both have certified.
+/// Blocks only join a chain once [`validate_certified`] accepts them.
+fn assert_safe(sim: &Simulation<Validator>) {
+    let chains: Vec<&Vec<BlockV1>> = sim.nodes()[..VALIDATORS].iter().map(|v| &v.chain).collect();
+    for a in &chains {
+        for b in &chains {
+            let common = a.len().min(b.len());
+            assert_eq!(a[..common], b[..common], "conflicting certified blocks");
+        }
+    }
+}
+
+fn heights(sim: &Simulation<Validator>) -> Vec<usize> {
+    sim.nodes()[..VALIDATORS]
+        .iter()
+        .map(|v| v.chain.len())
+        .collect()
+}
+
+fn rounds(sim: &Simulation<Validator>) -> Vec<u64> {
+    sim.nodes()[..VALIDATORS].iter().map(|v| v.round).collect()
+}
+
+/// Run, split the validators 2|2 with the outsider on one side for ten
+/// round timeouts, heal, run. Returns the chains and message stats.
+fn split_and_heal(seed: u64) -> (Vec<Vec<BlockV1>>, Stats) {
+    let mut sim = Simulation::new(seed, network(), nodes());
+    sim.run_for(1_500);
+    assert_safe(&sim);
+    let before = heights(&sim);
+    assert!(
+        before.iter().all(|h| *h > 0),
+        "no progress before the split"
+    );
+
+    // Two validators and the outsider make three votes, but the outsider's
+    // never pass QC validation, and neither side has a quorum of timeouts
+    // to leave its round: nothing is certified beyond a block that was
+    // already on its way before the split.
+    sim.partition(&[&[0, 1, OUTSIDER], &[2, 3]]);
+    sim.run_for(1);
+    let split_rounds = rounds(&sim);
+    sim.run_for(10 * ROUND_TIMEOUT);
+    let top = *before.iter().max().unwrap();
+    assert!(
+        heights(&sim).iter().all(|h| *h <= top + 1),
+        "certified without a quorum: {before:?} -> {:?}",
+        heights(&sim)
+    );
+    assert!(rounds(&sim)
+        .iter()
+        .zip(&split_rounds)
+        .all(|(now, then)| now <= &(then + 1)));
+    assert_safe(&sim);
+    let top = *heights(&sim).iter().max().unwrap();
+
+    sim.heal();
+    sim.run_for(3 * ROUND_TIMEOUT);
+    assert_safe(&sim);
+    assert!(
+        heights(&sim).iter().all(|h| *h > top),
+        "no progress after healing: {before:?} -> {:?}",
+        heights(&sim)
+    );
+    (
+        sim.nodes().iter().map(|v| v.chain.clone()).collect(),
+        sim.stats(),
+    )
+}
+
+#[test]
+fn partition_for_ten_rounds_then_heal_keeps_safety_and_resumes() {
+    let (chains, stats) = split_and_heal(0);
+    assert_ne!(split_and_heal(1).0, chains);
+    // Same seed, same run.
+    assert_eq!(split_and_heal(0), (chains, stats));
+}
+
+#[test]
+fn outsider_votes_and_forgeries_never_count() {
+    let mut sim = Simulation::new(5, network(), nodes());
+    sim.run_for(3_000);
+    assert_safe(&sim);
+    let validators = &sim.nodes()[..VALIDATORS];
+    assert!(validators.iter().map(|v| v.rejected).sum::<u64>() > 0);
+    assert!(validators.iter().map(|v| v.refused_votes).sum::<u64>() > 0);
+    let outsider = address(OUTSIDER);
+    for block in &sim.node(0).chain {
+        assert!(block.qc.votes.iter().all(|(voter, _)| *voter != outsider));
+    }
+}
+
+#[test]
+fn cut_off_minority_catches_up_after_heal() {
+    let mut sim = Simulation::new(7, network(), nodes());
+    sim.run_for(1_500);
+    sim.partition(&[&[0, 1, 2]]);
+    sim.run_for(1);
+    let before = heights(&sim);
+    sim.run_for(4 * ROUND_TIMEOUT);
+    let during = heights(&sim);
+    assert_eq!(during[3], before[3], "isolated validator certified alone");
+    assert!(during[..3].iter().all(|h| *h > before[3] + 3), "{during:?}");
+    assert_safe(&sim);
+
+    sim.heal();
+    sim.run_for(2 * ROUND_TIMEOUT);
+    assert_safe(&sim);
+    let after = heights(&sim);
+    assert!(after[3] >= during[0], "{after:?}");
+    let (lo, hi) = (after.iter().min().unwrap(), after.iter().max().unwrap());
+    assert!(hi - lo <= 1, "{after:?}");
+}
@@ -1,5 +1,563 @@
 //! novai-smt
 //!
-//! Purpose: placeholder crate for clean-room workspace bootstrap.
-//! Invariants: no external dependencies (Week 1).
-//! Failure modes: none (placeholder).
+//! Purpose: sparse Merkle tree over 256-bit keys, with range proofs so state
+//! snapshots can be transferred in independently verifiable chunks.
+//! Invariants: the root depends only on the set of (key, value) pairs, never
+//! on insertion order. Hashing is domain separated:
+//!
+//! ```text
+//! empty subtree        = [0u8; 32]
+//! subtree with 1 leaf  = blake3(0x00 || key || blake3(value))
+//! otherwise            = blake3(0x01 || left || right)
+//! ```
+//!
+//! Children split on the key bit at the subtree's depth, most significant
+//! bit first, so a subtree always covers one contiguous key range.
+//! Failure modes: chunk verification returns [`SmtError`]; nothing panics on
+//! untrusted input.
+
+use std::collections::{BTreeMap, HashMap};
+
+use novai_types::{Hash32, RangeProofNodeV1, SnapshotChunkV1};
+
+/// Root of the empty tree.
+pub const EMPTY_ROOT: Hash32 = [0u8; 32];
+
+/// Largest key; a chunk ending here is the last one.
+pub const MAX_KEY: Hash32 = [0xffu8; 32];
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SmtError {
+    /// Chunk `start` is above its `end`.
+    InvalidRange,
+    /// Chunk leaves are not strictly ascending inside `start..=end`.
+    LeavesOutOfRange,
+    /// Proof does not match the shape of the tree around the range.
+    MalformedProof,
+    /// Leaves and proof hash to a different root.
+    RootMismatch,
+}
+
+pub fn value_hash(value: &[u8]) -> Hash32 {
+    *blake3::hash(value).as_bytes()
+}
+
+pub fn leaf_hash(key: &Hash32, value_hash: &Hash32) -> Hash32 {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[LEAF_PREFIX]);
+    hasher.update(key);
+    hasher.update(value_hash);
+    *hasher.finalize().as_bytes()
+}
+
+fn node_hash(left: &Hash32, right: &Hash32) -> Hash32 {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    *hasher.finalize().as_bytes()
+}
+
+fn bit(key: &Hash32, depth: usize) -> u8 {
+    (key[depth / 8] >> (7 - depth % 8)) & 1
+}
+
+fn with_bit(key: &Hash32, depth: usize) -> Hash32 {
+    let mut key = *key;
+    key[depth / 8] |= 0x80 >> (depth % 8);
+    key
+}
+
+/// Highest key in the subtree at `depth` whose lowest key is `lo`.
+fn upper(lo: &Hash32, depth: usize) -> Hash32 {
+    let mut hi = *lo;
+    for d in depth..256 {
+        hi[d / 8] |= 0x80 >> (d % 8);
+    }
+    hi
+}
+
+/// Index of the first item in the right child.
+fn split_point(items: &[(Hash32, Hash32)], depth: usize) -> usize {
+    items.partition_point(|(key, _)| bit(key, depth) == 0)
+}
+
+/// Hash of the subtree at `depth` holding `items` (sorted, distinct keys,
+/// paired with their value hashes).
+fn subtree_hash(items: &[(Hash32, Hash32)], depth: usize) -> Hash32 {
+    match items {
+        [] => EMPTY_ROOT,
+        [(key, value_hash)] => leaf_hash(key, value_hash),
+        _ => {
+            let mid = split_point(items, depth);
+            node_hash(
+                &subtree_hash(&items[..mid], depth + 1),
+                &subtree_hash(&items[mid..], depth + 1),
+            )
+        }
+    }
+}
+
+/// Key after `end`, or `None` once the key space is exhausted.
+pub fn next_start(end: &Hash32) -> Option<Hash32> {
+    let mut key = *end;
+    for byte in key.iter_mut().rev() {
+        let (next, carry) = byte.overflowing_add(1);
+        *byte = next;
+        if !carry {
+            return Some(key);
+        }
+    }
+    None
+}
+
+/// In-memory sparse Merkle tree. Hashes are recomputed on demand, so
+/// [`SparseMerkleTree::root`] and [`SparseMerkleTree::chunk`] are O(n);
+/// freeze it into a [`HashedTree`] to serve many chunks of one state.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SparseMerkleTree {
+    leaves: BTreeMap<Hash32, Vec<u8>>,
+}
+
+impl SparseMerkleTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, key: Hash32, value: Vec<u8>) -> Option<Vec<u8>> {
+        self.leaves.insert(key, value)
+    }
+
+    pub fn remove(&mut self, key: &Hash32) -> Option<Vec<u8>> {
+        self.leaves.remove(key)
+    }
+
+    pub fn get(&self, key: &Hash32) -> Option<&[u8]> {
+        self.leaves.get(key).map(Vec::as_slice)
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Leaves in ascending key order.
+    pub fn iter(&self) -> impl Iterator<Item = (&Hash32, &[u8])> {
+        self.leaves.iter().map(|(k, v)| (k, v.as_slice()))
+    }
+
+    fn items(&self) -> Vec<(Hash32, Hash32)> {
+        self.leaves
+            .iter()
+            .map(|(key, value)| (*key, value_hash(value)))
+            .collect()
+    }
+
+    pub fn root(&self) -> Hash32 {
+        subtree_hash(&self.items(), 0)
+    }
+
+    /// Up to `max_leaves` (at least one) leaves from `start` on, with a
+    /// proof against [`SparseMerkleTree::root`].
+    ///
+    /// The chunk ends at its last leaf if more leaves follow, otherwise at
+    /// [`MAX_KEY`]; the next chunk starts at [`next_start`] of its end.
+    pub fn chunk(&self, start: &Hash32, max_leaves: usize) -> SnapshotChunkV1 {
+        chunk(self, &self.items(), &Recompute, start, max_leaves)
+    }
+}
+
+/// A [`SparseMerkleTree`] frozen with every subtree hash computed once, so
+/// its root and chunks cost no rehashing of the whole state. For states
+/// kept around to be served.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashedTree {
+    tree: SparseMerkleTree,
+    items: Vec<(Hash32, Hash32)>,
+    nodes: NodeHashes,
+    root: Hash32,
+}
+
+impl HashedTree {
+    pub fn new(tree: SparseMerkleTree) -> Self {
+        let items = tree.items();
+        let mut nodes = NodeHashes::default();
+        let root = nodes.fill(&items, 0, 0);
+        Self {
+            tree,
+            items,
+            nodes,
+            root,
+        }
+    }
+
+    pub fn tree(&self) -> &SparseMerkleTree {
+        &self.tree
+    }
+
+    pub fn into_tree(self) -> SparseMerkleTree {
+        self.tree
+    }
+
+    pub fn root(&self) -> Hash32 {
+        self.root
+    }
+
+    /// [`SparseMerkleTree::chunk`], hashing only the chunk's leaves.
+    pub fn chunk(&self, start: &Hash32, max_leaves: usize) -> SnapshotChunkV1 {
+        chunk(&self.tree, &self.items, &self.nodes, start, max_leaves)
+    }
+}
+
+impl From<SparseMerkleTree> for HashedTree {
+    fn from(tree: SparseMerkleTree) -> Self {
+        Self::new(tree)
+    }
+}
+
+/// Where [`Prover`] gets subtree hashes from.
+trait SubtreeHashes {
+    /// Hash of the subtree at `depth` holding `items`, the first of which
+    /// is item `offset` of the whole tree.
+    fn subtree(&self, items: &[(Hash32, Hash32)], offset: usize, depth: usize) -> Hash32;
+}
+
+/// Hashes every subtree from scratch.
+struct Recompute;
+
+impl SubtreeHashes for Recompute {
+    fn subtree(&self, items: &[(Hash32, Hash32)], _offset: usize, depth: usize) -> Hash32 {
+        subtree_hash(items, depth)
+    }
+}
+
+/// Hash of every subtree holding two or more items, by depth and the index
+/// of its first item. Non-empty subtrees at one depth are disjoint, so that
+/// pair names exactly one of them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct NodeHashes(HashMap<(usize, usize), Hash32>);
+
+impl NodeHashes {
+    /// [`subtree_hash`], recording every inner node on the way.
+    fn fill(&mut self, items: &[(Hash32, Hash32)], offset: usize, depth: usize) -> Hash32 {
+        match items {
+            [] => EMPTY_ROOT,
+            [(key, value_hash)] => leaf_hash(key, value_hash),
+            _ => {
+                let mid = split_point(items, depth);
+                let left = self.fill(&items[..mid], offset, depth + 1);
+                let right = self.fill(&items[mid..], offset + mid, depth + 1);
+                let hash = node_hash(&left, &right);
+                self.0.insert((depth, offset), hash);
+                hash
+            }
+        }
+    }
+}
+
+impl SubtreeHashes for NodeHashes {
+    fn subtree(&self, items: &[(Hash32, Hash32)], offset: usize, depth: usize) -> Hash32 {
+        match items {
+            [] => EMPTY_ROOT,
+            [(key, value_hash)] => leaf_hash(key, value_hash),
+            _ => self.0[&(depth, offset)],
+        }
+    }
+}
+
+/// `tree`'s chunk from `start`; `items` are its leaves paired with their
+/// value hashes.
+fn chunk(
+    tree: &SparseMerkleTree,
+    items: &[(Hash32, Hash32)],
+    hashes: &impl SubtreeHashes,
+    start: &Hash32,
+    max_leaves: usize,
+) -> SnapshotChunkV1 {
+    let max_leaves = max_leaves.max(1);
+    let mut leaves: Vec<(Hash32, Vec<u8>)> = tree
+        .leaves
+        .range(*start..)
+        .take(max_leaves + 1)
+        .map(|(k, v)| (*k, v.clone()))
+        .collect();
+    let end = if leaves.len() > max_leaves {
+        leaves.pop();
+        leaves.last().expect("max_leaves >= 1").0
+    } else {
+        MAX_KEY
+    };
+
+    let mut prover = Prover {
+        hashes,
+        start,
+        end: &end,
+        proof: Vec::new(),
+    };
+    prover.prove(items, 0, 0, [0u8; 32]);
+    SnapshotChunkV1 {
+        start: *start,
+        end,
+        leaves,
+        proof: prover.proof,
+    }
+}
+
+/// Range proof for `start..=end`, built by walking down from the root.
+struct Prover<'a, H> {
+    hashes: &'a H,
+    start: &'a Hash32,
+    end: &'a Hash32,
+    proof: Vec<RangeProofNodeV1>,
+}
+
+impl<H: SubtreeHashes> Prover<'_, H> {
+    fn prove(&mut self, items: &[(Hash32, Hash32)], offset: usize, depth: usize, lo: Hash32) {
+        let (start, end) = (self.start, self.end);
+        let hi = upper(&lo, depth);
+        if hi < *start || lo > *end {
+            let hash = self.hashes.subtree(items, offset, depth);
+            self.proof.push(RangeProofNodeV1::Subtree(hash));
+            return;
+        }
+        if *start <= lo && hi <= *end {
+            return;
+        }
+        // Straddling a boundary, so lo != hi and depth < 256.
+        match items {
+            [(key, value_hash)] if key < start || key > end => {
+                self.proof.push(RangeProofNodeV1::OutsideLeaf {
+                    key: *key,
+                    value_hash: *value_hash,
+                });
+            }
+            [] | [_] => self.proof.push(RangeProofNodeV1::Collapsed),
+            _ => {
+                self.proof.push(RangeProofNodeV1::Split);
+                let mid = split_point(items, depth);
+                self.prove(&items[..mid], offset, depth + 1, lo);
+                self.prove(&items[mid..], offset + mid, depth + 1, with_bit(&lo, depth));
+            }
+        }
+    }
+}
+
+fn rebuild<'a>(
+    items: &[(Hash32, Hash32)],
+    depth: usize,
+    lo: Hash32,
+    start: &Hash32,
+    end: &Hash32,
+    proof: &mut impl Iterator<Item = &'a RangeProofNodeV1>,
+) -> Result<Hash32, SmtError> {
+    let hi = upper(&lo, depth);
+    if hi < *start || lo > *end {
+        // Chunk leaves are all in range, so `items` is empty here.
+        return match proof.next() {
+            Some(RangeProofNodeV1::Subtree(hash)) => Ok(*hash),
+            _ => Err(SmtError::MalformedProof),
+        };
+    }
+    if *start <= lo && hi <= *end {
+        return Ok(subtree_hash(items, depth));
+    }
+    match proof.next() {
+        Some(RangeProofNodeV1::Collapsed) if items.len() <= 1 => Ok(subtree_hash(items, depth)),
+        Some(RangeProofNodeV1::OutsideLeaf { key, value_hash })
+            if items.is_empty() && (lo..=hi).contains(key) && !(start..=end).contains(&key) =>
+        {
+            Ok(leaf_hash(key, value_hash))
+        }
+        Some(RangeProofNodeV1::Split) => {
+            let mid = split_point(items, depth);
+            let left = rebuild(&items[..mid], depth + 1, lo, start, end, proof)?;
+            let right = rebuild(
+                &items[mid..],
+                depth + 1,
+                with_bit(&lo, depth),
+                start,
+                end,
+                proof,
+            )?;
+            Ok(node_hash(&left, &right))
+        }
+        _ => Err(SmtError::MalformedProof),
+    }
+}
+
+/// Check that `chunk` holds exactly the leaves of the tree with `root`
+/// whose keys lie in `chunk.start..=chunk.end`.
+pub fn verify_chunk(root: &Hash32, chunk: &SnapshotChunkV1) -> Result<(), SmtError> {
+    if chunk.start > chunk.end {
+        return Err(SmtError::InvalidRange);
+    }
+    let mut prev: Option<&Hash32> = None;
+    for (key, _) in &chunk.leaves {
+        if *key < chunk.start || *key > chunk.end || prev.is_some_and(|p| p >= key) {
+            return Err(SmtError::LeavesOutOfRange);
+        }
+        prev = Some(key);
+    }
+
+    let items: Vec<(Hash32, Hash32)> = chunk
+        .leaves
+        .iter()
+        .map(|(key, value)| (*key, value_hash(value)))
+        .collect();
+    let mut proof = chunk.proof.iter();
+    let rebuilt = rebuild(&items, 0, [0u8; 32], &chunk.start, &chunk.end, &mut proof)?;
+    if proof.next().is_some() {
+        return Err(SmtError::MalformedProof);
+    }
+    if rebuilt != *root {
+        return Err(SmtError::RootMismatch);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(i: u32) -> Hash32 {
+        *blake3::hash(&i.to_le_bytes()).as_bytes()
+    }
+
+    fn tree(n: u32) -> SparseMerkleTree {
+        let mut tree = SparseMerkleTree::new();
+        for i in 0..n {
+            tree.insert(key(i), i.to_le_bytes().repeat(i as usize % 4));
+        }
+        tree
+    }
+
+    /// Walk the whole key space in chunks, verifying each one.
+    fn reassemble(tree: &SparseMerkleTree, max_leaves: usize) -> SparseMerkleTree {
+        let root = tree.root();
+        let mut out = SparseMerkleTree::new();
+        let mut start = Some([0u8; 32]);
+        while let Some(from) = start {
+            let chunk = tree.chunk(&from, max_leaves);
+            assert_eq!(verify_chunk(&root, &chunk), Ok(()));
+            for (k, v) in chunk.leaves {
+                out.insert(k, v);
+            }
+            start = next_start(&chunk.end);
+        }
+        out
+    }
+
+    #[test]
+    fn root_is_order_independent_and_tracks_removals() {
+        assert_eq!(SparseMerkleTree::new().root(), EMPTY_ROOT);
+
+        let mut single = SparseMerkleTree::new();
+        single.insert([7u8; 32], vec![1]);
+        assert_eq!(single.root(), leaf_hash(&[7u8; 32], &value_hash(&[1])));
+
+        let forward = tree(50);
+        let mut backward = SparseMerkleTree::new();
+        for i in (0..50).rev() {
+            backward.insert(key(i), i.to_le_bytes().repeat(i as usize % 4));
+        }
+        assert_eq!(forward.root(), backward.root());
+
+        let mut fewer = forward.clone();
+        fewer.remove(&key(10));
+        assert_ne!(fewer.root(), forward.root());
+        fewer.insert(key(10), forward.get(&key(10)).unwrap().to_vec());
+        assert_eq!(fewer.root(), forward.root());
+    }
+
+    #[test]
+    fn chunks_verify_and_reassemble_the_tree() {
+        for n in [0, 1, 2, 3, 40] {
+            let tree = tree(n);
+            for max_leaves in [1, 3, 7, 64] {
+                assert_eq!(
+                    reassemble(&tree, max_leaves),
+                    tree,
+                    "n={n} max={max_leaves}"
+                );
+            }
+        }
+
+        // Keys at the very edges of the key space.
+        let mut edges = tree(5);
+        edges.insert([0u8; 32], vec![1]);
+        edges.insert(MAX_KEY, vec![2]);
+        assert_eq!(reassemble(&edges, 2), edges);
+        assert_eq!(next_start(&MAX_KEY), None);
+    }
+
+    #[test]
+    fn tampered_chunks_are_rejected() {
+        let tree = tree(40);
+        let root = tree.root();
+        let chunk = tree.chunk(&key(3), 8);
+        assert_eq!(verify_chunk(&root, &chunk), Ok(()));
+        assert_eq!(
+            verify_chunk(&[1u8; 32], &chunk),
+            Err(SmtError::RootMismatch)
+        );
+
+        let mut changed = chunk.clone();
+        changed.leaves[2].1.push(0);
+        assert_eq!(verify_chunk(&root, &changed), Err(SmtError::RootMismatch));
+
+        // Withholding a leaf is detected: the range claims to be complete.
+        let mut dropped = chunk.clone();
+        dropped.leaves.remove(4);
+        assert!(verify_chunk(&root, &dropped).is_err());
+
+        let mut unsorted = chunk.clone();
+        unsorted.leaves.swap(0, 1);
+        assert_eq!(
+            verify_chunk(&root, &unsorted),
+            Err(SmtError::LeavesOutOfRange)
+        );
+
+        let mut truncated = chunk.clone();
+        truncated.proof.pop();
+        assert_eq!(
+            verify_chunk(&root, &truncated),
+            Err(SmtError::MalformedProof)
+        );
+
+        let mut extended = chunk.clone();
+        extended.proof.push(RangeProofNodeV1::Collapsed);
+        assert_eq!(
+            verify_chunk(&root, &extended),
+            Err(SmtError::MalformedProof)
+        );
+
+        let mut inverted = chunk;
+        std::mem::swap(&mut inverted.start, &mut inverted.end);
+        assert_eq!(verify_chunk(&root, &inverted), Err(SmtError::InvalidRange));
+    }
+
+    #[test]
+    fn hashed_tree_serves_the_same_chunks() {
+        for n in [0, 1, 2, 3, 40] {
+            let tree = tree(n);
+            let hashed = HashedTree::new(tree.clone());
+            assert_eq!(hashed.root(), tree.root());
+            for start in [[0u8; 32], key(3), MAX_KEY] {
+                for max_leaves in [1, 7, 64] {
+                    assert_eq!(
+                        hashed.chunk(&start, max_leaves),
+                        tree.chunk(&start, max_leaves),
+                        "n={n} max={max_leaves}"
+                    );
+                }
+            }
+            assert_eq!(hashed.into_tree(), tree);
+        }
+    }
+}
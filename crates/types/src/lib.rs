@@ -4,6 +4,10 @@
 //! - These structs participate in consensus/networking.
 //! - Changing field order or encoding is a hard-fork unless you bump `version`.
 //! - Avoid HashMap/iteration-order-dependent structures in consensus-relevant types.
+//! - Types deriving `Encode`/`Decode` are encoded field by field in declared
+//!   order; see `novai-encoding`.
+
+use novai_encoding::{Decode, Encode};
 
 pub type Address = [u8; 32]; // For Week 2: ed25519 public key bytes.
 pub type TxId = [u8; 32];
@@ -15,7 +19,8 @@ pub type Fee = u64;
 pub type SignatureBytes = [u8; 64];
 
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+#[codec(version)]
 pub enum TxVersion {
     V1 = 1,
 }
@@ -30,7 +35,8 @@ impl TxVersion {
 }
 
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+#[codec(version)]
 pub enum BlockHeaderVersion {
     V1 = 1,
 }
@@ -50,13 +56,14 @@ impl BlockHeaderVersion {
 /// - Signature is computed over the canonical *unsigned* encoding of this tx
 ///   (everything except `sig`).
 /// - `from` is the 32-byte ed25519 public key (Address).
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
 pub struct TxV1 {
     pub version: TxVersion,
     pub from: Address,
     pub nonce: Nonce,
     pub fee: Fee,
     pub payload: Vec<u8>,
+    #[codec(signature)]
     pub sig: SignatureBytes,
 }
 
@@ -65,7 +72,7 @@ pub struct TxV1 {
 /// Notes:
 /// - All hashes are 32 bytes.
 /// - `qc_hash` is a placeholder for Week 2 (still fixed-size).
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
 pub struct BlockHeaderV1 {
     pub version: BlockHeaderVersion,
     pub height: u64,
@@ -75,3 +82,162 @@ pub struct BlockHeaderV1 {
     pub proposer: Address,
     pub qc_hash: Hash32,
 }
+
+/// Consensus proposal: the leader's block header for `round`.
+///
+/// Signed by `header.proposer` over the domain-separated unsigned encoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProposalV1 {
+    pub round: u64,
+    pub header: BlockHeaderV1,
+    pub sig: SignatureBytes,
+}
+
+/// Validator vote for the block with hash `block_hash` at (`height`, `round`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VoteV1 {
+    pub height: u64,
+    pub round: u64,
+    pub block_hash: Hash32,
+    pub voter: Address,
+    pub sig: SignatureBytes,
+}
+
+/// Validator gave up waiting for a proposal at (`height`, `round`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeoutV1 {
+    pub height: u64,
+    pub round: u64,
+    pub voter: Address,
+    pub sig: SignatureBytes,
+}
+
+/// Everything carried by the consensus wire envelope.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConsensusMessage {
+    Proposal(ProposalV1),
+    Vote(VoteV1),
+    Timeout(TimeoutV1),
+}
+
+impl ConsensusMessage {
+    /// Public key bytes the signature must verify under.
+    pub fn signer(&self) -> &Address {
+        match self {
+            ConsensusMessage::Proposal(p) => &p.header.proposer,
+            ConsensusMessage::Vote(v) => &v.voter,
+            ConsensusMessage::Timeout(t) => &t.voter,
+        }
+    }
+
+    pub fn sig(&self) -> &SignatureBytes {
+        match self {
+            ConsensusMessage::Proposal(p) => &p.sig,
+            ConsensusMessage::Vote(v) => &v.sig,
+            ConsensusMessage::Timeout(t) => &t.sig,
+        }
+    }
+
+    pub fn sig_mut(&mut self) -> &mut SignatureBytes {
+        match self {
+            ConsensusMessage::Proposal(p) => &mut p.sig,
+            ConsensusMessage::Vote(v) => &mut v.sig,
+            ConsensusMessage::Timeout(t) => &mut t.sig,
+        }
+    }
+}
+
+/// Quorum certificate: validator votes for `block_hash` at (`height`, `round`).
+///
+/// Each `(voter, sig)` pair is a [`VoteV1`] signature with the QC's
+/// height, round and block hash. Voters are listed in ascending order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuorumCertV1 {
+    pub height: u64,
+    pub round: u64,
+    pub block_hash: Hash32,
+    pub votes: Vec<(Address, SignatureBytes)>,
+}
+
+/// Canonical V1 block: header, txs in block order, and the QC that
+/// certified it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockV1 {
+    pub header: BlockHeaderV1,
+    pub txs: Vec<TxV1>,
+    pub qc: QuorumCertV1,
+}
+
+/// Exchanged when a connection opens. Peers on another chain, or with no
+/// tx or header version in common, are disconnected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandshakeV1 {
+    pub chain_id: u64,
+    pub genesis_hash: Hash32,
+    /// Supported [`TxVersion`] bytes.
+    pub tx_versions: Vec<u8>,
+    /// Supported [`BlockHeaderVersion`] bytes.
+    pub header_versions: Vec<u8>,
+    pub best_finalized_height: u64,
+}
+
+/// One step of an SMT range proof, in left-to-right traversal order.
+///
+/// Subtrees entirely outside the proven key range are given by hash.
+/// Subtrees straddling a range boundary are either split further or, if
+/// they hold at most one leaf, collapsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeProofNodeV1 {
+    /// Subtree entirely outside the range.
+    Subtree(Hash32),
+    /// Straddling subtree with two or more leaves: both children follow.
+    Split,
+    /// Straddling subtree whose only leaf, if any, is in the chunk.
+    Collapsed,
+    /// Straddling subtree whose only leaf is outside the range.
+    OutsideLeaf { key: Hash32, value_hash: Hash32 },
+}
+
+/// All state leaves with keys in `start..=end`, plus the proof tying them
+/// to a state root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotChunkV1 {
+    pub start: Hash32,
+    pub end: Hash32,
+    /// Ascending by key.
+    pub leaves: Vec<(Hash32, Vec<u8>)>,
+    pub proof: Vec<RangeProofNodeV1>,
+}
+
+/// Block sync request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncRequestV1 {
+    /// Up to `count` consecutive blocks starting at height `start`.
+    BlocksByRange {
+        start: u64,
+        count: u32,
+    },
+    BlockByHash(Hash32),
+    /// Up to `count` consecutive headers starting at height `start`.
+    Headers {
+        start: u64,
+        count: u32,
+    },
+    /// Leaves of the state at `height` from key `start` on, at most
+    /// `max_leaves` of them.
+    SnapshotChunk {
+        height: u64,
+        start: Hash32,
+        max_leaves: u32,
+    },
+}
+
+/// Block sync response. Anything the server does not have is simply
+/// left out, so "not found" is an empty list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncResponseV1 {
+    Blocks(Vec<BlockV1>),
+    Headers(Vec<BlockHeaderV1>),
+    /// At most one chunk.
+    SnapshotChunks(Vec<SnapshotChunkV1>),
+}